@@ -0,0 +1,25 @@
+#ifndef WGROUTER_H
+#define WGROUTER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Generates routes for the hosts and capture files in a JSON request, e.g.
+ *   {"hosts": ["example.com", "https://cdn.example.net/x.js"], "files": ["/tmp/site.har"], "lenient": false, "prefix_len": 16, "slack": 0,
+ *    "ipv6": false, "prefix_len6": 48}
+ * and returns a JSON response:
+ *   {"networks": [...], "allowed_ips": "AllowedIPs = ...", "unresolved": {...}, "warnings": [...]}
+ * or {"error": "..."}. Never returns NULL. Blocks while resolving hostnames.
+ * The response must be released with wgrouter_free().
+ */
+char *wgrouter_generate_routes(const char *request);
+
+void wgrouter_free(char *response);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use ipnetwork::IpNetwork;
+use crate::dns::StrResult;
+
+// how the resolved addresses become networks: each address is widened to prefix_len (prefix_len6 for IPv6 ones,
+// which are dropped unless ipv6 is set), then the networks are merged into fewer, larger ones; merges that are not
+// exact may claim up to slack addresses nobody asked for, in total; with max_networks, merging then goes on, whatever
+// the slack, until there are no more networks than that
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aggregation {
+    pub prefix_len: u8,
+    pub prefix_len6: u8,
+    pub slack: u64,
+    pub ipv6: bool,
+    pub max_networks: Option<usize>,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Self { prefix_len: crate::routes::DEFAULT_PREFIX_LEN, prefix_len6: crate::routes::DEFAULT_PREFIX_LEN6, slack: 0, ipv6: false, max_networks: None }
+    }
+}
+
+impl Aggregation {
+    // the optional "prefix_len", "prefix_len6", "slack", "ipv6" and "max_networks" members of an RPC or FFI request
+    pub fn from_json(params: &serde_json::Value) -> StrResult<Self> {
+        let number = |name: &str| match params.get(name) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or(format!("'{name}' must be a number")),
+        };
+        let ipv6 = match params.get("ipv6") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(value) => Some(value.as_bool().ok_or("'ipv6' must be a boolean".to_string())?),
+        };
+        let defaults = Self::default();
+        Ok(Self {
+            prefix_len: number("prefix_len")?.map(crate::routes::prefix_len).transpose()?.unwrap_or(defaults.prefix_len),
+            prefix_len6: number("prefix_len6")?.map(crate::routes::prefix_len6).transpose()?.unwrap_or(defaults.prefix_len6),
+            slack: number("slack")?.unwrap_or(defaults.slack),
+            ipv6: ipv6.unwrap_or(defaults.ipv6),
+            max_networks: number("max_networks")?.map(|max| max as usize).or(defaults.max_networks),
+        })
+    }
+
+    // the prefix length an address of this family is widened to, None for IPv6 ones when only IPv4 is routed
+    pub fn prefix_len_for(&self, ip: &IpAddr) -> Option<u8> {
+        match ip {
+            IpAddr::V4(_) => Some(self.prefix_len),
+            IpAddr::V6(_) if self.ipv6 => Some(self.prefix_len6),
+            IpAddr::V6(_) => None,
+        }
+    }
+}
+
+// both families share the arithmetic below: an IPv4 address is just a 128-bit number with 32 significant bits
+fn bits(net: &IpNetwork) -> u32 {
+    if net.is_ipv4() { 32 } else { 128 }
+}
+
+fn size(net: &IpNetwork) -> u128 {
+    1u128.checked_shl(bits(net) - u32::from(net.prefix())).unwrap_or(u128::MAX)
+}
+
+fn start(net: &IpNetwork) -> u128 {
+    match net.network() {
+        IpAddr::V4(ip) => u128::from(u32::from(ip)),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+fn network(v6: bool, start: u128, prefix_len: u8) -> IpNetwork {
+    let bits = if v6 { 128 } else { 32 };
+    let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0) >> (128 - bits);
+    let ip = if v6 { IpAddr::V6(Ipv6Addr::from(start & mask)) } else { IpAddr::V4(Ipv4Addr::from((start & mask) as u32)) };
+    IpNetwork::new(ip, prefix_len).unwrap_or_else(|_| unreachable!("prefix length {prefix_len} fits a {bits}-bit address"))
+}
+
+pub fn widen(ip: IpAddr, prefix_len: u8) -> StrResult<IpNetwork> {
+    let (v6, bits, start) = match ip {
+        IpAddr::V4(ip) => (false, 32, u128::from(u32::from(ip))),
+        IpAddr::V6(ip) => (true, 128, u128::from(ip)),
+    };
+    if prefix_len > bits {
+        return Err(format!("bad prefix length /{prefix_len} for {ip}"));
+    }
+    Ok(network(v6, start, prefix_len))
+}
+
+// the network without the hole: nothing when the hole covers it, otherwise the largest networks left around the hole,
+// one for each prefix length from the network's to the hole's
+pub fn exclude(net: IpNetwork, hole: IpNetwork) -> Vec<IpNetwork> {
+    if net.is_ipv4() != hole.is_ipv4() {
+        return vec![net];
+    }
+    if hole.prefix() <= net.prefix() {
+        return if hole.contains(net.network()) { vec![] } else { vec![net] };
+    }
+    if !net.contains(hole.network()) {
+        return vec![net];
+    }
+    (net.prefix()..hole.prefix()).map(|len| network(net.is_ipv6(), start(&hole) ^ (1u128 << (bits(&net) - 1 - u32::from(len))), len + 1)).collect()
+}
+
+// smallest network holding both, none for networks of different families
+fn common_supernet(a: &IpNetwork, b: &IpNetwork) -> Option<IpNetwork> {
+    if a.is_ipv4() != b.is_ipv4() {
+        return None;
+    }
+    let shared = ((start(a) ^ start(b)) << (128 - bits(a))).leading_zeros().min(u32::from(a.prefix())).min(u32::from(b.prefix()));
+    Some(network(a.is_ipv6(), start(a), shared as u8))
+}
+
+// the same addresses in as few networks as possible: nested ones dropped, sibling halves joined
+fn exact(mut nets: Vec<IpNetwork>) -> Vec<IpNetwork> {
+    loop {
+        nets.sort_by_key(|net| (net.is_ipv6(), start(net), net.prefix()));
+        let mut merged: Vec<IpNetwork> = Vec::with_capacity(nets.len());
+        let mut changed = false;
+        nets.iter().for_each(|net| match merged.last() {
+            Some(last) if last.contains(net.network()) && last.prefix() <= net.prefix() => changed = true,
+            Some(last) if last.prefix() == net.prefix() && last.prefix() > 0
+                && common_supernet(last, net).is_some_and(|parent| parent.prefix() == net.prefix() - 1) => {
+                let parent = common_supernet(last, net).unwrap_or(*net);
+                merged.pop();
+                merged.push(parent);
+                changed = true;
+            }
+            _ => merged.push(*net),
+        });
+        nets = merged;
+        if !changed {
+            return nets;
+        }
+    }
+}
+
+// addresses a supernet would add on top of the networks it replaces
+fn waste(nets: &[IpNetwork], supernet: &IpNetwork) -> u128 {
+    size(supernet).saturating_sub(nets.iter().filter(|net| supernet.contains(net.network())).map(size).fold(0u128, u128::saturating_add))
+}
+
+// traffic seen per address, in bytes
+pub type Weights = HashMap<IpAddr, u64>;
+
+// allowed vetoes supernets, e.g. those that would capture a connection that must stay outside the tunnel
+pub fn aggregate(nets: impl IntoIterator<Item = IpNetwork>, slack: u64, allowed: impl Fn(&IpNetwork) -> bool) -> Vec<IpNetwork> {
+    aggregate_weighted(nets, slack, allowed, &Weights::new())
+}
+
+// aggregate, with the extra addresses of a merge counted as many times more as the traffic it covers is above the
+// mean traffic of an address: busy networks stay close to their addresses, quiet ones are merged first
+pub fn aggregate_weighted(nets: impl IntoIterator<Item = IpNetwork>, slack: u64, allowed: impl Fn(&IpNetwork) -> bool, weights: &Weights)
+    -> Vec<IpNetwork> {
+    let mut nets = exact(nets.into_iter().collect());
+    let mut budget = u128::from(slack);
+    while let Some((cost, supernet)) = cheapest_merge(&nets, &allowed, weights).filter(|(cost, _)| *cost <= budget) {
+        budget -= cost;
+        nets = merged(nets, supernet);
+    }
+    nets
+}
+
+// merges the cheapest pairs until there are at most max networks, or nothing left that may be merged
+pub fn compress(nets: impl IntoIterator<Item = IpNetwork>, max: usize, allowed: impl Fn(&IpNetwork) -> bool, weights: &Weights) -> Vec<IpNetwork> {
+    let mut nets = exact(nets.into_iter().collect());
+    while nets.len() > max
+        && let Some((_, supernet)) = cheapest_merge(&nets, &allowed, weights) {
+        nets = merged(nets, supernet);
+    }
+    nets
+}
+
+// the supernet of two neighbours with the fewest extra addresses, each counted as many times more as the traffic the
+// supernet covers is above the mean traffic of an address
+fn cheapest_merge(nets: &[IpNetwork], allowed: impl Fn(&IpNetwork) -> bool, weights: &Weights) -> Option<(u128, IpNetwork)> {
+    let total = weights.values().map(|&bytes| u128::from(bytes)).sum::<u128>();
+    let mean = total / weights.len().max(1) as u128;
+    let cost = |waste: u128, supernet: &IpNetwork| {
+        if mean == 0 {
+            return waste;
+        }
+        let traffic = weights.iter().filter(|(ip, _)| supernet.contains(**ip)).map(|(_, &bytes)| u128::from(bytes)).sum::<u128>();
+        waste.saturating_mul(mean.saturating_add(traffic)) / mean
+    };
+    nets.windows(2)
+        .filter_map(|pair| common_supernet(&pair[0], &pair[1]))
+        .filter(|supernet| allowed(supernet))
+        .map(|supernet| (cost(waste(nets, &supernet), &supernet), supernet))
+        .min_by_key(|(cost, supernet)| (*cost, supernet.is_ipv6(), start(supernet), supernet.prefix()))
+}
+
+fn merged(mut nets: Vec<IpNetwork>, supernet: IpNetwork) -> Vec<IpNetwork> {
+    nets.retain(|net| !supernet.contains(net.network()));
+    nets.push(supernet);
+    exact(nets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &[&str]) -> Vec<IpNetwork> {
+        nets.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(Aggregation::from_json(&serde_json::json!({})), Ok(Aggregation::default()));
+        assert_eq!(Aggregation::from_json(&serde_json::json!({"prefix_len": 24, "slack": 512})), Ok(Aggregation { prefix_len: 24, slack: 512, ..Aggregation::default() }));
+        assert_eq!(Aggregation::from_json(&serde_json::json!({"ipv6": true, "prefix_len6": 64})),
+            Ok(Aggregation { prefix_len6: 64, ipv6: true, ..Aggregation::default() }));
+        assert!(Aggregation::from_json(&serde_json::json!({"ipv6": "yes"})).is_err());
+        assert_eq!(Aggregation::from_json(&serde_json::json!({"prefix_len": "16"})), Err("'prefix_len' must be a number".to_string()));
+        assert!(Aggregation::from_json(&serde_json::json!({"prefix_len": 40})).is_err());
+        assert!(Aggregation::from_json(&serde_json::json!({"slack": -1})).is_err());
+        assert_eq!(Aggregation::from_json(&serde_json::json!({"max_networks": 250})).unwrap().max_networks, Some(250));
+    }
+
+    #[test]
+    fn test_widen() {
+        assert_eq!(widen("93.184.216.34".parse().unwrap(), 20), Ok("93.184.208.0/20".parse().unwrap()));
+        assert_eq!(widen("93.184.216.34".parse().unwrap(), 0), Ok("0.0.0.0/0".parse().unwrap()));
+        assert!(widen("93.184.216.34".parse().unwrap(), 33).is_err());
+        assert_eq!(widen("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap(), 48), Ok("2606:2800:220::/48".parse().unwrap()));
+        assert_eq!(widen("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap(), 0), Ok("::/0".parse().unwrap()));
+        assert!(widen("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap(), 129).is_err());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let net = "198.51.100.0/29".parse().unwrap();
+        assert_eq!(exclude(net, "198.51.100.5/32".parse().unwrap()), nets(&["198.51.100.0/30", "198.51.100.6/31", "198.51.100.4/32"]));
+        assert_eq!(exclude(net, "198.51.100.4/31".parse().unwrap()), nets(&["198.51.100.0/30", "198.51.100.6/31"]));
+        assert_eq!(exclude(net, "198.51.101.5/32".parse().unwrap()), vec![net]);
+        assert_eq!(exclude(net, "198.51.0.0/16".parse().unwrap()), vec![]);
+        assert_eq!(exclude(net, "203.0.113.0/24".parse().unwrap()), vec![net]);
+        assert_eq!(exclude("2001:db8::/127".parse().unwrap(), "2001:db8::1/128".parse().unwrap()), nets(&["2001:db8::/128"]));
+        assert_eq!(exclude("0.0.0.0/0".parse().unwrap(), "1.1.1.1/32".parse().unwrap()).len(), 32);
+    }
+
+    #[test]
+    fn test_exact() {
+        let all = |_: &IpNetwork| true;
+        assert_eq!(aggregate(nets(&["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24", "10.0.3.0/24"]), 0, all), nets(&["10.0.0.0/22"]));
+        assert_eq!(aggregate(nets(&["10.0.1.0/24", "10.0.2.0/24"]), 0, all), nets(&["10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(aggregate(nets(&["10.0.0.0/16", "10.0.3.7/32", "10.1.0.0/16", "1.1.1.1/32", "1.1.1.1/32"]), 0, all),
+            nets(&["1.1.1.1/32", "10.0.0.0/15"]));
+        assert_eq!(aggregate(nets(&["0.0.0.0/1", "128.0.0.0/1"]), 0, all), nets(&["0.0.0.0/0"]));
+        assert!(aggregate(Vec::new(), 100, all).is_empty());
+        assert_eq!(aggregate(nets(&["2001:db8::/48", "2001:db8:1::/48", "10.0.0.0/24", "10.0.1.0/24"]), 0, all),
+            nets(&["10.0.0.0/23", "2001:db8::/47"]));
+        assert_eq!(aggregate(nets(&["::/1", "8000::/1", "0.0.0.0/1"]), 0, all), nets(&["0.0.0.0/1", "::/0"]));
+    }
+
+    #[test]
+    fn test_slack() {
+        let all = |_: &IpNetwork| true;
+        let spread = nets(&["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24", "10.0.8.0/24"]);
+        assert_eq!(aggregate(spread.clone(), 255, all), nets(&["10.0.0.0/23", "10.0.2.0/24", "10.0.8.0/24"]));
+        assert_eq!(aggregate(spread.clone(), 256, all), nets(&["10.0.0.0/22", "10.0.8.0/24"]));
+        assert_eq!(aggregate(spread.clone(), 256 + 3 * 1024, all), nets(&["10.0.0.0/20"]));
+        let forbidden = |net: &IpNetwork| !net.contains("10.0.3.1".parse().unwrap());
+        assert_eq!(aggregate(spread, 1 << 20, forbidden), nets(&["10.0.0.0/23", "10.0.2.0/24", "10.0.8.0/24"]));
+        // networks of different families are never merged, whatever the slack
+        assert_eq!(aggregate(nets(&["0.0.0.0/32", "::/128"]), u64::MAX, all), nets(&["0.0.0.0/32", "::/128"]));
+        assert_eq!(aggregate(nets(&["2001:db8::/48", "2001:db8:2::/48"]), 1 << 16, all), nets(&["2001:db8::/48", "2001:db8:2::/48"]));
+        assert_eq!(aggregate(nets(&["2001:db8::/127", "2001:db8::4/127"]), 4, all), nets(&["2001:db8::/125"]));
+        // a /64 is already more addresses than any slack
+        assert_eq!(aggregate(nets(&["2001:db8::/64", "2001:db8:0:2::/64"]), u64::MAX, all), nets(&["2001:db8::/64", "2001:db8:0:2::/64"]));
+    }
+
+    #[test]
+    fn test_compress() {
+        let all = |_: &IpNetwork| true;
+        let spread = nets(&["10.0.0.0/24", "10.0.2.0/24", "10.8.0.0/24", "10.8.2.0/24", "2001:db8::/48"]);
+        assert_eq!(compress(spread.clone(), 5, all, &Weights::new()), spread);
+        assert_eq!(compress(spread.clone(), 4, all, &Weights::new()), nets(&["10.0.0.0/22", "10.8.0.0/24", "10.8.2.0/24", "2001:db8::/48"]));
+        assert_eq!(compress(spread.clone(), 2, all, &Weights::new()), nets(&["10.0.0.0/12", "2001:db8::/48"]));
+        // the families are never merged, so two is as far as it goes
+        assert_eq!(compress(spread.clone(), 1, all, &Weights::new()).len(), 2);
+        let forbidden = |net: &IpNetwork| !net.contains("10.4.0.1".parse().unwrap());
+        assert_eq!(compress(spread, 1, forbidden, &Weights::new()), nets(&["10.0.0.0/22", "10.8.0.0/22", "2001:db8::/48"]));
+    }
+
+    #[test]
+    fn test_weighted() {
+        let all = |_: &IpNetwork| true;
+        let spread = nets(&["10.0.0.0/24", "10.0.2.0/24", "10.8.0.0/24", "10.8.2.0/24"]);
+        let unweighted = aggregate(spread.clone(), 512, all);
+        assert_eq!(unweighted, nets(&["10.0.0.0/22", "10.8.0.0/24", "10.8.2.0/24"]));
+        // the busy 10.0.x.x pair now costs about three times its 512 extra addresses, the quiet one is merged instead
+        let weights = Weights::from([("10.0.0.1".parse().unwrap(), 1_000_000), ("10.8.0.1".parse().unwrap(), 10)]);
+        assert_eq!(aggregate_weighted(spread.clone(), 512, all, &weights), nets(&["10.0.0.0/24", "10.0.2.0/24", "10.8.0.0/22"]));
+        assert_eq!(aggregate_weighted(spread.clone(), 512, all, &Weights::new()), unweighted);
+        assert_eq!(aggregate_weighted(spread, 512, all, &Weights::from([("10.0.0.1".parse().unwrap(), 0)])), unweighted);
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use crate::dns::StrResult;
+use std::collections::BTreeSet;
+use ipnetwork::IpNetwork;
+use crate::history::{self, unix_time, Generation, History, Transition};
+
+#[cfg(target_os = "linux")]
+pub mod netlink;
+
+pub fn ip_route_commands(verb: &str, nets: &[String], iface: &str, table: Option<u32>) -> Vec<Vec<String>> {
+    nets.iter()
+        .map(|net| {
+            let mut cmd = ["route", verb, net, "dev", iface].map(String::from).to_vec();
+            if let Some(table) = table {
+                cmd.extend(["table".to_string(), table.to_string()]);
+            }
+            cmd
+        })
+        .collect()
+}
+
+pub(crate) fn run_ip(args: &[String]) -> StrResult<()> {
+    let output = Command::new("ip").args(args).output().map_err(|e| format!("could not run ip: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("'ip {}' failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+pub fn apply_ip_routes(nets: &[String], iface: &str, table: Option<u32>) -> StrResult<()> {
+    ip_route_commands("replace", nets, iface, table).iter().try_for_each(|args| run_ip(args))
+}
+
+// routes removed by hand in the meantime are not an error
+pub fn remove_ip_routes(nets: &[String], iface: &str, table: Option<u32>) {
+    ip_route_commands("del", nets, iface, table).iter().for_each(|args| {
+        if let Err(e) = run_ip(args) {
+            eprintln!("warning: {e}");
+        }
+    });
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    // one route per network via the interface
+    Route,
+    // the networks in an nftables set, traffic to them marked for policy routing
+    Nft { mark: u32, scope: Scope },
+    // one route per network via the interface, installed through rtnetlink and tagged as wgrouter's
+    Netlink,
+}
+
+// whose traffic the nft backend marks: everyone's, that of these LAN addresses (kept in a set of their own), or that of
+// the addresses in a set named by the user and filled by other means (e.g. a DHCP hook); the others go direct
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Scope {
+    #[default]
+    All,
+    Clients(Vec<IpNetwork>),
+    Set(String),
+}
+
+pub const NFT_TABLE: &str = "wgrouter";
+// not wg-quick's 51820 (0xca6c): that one marks the tunnel's own packets so they bypass it
+pub const DEFAULT_FWMARK: u32 = 0x7767;
+
+fn nft_name(target: &str) -> String {
+    target.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+// the networks without those inside another of them: an interval set refuses overlapping elements, and without
+// --replace the networks of the last generation come along with the new ones
+fn outermost<'a>(nets: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let (parsed, unparsed): (Vec<_>, Vec<_>) = nets.into_iter().map(|net| net.parse::<IpNetwork>().map_err(|_| net)).partition(Result::is_ok);
+    let parsed = parsed.into_iter().flatten().map(|net| IpNetwork::new(net.network(), net.prefix()).unwrap_or(net)).collect::<BTreeSet<_>>();
+    parsed
+        .iter()
+        .filter(|net| !parsed.iter().any(|other| other.prefix() < net.prefix() && other.contains(net.network())))
+        .map(IpNetwork::to_string)
+        .chain(unparsed.into_iter().filter_map(Result::err).cloned())
+        .collect()
+}
+
+// one nft -f transaction: either all of it applies or none, so there is never a half-filled set or a chain without its rule;
+// a set holds one address family, so IPv6 networks go to a second set, <name>_v6; a scope adds source sets, <name>_clients
+// (or the named set) and its _v6 twin, which the rules then also match on
+pub fn nft_script(target: &str, nets: &BTreeSet<String>, mark: u32, scope: &Scope) -> String {
+    let name = nft_name(target);
+    let (v4, v6): (Vec<String>, Vec<String>) = outermost(nets).into_iter().partition(|net| !net.contains(':'));
+    let families = [("ip", "ipv4_addr", name.clone(), v4), ("ip6", "ipv6_addr", format!("{name}_v6"), v6)];
+    let mut script = format!("add table inet {NFT_TABLE}\n");
+    families.iter().for_each(|(_, kind, set, nets)| {
+        script.push_str(&format!("add set inet {NFT_TABLE} {set} {{ type {kind}; flags interval; }}\n\
+            flush set inet {NFT_TABLE} {set}\n"));
+        if !nets.is_empty() {
+            script.push_str(&format!("add element inet {NFT_TABLE} {set} {{ {} }}\n", nets.join(", ")));
+        }
+    });
+    let sources = match scope {
+        Scope::All => None,
+        Scope::Clients(_) => Some(format!("{name}_clients")),
+        Scope::Set(set) => Some(nft_name(set)),
+    };
+    if let Some(sources) = &sources {
+        let (v4, v6): (Vec<String>, Vec<String>) = match scope {
+            Scope::Clients(clients) => outermost(&clients.iter().map(|client| client.to_string()).collect::<Vec<_>>()).into_iter().partition(|client| !client.contains(':')),
+            _ => Default::default(),
+        };
+        [("ipv4_addr", sources.clone(), v4), ("ipv6_addr", format!("{sources}_v6"), v6)].iter().for_each(|(kind, set, clients)| {
+            // a named set is the user's to fill, it is only created if missing
+            script.push_str(&format!("add set inet {NFT_TABLE} {set} {{ type {kind}; flags interval; }}\n"));
+            if let Scope::Clients(_) = scope {
+                script.push_str(&format!("flush set inet {NFT_TABLE} {set}\n"));
+                if !clients.is_empty() {
+                    script.push_str(&format!("add element inet {NFT_TABLE} {set} {{ {} }}\n", clients.join(", ")));
+                }
+            }
+        });
+    }
+    [("output", "route", "output"), ("prerouting", "filter", "prerouting")].iter().for_each(|(chain, kind, hook)| {
+        script.push_str(&format!("add chain inet {NFT_TABLE} {name}_{chain} {{ type {kind} hook {hook} priority mangle; policy accept; }}\n\
+            flush chain inet {NFT_TABLE} {name}_{chain}\n"));
+        families.iter().for_each(|(family, _, set, _)| {
+            let from = sources.as_ref().map(|sources| {
+                let sources = if *family == "ip" { sources.clone() } else { format!("{sources}_v6") };
+                format!("{family} saddr @{sources} ")
+            }).unwrap_or_default();
+            script.push_str(&format!("add rule inet {NFT_TABLE} {name}_{chain} {from}{family} daddr @{set} meta mark set {mark:#x}\n"));
+        });
+    });
+    script
+}
+
+// on the running interface only: the config file is left alone, so the change lasts until the interface is brought
+// up from it again
+pub fn wg_set_allowed_ips(iface: &str, peer: &str, ips: &[String]) -> StrResult<()> {
+    let output = Command::new("wg").args(["set", iface, "peer", peer, "allowed-ips", &ips.join(",")]).output().map_err(|e| format!("could not run wg: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("'wg set {iface} peer {peer} allowed-ips ...' failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+fn run_nft(script: &str) -> StrResult<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run nft: {e}"))?;
+    child.stdin.take().map(|mut stdin| stdin.write_all(script.as_bytes())).transpose().map_err(|e| format!("could not pass rules to nft: {e}"))?;
+    let output = child.wait_with_output().map_err(|e| format!("could not run nft: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("nft failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+// the route backend changes only what differs, the nft one swaps the whole set in a single transaction
+pub fn install(backend: &Backend, transition: &Transition, next: &BTreeSet<String>, iface: &str, table: Option<u32>) -> StrResult<()> {
+    match backend {
+        Backend::Route => apply_transition(transition, iface, table),
+        Backend::Nft { mark, scope } => run_nft(&nft_script(&history::target_name(iface, table), next, *mark, scope)),
+        Backend::Netlink => apply_netlink_transition(transition, iface, table),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_netlink_transition(transition: &Transition, iface: &str, table: Option<u32>) -> StrResult<()> {
+    netlink::add_routes(&transition.add, iface, table)?;
+    netlink::remove_routes(&transition.remove, iface, table)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_netlink_transition(_transition: &Transition, _iface: &str, _table: Option<u32>) -> StrResult<()> {
+    Err("the netlink backend is only supported on Linux".to_string())
+}
+
+// routes are added before the stale ones are deleted, so traffic that moves between networks never loses its route
+pub fn apply_transition(transition: &Transition, iface: &str, table: Option<u32>) -> StrResult<()> {
+    apply_ip_routes(&transition.add, iface, table)?;
+    remove_ip_routes(&transition.remove, iface, table);
+    Ok(())
+}
+
+// installs nets and records the result as a new generation for undo (in the history under state_dir), touching only
+// the routes that differ from the last generation; the nets are added to what is installed, or replace it (dropping
+// routes they no longer need)
+pub fn apply_generation(backend: Backend, nets: &[String], iface: &str, table: Option<u32>, keep: usize, replace: bool, state_dir: &Path) -> StrResult<(Transition, usize)> {
+    let path = history::history_path(state_dir, &history::target_name(iface, table));
+    let mut history = History::load(&path)?;
+    let next = next_generation(&history, nets, replace);
+    let transition = history.plan(&next);
+    install(&backend, &transition, &next, iface, table)?;
+    let count = next.len();
+    history.record(Generation { created: unix_time(), nets: next }, keep);
+    history.save(&path)?;
+    Ok((transition, count))
+}
+
+fn next_generation(history: &History, nets: &[String], replace: bool) -> BTreeSet<String> {
+    if replace {
+        nets.iter().cloned().collect()
+    } else {
+        history.current().iter().cloned().chain(nets.iter().cloned()).collect()
+    }
+}
+
+// what apply_generation would change, without changing anything
+pub fn plan_generation(nets: &[String], iface: &str, table: Option<u32>, replace: bool, state_dir: &Path) -> StrResult<Transition> {
+    let history = History::load(&history::history_path(state_dir, &history::target_name(iface, table)))?;
+    Ok(history.plan(&next_generation(&history, nets, replace)))
+}
+
+// takes the networks out of what is installed, recorded as a new generation
+pub fn remove_from_generation(backend: Backend, nets: &[String], iface: &str, table: Option<u32>, keep: usize, state_dir: &Path) -> StrResult<(Transition, usize)> {
+    let path = history::history_path(state_dir, &history::target_name(iface, table));
+    let mut history = History::load(&path)?;
+    let next = history.current().iter().filter(|net| !nets.contains(net)).cloned().collect::<BTreeSet<_>>();
+    let transition = history.plan(&next);
+    install(&backend, &transition, &next, iface, table)?;
+    let count = next.len();
+    history.record(Generation { created: unix_time(), nets: next }, keep);
+    history.save(&path)?;
+    Ok((transition, count))
+}
+
+// removes the routes tagged as wgrouter's via the interface, whatever apply installed them, and records what is left
+// as a new generation, so undo puts them back
+#[cfg(target_os = "linux")]
+pub fn flush_generation(nets: &[String], iface: &str, table: Option<u32>, keep: usize, state_dir: &Path) -> StrResult<(Transition, usize)> {
+    let path = history::history_path(state_dir, &history::target_name(iface, table));
+    let mut history = History::load(&path)?;
+    netlink::remove_routes(nets, iface, table)?;
+    let next = history.current().iter().filter(|net| !nets.contains(net)).cloned().collect::<BTreeSet<_>>();
+    let transition = Transition { remove: nets.to_vec(), add: vec![] };
+    let count = next.len();
+    history.record(Generation { created: unix_time(), nets: next }, keep);
+    history.save(&path)?;
+    Ok((transition, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_route_commands() {
+        let nets = ["1.2.0.0/16".to_string(), "5.6.0.0/16".to_string()];
+        assert_eq!(ip_route_commands("replace", &nets, "wg0", None), vec![
+            vec!["route", "replace", "1.2.0.0/16", "dev", "wg0"],
+            vec!["route", "replace", "5.6.0.0/16", "dev", "wg0"],
+        ]);
+        assert_eq!(ip_route_commands("del", &nets[..1], "wg1", Some(100)), vec![vec!["route", "del", "1.2.0.0/16", "dev", "wg1", "table", "100"]]);
+        assert!(ip_route_commands("replace", &[], "wg0", None).is_empty());
+    }
+
+    #[test]
+    fn test_nft_script() {
+        let nets = BTreeSet::from(["1.2.0.0/16".to_string(), "5.6.0.0/16".to_string(), "2001:db8::/48".to_string()]);
+        assert_eq!(nft_script("wg0-table100", &nets, 0xca6c, &Scope::All), "add table inet wgrouter
+add set inet wgrouter wg0_table100 { type ipv4_addr; flags interval; }
+flush set inet wgrouter wg0_table100
+add element inet wgrouter wg0_table100 { 1.2.0.0/16, 5.6.0.0/16 }
+add set inet wgrouter wg0_table100_v6 { type ipv6_addr; flags interval; }
+flush set inet wgrouter wg0_table100_v6
+add element inet wgrouter wg0_table100_v6 { 2001:db8::/48 }
+add chain inet wgrouter wg0_table100_output { type route hook output priority mangle; policy accept; }
+flush chain inet wgrouter wg0_table100_output
+add rule inet wgrouter wg0_table100_output ip daddr @wg0_table100 meta mark set 0xca6c
+add rule inet wgrouter wg0_table100_output ip6 daddr @wg0_table100_v6 meta mark set 0xca6c
+add chain inet wgrouter wg0_table100_prerouting { type filter hook prerouting priority mangle; policy accept; }
+flush chain inet wgrouter wg0_table100_prerouting
+add rule inet wgrouter wg0_table100_prerouting ip daddr @wg0_table100 meta mark set 0xca6c
+add rule inet wgrouter wg0_table100_prerouting ip6 daddr @wg0_table100_v6 meta mark set 0xca6c
+");
+        assert!(!nft_script("wg0", &BTreeSet::new(), 1, &Scope::All).contains("add element"));
+        // what the last generation had comes along without --replace, overlapping the new networks or inside them
+        let overlapping = BTreeSet::from(["10.0.0.0/8".to_string(), "10.1.0.0/16".into(), "10.1.2.0/24".into(), "11.0.0.0/16".into(),
+            "11.0.0.0/15".into(), "2001:db8::/32".into(), "2001:db8:1::/48".into()]);
+        let script = nft_script("wg0", &overlapping, 1, &Scope::All);
+        assert!(script.contains("add element inet wgrouter wg0 { 10.0.0.0/8, 11.0.0.0/15 }\n"));
+        assert!(script.contains("add element inet wgrouter wg0_v6 { 2001:db8::/32 }\n"));
+        assert_eq!(outermost(&["10.1.2.0/8".to_string(), "10.0.0.0/8".into(), "wg-peer".into()]), vec!["10.0.0.0/8", "wg-peer"]);
+
+        let clients = Scope::Clients(vec!["192.168.1.20".parse().unwrap(), "192.168.2.0/24".parse().unwrap(), "192.168.2.7".parse().unwrap()]);
+        let script = nft_script("wg0", &nets, 1, &clients);
+        assert!(script.contains("flush set inet wgrouter wg0_clients\nadd element inet wgrouter wg0_clients { 192.168.1.20/32, 192.168.2.0/24 }\n"));
+        assert!(script.contains("add rule inet wgrouter wg0_prerouting ip saddr @wg0_clients ip daddr @wg0 meta mark set 0x1\n"));
+        assert!(script.contains("add rule inet wgrouter wg0_prerouting ip6 saddr @wg0_clients_v6 ip6 daddr @wg0_v6 meta mark set 0x1\n"));
+        let script = nft_script("wg0", &nets, 1, &Scope::Set("kids-devices".to_string()));
+        assert!(script.contains("add set inet wgrouter kids_devices { type ipv4_addr; flags interval; }\nadd set inet wgrouter kids_devices_v6"));
+        assert!(!script.contains("flush set inet wgrouter kids_devices"));
+        assert!(script.contains("add rule inet wgrouter wg0_output ip saddr @kids_devices ip daddr @wg0 meta mark set 0x1\n"));
+    }
+}
@@ -0,0 +1,258 @@
+use std::ffi::CString;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use ipnetwork::IpNetwork;
+use crate::dns::StrResult;
+
+// the route protocol the routes installed through netlink are tagged with ('ip route show proto 119'), so they can be
+// told apart from everyone else's and flushed
+pub const RTPROT_WGROUTER: u8 = 119;
+
+const RT_TABLE_MAIN: u32 = 254;
+// nlmsghdr, then rtmsg
+const HEADER_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+
+// a route as the kernel lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub net: IpNetwork,
+    pub table: u32,
+    pub ifindex: Option<u32>,
+    pub protocol: u8,
+}
+
+fn attribute(out: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    out.extend(((4 + data.len()) as u16).to_ne_bytes());
+    out.extend(kind.to_ne_bytes());
+    out.extend(data);
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+fn with_header(mut out: Vec<u8>, kind: u16, flags: u16, seq: u32) -> Vec<u8> {
+    let len = out.len() as u32;
+    out[0..4].copy_from_slice(&len.to_ne_bytes());
+    out[4..6].copy_from_slice(&kind.to_ne_bytes());
+    out[6..8].copy_from_slice(&flags.to_ne_bytes());
+    out[8..12].copy_from_slice(&seq.to_ne_bytes());
+    out
+}
+
+// an RTM_NEWROUTE or RTM_DELROUTE request for the route to the network out of the interface, tagged as wgrouter's; the
+// kernel only deletes a route of the same protocol, so what others installed stays
+pub fn route_message(kind: u16, flags: u16, seq: u32, net: &IpNetwork, ifindex: u32, table: Option<u32>) -> Vec<u8> {
+    let (family, addr) = match net.network() {
+        IpAddr::V4(ip) => (libc::AF_INET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (libc::AF_INET6, ip.octets().to_vec()),
+    };
+    let table = table.unwrap_or(RT_TABLE_MAIN);
+    let mut out = vec![0; HEADER_LEN];
+    out.extend([family as u8, net.prefix(), 0, 0, u8::try_from(table).unwrap_or(libc::RT_TABLE_UNSPEC), RTPROT_WGROUTER, libc::RT_SCOPE_LINK, libc::RTN_UNICAST]);
+    out.extend(0u32.to_ne_bytes());
+    attribute(&mut out, libc::RTA_DST, &addr);
+    attribute(&mut out, libc::RTA_OIF, &ifindex.to_ne_bytes());
+    attribute(&mut out, libc::RTA_TABLE, &table.to_ne_bytes());
+    with_header(out, kind, flags, seq)
+}
+
+fn dump_message(seq: u32) -> Vec<u8> {
+    let mut out = vec![0; HEADER_LEN];
+    out.extend([0; RTMSG_LEN]);
+    with_header(out, libc::RTM_GETROUTE, (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16, seq)
+}
+
+fn u16_at(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+// the netlink messages in a buffer read from the socket, as type and payload
+fn messages(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut out = Vec::new();
+    let mut at = 0;
+    while let (Some(len), Some(kind)) = (u32_at(buf, at), u16_at(buf, at + 4)) {
+        let len = len as usize;
+        let Some(payload) = buf.get(at + HEADER_LEN..at + len).filter(|_| len >= HEADER_LEN) else {
+            break;
+        };
+        out.push((kind, payload));
+        at += len.next_multiple_of(4);
+    }
+    out
+}
+
+// the route of an RTM_NEWROUTE payload; none for a default route or one of a family other than IPv4 and IPv6
+fn parse_route(payload: &[u8]) -> Option<Route> {
+    let rtmsg = payload.get(..RTMSG_LEN)?;
+    let (family, prefix, protocol) = (i32::from(rtmsg[0]), rtmsg[1], rtmsg[5]);
+    let (mut dst, mut ifindex, mut table) = (None, None, u32::from(rtmsg[4]));
+    let mut at = RTMSG_LEN;
+    while let (Some(len), Some(kind)) = (u16_at(payload, at), u16_at(payload, at + 2)) {
+        let data = payload.get(at + 4..at + usize::from(len)).filter(|_| len >= 4)?;
+        match kind {
+            libc::RTA_DST => dst = Some(data),
+            libc::RTA_OIF => ifindex = u32_at(data, 0),
+            libc::RTA_TABLE => table = u32_at(data, 0)?,
+            _ => {}
+        }
+        at += usize::from(len).next_multiple_of(4);
+    }
+    let ip = match (family, dst?.len()) {
+        (libc::AF_INET, 4) => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(dst?).ok()?)),
+        (libc::AF_INET6, 16) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(dst?).ok()?)),
+        _ => return None,
+    };
+    Some(Route { net: IpNetwork::new(ip, prefix).ok()?, table, ifindex, protocol })
+}
+
+// the error of an NLMSG_ERROR payload, none for the acknowledgement (error 0)
+fn error(payload: &[u8]) -> Option<std::io::Error> {
+    let errno = u32_at(payload, 0)? as i32;
+    (errno != 0).then(|| std::io::Error::from_raw_os_error(-errno))
+}
+
+pub fn ifindex(iface: &str) -> StrResult<u32> {
+    let name = CString::new(iface).map_err(|e| format!("bad interface name '{iface}': {e}"))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(format!("no such interface '{iface}'")),
+        index => Ok(index),
+    }
+}
+
+struct Socket {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Socket {
+    // an unbound socket talks to the kernel
+    fn open() -> StrResult<Self> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(format!("could not open netlink socket: {}", std::io::Error::last_os_error()));
+        }
+        Ok(Self { fd: unsafe { OwnedFd::from_raw_fd(fd) }, seq: 0 })
+    }
+
+    fn send(&mut self, message: impl FnOnce(u32) -> Vec<u8>) -> StrResult<()> {
+        self.seq += 1;
+        let message = message(self.seq);
+        let sent = unsafe { libc::send(self.fd.as_raw_fd(), message.as_ptr() as *const libc::c_void, message.len(), 0) };
+        if sent < 0 {
+            return Err(format!("could not send netlink request: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> StrResult<usize> {
+        let len = unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if len < 0 {
+            return Err(format!("could not receive netlink reply: {}", std::io::Error::last_os_error()));
+        }
+        Ok(len as usize)
+    }
+
+    // a request the kernel acknowledges
+    fn request(&mut self, message: impl FnOnce(u32) -> Vec<u8>) -> Result<(), std::io::Error> {
+        self.send(message).map_err(std::io::Error::other)?;
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let len = self.recv(&mut buf).map_err(std::io::Error::other)?;
+            if let Some((_, payload)) = messages(&buf[..len]).into_iter().find(|(kind, _)| i32::from(*kind) == libc::NLMSG_ERROR) {
+                return error(payload).map_or(Ok(()), Err);
+            }
+        }
+    }
+
+    fn routes(&mut self) -> StrResult<Vec<Route>> {
+        self.send(dump_message)?;
+        let mut buf = vec![0u8; 65536];
+        let mut routes = Vec::new();
+        loop {
+            let len = self.recv(&mut buf)?;
+            for (kind, payload) in messages(&buf[..len]) {
+                match i32::from(kind) {
+                    libc::NLMSG_DONE => return Ok(routes),
+                    libc::NLMSG_ERROR => return Err(format!("could not list routes: {}", error(payload).unwrap_or_else(|| std::io::Error::other("no error")))),
+                    _ if kind == libc::RTM_NEWROUTE => routes.extend(parse_route(payload)),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn parsed(nets: &[String]) -> StrResult<Vec<IpNetwork>> {
+    nets.iter().map(|net| net.parse::<IpNetwork>().map_err(|e| format!("could not parse network {net}: {e}"))).collect()
+}
+
+// routes replace whatever route to the same network there was, as 'ip route replace' does
+pub fn add_routes(nets: &[String], iface: &str, table: Option<u32>) -> StrResult<()> {
+    let index = ifindex(iface)?;
+    let mut socket = Socket::open()?;
+    let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    parsed(nets)?.iter().try_for_each(|net| {
+        socket.request(|seq| route_message(libc::RTM_NEWROUTE, flags, seq, net, index, table))
+            .map_err(|e| format!("could not add route to {net} via {iface}: {e}"))
+    })
+}
+
+// routes removed by hand in the meantime are not an error
+pub fn remove_routes(nets: &[String], iface: &str, table: Option<u32>) -> StrResult<()> {
+    let index = ifindex(iface)?;
+    let mut socket = Socket::open()?;
+    let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+    parsed(nets)?.iter().for_each(|net| {
+        if let Err(e) = socket.request(|seq| route_message(libc::RTM_DELROUTE, flags, seq, net, index, table)) {
+            eprintln!("warning: could not remove route to {net} via {iface}: {e}");
+        }
+    });
+    Ok(())
+}
+
+// the networks routed via the interface in the table by routes tagged as wgrouter's
+pub fn tagged_routes(iface: &str, table: Option<u32>) -> StrResult<Vec<String>> {
+    let index = ifindex(iface)?;
+    let table = table.unwrap_or(RT_TABLE_MAIN);
+    Ok(Socket::open()?
+        .routes()?
+        .into_iter()
+        .filter(|route| route.protocol == RTPROT_WGROUTER && route.table == table && route.ifindex == Some(index))
+        .map(|route| route.net.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_message() {
+        let net = "93.184.0.0/16".parse().unwrap();
+        let message = route_message(libc::RTM_NEWROUTE, 0x505, 7, &net, 3, Some(100));
+        assert_eq!(message.len(), HEADER_LEN + RTMSG_LEN + 8 + 8 + 8);
+        assert_eq!(u32_at(&message, 0), Some(message.len() as u32));
+        assert_eq!((u16_at(&message, 4), u16_at(&message, 6), u32_at(&message, 8)), (Some(libc::RTM_NEWROUTE), Some(0x505), Some(7)));
+        let parsed = messages(&message);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parse_route(parsed[0].1), Some(Route { net, table: 100, ifindex: Some(3), protocol: RTPROT_WGROUTER }));
+
+        let net6 = "2606:2800:220::/48".parse().unwrap();
+        let two = [route_message(libc::RTM_NEWROUTE, 0, 1, &net6, 4, Some(1000)), route_message(libc::RTM_NEWROUTE, 0, 2, &net, 4, None)].concat();
+        assert_eq!(messages(&two).iter().map(|(_, payload)| parse_route(payload).unwrap()).collect::<Vec<_>>(), vec![
+            Route { net: net6, table: 1000, ifindex: Some(4), protocol: RTPROT_WGROUTER },
+            Route { net, table: RT_TABLE_MAIN, ifindex: Some(4), protocol: RTPROT_WGROUTER },
+        ]);
+        assert!(messages(&message[..20]).is_empty());
+        assert_eq!(parse_route(&[0; RTMSG_LEN]), None);
+    }
+
+    #[test]
+    fn test_error() {
+        assert!(error(&0u32.to_ne_bytes()).is_none());
+        assert_eq!(error(&(-libc::ESRCH).to_ne_bytes()).map(|e| e.raw_os_error()), Some(Some(libc::ESRCH)));
+    }
+}
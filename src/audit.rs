@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Arc;
+use ipnetwork::IpNetwork;
+use serde_json::{json, Value};
+use crate::dns::StrResult;
+use crate::clock::Clock;
+use crate::output::Report;
+
+// append-only JSON lines, one per decision (extracted, filtered, resolved, aggregated, applied), so that what got
+// routed can be traced back to where it came from; lines of one run share its "run" id
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Option<File>,
+    run: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuditLog {
+    // none: nothing is recorded
+    pub fn open(path: Option<&str>) -> StrResult<Self> {
+        Self::with_clock(path, crate::clock::system())
+    }
+
+    pub fn with_clock(path: Option<&str>, clock: Arc<dyn Clock>) -> StrResult<Self> {
+        let file = path
+            .map(|path| OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("could not open audit log {path}: {e}")))
+            .transpose()?;
+        Ok(Self { file, run: format!("{}-{}", clock.unix_time(), std::process::id()), clock })
+    }
+
+    // each line goes out in a single write, so lines of concurrent runs do not interleave
+    pub fn record(&mut self, event: &str, details: Value) -> StrResult<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let mut line = json!({"ts": self.clock.unix_time(), "run": self.run, "event": event});
+        if let (Some(line), Value::Object(details)) = (line.as_object_mut(), details) {
+            line.extend(details);
+        }
+        file.write_all(format!("{line}\n").as_bytes()).map_err(|e| format!("could not write audit log: {e}"))
+    }
+
+    // which addresses, and so which hosts, each routed network is there for
+    pub fn record_networks(&mut self, report: &Report) -> StrResult<()> {
+        report.networks.iter().try_for_each(|net| {
+            let Ok(parsed) = net.parse::<IpNetwork>() else {
+                return Ok(());
+            };
+            let covered = |ip: &String| ip.parse::<IpNetwork>().is_ok_and(|ip| parsed.contains(ip.network()));
+            let hosts = report.hosts.iter().filter(|(_, ips)| ips.iter().any(covered)).map(|(host, _)| host).collect::<Vec<_>>();
+            let addresses = report.hosts.values().flatten().filter(|ip| covered(ip)).collect::<BTreeSet<_>>();
+            let mut record = json!({"network": net, "addresses": addresses, "hosts": hosts});
+            if let Some(bytes) = report.weights.get(net) {
+                record["bytes"] = json!(bytes);
+            }
+            self.record("aggregated", record)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join(format!("wgrouter-audit-{}.jsonl", std::process::id()));
+        let report = Report {
+            networks: vec!["1.1.0.0/16".to_string(), "93.184.0.0/16".into()],
+            hosts: BTreeMap::from([
+                ("example.com".to_string(), BTreeSet::from(["93.184.216.34".to_string()])),
+                ("one.one".to_string(), BTreeSet::from(["1.1.1.1".to_string(), "1.1.2.2".into()])),
+            ]),
+            weights: BTreeMap::from([("1.1.0.0/16".to_string(), 4120)]),
+            ..Report::default()
+        };
+        let clock = Arc::new(crate::clock::ManualClock::new(1_700_000_000));
+        let mut log = AuditLog::with_clock(path.to_str(), clock.clone()).unwrap();
+        log.record("extracted", json!({"host": "example.com", "source": "site"})).unwrap();
+        clock.advance(std::time::Duration::from_secs(5));
+        log.record_networks(&report).unwrap();
+        AuditLog::open(path.to_str()).unwrap().record("applied", json!({"target": "wg0"})).unwrap();
+
+        let lines = std::fs::read_to_string(&path).unwrap().lines().map(|l| serde_json::from_str::<Value>(l).unwrap()).collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.iter().map(|l| l["event"].as_str().unwrap()).collect::<Vec<_>>(), vec!["extracted", "aggregated", "aggregated", "applied"]);
+        assert_eq!(lines[0]["host"], json!("example.com"));
+        assert_eq!((lines[0]["ts"].clone(), lines[1]["ts"].clone()), (json!(1_700_000_000), json!(1_700_000_005)));
+        assert_eq!(lines[0]["run"], json!(format!("1700000000-{}", std::process::id())));
+        assert_eq!(lines[1]["run"], lines[0]["run"]);
+        assert_eq!(lines[1]["network"], json!("1.1.0.0/16"));
+        assert_eq!(lines[1]["addresses"], json!(["1.1.1.1", "1.1.2.2"]));
+        assert_eq!(lines[1]["bytes"], json!(4120));
+        assert_eq!(lines[2]["hosts"], json!(["example.com"]));
+        assert_eq!(lines[2].get("bytes"), None);
+        assert!(lines[3]["ts"].is_u64());
+
+        let mut disabled = AuditLog::open(None).unwrap();
+        assert_eq!(disabled.record("extracted", json!({})), Ok(()));
+        assert!(AuditLog::open(Some("/nonexistent/audit.jsonl")).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use crate::dns::{ascii_host, discard_port, StrResult};
+
+// how narrow (min) and how wide (max) the networks routed for the addresses of some hosts may get, as prefix lengths,
+// e.g. min 24 and max 20 for anything between a /24 and a /20; the v6 ones are for IPv6 addresses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bound {
+    pub pattern: String,
+    pub min: Option<u8>,
+    pub max: Option<u8>,
+    pub min6: Option<u8>,
+    pub max6: Option<u8>,
+}
+
+impl Bound {
+    // "example.com" is just that host, "*.example.com" any host below it (but not example.com itself); patterns and
+    // hosts match in their ASCII form, so "*.рф" takes "xn--e1afmkfd.xn--p1ai" as well
+    pub fn matches(&self, host: &str) -> bool {
+        let host = ascii_host(discard_port(host)).trim_end_matches('.').to_ascii_lowercase();
+        match self.pattern.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{domain}")),
+            None => host == self.pattern,
+        }
+    }
+
+    // widest and narrowest prefix length for an address of this family
+    fn range(&self, ip: &IpAddr) -> Option<(u8, u8)> {
+        let (min, max, bits) = match ip {
+            IpAddr::V4(_) => (self.min, self.max, 32),
+            IpAddr::V6(_) => (self.min6, self.max6, 128),
+        };
+        (min.is_some() || max.is_some()).then(|| (max.unwrap_or(0), min.unwrap_or(bits)))
+    }
+}
+
+// the prefix length range each bounded address must stay within, widest first
+pub type AddressBounds = HashMap<IpAddr, (u8, u8)>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bounds(pub Vec<Bound>);
+
+impl Bounds {
+    pub fn load(path: &str) -> StrResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read bounds {path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{e} in bounds {path}"))
+    }
+
+    // statements like: bounds "*.example.com" { min = /24, max = /20 }
+    pub fn parse(text: &str) -> StrResult<Self> {
+        let text = text.lines().map(|line| line.split('#').next().unwrap_or_default()).collect::<Vec<_>>().join("\n");
+        let mut rest = text.trim_start();
+        let mut bounds = Vec::new();
+        while !rest.is_empty() {
+            let after_keyword = rest.strip_prefix("bounds").ok_or(format!("expected 'bounds' at '{}'", first_line(rest)))?.trim_start();
+            let (pattern, after_pattern) = after_keyword
+                .strip_prefix('"')
+                .and_then(|quoted| quoted.split_once('"'))
+                .ok_or(format!("expected a quoted host pattern at '{}'", first_line(after_keyword)))?;
+            let (body, after_body) = after_pattern
+                .trim_start()
+                .strip_prefix('{')
+                .and_then(|body| body.split_once('}'))
+                .ok_or(format!("expected {{ ... }} after \"{pattern}\""))?;
+            bounds.push(bound(pattern, body)?);
+            rest = after_body.trim_start();
+        }
+        Ok(Self(bounds))
+    }
+
+    // the first bound a host matches applies to it
+    pub fn for_host(&self, host: &str) -> Option<&Bound> {
+        self.0.iter().find(|bound| bound.matches(host))
+    }
+
+    // an address shared by hosts with different bounds gets the strictest widest length, and a narrowest one no
+    // narrower than that
+    pub fn for_addresses(&self, hosts: &BTreeMap<String, BTreeSet<String>>) -> AddressBounds {
+        let mut ranges = AddressBounds::new();
+        hosts.iter().filter_map(|(host, ips)| self.for_host(host).map(|bound| (bound, ips))).for_each(|(bound, ips)| {
+            ips.iter().filter_map(|ip| ip.parse::<IpAddr>().ok()).for_each(|ip| {
+                if let Some((widest, narrowest)) = bound.range(&ip) {
+                    let range = ranges.entry(ip).or_insert((widest, narrowest));
+                    range.0 = range.0.max(widest);
+                    range.1 = range.1.min(narrowest).max(range.0);
+                }
+            });
+        });
+        ranges
+    }
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or_default()
+}
+
+fn bound(pattern: &str, body: &str) -> StrResult<Bound> {
+    let pattern = pattern.trim().trim_end_matches('.');
+    let pattern = match pattern.strip_prefix("*.") {
+        Some(domain) => format!("*.{}", ascii_host(domain)),
+        None => ascii_host(pattern),
+    }.to_ascii_lowercase();
+    if pattern.is_empty() || pattern[pattern.strip_prefix("*.").map_or(0, |_| 2)..].contains('*') {
+        return Err(format!("bad host pattern \"{pattern}\", use a host or *.domain"));
+    }
+    let mut bound = Bound { pattern: pattern.clone(), ..Bound::default() };
+    body.split([',', ';', '\n']).map(str::trim).filter(|item| !item.is_empty()).try_for_each(|item| {
+        let (key, value) = item.split_once('=').ok_or(format!("expected 'key = /len' in \"{pattern}\", got '{item}'"))?;
+        let (key, value) = (key.trim(), value.trim());
+        let len = value.strip_prefix('/').unwrap_or(value).parse::<u8>().map_err(|e| format!("bad prefix length '{value}' in \"{pattern}\": {e}"))?;
+        let (slot, bits) = match key {
+            "min" => (&mut bound.min, 32),
+            "max" => (&mut bound.max, 32),
+            "min6" => (&mut bound.min6, 128),
+            "max6" => (&mut bound.max6, 128),
+            _ => return Err(format!("unknown key '{key}' in \"{pattern}\", expected min, max, min6 or max6")),
+        };
+        if len > bits {
+            return Err(format!("prefix length /{len} of {key} in \"{pattern}\" is longer than an address"));
+        }
+        *slot = Some(len);
+        Ok(())
+    })?;
+    let inverted = |min: Option<u8>, max: Option<u8>| min.zip(max).is_some_and(|(min, max)| max > min);
+    if inverted(bound.min, bound.max) || inverted(bound.min6, bound.max6) {
+        return Err(format!("max is narrower than min in \"{pattern}\": max is the widest network allowed, e.g. min = /24, max = /20"));
+    }
+    Ok(bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let bounds = Bounds::parse("# streaming\nbounds \"*.Example.com\" { min = /24, max = /20 }\n\
+            bounds \"api.example.net\" {\n  max = 16  # a big CDN\n  max6 = /32\n}\n").unwrap();
+        assert_eq!(bounds, Bounds(vec![
+            Bound { pattern: "*.example.com".to_string(), min: Some(24), max: Some(20), ..Bound::default() },
+            Bound { pattern: "api.example.net".to_string(), max: Some(16), max6: Some(32), ..Bound::default() },
+        ]));
+        assert_eq!(Bounds::parse(""), Ok(Bounds::default()));
+        assert!(Bounds::parse("bounds \"x.com\" { min = /20, max = /24 }").unwrap_err().contains("narrower"));
+        assert!(Bounds::parse("bounds \"x.com\" { min = /33 }").is_err());
+        assert!(Bounds::parse("bounds \"x.com\" { size = /24 }").unwrap_err().contains("unknown key"));
+        assert!(Bounds::parse("bounds x.com { min = /24 }").unwrap_err().contains("quoted"));
+        assert!(Bounds::parse("bounds \"a.*.com\" { min = /24 }").is_err());
+        assert!(Bounds::parse("route \"x.com\" {}").unwrap_err().contains("expected 'bounds'"));
+
+        let idn = Bounds::parse("bounds \"*.рф\" { max = /20 }\nbounds \"xn--bcher-kva.example\" { max = /22 }").unwrap();
+        assert_eq!(idn.0[0].pattern, "*.xn--p1ai");
+        assert!(idn.0[0].matches("xn--e1afmkfd.xn--p1ai") && idn.0[0].matches("пример.рф"));
+        assert!(idn.0[1].matches("Bücher.example:443"));
+    }
+
+    #[test]
+    fn test_for_addresses() {
+        let bounds = Bounds::parse("bounds \"*.example.com\" { min = /24, max = /20 }\nbounds \"cdn.example.com\" { min = /28 }\n\
+            bounds \"example.org\" { max = /22, max6 = /40 }").unwrap();
+        assert!(bounds.for_host("www.example.com:443").is_some());
+        assert_eq!(bounds.for_host("example.com"), None);
+        assert_eq!(bounds.for_host("cdn.example.com").map(|b| b.min), Some(Some(24)));
+        let hosts = BTreeMap::from([
+            ("www.example.com".to_string(), BTreeSet::from(["192.0.2.1".to_string(), "192.0.2.2".into()])),
+            ("example.org".to_string(), BTreeSet::from(["192.0.2.2".to_string(), "198.51.100.1".into(), "2001:db8::1".into()])),
+            ("other.net".to_string(), BTreeSet::from(["203.0.113.1".to_string()])),
+        ]);
+        assert_eq!(bounds.for_addresses(&hosts), AddressBounds::from([
+            ("192.0.2.1".parse().unwrap(), (20, 24)),
+            ("192.0.2.2".parse().unwrap(), (22, 24)),
+            ("198.51.100.1".parse().unwrap(), (22, 32)),
+            ("2001:db8::1".parse().unwrap(), (40, 128)),
+        ]));
+    }
+}
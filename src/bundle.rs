@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use serde_json::{json, Value};
+use crate::dns::StrResult;
+
+// what the bundle holds besides the parts, and where they came from
+pub const MANIFEST: &str = "wgrouter-bundle.json";
+pub const BUNDLE_SCHEMA: &str = "wgrouter-bundle/1";
+
+// a part of a setup: its name in the bundle and where it is kept on this machine, a file or a directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub name: &'static str,
+    pub path: PathBuf,
+}
+
+impl Part {
+    pub fn new(name: &'static str, path: impl Into<PathBuf>) -> Self {
+        Self { name, path: path.into() }
+    }
+}
+
+// each file under path with its path relative to it, sorted; a file on its own has an empty one
+fn files(path: &Path) -> StrResult<Vec<(PathBuf, PathBuf)>> {
+    if path.is_file() {
+        return Ok(vec![(path.to_path_buf(), PathBuf::new())]);
+    }
+    let mut found = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).map_err(|e| format!("could not read {}: {e}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("could not read {}: {e}", dir.display()))?;
+            let kind = entry.file_type().map_err(|e| format!("could not read {}: {e}", entry.path().display()))?;
+            if kind.is_dir() {
+                dirs.push(entry.path());
+            } else if kind.is_file() {
+                let relative = entry.path().strip_prefix(path).map(Path::to_path_buf).unwrap_or_default();
+                found.push((entry.path(), relative));
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(unix)]
+fn mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().ok().map(|meta| meta.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn mode(_: &Path) -> Option<u32> {
+    None
+}
+
+// the parts that exist packed into a zip archive at bundle, a file as the entry of its name and a directory as the
+// entries under it, with a manifest of where each was; the names of the parts packed
+pub fn export(bundle: &Path, parts: &[Part]) -> StrResult<Vec<&'static str>> {
+    let file = std::fs::File::create(bundle).map_err(|e| format!("could not create {}: {e}", bundle.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let fail = |e: zip::result::ZipError| format!("could not write {}: {e}", bundle.display());
+    let mut packed = BTreeMap::new();
+    for part in parts.iter().filter(|part| part.path.exists()) {
+        for (path, relative) in files(&part.path)? {
+            let name = match relative.to_str() {
+                Some("") => part.name.to_string(),
+                Some(relative) => format!("{}/{}", part.name, relative.replace('\\', "/")),
+                None => return Err(format!("cannot pack {}: its name is not UTF-8", path.display())),
+            };
+            let data = std::fs::read(&path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+            let options = zip::write::SimpleFileOptions::default();
+            let options = match mode(&path) {
+                Some(mode) => options.unix_permissions(mode),
+                None => options,
+            };
+            zip.start_file(name, options).map_err(fail)?;
+            zip.write_all(&data).map_err(|e| format!("could not write {}: {e}", bundle.display()))?;
+        }
+        packed.insert(part.name, part.path.display().to_string());
+    }
+    zip.start_file(MANIFEST, zip::write::SimpleFileOptions::default()).map_err(fail)?;
+    let manifest = json!({"schema": BUNDLE_SCHEMA, "parts": packed});
+    zip.write_all(manifest.to_string().as_bytes()).map_err(|e| format!("could not write {}: {e}", bundle.display()))?;
+    zip.finish().map_err(fail)?;
+    Ok(packed.into_keys().collect())
+}
+
+// where an entry goes: under the path given for its part; the paths in the manifest are the bundle's word only, so a
+// part without one given here is not imported
+fn target(name: &str, parts: &[Part], packed: &BTreeMap<String, String>) -> StrResult<PathBuf> {
+    let (part, relative) = name.split_once('/').unwrap_or((name, ""));
+    let Some(base) = parts.iter().find(|p| p.name == part).map(|p| p.path.clone()) else {
+        return Err(match packed.get(part) {
+            Some(from) => format!("holds {name} of the part '{part}' packed from {from}, give where it goes to import it"),
+            None => format!("holds {name}, which is of no part to import"),
+        });
+    };
+    // entries cannot climb out of their part
+    let relative = Path::new(relative);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("holds {name}, which climbs out of its part"));
+    }
+    Ok(if relative.as_os_str().is_empty() { base } else { base.join(relative) })
+}
+
+// the files of a bundle written where the parts go on this machine; nothing is written when one of them exists
+// already, unless overwriting; the files written
+pub fn import(bundle: &Path, parts: &[Part], overwrite: bool) -> StrResult<Vec<PathBuf>> {
+    let file = std::fs::File::open(bundle).map_err(|e| format!("could not open {}: {e}", bundle.display()))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("could not open archive {}: {e}", bundle.display()))?;
+    let fail = |e: std::io::Error| format!("could not read {}: {e}", bundle.display());
+    let mut entries = Vec::new();
+    let mut manifest = None;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| format!("could not read {}: {e}", bundle.display()))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(fail)?;
+        match entry.name() {
+            MANIFEST => manifest = Some(serde_json::from_slice::<Value>(&data).map_err(|e| format!("bad manifest in {}: {e}", bundle.display()))?),
+            name => entries.push((name.to_string(), data, entry.unix_mode())),
+        }
+    }
+    let manifest = manifest.ok_or(format!("{} is not a wgrouter bundle: it has no {MANIFEST}", bundle.display()))?;
+    if manifest["schema"] != BUNDLE_SCHEMA {
+        return Err(format!("{} is a bundle of another version ({}), expected {BUNDLE_SCHEMA}", bundle.display(), manifest["schema"]));
+    }
+    let packed = serde_json::from_value::<BTreeMap<String, String>>(manifest["parts"].clone()).unwrap_or_default();
+    let entries = entries
+        .into_iter()
+        .map(|(name, data, mode)| Ok((target(&name, parts, &packed).map_err(|e| format!("{} {e}, nothing was imported", bundle.display()))?, data, mode)))
+        .collect::<StrResult<Vec<_>>>()?;
+    if !overwrite && let Some((path, ..)) = entries.iter().find(|(path, ..)| path.exists()) {
+        return Err(format!("{} exists already, nothing was imported; --force overwrites it", path.display()));
+    }
+    entries.iter().try_for_each(|(path, data, mode)| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+        }
+        std::fs::write(path, data).map_err(|e| format!("could not write {}: {e}", path.display()))?;
+        set_mode(path, *mode)
+    })?;
+    Ok(entries.into_iter().map(|(path, ..)| path).collect())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> StrResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777)).map_err(|e| format!("could not set the mode of {}: {e}", path.display())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: Option<u32>) -> StrResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle() {
+        let dir = std::env::temp_dir().join(format!("wgrouter-bundle-{}", std::process::id()));
+        let (from, to) = (dir.join("laptop"), dir.join("router"));
+        std::fs::create_dir_all(from.join("state/history")).unwrap();
+        std::fs::write(from.join("config"), "prefix-len = 24\n").unwrap();
+        std::fs::write(from.join("state/history/wg0.json"), "{}").unwrap();
+        std::fs::write(from.join("state/last-run.json"), "{}").unwrap();
+        let parts = |base: &Path| vec![Part::new("config", base.join("config")), Part::new("state", base.join("state")), Part::new("plugins", base.join("plugins"))];
+        let bundle = dir.join("bundle.zip");
+        assert_eq!(export(&bundle, &parts(&from)), Ok(vec!["config", "state"]));
+
+        let written = import(&bundle, &parts(&to), false).unwrap();
+        assert_eq!(written, vec![to.join("config"), to.join("state/history/wg0.json"), to.join("state/last-run.json")]);
+        assert_eq!(std::fs::read_to_string(to.join("config")).unwrap(), "prefix-len = 24\n");
+        assert!(import(&bundle, &parts(&to), false).unwrap_err().contains("exists already"));
+        assert_eq!(import(&bundle, &parts(&to), true).map(|written| written.len()), Ok(3));
+
+        // a part not given is not imported, whatever the manifest says about it
+        assert!(import(&bundle, &parts(&to)[1..], true).unwrap_err().contains(&format!("packed from {}", from.join("config").display())));
+        assert_eq!(target("state/../../etc/passwd", &parts(&to), &BTreeMap::new()), Err("holds state/../../etc/passwd, which climbs out of its part".into()));
+        // nor is a part wgrouter does not know, or one the manifest points outside
+        let outside = dir.join("outside");
+        let crafted = dir.join("crafted.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&crafted).unwrap());
+        zip.start_file("authorized_keys", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"ssh-ed25519 AAAA attacker").unwrap();
+        zip.start_file("state-file", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.start_file(MANIFEST, zip::write::SimpleFileOptions::default()).unwrap();
+        let manifest = json!({"schema": BUNDLE_SCHEMA, "parts": {"authorized_keys": outside.join("keys"), "state-file": outside.join("state.json")}});
+        zip.write_all(manifest.to_string().as_bytes()).unwrap();
+        zip.finish().unwrap();
+        assert!(import(&crafted, &parts(&to), true).unwrap_err().contains("nothing was imported"));
+        assert!(import(&crafted, &[parts(&to), vec![Part::new("authorized_keys", to.join("keys"))]].concat(), true).unwrap_err().contains("'state-file'"));
+        assert!(!outside.exists());
+        assert!(import(&from.join("state/last-run.json"), &parts(&to), true).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
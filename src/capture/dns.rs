@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const TYPE_HTTPS: u16 = 65;
+const CLASS_IN: u16 = 1;
+const MAX_POINTERS: usize = 16;
+
+fn be16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+// returns the name and the position right after it in the message
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *msg.get(pos)?;
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                let label = msg.get(pos + 1..pos + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                pos += 1 + usize::from(len);
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(pos + 2);
+                pos = usize::from(be16(msg, pos)? & 0x3fff);
+                continue;
+            }
+            _ => return None,
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(pos + 1)))
+}
+
+// every name asked about in a DNS response, mapped to the addresses it finally resolved to (following CNAMEs)
+pub fn addresses_from_response(msg: &[u8]) -> Option<HashMap<String, BTreeSet<IpAddr>>> {
+    let flags = be16(msg, 2)?;
+    let (qdcount, ancount) = (be16(msg, 4)?, be16(msg, 6)?);
+    if flags & 0x8000 == 0 || flags & 0x000f != 0 {
+        return None;
+    }
+    let mut pos = 12;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount {
+        let (name, next) = read_name(msg, pos)?;
+        questions.push(name);
+        pos = next + 4;
+    }
+    let mut cnames = HashMap::new();
+    let mut addrs = HashMap::<String, BTreeSet<IpAddr>>::new();
+    for _ in 0..ancount {
+        let (owner, next) = read_name(msg, pos)?;
+        let (kind, class) = (be16(msg, next)?, be16(msg, next + 2)?);
+        let rdlen = usize::from(be16(msg, next + 8)?);
+        let rdata_pos = next + 10;
+        let rdata = msg.get(rdata_pos..rdata_pos + rdlen)?;
+        pos = rdata_pos + rdlen;
+        if class != CLASS_IN {
+            continue;
+        }
+        match kind {
+            TYPE_A => {
+                let octets: [u8; 4] = rdata.try_into().ok()?;
+                addrs.entry(owner).or_default().insert(IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            TYPE_AAAA => {
+                let octets: [u8; 16] = rdata.try_into().ok()?;
+                addrs.entry(owner).or_default().insert(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            TYPE_CNAME => {
+                cnames.insert(owner, read_name(msg, rdata_pos)?.0);
+            }
+            _ => {}
+        }
+    }
+    let resolve = |name: &String| {
+        let mut name = name;
+        for _ in 0..MAX_POINTERS {
+            match cnames.get(name) {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        addrs.get(name).cloned()
+    };
+    Some(questions
+        .iter()
+        .chain(cnames.keys())
+        .filter_map(|name| resolve(name).map(|ips| (name.clone(), ips)))
+        .chain(addrs.clone())
+        .collect())
+}
+
+// names looked up by a standard query for addresses (A, AAAA, HTTPS), so not reverse lookups and the like
+pub fn names_from_query(msg: &[u8]) -> Option<Vec<String>> {
+    let flags = be16(msg, 2)?;
+    if flags & 0xf800 != 0 {
+        return None;
+    }
+    let mut pos = 12;
+    let mut names = Vec::new();
+    for _ in 0..be16(msg, 4)? {
+        let (name, next) = read_name(msg, pos)?;
+        if matches!(be16(msg, next)?, TYPE_A | TYPE_AAAA | TYPE_HTTPS) && !name.is_empty() {
+            names.push(name);
+        }
+        pos = next + 4;
+    }
+    Some(names)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassiveDns {
+    pub hosts: BTreeMap<String, BTreeSet<IpAddr>>,
+}
+
+impl PassiveDns {
+    pub fn push(&mut self, msg: &[u8]) {
+        addresses_from_response(msg).into_iter().flatten().for_each(|(name, ips)| {
+            self.hosts.entry(name).or_default().extend(ips);
+        });
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn response(qname: &str, cname: Option<&str>, ips: &[IpAddr]) -> Vec<u8> {
+    let encode = |name: &str| {
+        let mut out = name.split('.').flat_map(|l| [&[l.len() as u8][..], l.as_bytes()].concat()).collect::<Vec<_>>();
+        out.push(0);
+        out
+    };
+    let record = |owner: &[u8], kind: u16, rdata: &[u8]| {
+        [owner, &kind.to_be_bytes(), &CLASS_IN.to_be_bytes(), &300u32.to_be_bytes(), &(rdata.len() as u16).to_be_bytes(), rdata].concat()
+    };
+    let ip_owner = match cname {
+        Some(target) => encode(target),
+        None => vec![0xc0, 12],
+    };
+    let answers = cname
+        .map(|target| record(&[0xc0, 12], TYPE_CNAME, &encode(target)))
+        .into_iter()
+        .chain(ips.iter().map(|ip| match ip {
+            IpAddr::V4(v4) => record(&ip_owner, TYPE_A, &v4.octets()),
+            IpAddr::V6(v6) => record(&ip_owner, TYPE_AAAA, &v6.octets()),
+        }))
+        .collect::<Vec<_>>();
+    let header = [&[0x12, 0x34, 0x81, 0x80, 0, 1][..], &(answers.len() as u16).to_be_bytes(), &[0, 0, 0, 0]].concat();
+    [header, encode(qname), [0, 1, 0, 1].to_vec(), answers.concat()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(list: &[&str]) -> BTreeSet<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_addresses_from_response() {
+        let msg = response("WWW.Example.com", None, &["93.184.216.34".parse().unwrap(), "2606:2800:220:1::1".parse().unwrap()]);
+        assert_eq!(addresses_from_response(&msg),
+            Some(HashMap::from([("www.example.com".to_string(), ips(&["93.184.216.34", "2606:2800:220:1::1"]))])));
+
+        let msg = response("static.example.com", Some("edge.cdn.example.net"), &["192.0.2.10".parse().unwrap(), "192.0.2.11".parse().unwrap()]);
+        assert_eq!(addresses_from_response(&msg), Some(HashMap::from([
+            ("static.example.com".to_string(), ips(&["192.0.2.10", "192.0.2.11"])),
+            ("edge.cdn.example.net".to_string(), ips(&["192.0.2.10", "192.0.2.11"])),
+        ])));
+
+        let mut query = response("www.example.com", None, &[]);
+        query[2] &= 0x7f;
+        assert_eq!(addresses_from_response(&query), None);
+        let mut nxdomain = response("nope.example.com", None, &[]);
+        nxdomain[3] |= 3;
+        assert_eq!(addresses_from_response(&nxdomain), None);
+        assert_eq!(addresses_from_response(&msg[..msg.len() - 2]), None);
+    }
+
+    #[test]
+    fn test_names_from_query() {
+        let mut query = response("WWW.Example.com", None, &[]);
+        query[2] = 0x01;
+        query[3] = 0x00;
+        assert_eq!(names_from_query(&query), Some(vec!["www.example.com".to_string()]));
+        let mut reverse = query.clone();
+        let len = reverse.len();
+        reverse[len - 3] = 12;
+        assert_eq!(names_from_query(&reverse), Some(vec![]));
+        assert_eq!(names_from_query(&response("www.example.com", None, &[])), None);
+        assert_eq!(names_from_query(&query[..query.len() - 5]), None);
+    }
+
+    #[test]
+    fn test_pointer_loop() {
+        let mut msg = response("a.example.com", None, &[]);
+        msg.truncate(12);
+        msg.extend([0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(read_name(&msg, 12), None);
+    }
+
+    #[test]
+    fn test_passive_dns() {
+        let mut passive = PassiveDns::default();
+        passive.push(&response("www.example.com", None, &["192.0.2.1".parse().unwrap()]));
+        passive.push(&response("www.example.com", None, &["192.0.2.2".parse().unwrap()]));
+        passive.push(b"garbage");
+        assert_eq!(passive.hosts, BTreeMap::from([("www.example.com".to_string(), ips(&["192.0.2.1", "192.0.2.2"]))]));
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use rustls::crypto::ring::sign::any_ecdsa_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use crate::dns::StrResult;
+
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_SUBJECT_KEY_ID: &[u8] = &[0x55, 0x1d, 0x0e];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_AUTHORITY_KEY_ID: &[u8] = &[0x55, 0x1d, 0x23];
+const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_SERVER_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+// keyCertSign and cRLSign for the CA, digitalSignature for the hosts
+const KEY_USAGE_CA: &[u8] = &[0x01, 0x06];
+const KEY_USAGE_LEAF: &[u8] = &[0x07, 0x80];
+// phones reject certificates from before their clock, which may be a little behind
+const BACKDATE: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_COMMON_NAME: usize = 64;
+
+// a DER element: tag, length and content
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len().to_be_bytes();
+    let significant = &len[len.iter().position(|b| *b != 0).unwrap_or(len.len() - 1)..];
+    let length = match content.len() {
+        0..0x80 => vec![content.len() as u8],
+        _ => [&[0x80 | significant.len() as u8][..], significant].concat(),
+    };
+    [&[tag][..], &length, content].concat()
+}
+
+fn seq(parts: &[&[u8]]) -> Vec<u8> {
+    tlv(0x30, &parts.concat())
+}
+
+fn oid(id: &[u8]) -> Vec<u8> {
+    tlv(0x06, id)
+}
+
+// a non-negative INTEGER from its big-endian bytes
+fn integer(bytes: &[u8]) -> Vec<u8> {
+    let bytes = &bytes[bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len().saturating_sub(1))..];
+    match bytes.first() {
+        Some(first) if first & 0x80 != 0 => tlv(0x02, &[&[0][..], bytes].concat()),
+        _ => tlv(0x02, bytes),
+    }
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(0x03, &[&[0][..], bytes].concat())
+}
+
+fn name(common_name: &str) -> Vec<u8> {
+    if common_name.len() > MAX_COMMON_NAME {
+        return seq(&[]);
+    }
+    seq(&[&tlv(0x31, &seq(&[&oid(OID_COMMON_NAME), &tlv(0x0c, common_name.as_bytes())]))])
+}
+
+fn extension(id: &[u8], critical: bool, value: &[u8]) -> Vec<u8> {
+    let critical = if critical { tlv(0x01, &[0xff]) } else { Vec::new() };
+    seq(&[&oid(id), &critical, &tlv(0x04, value)])
+}
+
+// UTCTime up to 2049, GeneralizedTime after, as RFC 5280 wants them
+fn time(at: SystemTime) -> Vec<u8> {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rest) = (secs / 86400, secs % 86400);
+    // days since 1970-01-01 to the civil date, after Howard Hinnant's days_from_civil inverse
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    let clock = format!("{month:02}{day:02}{:02}{:02}{:02}Z", rest / 3600, rest / 60 % 60, rest % 60);
+    if year < 2050 {
+        tlv(0x17, format!("{:02}{clock}", year % 100).as_bytes())
+    } else {
+        tlv(0x18, format!("{year:04}{clock}").as_bytes())
+    }
+}
+
+// the host as a subjectAltName: an iPAddress for an address, a dNSName otherwise
+fn alt_name(host: &str) -> Vec<u8> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => tlv(0x87, &ip.octets()),
+        Ok(IpAddr::V6(ip)) => tlv(0x87, &ip.octets()),
+        Err(_) => tlv(0x82, host.as_bytes()),
+    }
+}
+
+fn public_key_info(key: &EcdsaKeyPair) -> Vec<u8> {
+    seq(&[&seq(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P256)]), &bit_string(key.public_key().as_ref())])
+}
+
+fn key_id(key: &EcdsaKeyPair) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, key.public_key().as_ref()).as_ref()[..20].to_vec()
+}
+
+fn generate_key(rng: &SystemRandom) -> StrResult<(EcdsaKeyPair, Vec<u8>)> {
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, rng).map_err(|e| format!("could not generate a key: {e}"))?;
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), rng).map_err(|e| format!("could not load the generated key: {e}"))?;
+    Ok((key, pkcs8.as_ref().to_vec()))
+}
+
+// a CA made up for one run: its key never leaves the process, and it signs a certificate for every host a client
+// connects to through the proxy
+pub struct Authority {
+    key: EcdsaKeyPair,
+    cert: Vec<u8>,
+    name: String,
+    // one key for all the host certificates, the CA certificate is what the phone trusts
+    leaf_key: EcdsaKeyPair,
+    leaf_signer: Arc<dyn rustls::sign::SigningKey>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+    rng: SystemRandom,
+    leaves: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl std::fmt::Debug for Authority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authority").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl Authority {
+    // a new CA and host key, valid from a day ago for valid_for
+    pub fn generate(valid_for: Duration) -> StrResult<Self> {
+        let rng = SystemRandom::new();
+        let (key, _) = generate_key(&rng)?;
+        let (leaf_key, leaf_pkcs8) = generate_key(&rng)?;
+        let leaf_signer = any_ecdsa_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(leaf_pkcs8))).map_err(|e| format!("could not load the host key: {e}"))?;
+        let now = SystemTime::now();
+        let mut authority = Self {
+            name: format!("wgrouter mobile capture {}", hex(&key_id(&key)[..4])),
+            key,
+            cert: Vec::new(),
+            leaf_key,
+            leaf_signer,
+            not_before: now - BACKDATE,
+            not_after: now + valid_for,
+            rng,
+            leaves: Mutex::default(),
+        };
+        let extensions = [
+            extension(OID_BASIC_CONSTRAINTS, true, &seq(&[&tlv(0x01, &[0xff])])),
+            extension(OID_KEY_USAGE, true, &tlv(0x03, KEY_USAGE_CA)),
+            extension(OID_SUBJECT_KEY_ID, false, &tlv(0x04, &key_id(&authority.key))),
+        ];
+        authority.cert = authority.sign(&authority.name, &public_key_info(&authority.key), &extensions)?;
+        Ok(authority)
+    }
+
+    // the CA certificate in PEM, to install on the phone
+    pub fn pem(&self) -> String {
+        let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &self.cert);
+        let lines = encoded.as_bytes().chunks(64).map(|line| String::from_utf8_lossy(line) + "\n").collect::<String>();
+        format!("-----BEGIN CERTIFICATE-----\n{lines}-----END CERTIFICATE-----\n")
+    }
+
+    pub fn der(&self) -> &[u8] {
+        &self.cert
+    }
+
+    // a certificate signed by the CA with the subject and extensions given
+    fn sign(&self, subject: &str, public_key_info: &[u8], extensions: &[Vec<u8>]) -> StrResult<Vec<u8>> {
+        let mut serial = [0u8; 16];
+        self.rng.fill(&mut serial).map_err(|e| format!("could not generate a serial number: {e}"))?;
+        serial[0] &= 0x7f;
+        let algorithm = seq(&[&oid(OID_ECDSA_SHA256)]);
+        let tbs = seq(&[
+            &tlv(0xa0, &integer(&[2])),
+            &integer(&serial),
+            &algorithm,
+            &name(&self.name),
+            &seq(&[&time(self.not_before), &time(self.not_after)]),
+            &name(subject),
+            public_key_info,
+            &tlv(0xa3, &seq(&extensions.iter().map(Vec::as_slice).collect::<Vec<_>>())),
+        ]);
+        let signature = self.key.sign(&self.rng, &tbs).map_err(|e| format!("could not sign a certificate: {e}"))?;
+        Ok(seq(&[&tbs, &algorithm, &bit_string(signature.as_ref())]))
+    }
+
+    // the certificate for the host with the CA certificate after it, made the first time the host is asked for
+    pub fn certified(&self, host: &str) -> StrResult<Arc<CertifiedKey>> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(leaf) = self.leaves.lock().unwrap().get(&host) {
+            return Ok(leaf.clone());
+        }
+        let extensions = [
+            extension(OID_BASIC_CONSTRAINTS, true, &seq(&[])),
+            extension(OID_KEY_USAGE, true, &tlv(0x03, KEY_USAGE_LEAF)),
+            extension(OID_EXT_KEY_USAGE, false, &seq(&[&oid(OID_SERVER_AUTH)])),
+            extension(OID_SUBJECT_ALT_NAME, host.len() > MAX_COMMON_NAME, &seq(&[&alt_name(&host)])),
+            extension(OID_AUTHORITY_KEY_ID, false, &seq(&[&tlv(0x80, &key_id(&self.key))])),
+        ];
+        let cert = self.sign(&host, &public_key_info(&self.leaf_key), &extensions)?;
+        let leaf = Arc::new(CertifiedKey::new(vec![CertificateDer::from(cert), CertificateDer::from(self.cert.clone())], self.leaf_signer.clone()));
+        self.leaves.lock().unwrap().insert(host, leaf.clone());
+        Ok(leaf)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// the certificate for the server name of the ClientHello, or for the host of the CONNECT request when the client
+// sent none (connections to an address)
+#[derive(Debug)]
+struct Resolver {
+    authority: Arc<Authority>,
+    host: String,
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let host = hello.server_name().unwrap_or(&self.host);
+        self.authority
+            .certified(host)
+            .inspect_err(|e| eprintln!("warning: mobile capture: certificate for {host}: {e}"))
+            .ok()
+    }
+}
+
+// what the proxy needs to decrypt a connection: the CA to pose as the server to the phone, and the roots to check the
+// real server with
+#[derive(Debug)]
+pub struct Interceptor {
+    pub authority: Arc<Authority>,
+    upstream: Arc<rustls::ClientConfig>,
+}
+
+impl Interceptor {
+    // the real servers are checked against the roots browsers trust
+    pub fn new(authority: Authority) -> StrResult<Self> {
+        Self::with_roots(authority, rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() })
+    }
+
+    pub fn with_roots(authority: Authority, roots: rustls::RootCertStore) -> StrResult<Self> {
+        let upstream = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("could not set up TLS: {e}"))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self { authority: Arc::new(authority), upstream: Arc::new(upstream) })
+    }
+
+    // the TLS server side facing the phone for a CONNECT to host; no ALPN, so the phone speaks HTTP/1.1
+    pub fn server(&self, host: &str) -> StrResult<rustls::ServerConnection> {
+        let resolver = Resolver { authority: self.authority.clone(), host: host.to_string() };
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("could not set up TLS: {e}"))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        rustls::ServerConnection::new(Arc::new(config)).map_err(|e| format!("could not set up TLS: {e}"))
+    }
+
+    // the TLS client side towards the real server
+    pub fn client(&self, host: &str) -> StrResult<rustls::ClientConnection> {
+        let server_name = rustls::pki_types::ServerName::try_from(host.to_string()).map_err(|e| format!("bad server name {host}: {e}"))?;
+        rustls::ClientConnection::new(self.upstream.clone(), server_name).map_err(|e| format!("could not set up TLS: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_der() {
+        assert_eq!(tlv(0x04, &[1, 2]), vec![0x04, 2, 1, 2]);
+        assert_eq!(tlv(0x04, &[0; 200])[..3], [0x04, 0x81, 200]);
+        assert_eq!(tlv(0x04, &[0; 300])[..4], [0x04, 0x82, 1, 44]);
+        assert_eq!(integer(&[0, 0, 5]), vec![0x02, 1, 5]);
+        assert_eq!(integer(&[0x80]), vec![0x02, 2, 0, 0x80]);
+        assert_eq!(integer(&[0]), vec![0x02, 1, 0]);
+        assert_eq!(time(UNIX_EPOCH + Duration::from_secs(951_782_400)), tlv(0x17, b"000229000000Z"));
+        assert_eq!(time(UNIX_EPOCH + Duration::from_secs(2_524_607_999)), tlv(0x17, b"491231235959Z"));
+        assert_eq!(time(UNIX_EPOCH + Duration::from_secs(2_524_608_000)), tlv(0x18, b"20500101000000Z"));
+        assert_eq!(alt_name("10.0.0.1"), vec![0x87, 4, 10, 0, 0, 1]);
+        assert_eq!(alt_name("a.b"), vec![0x82, 3, b'a', b'.', b'b']);
+        assert_eq!(name(&"x".repeat(65)), vec![0x30, 0]);
+    }
+
+    #[test]
+    fn test_certificates_verify() {
+        let authority = Authority::generate(Duration::from_secs(3600)).unwrap();
+        assert!(authority.pem().starts_with("-----BEGIN CERTIFICATE-----\nMII"));
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from(authority.der().to_vec())).unwrap();
+        let verifier = rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::new(rustls::crypto::ring::default_provider()))
+            .build()
+            .unwrap();
+        let now = rustls::pki_types::UnixTime::now();
+        for host in ["api.example.com", "192.0.2.10", "2001:db8::1"] {
+            let leaf = authority.certified(host).unwrap();
+            assert!(Arc::ptr_eq(&leaf, &authority.certified(&host.to_uppercase()).unwrap()));
+            let server_name = rustls::pki_types::ServerName::try_from(host).unwrap();
+            rustls::client::danger::ServerCertVerifier::verify_server_cert(&*verifier, &leaf.cert[0], &leaf.cert[1..], &server_name, &[], now)
+                .unwrap();
+        }
+        let other = rustls::pki_types::ServerName::try_from("other.example.com").unwrap();
+        let leaf = authority.certified("api.example.com").unwrap();
+        assert!(rustls::client::danger::ServerCertVerifier::verify_server_cert(&*verifier, &leaf.cert[0], &[], &other, &[], now).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+pub mod dns;
+#[cfg(feature = "mobile-capture")]
+pub mod mitm;
+pub mod packet;
+pub mod pcap;
+#[cfg(feature = "mobile-capture")]
+pub mod proxy;
+pub mod quic;
+#[cfg(target_os = "linux")]
+pub mod sniff;
+pub mod tls;
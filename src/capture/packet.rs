@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+use super::dns::{self, PassiveDns};
+use super::quic::QuicSniTracker;
+use super::tls;
+
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+const DNS_PORT: u16 = 53;
+
+// everything the capture backends learn from the packets they see
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    quic: QuicSniTracker,
+    pub sni_hosts: BTreeSet<String>,
+    pub passive_dns: PassiveDns,
+    // names looked up, answered or not (the answer may be missing from the capture)
+    pub dns_queries: BTreeSet<String>,
+}
+
+// link-layer header types of pcap files, see https://www.tcpdump.org/linktypes.html
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LOOP: u32 = 108;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+
+impl PacketDecoder {
+    // a captured frame with the given link-layer header; frames of other link types and non-IP frames are skipped
+    pub fn push_frame(&mut self, link_type: u32, frame: &[u8]) {
+        if let Some(packet) = ip_from_frame(link_type, frame) {
+            self.push_ip(packet);
+        }
+    }
+
+    pub fn push_ip(&mut self, packet: &[u8]) {
+        let Some((protocol, payload)) = transport_payload(packet) else {
+            return;
+        };
+        match protocol {
+            PROTO_TCP => self.push_tcp(payload),
+            PROTO_UDP => self.push_udp(payload),
+            _ => {}
+        }
+    }
+
+    fn push_tcp(&mut self, segment: &[u8]) {
+        let Some(offset) = segment.get(12).map(|b| usize::from(b >> 4) * 4) else {
+            return;
+        };
+        let payload = segment.get(offset..).unwrap_or_default();
+        if payload.starts_with(&[0x16, 0x03]) {
+            self.sni_hosts.extend(tls::sni_from_record(payload));
+        }
+    }
+
+    fn push_udp(&mut self, datagram: &[u8]) {
+        let (Some(src_port), Some(payload)) = (datagram.get(0..2), datagram.get(8..)) else {
+            return;
+        };
+        let dst_port = datagram.get(2..4).map(|port| u16::from_be_bytes([port[0], port[1]]));
+        if u16::from_be_bytes([src_port[0], src_port[1]]) == DNS_PORT {
+            self.passive_dns.push(payload);
+        } else if dst_port == Some(DNS_PORT) {
+            self.dns_queries.extend(dns::names_from_query(payload).into_iter().flatten());
+        } else if payload.first().is_some_and(|b| b & 0xc0 == 0xc0) {
+            self.sni_hosts.extend(self.quic.push(payload));
+        }
+    }
+}
+
+fn ip_from_frame(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    let ethertype = |pos: usize| frame.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let (ethertype, payload) = match link_type {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Some(frame),
+        // the address family in host byte order of the capturing machine (NULL) or big endian (LOOP): any IP version
+        // is recognized from the packet itself
+        LINKTYPE_NULL | LINKTYPE_LOOP => return frame.get(4..),
+        LINKTYPE_ETHERNET => {
+            let mut pos = 12;
+            while ethertype(pos).is_some_and(|kind| ETHERTYPE_VLAN.contains(&kind)) {
+                pos += 4;
+            }
+            (ethertype(pos)?, frame.get(pos + 2..)?)
+        }
+        LINKTYPE_LINUX_SLL => (ethertype(14)?, frame.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => (ethertype(0)?, frame.get(20..)?),
+        _ => return None,
+    };
+    matches!(ethertype, ETHERTYPE_IPV4 | ETHERTYPE_IPV6).then_some(payload)
+}
+
+// unfragmented IPv4 or IPv6 (without extension headers) packets only
+fn transport_payload(packet: &[u8]) -> Option<(u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            if fragment & 0x3fff != 0 {
+                return None;
+            }
+            Some((*packet.get(9)?, packet.get(header_len..total_len.min(packet.len()))?))
+        }
+        6 => {
+            let payload_len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            Some((*packet.get(6)?, packet.get(40..(40 + payload_len).min(packet.len()))?))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn ipv4_packet(protocol: u8, src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let transport = match protocol {
+        PROTO_TCP => [&src_port.to_be_bytes()[..], &dst_port.to_be_bytes(), &[0; 8], &[0x50, 0x18], &[0; 6], payload].concat(),
+        _ => [&src_port.to_be_bytes()[..], &dst_port.to_be_bytes(), &((8 + payload.len()) as u16).to_be_bytes(), &[0, 0], payload].concat(),
+    };
+    let total_len = ((20 + transport.len()) as u16).to_be_bytes();
+    [&[0x45, 0, total_len[0], total_len[1], 0, 0, 0x40, 0, 64, protocol, 0, 0, 10, 0, 0, 2, 192, 0, 2, 1][..], &transport].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_push_ip() {
+        let hello = tls::client_hello("www.example.com");
+        let record = [&[0x16, 3, 1][..], &(hello.len() as u16).to_be_bytes(), &hello].concat();
+        let dns = super::super::dns::response("api.example.com", None, &["192.0.2.7".parse().unwrap()]);
+
+        let mut decoder = PacketDecoder::default();
+        decoder.push_ip(&ipv4_packet(PROTO_TCP, 50000, 443, &record));
+        decoder.push_ip(&ipv4_packet(PROTO_UDP, 53, 40000, &dns));
+        decoder.push_ip(&ipv4_packet(PROTO_UDP, 40000, 53, b"query"));
+        decoder.push_ip(&ipv4_packet(PROTO_TCP, 50001, 80, b"GET / HTTP/1.1\r\n\r\n"));
+        decoder.push_ip(&[0x45, 0]);
+        decoder.push_ip(&[]);
+
+        assert_eq!(decoder.sni_hosts, BTreeSet::from(["www.example.com".to_string()]));
+        assert_eq!(decoder.passive_dns.hosts,
+            BTreeMap::from([("api.example.com".to_string(), BTreeSet::from(["192.0.2.7".parse::<IpAddr>().unwrap()]))]));
+    }
+
+    #[test]
+    fn test_push_frame() {
+        let mut query = super::super::dns::response("www.example.com", None, &[]);
+        query[2] = 0x01;
+        query[3] = 0x00;
+        let packet = ipv4_packet(PROTO_UDP, 40000, 53, &query);
+        let ethernet = [&[0xff; 12][..], &[0x81, 0x00, 0, 5], &[0x08, 0x00], &packet].concat();
+        let sll = [&[0; 14][..], &[0x08, 0x00], &packet].concat();
+        let sll2 = [&[0x08, 0x00][..], &[0; 18], &packet].concat();
+        assert_eq!(ip_from_frame(LINKTYPE_ETHERNET, &ethernet), Some(&packet[..]));
+        assert_eq!(ip_from_frame(LINKTYPE_LINUX_SLL, &sll), Some(&packet[..]));
+        assert_eq!(ip_from_frame(LINKTYPE_LINUX_SLL2, &sll2), Some(&packet[..]));
+        assert_eq!(ip_from_frame(LINKTYPE_NULL, &[&[2, 0, 0, 0][..], &packet].concat()), Some(&packet[..]));
+        assert_eq!(ip_from_frame(LINKTYPE_ETHERNET, &[&[0xff; 12][..], &[0x08, 0x06], &[0; 28]].concat()), None);
+        assert_eq!(ip_from_frame(127, &packet), None);
+
+        let mut decoder = PacketDecoder::default();
+        decoder.push_frame(LINKTYPE_ETHERNET, &ethernet);
+        assert_eq!(decoder.dns_queries, BTreeSet::from(["www.example.com".to_string()]));
+    }
+
+    #[test]
+    fn test_fragments_ignored() {
+        let mut packet = ipv4_packet(PROTO_UDP, 53, 40000, &super::super::dns::response("a.example.com", None, &[]));
+        packet[6] |= 0x20;
+        assert_eq!(transport_payload(&packet), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::dns::StrResult;
+use super::packet::PacketDecoder;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+// the same read either way round, the byte order magic inside the block tells which one the section uses
+const PCAPNG_SECTION: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+fn u16_at(data: &[u8], pos: usize, big_endian: bool) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?.try_into().ok()?;
+    Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+}
+
+fn u32_at(data: &[u8], pos: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+pub fn decode_file(path: &str, decoder: &mut PacketDecoder) -> StrResult<()> {
+    let data = std::fs::read(path).map_err(|e| format!("could not read capture file {path}: {e}"))?;
+    decode(&data, decoder).map_err(|e| format!("{e} in capture file {path}"))
+}
+
+// feeds every packet of a pcap or pcapng capture to the decoder; a capture cut short (tcpdump killed mid-write) is
+// decoded up to its last complete packet
+pub fn decode(data: &[u8], decoder: &mut PacketDecoder) -> StrResult<()> {
+    match u32_at(data, 0, false) {
+        Some(PCAPNG_SECTION) => decode_pcapng(data, decoder),
+        Some(PCAP_MAGIC | PCAP_MAGIC_NANOS) => decode_pcap(data, false, decoder),
+        Some(magic) if [PCAP_MAGIC, PCAP_MAGIC_NANOS].contains(&magic.swap_bytes()) => decode_pcap(data, true, decoder),
+        _ => Err("not a pcap or pcapng capture".to_string()),
+    }
+}
+
+fn decode_pcap(data: &[u8], big_endian: bool, decoder: &mut PacketDecoder) -> StrResult<()> {
+    // the upper bits may carry the FCS length
+    let link_type = u32_at(data, 20, big_endian).ok_or("truncated pcap header")? & 0x0fff_ffff;
+    let mut pos = 24;
+    while let Some(len) = u32_at(data, pos + 8, big_endian) {
+        let Some(frame) = data.get(pos + 16..pos + 16 + len as usize) else {
+            break;
+        };
+        decoder.push_frame(link_type, frame);
+        pos += 16 + len as usize;
+    }
+    Ok(())
+}
+
+fn decode_pcapng(data: &[u8], decoder: &mut PacketDecoder) -> StrResult<()> {
+    let mut pos = 0;
+    let mut big_endian = false;
+    // link types of the interfaces of the current section, by interface id
+    let mut link_types = Vec::new();
+    while let Some(kind) = u32_at(data, pos, big_endian) {
+        if kind == PCAPNG_SECTION {
+            big_endian = match u32_at(data, pos + 8, false) {
+                Some(PCAPNG_BYTE_ORDER) => false,
+                Some(order) if order.swap_bytes() == PCAPNG_BYTE_ORDER => true,
+                _ => return Err(format!("bad byte order magic at byte {pos}")),
+            };
+            link_types.clear();
+        }
+        let Some(len) = u32_at(data, pos + 4, big_endian).map(|len| len as usize) else {
+            break;
+        };
+        if len < 12 || len % 4 != 0 {
+            return Err(format!("bad block length {len} at byte {pos}"));
+        }
+        let Some(body) = data.get(pos + 8..pos + len - 4) else {
+            break;
+        };
+        let packet = match kind {
+            PCAPNG_INTERFACE => {
+                link_types.extend(u16_at(body, 0, big_endian).map(u32::from));
+                None
+            }
+            PCAPNG_ENHANCED_PACKET => u32_at(body, 0, big_endian).zip(u32_at(body, 12, big_endian)).map(|(id, len)| (id, 20, len)),
+            PCAPNG_PACKET => u16_at(body, 0, big_endian).zip(u32_at(body, 12, big_endian)).map(|(id, len)| (u32::from(id), 20, len)),
+            // no captured length of its own: the original one, unless the block had to cut the packet short
+            PCAPNG_SIMPLE_PACKET => u32_at(body, 0, big_endian).map(|len| (0, 4, len.min(body.len().saturating_sub(4) as u32))),
+            _ => None,
+        };
+        if let Some((interface, offset, len)) = packet
+            && let (Some(link_type), Some(frame)) = (link_types.get(interface as usize), body.get(offset..offset + len as usize)) {
+            decoder.push_frame(*link_type, frame);
+        }
+        pos += len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use crate::capture::packet::{ipv4_packet, LINKTYPE_ETHERNET, LINKTYPE_RAW};
+
+    fn query(name: &str) -> Vec<u8> {
+        let mut query = crate::capture::dns::response(name, None, &[]);
+        query[2] = 0x01;
+        query[3] = 0x00;
+        ipv4_packet(17, 40000, 53, &query)
+    }
+
+    fn pcap(big_endian: bool, link_type: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let version = if big_endian { [0, 2, 0, 4] } else { [2, 0, 4, 0] };
+        let header = [&u32_bytes(PCAP_MAGIC)[..], &version, &[0; 8], &u32_bytes(65535), &u32_bytes(link_type)].concat();
+        packets.iter().fold(header, |out, packet| {
+            [out, vec![0; 8], u32_bytes(packet.len() as u32).to_vec(), u32_bytes(packet.len() as u32).to_vec(), packet.clone()].concat()
+        })
+    }
+
+    fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+        let padded = [body, &vec![0; (4 - body.len() % 4) % 4]].concat();
+        let len = (padded.len() as u32 + 12).to_le_bytes();
+        [&kind.to_le_bytes()[..], &len, &padded, &len].concat()
+    }
+
+    #[test]
+    fn test_pcap() {
+        let packets = [query("www.example.com"), query("api.example.org")];
+        let expected = BTreeSet::from(["api.example.org".to_string(), "www.example.com".into()]);
+        [false, true].iter().for_each(|big_endian| {
+            let mut decoder = PacketDecoder::default();
+            decode(&pcap(*big_endian, LINKTYPE_RAW, &packets), &mut decoder).unwrap();
+            assert_eq!(decoder.dns_queries, expected);
+        });
+        // cut in the middle of the second packet
+        let mut decoder = PacketDecoder::default();
+        let data = pcap(false, LINKTYPE_RAW, &packets);
+        decode(&data[..data.len() - 10], &mut decoder).unwrap();
+        assert_eq!(decoder.dns_queries, BTreeSet::from(["www.example.com".to_string()]));
+        assert!(decode(b"GET / HTTP/1.1", &mut decoder).is_err());
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let ethernet = [&[0xff; 12][..], &[0x08, 0x00], &query("cdn.example.net")].concat();
+        let raw = query("www.example.com");
+        let section = block(PCAPNG_SECTION, &[&PCAPNG_BYTE_ORDER.to_le_bytes()[..], &[1, 0, 0, 0], &[0xff; 8]].concat());
+        let interface = |link_type: u32| block(PCAPNG_INTERFACE, &[&(link_type as u16).to_le_bytes()[..], &[0, 0], &[0; 4]].concat());
+        let enhanced = |id: u32, frame: &[u8]| {
+            block(PCAPNG_ENHANCED_PACKET, &[&id.to_le_bytes()[..], &[0; 8], &(frame.len() as u32).to_le_bytes(), &(frame.len() as u32).to_le_bytes(), frame].concat())
+        };
+        let simple = block(PCAPNG_SIMPLE_PACKET, &[&(ethernet.len() as u32).to_le_bytes()[..], &ethernet].concat());
+        let data = [section, interface(LINKTYPE_ETHERNET), interface(LINKTYPE_RAW), enhanced(1, &raw), simple, block(5, &[0; 8]), enhanced(7, &raw)].concat();
+        let mut decoder = PacketDecoder::default();
+        decode(&data, &mut decoder).unwrap();
+        assert_eq!(decoder.dns_queries, BTreeSet::from(["cdn.example.net".to_string(), "www.example.com".into()]));
+
+        let mut bad = data.clone();
+        bad[4] = 13;
+        assert!(decode(&bad, &mut decoder).unwrap_err().contains("bad block length"));
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::dns::StrResult;
+use crate::input::{hostnames_from_text, Hosts};
+use super::mitm::Interceptor;
+use super::tls;
+
+const MAX_HEAD: usize = 64 * 1024;
+const MAX_HELLO: usize = 5 + 16 * 1024;
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+const HEAD_TIMEOUT: Duration = Duration::from_secs(30);
+// protocols where the server speaks first never send a ClientHello, don't wait for it forever
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+// how long a decrypted relay waits on one side before looking at the other
+const RELAY_POLL: Duration = Duration::from_millis(10);
+const SCAN_OVERLAP: usize = 512;
+const CONTENT_HANDSHAKE: u8 = 22;
+
+type Seen = Arc<Mutex<Capture>>;
+
+#[derive(Debug, PartialEq)]
+struct ProxyRequest {
+    connect: bool,
+    host: String,
+    port: u16,
+    // the URL of a plain HTTP request
+    url: Option<String>,
+    forward_head: Vec<u8>,
+}
+
+// the hosts and request URLs seen by the proxy; hosts only referenced from decrypted responses are candidates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capture {
+    pub hosts: Hosts,
+    pub urls: BTreeSet<String>,
+}
+
+// with an interceptor, CONNECT tunnels carrying TLS are decrypted with certificates from its CA, which the phone has to
+// trust; without one only the CONNECT hosts and the server names of the ClientHellos are seen
+pub fn hostnames_from_proxy(addr: &str, duration: Duration, interceptor: Option<Arc<Interceptor>>) -> StrResult<Capture> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("could not listen for proxy clients on {addr}: {e}"))?;
+    listener.set_nonblocking(true).map_err(|e| format!("could not set up proxy listener: {e}"))?;
+    let seen = Seen::default();
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        match listener.accept() {
+            Ok((client, from)) => {
+                let (seen, interceptor) = (seen.clone(), interceptor.clone());
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_client(client, &seen, interceptor.as_deref()) {
+                        eprintln!("warning: proxy connection from {from}: {e}");
+                    }
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+            Err(e) => return Err(format!("could not accept proxy client: {e}")),
+        }
+    }
+    let mut capture = seen.lock().unwrap().clone();
+    let confirmed = &capture.hosts.confirmed;
+    capture.hosts.candidates.retain(|host| !confirmed.contains(host));
+    Ok(capture)
+}
+
+fn handle_client(mut client: TcpStream, seen: &Seen, interceptor: Option<&Interceptor>) -> StrResult<()> {
+    client.set_nonblocking(false).map_err(|e| format!("could not set up client socket: {e}"))?;
+    client.set_read_timeout(Some(HEAD_TIMEOUT)).map_err(|e| format!("could not set up client socket: {e}"))?;
+    let (head, rest) = read_head(&mut client)?;
+    let request = parse_request(&head)?;
+    {
+        let mut seen = seen.lock().unwrap();
+        seen.hosts.confirmed.insert(request.host.clone());
+        seen.urls.extend(request.url.clone());
+    }
+
+    let mut upstream = match TcpStream::connect((request.host.as_str(), request.port)) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\nConnection: close\r\n\r\n");
+            return Err(format!("could not connect to {}:{}: {e}", request.host, request.port));
+        }
+    };
+    let first_bytes = if request.connect {
+        client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").map_err(|e| format!("write error: {e}"))?;
+        let hello = read_client_hello(&mut client, rest)?;
+        let server_name = tls::sni_from_record(&hello);
+        seen.lock().unwrap().hosts.confirmed.extend(server_name.clone());
+        if let Some(interceptor) = interceptor
+            && hello.first() == Some(&CONTENT_HANDSHAKE)
+        {
+            return intercept(client, upstream, &hello, &server_name.unwrap_or(request.host), interceptor, seen);
+        }
+        hello
+    } else if interceptor.is_some() {
+        [without_accept_encoding(&request.forward_head), rest].concat()
+    } else {
+        [request.forward_head, rest].concat()
+    };
+    upstream.write_all(&first_bytes).map_err(|e| format!("write error to {}: {e}", request.host))?;
+    client.set_read_timeout(None).map_err(|e| format!("could not set up client socket: {e}"))?;
+    match interceptor {
+        // the request went out whole with Connection: close, what follows is its body and the response
+        Some(_) if !request.connect => {
+            polled_sockets(&client, &upstream)?;
+            relay_recorded(&mut client, &mut upstream, &mut Requests::opaque(), seen)
+        }
+        _ => relay(client, upstream),
+    }
+}
+
+// the tunnel decrypted on both sides: the phone gets a certificate for the server name from the CA, the real server
+// is checked against the roots; a phone that pins its certificates ends the connection here
+fn intercept(client: TcpStream, upstream: TcpStream, hello: &[u8], host: &str, interceptor: &Interceptor, seen: &Seen) -> StrResult<()> {
+    polled_sockets(&client, &upstream)?;
+    let mut server = interceptor.server(host)?;
+    let mut hello = hello;
+    while !hello.is_empty() {
+        server.read_tls(&mut hello).map_err(|e| format!("could not read the ClientHello: {e}"))?;
+        server.process_new_packets().map_err(|e| format!("bad ClientHello: {e}"))?;
+    }
+    let mut client = rustls::StreamOwned::new(server, client);
+    let mut upstream = rustls::StreamOwned::new(interceptor.client(host)?, upstream);
+    let result = relay_recorded(&mut client, &mut upstream, &mut Requests::new("https", host), seen);
+    client.conn.send_close_notify();
+    upstream.conn.send_close_notify();
+    let _ = client.flush().and(upstream.flush());
+    result.map_err(|e| format!("{host}: {e}"))
+}
+
+fn polled_sockets(client: &TcpStream, upstream: &TcpStream) -> StrResult<()> {
+    client
+        .set_read_timeout(Some(RELAY_POLL))
+        .and(upstream.set_read_timeout(Some(RELAY_POLL)))
+        .map_err(|e| format!("could not set up the sockets: {e}"))
+}
+
+// a read of a socket with a timeout: None when nothing came in time, Some(0) at the end
+fn polled(read: std::io::Result<usize>) -> StrResult<Option<usize>> {
+    match read {
+        Ok(n) => Ok(Some(n)),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+        // TLS peers closing without close_notify
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(Some(0)),
+        Err(e) => Err(format!("read error: {e}")),
+    }
+}
+
+// both directions in turn on one thread, as a TLS connection can't be split; requests are recorded and rewritten on
+// the way up, responses scanned for hosts on the way down, until either side closes
+fn relay_recorded(client: &mut impl ReadWrite, upstream: &mut impl ReadWrite, requests: &mut Requests, seen: &Seen) -> StrResult<()> {
+    let mut responses = Responses::default();
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        match polled(client.read(&mut buf))? {
+            Some(0) => return Ok(()),
+            Some(n) => upstream.write_all(&requests.feed(&buf[..n], seen)).and_then(|_| upstream.flush()).map_err(|e| format!("write error upstream: {e}"))?,
+            None => {}
+        }
+        match polled(upstream.read(&mut buf))? {
+            Some(0) => return Ok(()),
+            Some(n) => {
+                responses.feed(&buf[..n], seen);
+                client.write_all(&buf[..n]).and_then(|_| client.flush()).map_err(|e| format!("write error to the client: {e}"))?;
+            }
+            None => {}
+        }
+    }
+}
+
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
+
+// where the decrypted stream from the phone is: in a request head, in a body of known length, or past what can be
+// followed (chunked bodies, upgrades), which is passed on untouched
+#[derive(Debug, PartialEq)]
+enum Framing {
+    Head(Vec<u8>),
+    Body(usize),
+    Opaque,
+}
+
+#[derive(Debug)]
+struct Requests {
+    scheme: &'static str,
+    host: String,
+    framing: Framing,
+}
+
+impl Requests {
+    fn new(scheme: &'static str, host: &str) -> Self {
+        Self { scheme, host: host.to_string(), framing: Framing::Head(Vec::new()) }
+    }
+
+    fn opaque() -> Self {
+        Self { scheme: "http", host: String::new(), framing: Framing::Opaque }
+    }
+
+    // the bytes to send on for data from the phone: request heads without Accept-Encoding, so the responses come
+    // uncompressed and can be scanned
+    fn feed(&mut self, data: &[u8], seen: &Seen) -> Vec<u8> {
+        match &mut self.framing {
+            _ if data.is_empty() => Vec::new(),
+            Framing::Opaque => data.to_vec(),
+            Framing::Body(left) => {
+                let n = (*left).min(data.len());
+                *left -= n;
+                if *left == 0 {
+                    self.framing = Framing::Head(Vec::new());
+                }
+                [&data[..n], &self.feed(&data[n..], seen)].concat()
+            }
+            Framing::Head(buf) => {
+                let from = buf.len().saturating_sub(3);
+                buf.extend(data);
+                match buf[from..].windows(4).position(|w| w == b"\r\n\r\n") {
+                    Some(end) => {
+                        let rest = buf.split_off(from + end + 4);
+                        let head = std::mem::take(buf);
+                        self.framing = self.record(&head, seen);
+                        [without_accept_encoding(&head), self.feed(&rest, seen)].concat()
+                    }
+                    None if buf.len() > MAX_HEAD => {
+                        let buf = std::mem::take(buf);
+                        self.framing = Framing::Opaque;
+                        buf
+                    }
+                    None => Vec::new(),
+                }
+            }
+        }
+    }
+
+    // the URL and host of a request head, and how its body is framed
+    fn record(&self, head: &[u8], seen: &Seen) -> Framing {
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+        let target = lines.next().unwrap_or_default().split_ascii_whitespace().nth(1).unwrap_or_default();
+        let headers = lines.filter_map(|l| l.split_once(':')).map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim())).collect::<Vec<_>>();
+        let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, value)| *value);
+        let authority = header("host").unwrap_or(&self.host);
+        let url = if target.starts_with("http://") || target.starts_with("https://") {
+            target.to_string()
+        } else {
+            format!("{}://{authority}{target}", self.scheme)
+        };
+        let mut seen = seen.lock().unwrap();
+        seen.urls.insert(url);
+        seen.hosts.confirmed.extend(split_host_port(authority, 443).ok().map(|(host, _)| host));
+        if header("transfer-encoding").is_some() || header("upgrade").is_some() {
+            return Framing::Opaque;
+        }
+        match header("content-length").map(str::parse::<usize>) {
+            Some(Ok(0)) | None => Framing::Head(Vec::new()),
+            Some(Ok(len)) => Framing::Body(len),
+            Some(Err(_)) => Framing::Opaque,
+        }
+    }
+}
+
+fn without_accept_encoding(head: &[u8]) -> Vec<u8> {
+    head.split_inclusive(|b| *b == b'\n')
+        .filter(|line| !line.to_ascii_lowercase().starts_with(b"accept-encoding:"))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+// the hosts URLs in the responses point at; the end of each piece is kept to catch URLs cut in two
+#[derive(Debug, Default)]
+struct Responses {
+    tail: Vec<u8>,
+}
+
+impl Responses {
+    fn feed(&mut self, data: &[u8], seen: &Seen) {
+        let text = [&self.tail[..], data].concat();
+        let hosts = hostnames_from_text(&String::from_utf8_lossy(&text));
+        seen.lock().unwrap().hosts.candidates.extend(hosts);
+        self.tail = text[text.len().saturating_sub(SCAN_OVERLAP)..].to_vec();
+    }
+}
+
+fn read_head(client: &mut TcpStream) -> StrResult<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            return Ok((String::from_utf8_lossy(&buf).to_string(), rest));
+        }
+        if buf.len() > MAX_HEAD {
+            return Err("too long request head".to_string());
+        }
+        match client.read(&mut chunk) {
+            Ok(0) => return Err("client closed the connection before sending a request".to_string()),
+            Ok(n) => buf.extend(&chunk[..n]),
+            Err(e) => return Err(format!("read error: {e}")),
+        }
+    }
+}
+
+fn read_client_hello(client: &mut TcpStream, mut buf: Vec<u8>) -> StrResult<Vec<u8>> {
+    client.set_read_timeout(Some(HELLO_TIMEOUT)).map_err(|e| format!("could not set up client socket: {e}"))?;
+    let mut chunk = [0u8; 4096];
+    while buf.len() < MAX_HELLO && tls::record_len(&buf).is_none_or(|len| buf.len() < len) {
+        match client.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => buf.extend(&chunk[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(format!("read error: {e}")),
+        }
+    }
+    Ok(buf)
+}
+
+fn relay(client: TcpStream, upstream: TcpStream) -> StrResult<()> {
+    let (mut client_r, mut upstream_w) = (
+        client.try_clone().map_err(|e| format!("could not clone client socket: {e}"))?,
+        upstream.try_clone().map_err(|e| format!("could not clone upstream socket: {e}"))?,
+    );
+    let to_upstream = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut client_r, &mut upstream_w);
+        let _ = upstream_w.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_r, mut client_w) = (upstream, client);
+    let _ = std::io::copy(&mut upstream_r, &mut client_w);
+    let _ = client_w.shutdown(Shutdown::Write);
+    to_upstream.join().map_err(|_| "relay thread panicked".to_string())
+}
+
+fn split_host_port(authority: &str, default_port: u16) -> StrResult<(String, u16)> {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse().map_err(|e| format!("bad port in '{authority}': {e}"))?)
+        }
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    if host.is_empty() {
+        Err(format!("no host in '{authority}'"))
+    } else {
+        Ok((host, port))
+    }
+}
+
+fn parse_request(head: &str) -> StrResult<ProxyRequest> {
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let [method, target, version] = request_line.split_ascii_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(format!("bad request line '{request_line}'"));
+    };
+    let headers = lines.filter(|l| !l.is_empty()).filter_map(|l| l.split_once(':')).collect::<Vec<_>>();
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target, 443)?;
+        return Ok(ProxyRequest { connect: true, host, port, url: None, forward_head: Vec::new() });
+    }
+
+    let (authority, path) = match target.strip_prefix("http://") {
+        Some(rest) => rest.find('/').map(|i| (rest[..i].to_string(), &rest[i..])).unwrap_or((rest.to_string(), "/")),
+        None => {
+            let host = headers
+                .iter()
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+                .map(|(_, value)| value.trim().to_string())
+                .ok_or(format!("no absolute URL or Host header in request for '{target}'"))?;
+            (host, target)
+        }
+    };
+    let authority = authority.rsplit('@').next().unwrap_or_default().to_string();
+    let (host, port) = split_host_port(&authority, 80)?;
+    let kept_headers = headers
+        .iter()
+        .filter(|(name, _)| !["connection", "proxy-connection", "keep-alive"].contains(&name.trim().to_ascii_lowercase().as_str()))
+        .map(|(name, value)| format!("{name}:{value}\r\n"))
+        .collect::<String>();
+    let forward_head = format!("{method} {path} {version}\r\n{kept_headers}Connection: close\r\n\r\n").into_bytes();
+    Ok(ProxyRequest { connect: false, host, port, url: Some(format!("http://{authority}{path}")), forward_head })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use super::super::mitm::Authority;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(parse_request("CONNECT api.example.com:8443 HTTP/1.1\r\nHost: api.example.com:8443\r\n\r\n"),
+            Ok(ProxyRequest { connect: true, host: "api.example.com".into(), port: 8443, url: None, forward_head: Vec::new() }));
+        assert_eq!(parse_request("CONNECT [2001:db8::1]:443 HTTP/1.1\r\n\r\n").map(|r| (r.host, r.port)),
+            Ok(("2001:db8::1".into(), 443)));
+
+        let request = parse_request("GET http://WWW.example.com/a?b=c HTTP/1.1\r\nHost: www.example.com\r\nProxy-Connection: keep-alive\r\nAccept: */*\r\n\r\n").unwrap();
+        assert_eq!((request.connect, request.host.as_str(), request.port), (false, "www.example.com", 80));
+        assert_eq!(request.url.as_deref(), Some("http://WWW.example.com/a?b=c"));
+        assert_eq!(String::from_utf8(request.forward_head).unwrap(),
+            "GET /a?b=c HTTP/1.1\r\nHost: www.example.com\r\nAccept: */*\r\nConnection: close\r\n\r\n");
+
+        let request = parse_request("POST /api HTTP/1.1\r\nhost: plain.example.org:8080\r\n\r\n").unwrap();
+        assert_eq!((request.host.as_str(), request.port), ("plain.example.org", 8080));
+        assert_eq!(parse_request("http://x.y/ HTTP/1.1\r\n\r\n").map(|r| r.host), Err("bad request line 'http://x.y/ HTTP/1.1'".into()));
+        assert!(parse_request("GET / HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_request("CONNECT x.y:https HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_proxy_records_connect_and_sni() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let received = std::thread::spawn(move || {
+            let (mut conn, _) = upstream.accept().unwrap();
+            let mut buf = Vec::new();
+            conn.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let proxy_addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let proxy = {
+            let proxy_addr = proxy_addr.clone();
+            std::thread::spawn(move || hostnames_from_proxy(&proxy_addr, Duration::from_millis(1500), None))
+        };
+        std::thread::sleep(Duration::from_millis(200));
+
+        let hello = [&[22, 3, 1][..], &(tls::client_hello("app.example.com").len() as u16).to_be_bytes(), &tls::client_hello("app.example.com")].concat();
+        let mut client = TcpStream::connect(&proxy_addr).unwrap();
+        write!(client, "CONNECT 127.0.0.1:{upstream_port} HTTP/1.1\r\n\r\n").unwrap();
+        let mut reply = [0u8; 39];
+        client.read_exact(&mut reply).unwrap();
+        assert!(reply.starts_with(b"HTTP/1.1 200"));
+        client.write_all(&hello).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        assert_eq!(received.join().unwrap(), hello);
+        let capture = proxy.join().unwrap().unwrap();
+        assert_eq!(capture.hosts.confirmed, HashSet::from(["127.0.0.1".into(), "app.example.com".into()]));
+        assert!(capture.urls.is_empty());
+    }
+
+    #[test]
+    fn test_requests() {
+        let seen = Seen::default();
+        let mut requests = Requests::new("https", "app.example.com");
+        assert_eq!(requests.feed(b"GET /a HTTP/1.1\r\nHost: app.example.com\r\nAccept-Encoding: gzip\r\n", &seen), b"");
+        assert_eq!(requests.feed(b"\r\nPOST /b HTTP/1.1\r\nContent-Length: 5\r\naccept-encoding: br\r\n\r\nab", &seen),
+            b"GET /a HTTP/1.1\r\nHost: app.example.com\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nab");
+        assert_eq!(requests.framing, Framing::Body(3));
+        assert_eq!(requests.feed(b"cdeGET http://other.example.org/c HTTP/1.1\r\nHost: other.example.org\r\n\r\n", &seen),
+            b"cdeGET http://other.example.org/c HTTP/1.1\r\nHost: other.example.org\r\n\r\n");
+        assert_eq!(requests.feed(b"GET /ws HTTP/1.1\r\nHost: app.example.com:8443\r\nUpgrade: websocket\r\n\r\n\x81\x05", &seen).len(), 70);
+        assert_eq!(requests.framing, Framing::Opaque);
+        assert_eq!(requests.feed(b"Accept-Encoding: gzip\r\n\r\n", &seen), b"Accept-Encoding: gzip\r\n\r\n");
+        let capture = seen.lock().unwrap().clone();
+        assert_eq!(capture.urls, BTreeSet::from(["https://app.example.com/a".into(), "https://app.example.com/b".into(),
+            "http://other.example.org/c".into(), "https://app.example.com:8443/ws".into()]));
+        assert_eq!(capture.hosts.confirmed, HashSet::from(["app.example.com".into(), "other.example.org".into()]));
+
+        let mut responses = Responses::default();
+        responses.feed(b"HTTP/1.1 200 OK\r\n\r\n{\"img\": \"https://cdn.exa", &seen);
+        responses.feed(b"mple.net/a.png\"}", &seen);
+        assert!(seen.lock().unwrap().hosts.candidates.contains("cdn.example.net"));
+    }
+
+    #[test]
+    fn test_proxy_intercepts() {
+        // the real server, with a certificate from a CA of its own that the proxy trusts
+        let server_ca = Interceptor::new(Authority::generate(Duration::from_secs(3600)).unwrap()).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_ca.authority.der().to_vec().into()).unwrap();
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        let received = std::thread::spawn(move || {
+            let (conn, _) = upstream.accept().unwrap();
+            let mut tls = rustls::StreamOwned::new(server_ca.server("app.example.com").unwrap(), conn);
+            let head = read_head_tls(&mut tls);
+            let body = r#"{"next": "https://cdn.example.net/x"}"#;
+            write!(tls, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+            tls.conn.send_close_notify();
+            tls.flush().unwrap();
+            head
+        });
+
+        let interceptor = Arc::new(Interceptor::with_roots(Authority::generate(Duration::from_secs(3600)).unwrap(), roots).unwrap());
+        let mut phone_roots = rustls::RootCertStore::empty();
+        phone_roots.add(interceptor.authority.der().to_vec().into()).unwrap();
+        let proxy_addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let proxy = {
+            let proxy_addr = proxy_addr.clone();
+            std::thread::spawn(move || hostnames_from_proxy(&proxy_addr, Duration::from_millis(2000), Some(interceptor)))
+        };
+        std::thread::sleep(Duration::from_millis(200));
+
+        let mut client = TcpStream::connect(&proxy_addr).unwrap();
+        write!(client, "CONNECT 127.0.0.1:{upstream_port} HTTP/1.1\r\n\r\n").unwrap();
+        let mut reply = [0u8; 39];
+        client.read_exact(&mut reply).unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(phone_roots)
+            .with_no_client_auth();
+        let connection = rustls::ClientConnection::new(Arc::new(config), "app.example.com".try_into().unwrap()).unwrap();
+        let mut tls = rustls::StreamOwned::new(connection, client);
+        tls.write_all(b"GET /feed?x=1 HTTP/1.1\r\nHost: app.example.com\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tls.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with(r#"{"next": "https://cdn.example.net/x"}"#));
+        assert_eq!(received.join().unwrap(), "GET /feed?x=1 HTTP/1.1\r\nHost: app.example.com\r\n\r\n");
+
+        let capture = proxy.join().unwrap().unwrap();
+        assert_eq!(capture.hosts.confirmed, HashSet::from(["127.0.0.1".into(), "app.example.com".into()]));
+        assert_eq!(capture.hosts.candidates, HashSet::from(["cdn.example.net".into()]));
+        assert_eq!(capture.urls, BTreeSet::from(["https://app.example.com/feed?x=1".into()]));
+    }
+
+    fn read_head_tls(tls: &mut impl Read) -> String {
+        let mut buf = Vec::new();
+        let mut byte = [0u8; 1];
+        while !buf.ends_with(b"\r\n\r\n") {
+            tls.read_exact(&mut byte).unwrap();
+            buf.push(byte[0]);
+        }
+        String::from_utf8(buf).unwrap()
+    }
+}
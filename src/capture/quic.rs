@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ring::aead::{self, quic::HeaderProtectionKey, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::hkdf;
+use super::tls;
+use crate::clock::Clock;
+
+const VERSION_1: u32 = 1;
+const VERSION_2: u32 = 0x6b3343cf;
+const SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
+];
+const SALT_V2: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd, 0x2e, 0xd9,
+];
+const FRAME_PADDING: u64 = 0x00;
+const FRAME_PING: u64 = 0x01;
+const FRAME_ACK: u64 = 0x02;
+const FRAME_ACK_ECN: u64 = 0x03;
+const FRAME_CRYPTO: u64 = 0x06;
+const MAX_CRYPTO: u64 = 64 * 1024;
+// a client sends the rest of its hello right away, so a connection quiet for longer is not going to complete it
+const STREAM_IDLE: Duration = Duration::from_secs(10);
+// connections with an incomplete hello kept at once, the one heard from least recently goes first
+const MAX_STREAMS: usize = 1024;
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct InitialKeys {
+    key: [u8; 16],
+    iv: [u8; 12],
+    hp: [u8; 16],
+}
+
+fn expand_label(prk: &hkdf::Prk, label: &str, out: &mut [u8]) -> Option<()> {
+    let full_label = format!("tls13 {label}");
+    let info = [&(out.len() as u16).to_be_bytes()[..], &[full_label.len() as u8], full_label.as_bytes(), &[0]].concat();
+    prk.expand(&[&info], Len(out.len())).ok()?.fill(out).ok()
+}
+
+// RFC 9001 section 5.2 and RFC 9369 section 3.3: Initial packets are protected with keys anyone can derive from the DCID
+fn client_initial_keys(version: u32, dcid: &[u8]) -> Option<InitialKeys> {
+    let (salt, prefix) = match version {
+        VERSION_1 => (&SALT_V1, "quic"),
+        VERSION_2 => (&SALT_V2, "quicv2"),
+        _ => return None,
+    };
+    let initial = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(dcid);
+    let mut client_secret = [0u8; 32];
+    expand_label(&initial, "client in", &mut client_secret)?;
+    let client = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &client_secret);
+    let mut keys = InitialKeys { key: [0; 16], iv: [0; 12], hp: [0; 16] };
+    expand_label(&client, &format!("{prefix} key"), &mut keys.key)?;
+    expand_label(&client, &format!("{prefix} iv"), &mut keys.iv)?;
+    expand_label(&client, &format!("{prefix} hp"), &mut keys.hp)?;
+    Some(keys)
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let first = *data.get(*pos)?;
+    let len = 1usize << (first >> 6);
+    let bytes = data.get(*pos..*pos + len)?;
+    *pos += len;
+    Some(bytes[1..].iter().fold(u64::from(first & 0x3f), |acc, b| acc << 8 | u64::from(*b)))
+}
+
+fn is_initial(first: u8, version: u32) -> bool {
+    let packet_type = (first >> 4) & 0x03;
+    first & 0x80 != 0 && match version {
+        VERSION_1 => packet_type == 0,
+        VERSION_2 => packet_type == 1,
+        _ => false,
+    }
+}
+
+// returns the DCID of a client Initial packet and its decrypted payload
+fn decrypt_initial(datagram: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let first = *datagram.first()?;
+    let version = u32::from_be_bytes(datagram.get(1..5)?.try_into().ok()?);
+    if !is_initial(first, version) {
+        return None;
+    }
+    let mut pos = 5;
+    let dcid_len = usize::from(*datagram.get(pos)?);
+    let dcid = datagram.get(pos + 1..pos + 1 + dcid_len)?.to_vec();
+    pos += 1 + dcid_len;
+    let scid_len = usize::from(*datagram.get(pos)?);
+    pos += 1 + scid_len;
+    let token_len = usize::try_from(varint(datagram, &mut pos)?).ok()?;
+    pos += token_len;
+    let length = usize::try_from(varint(datagram, &mut pos)?).ok()?;
+    let pn_offset = pos;
+    let packet = datagram.get(..pn_offset + length)?;
+
+    let keys = client_initial_keys(version, &dcid)?;
+    let sample = packet.get(pn_offset + 4..pn_offset + 20)?;
+    let mask = HeaderProtectionKey::new(&aead::quic::AES_128, &keys.hp).ok()?.new_mask(sample).ok()?;
+    let mut header = packet[..pn_offset + 4].to_vec();
+    header[0] ^= mask[0] & 0x0f;
+    let pn_len = usize::from(header[0] & 0x03) + 1;
+    header.truncate(pn_offset + pn_len);
+    header[pn_offset..].iter_mut().zip(&mask[1..]).for_each(|(b, m)| *b ^= m);
+
+    let mut nonce = keys.iv;
+    header[pn_offset..].iter().rev().zip(nonce.iter_mut().rev()).for_each(|(pn, n)| *n ^= pn);
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &keys.key).ok()?);
+    let mut payload = packet[pn_offset + pn_len..].to_vec();
+    let plain_len = key
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(&header), &mut payload)
+        .ok()?
+        .len();
+    payload.truncate(plain_len);
+    Some((dcid, payload))
+}
+
+fn crypto_frames(payload: &[u8]) -> Option<Vec<(u64, &[u8])>> {
+    let mut pos = 0;
+    let mut frames = Vec::new();
+    while pos < payload.len() {
+        match varint(payload, &mut pos)? {
+            FRAME_PADDING | FRAME_PING => {}
+            kind @ (FRAME_ACK | FRAME_ACK_ECN) => {
+                varint(payload, &mut pos)?;
+                varint(payload, &mut pos)?;
+                let ranges = varint(payload, &mut pos)?;
+                varint(payload, &mut pos)?;
+                (0..ranges * 2).try_for_each(|_| varint(payload, &mut pos).map(|_| ()))?;
+                if kind == FRAME_ACK_ECN {
+                    (0..3).try_for_each(|_| varint(payload, &mut pos).map(|_| ()))?;
+                }
+            }
+            FRAME_CRYPTO => {
+                let offset = varint(payload, &mut pos)?;
+                let len = usize::try_from(varint(payload, &mut pos)?).ok()?;
+                frames.push((offset, payload.get(pos..pos + len)?));
+                pos += len;
+            }
+            _ => return None,
+        }
+    }
+    Some(frames)
+}
+
+// a ClientHello may be split across several Initial packets (post-quantum key shares make it larger than one datagram),
+// so CRYPTO data is collected per connection until the hello is complete, it goes quiet, or too many are incomplete
+#[derive(Debug)]
+pub struct QuicSniTracker {
+    streams: HashMap<Vec<u8>, Stream>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Stream {
+    crypto: BTreeMap<u64, Vec<u8>>,
+    seen: Instant,
+}
+
+impl Default for QuicSniTracker {
+    fn default() -> Self {
+        Self::with_clock(crate::clock::system())
+    }
+}
+
+impl QuicSniTracker {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self { streams: HashMap::new(), clock }
+    }
+
+    pub fn push(&mut self, datagram: &[u8]) -> Option<String> {
+        let (dcid, payload) = decrypt_initial(datagram)?;
+        let frames = crypto_frames(&payload)?;
+        let now = self.clock.now();
+        self.streams.retain(|_, stream| now.saturating_duration_since(stream.seen) <= STREAM_IDLE);
+        if !self.streams.contains_key(&dcid) && self.streams.len() >= MAX_STREAMS
+            && let Some(oldest) = self.streams.iter().min_by_key(|(_, stream)| stream.seen).map(|(dcid, _)| dcid.clone()) {
+            self.streams.remove(&oldest);
+        }
+        let stream = self.streams.entry(dcid.clone()).or_insert_with(|| Stream { crypto: BTreeMap::new(), seen: now });
+        stream.seen = now;
+        let stream = &mut stream.crypto;
+        frames
+            .into_iter()
+            .filter(|(offset, data)| offset + data.len() as u64 <= MAX_CRYPTO)
+            .for_each(|(offset, data)| {
+                stream.insert(offset, data.to_vec());
+            });
+        let contiguous = stream.iter().try_fold(Vec::new(), |mut acc, (offset, data)| {
+            let start = usize::try_from(*offset).ok()?;
+            if start > acc.len() {
+                return None;
+            }
+            acc.extend(data.iter().skip(acc.len() - start));
+            Some(acc)
+        });
+        let sni = contiguous.and_then(|hello| tls::sni_from_handshake(&hello));
+        if sni.is_some() {
+            self.streams.remove(&dcid);
+        }
+        sni
+    }
+}
+
+pub fn sni_from_initial(datagram: &[u8]) -> Option<String> {
+    QuicSniTracker::default().push(datagram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC9001_DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn encode_varint(v: u64) -> Vec<u8> {
+        match v {
+            0..=63 => vec![v as u8],
+            64..=16383 => (v as u16 | 0x4000).to_be_bytes().to_vec(),
+            _ => (v as u32 | 0x8000_0000).to_be_bytes().to_vec(),
+        }
+    }
+
+    fn crypto_frame(offset: u64, data: &[u8]) -> Vec<u8> {
+        [&[FRAME_CRYPTO as u8][..], &encode_varint(offset), &encode_varint(data.len() as u64), data].concat()
+    }
+
+    // what a client does, see RFC 9001 section 5.4 and appendix A.2
+    fn protect_initial(version: u32, dcid: &[u8], pn: u32, frames: &[u8]) -> Vec<u8> {
+        let keys = client_initial_keys(version, dcid).unwrap();
+        let mut payload = frames.to_vec();
+        payload.resize(payload.len().max(1162), 0);
+        let pn_bytes = pn.to_be_bytes();
+        let type_bits = if version == VERSION_2 { 0x10 } else { 0x00 };
+        let header = [
+            &[0xc3 | type_bits][..],
+            &version.to_be_bytes(),
+            &[dcid.len() as u8], dcid,
+            &[0],
+            &[0],
+            &encode_varint(4 + payload.len() as u64 + 16),
+            &pn_bytes,
+        ].concat();
+        let pn_offset = header.len() - 4;
+        let mut nonce = keys.iv;
+        pn_bytes.iter().rev().zip(nonce.iter_mut().rev()).for_each(|(p, n)| *n ^= p);
+        let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &keys.key).unwrap());
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&header), &mut payload).unwrap();
+        let mut packet = [header, payload].concat();
+        let mask = HeaderProtectionKey::new(&aead::quic::AES_128, &keys.hp).unwrap()
+            .new_mask(&packet[pn_offset + 4..pn_offset + 20]).unwrap();
+        packet[0] ^= mask[0] & 0x0f;
+        packet[pn_offset..pn_offset + 4].iter_mut().zip(&mask[1..]).for_each(|(b, m)| *b ^= m);
+        packet
+    }
+
+    #[test]
+    fn test_client_initial_keys() {
+        assert_eq!(client_initial_keys(VERSION_1, &RFC9001_DCID), Some(InitialKeys {
+            key: hex("1f369613dd76d5467730efcbe3b1a22d").try_into().unwrap(),
+            iv: hex("fa044b2f42a3fd3b46fb255c").try_into().unwrap(),
+            hp: hex("9f50449e04a0e810283a1e9933adedd2").try_into().unwrap(),
+        }));
+        assert_eq!(client_initial_keys(VERSION_2, &RFC9001_DCID), Some(InitialKeys {
+            key: hex("8b1a0bc121284290a29e0971b5cd045d").try_into().unwrap(),
+            iv: hex("91f73e2351d8fa91660e909f").try_into().unwrap(),
+            hp: hex("45b95e15235d6f45a6b19cbcb0294ba9").try_into().unwrap(),
+        }));
+        assert_eq!(client_initial_keys(0xff00001d, &RFC9001_DCID), None);
+    }
+
+    #[test]
+    fn test_varint() {
+        let data = hex("c2197c5eff14e88c9d7b0e4e259c2c4e6d41c8");
+        let mut pos = 0;
+        assert_eq!(varint(&data, &mut pos), Some(151288809941952652));
+        assert_eq!(varint(&hex("9d7f3e7d"), &mut 0), Some(494878333));
+        assert_eq!(varint(&hex("7bbd"), &mut 0), Some(15293));
+        assert_eq!(varint(&hex("25"), &mut 0), Some(37));
+        assert_eq!(varint(&hex("c2"), &mut 0), None);
+    }
+
+    #[test]
+    fn test_sni_from_initial() {
+        let hello = tls::client_hello("www.example.com");
+        [VERSION_1, VERSION_2].into_iter().for_each(|version| {
+            let packet = protect_initial(version, &RFC9001_DCID, 2, &crypto_frame(0, &hello));
+            assert_eq!(sni_from_initial(&packet), Some("www.example.com".to_string()));
+        });
+        let mut tampered = protect_initial(VERSION_1, &RFC9001_DCID, 2, &crypto_frame(0, &hello));
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(sni_from_initial(&tampered), None);
+        assert_eq!(sni_from_initial(&hex("4000000000")), None);
+        assert_eq!(sni_from_initial(&[]), None);
+    }
+
+    #[test]
+    fn test_sni_split_across_packets() {
+        let hello = tls::client_hello("video.example.org");
+        let (head, tail) = hello.split_at(40);
+        let (middle, tail) = tail.split_at(20);
+        let first = protect_initial(VERSION_1, &RFC9001_DCID, 0, &[crypto_frame(60, tail), vec![FRAME_PING as u8], crypto_frame(0, head)].concat());
+        let second = protect_initial(VERSION_1, &RFC9001_DCID, 1, &crypto_frame(40, middle));
+        let mut tracker = QuicSniTracker::default();
+        assert_eq!(tracker.push(&first), None);
+        assert_eq!(tracker.push(&second), Some("video.example.org".to_string()));
+        assert!(tracker.streams.is_empty());
+    }
+
+    #[test]
+    fn test_streams_evicted() {
+        let hello = tls::client_hello("video.example.org");
+        let (head, tail) = hello.split_at(40);
+        let clock = Arc::new(crate::clock::ManualClock::new(0));
+        let mut tracker = QuicSniTracker::with_clock(clock.clone());
+        // the rest of the hello comes too late
+        assert_eq!(tracker.push(&protect_initial(VERSION_1, &RFC9001_DCID, 0, &crypto_frame(0, head))), None);
+        assert_eq!(tracker.streams.len(), 1);
+        clock.advance(STREAM_IDLE + Duration::from_secs(1));
+        assert_eq!(tracker.push(&protect_initial(VERSION_1, &RFC9001_DCID, 1, &crypto_frame(40, tail))), None);
+        assert_eq!(tracker.streams.len(), 1);
+
+        // too many incomplete hellos at once: the one heard from least recently goes
+        let mut tracker = QuicSniTracker::with_clock(clock.clone());
+        let dcid = |i: usize| (i as u64).to_be_bytes();
+        (0..=MAX_STREAMS).for_each(|i| {
+            assert_eq!(tracker.push(&protect_initial(VERSION_1, &dcid(i), 0, &crypto_frame(0, head))), None);
+            clock.advance(Duration::from_millis(1));
+        });
+        assert_eq!(tracker.streams.len(), MAX_STREAMS);
+        assert!(!tracker.streams.contains_key(&dcid(0)[..]));
+        assert_eq!(tracker.push(&protect_initial(VERSION_1, &dcid(1), 1, &crypto_frame(40, tail))), Some("video.example.org".to_string()));
+        assert_eq!(tracker.streams.len(), MAX_STREAMS - 1);
+    }
+}
@@ -0,0 +1,76 @@
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+use crate::dns::StrResult;
+use super::packet::PacketDecoder;
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(200);
+
+// a "cooked" packet socket delivers network layer packets whatever the link layer of the interface is (Ethernet, Wi-Fi, tun)
+fn open_packet_socket(iface: &str) -> StrResult<OwnedFd> {
+    let protocol = (libc::ETH_P_ALL as u16).to_be();
+    let ifindex = if iface == "any" {
+        0
+    } else {
+        let name = CString::new(iface).map_err(|e| format!("bad interface name '{iface}': {e}"))?;
+        match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+            0 => return Err(format!("no such interface '{iface}'")),
+            index => index as i32,
+        }
+    };
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM, i32::from(protocol)) };
+    if fd < 0 {
+        return Err(format!("could not open packet socket (are you root?): {}", std::io::Error::last_os_error()));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = protocol;
+    addr.sll_ifindex = ifindex;
+    let bound = unsafe {
+        libc::bind(fd.as_raw_fd(), &addr as *const libc::sockaddr_ll as *const libc::sockaddr, size_of::<libc::sockaddr_ll>() as u32)
+    };
+    if bound < 0 {
+        return Err(format!("could not bind packet socket to {iface}: {}", std::io::Error::last_os_error()));
+    }
+
+    let timeout = libc::timeval { tv_sec: 0, tv_usec: RECV_TIMEOUT.as_micros() as libc::suseconds_t };
+    let set = unsafe {
+        libc::setsockopt(fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void, size_of::<libc::timeval>() as u32)
+    };
+    if set < 0 {
+        return Err(format!("could not set packet socket timeout: {}", std::io::Error::last_os_error()));
+    }
+    Ok(fd)
+}
+
+pub fn sniff(iface: &str, duration: Duration, decoder: &mut PacketDecoder) -> StrResult<()> {
+    sniff_ticking(iface, duration, decoder, duration, |_| Ok(()))
+}
+
+// sniffing, with tick called on what was decoded so far every so often; packets arriving meanwhile wait in the socket
+pub fn sniff_ticking(iface: &str, duration: Duration, decoder: &mut PacketDecoder, every: Duration, mut tick: impl FnMut(&PacketDecoder) -> StrResult<()>)
+    -> StrResult<()> {
+    let fd = open_packet_socket(iface)?;
+    let deadline = Instant::now() + duration;
+    let mut next_tick = Instant::now() + every;
+    let mut buf = vec![0u8; 65536];
+    while Instant::now() < deadline {
+        if Instant::now() >= next_tick {
+            tick(decoder)?;
+            next_tick += every;
+        }
+        let len = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if len < 0 {
+            let e = std::io::Error::last_os_error();
+            match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted => continue,
+                _ => return Err(format!("could not receive packet on {iface}: {e}")),
+            }
+        }
+        decoder.push_ip(&buf[..len as usize]);
+    }
+    Ok(())
+}
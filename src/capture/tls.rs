@@ -0,0 +1,127 @@
+const CONTENT_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const NAME_TYPE_HOST: u8 = 0;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(b)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.bytes(3).map(|b| usize::from(b[0]) << 16 | usize::from(b[1]) << 8 | usize::from(b[2]))
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()?;
+        self.bytes(len.into())
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()?;
+        self.bytes(len.into())
+    }
+}
+
+// length of the TLS record starting at data, header included
+pub fn record_len(data: &[u8]) -> Option<usize> {
+    let mut r = Reader::new(data);
+    r.u8()?;
+    r.u16()?;
+    r.u16().map(|len| 5 + usize::from(len))
+}
+
+// first bytes a TLS client sends: a handshake record carrying the ClientHello
+pub fn sni_from_record(data: &[u8]) -> Option<String> {
+    let mut r = Reader::new(data);
+    if r.u8()? != CONTENT_HANDSHAKE {
+        return None;
+    }
+    r.u16()?;
+    sni_from_handshake(r.vec16()?)
+}
+
+// ClientHello handshake message, as found in a TLS record or in QUIC CRYPTO frames
+pub fn sni_from_handshake(data: &[u8]) -> Option<String> {
+    let mut r = Reader::new(data);
+    if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = r.u24()?;
+    let mut hello = Reader::new(r.bytes(len)?);
+    hello.bytes(2 + 32)?;
+    hello.vec8()?;
+    hello.vec16()?;
+    hello.vec8()?;
+    let mut extensions = Reader::new(hello.vec16()?);
+    while let Some(kind) = extensions.u16() {
+        let body = extensions.vec16()?;
+        if kind == EXTENSION_SERVER_NAME {
+            let mut names = Reader::new(body);
+            let mut list = Reader::new(names.vec16()?);
+            while let Some(name_type) = list.u8() {
+                let name = list.vec16()?;
+                if name_type == NAME_TYPE_HOST {
+                    return std::str::from_utf8(name).ok().map(|n| n.to_ascii_lowercase());
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) fn client_hello(sni: &str) -> Vec<u8> {
+    let with_len16 = |b: &[u8]| [&(b.len() as u16).to_be_bytes()[..], b].concat();
+    let name = [&[NAME_TYPE_HOST][..], &with_len16(sni.as_bytes())].concat();
+    let sni_ext = [&EXTENSION_SERVER_NAME.to_be_bytes()[..], &with_len16(&with_len16(&name))].concat();
+    let alpn_ext = [&16u16.to_be_bytes()[..], &with_len16(&with_len16(b"\x02h2"))].concat();
+    let body = [
+        &[3, 3][..],
+        &[0x42; 32],
+        &[32], &[7; 32],
+        &with_len16(&[0x13, 0x01, 0x13, 0x02]),
+        &[1, 0],
+        &with_len16(&[alpn_ext, sni_ext].concat()),
+    ].concat();
+    let len = (body.len() as u32).to_be_bytes();
+    [&[HANDSHAKE_CLIENT_HELLO][..], &len[1..], &body].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(handshake: &[u8]) -> Vec<u8> {
+        [&[CONTENT_HANDSHAKE, 3, 1][..], &(handshake.len() as u16).to_be_bytes(), handshake].concat()
+    }
+
+    #[test]
+    fn test_sni_from_record() {
+        assert_eq!(sni_from_record(&record(&client_hello("WWW.Example.com"))), Some("www.example.com".to_string()));
+        assert_eq!(sni_from_handshake(&client_hello("cdn.example.net")), Some("cdn.example.net".to_string()));
+        assert_eq!(sni_from_record(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(sni_from_record(&[]), None);
+        let hello = record(&client_hello("www.example.com"));
+        assert_eq!(sni_from_record(&hello[..hello.len() - 3]), None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use ipnetwork::IpNetwork;
+use crate::dns::StrResult;
+
+// a LAN client whose traffic is steered: by address (or a whole network of them), by MAC address, looked up in the
+// neighbour table and the DHCP leases, or by the hostname it got its lease with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Client {
+    Net(IpNetwork),
+    Mac(String),
+    Name(String),
+}
+
+fn is_mac(s: &str) -> bool {
+    let octets = s.split([':', '-']).collect::<Vec<_>>();
+    octets.len() == 6 && octets.iter().all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+impl Client {
+    pub fn parse(s: &str) -> StrResult<Self> {
+        let s = s.trim();
+        if let Ok(net) = s.parse::<IpNetwork>() {
+            return Ok(Client::Net(net));
+        }
+        if is_mac(s) {
+            return Ok(Client::Mac(s.replace('-', ":").to_ascii_lowercase()));
+        }
+        let name = s.trim_end_matches('.');
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+            return Ok(Client::Name(name.to_ascii_lowercase()));
+        }
+        Err(format!("'{s}' is neither an IP address, a network, a MAC address nor a hostname"))
+    }
+}
+
+// one client per line, # starts a comment
+pub fn clients_from_text(text: &str) -> StrResult<Vec<Client>> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, entry)| !entry.is_empty())
+        .map(|(n, entry)| Client::parse(entry).map_err(|e| format!("{e} on line {}", n + 1)))
+        .collect()
+}
+
+// where the addresses of clients given by MAC address or hostname are looked up
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Neighbours {
+    pub macs: HashMap<String, Vec<IpNetwork>>,
+    pub names: HashMap<String, Vec<IpNetwork>>,
+}
+
+impl Neighbours {
+    fn add(map: &mut HashMap<String, Vec<IpNetwork>>, key: String, ip: IpAddr) {
+        let ips = map.entry(key).or_default();
+        if !ips.contains(&IpNetwork::from(ip)) {
+            ips.push(IpNetwork::from(ip));
+        }
+    }
+
+    // a lease for "laptop.lan" also answers for plain "laptop"
+    pub fn add_leases(&mut self, leases: &[Lease]) {
+        leases.iter().for_each(|lease| {
+            if let Some(mac) = &lease.mac {
+                Self::add(&mut self.macs, mac.clone(), lease.ip);
+            }
+            if let Some(name) = &lease.hostname {
+                Self::add(&mut self.names, name.clone(), lease.ip);
+                if let Some((label, _)) = name.split_once('.') {
+                    Self::add(&mut self.names, label.to_string(), lease.ip);
+                }
+            }
+        });
+    }
+}
+
+// MAC -> addresses, from /proc/net/arp; incomplete entries (flags 0x0) have no usable MAC
+pub fn neighbours_from_proc_arp(text: &str) -> Neighbours {
+    let mut neighbours = Neighbours::default();
+    text.lines().skip(1).filter_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (ip, flags, mac) = (fields.first()?, fields.get(2)?, fields.get(3)?);
+        (*flags != "0x0").then_some(())?;
+        Some((mac.to_ascii_lowercase(), ip.parse::<IpAddr>().ok()?))
+    }).for_each(|(mac, ip)| Neighbours::add(&mut neighbours.macs, mac, ip));
+    neighbours
+}
+
+// an address handed out by a DHCP server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub ip: IpAddr,
+    pub mac: Option<String>,
+    pub hostname: Option<String>,
+}
+
+fn hostname(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+    (!name.is_empty() && name != "*").then_some(name)
+}
+
+pub fn leases_from_file(path: &str, now: u64) -> StrResult<Vec<Lease>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read leases {path}: {e}"))?;
+    leases_from_text(&text, now).map_err(|e| format!("{e} in leases {path}"))
+}
+
+// the leases still valid at now (Unix seconds), from a dnsmasq lease file or a Kea memfile (CSV with a header)
+pub fn leases_from_text(text: &str, now: u64) -> StrResult<Vec<Lease>> {
+    if text.starts_with("address,") {
+        kea_leases(text, now)
+    } else {
+        dnsmasq_leases(text, now)
+    }
+}
+
+// "expiry mac ip hostname client-id" lines, expiry 0 for infinite; DHCPv6 leases follow a "duid" line and have an
+// IAID where the MAC would be
+fn dnsmasq_leases(text: &str, now: u64) -> StrResult<Vec<Lease>> {
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.starts_with("duid ")).filter_map(|(n, line)| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let parsed = (|| {
+            let expiry = fields.first()?.parse::<u64>().ok()?;
+            let ip = fields.get(2)?.parse::<IpAddr>().ok()?;
+            Some((expiry, ip))
+        })();
+        let Some((expiry, ip)) = parsed else {
+            return Some(Err(format!("bad lease on line {}", n + 1)));
+        };
+        (expiry == 0 || expiry > now).then(|| Ok(Lease {
+            ip,
+            mac: fields.get(1).filter(|mac| is_mac(mac)).map(|mac| mac.to_ascii_lowercase()),
+            hostname: fields.get(3).and_then(|name| hostname(name)),
+        }))
+    }).collect()
+}
+
+// kea-leases4.csv and kea-leases6.csv: the file is appended to, so a later line for an address replaces the earlier
+// ones; only state 0 is an active lease
+fn kea_leases(text: &str, now: u64) -> StrResult<Vec<Lease>> {
+    let mut lines = text.lines();
+    let header = lines.next().unwrap_or_default().split(',').map(str::trim).collect::<Vec<_>>();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let (hwaddr, expire, name, state) = (column("hwaddr"), column("expire"), column("hostname"), column("state"));
+    let mut leases = BTreeMap::<IpAddr, Option<Lease>>::new();
+    lines.enumerate().filter(|(_, line)| !line.trim().is_empty()).try_for_each(|(n, line)| {
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let field = |index: Option<usize>| index.and_then(|index| fields.get(index)).copied().unwrap_or_default();
+        let ip = fields[0].parse::<IpAddr>().map_err(|e| format!("bad address '{}' on line {}: {e}", fields[0], n + 2))?;
+        let active = field(state).parse::<u8>().unwrap_or(0) == 0 && field(expire).parse::<u64>().is_ok_and(|expire| expire > now);
+        let lease = Lease { ip, mac: Some(field(hwaddr).to_ascii_lowercase()).filter(|mac| is_mac(mac)), hostname: hostname(field(name)) };
+        leases.insert(ip, active.then_some(lease));
+        Ok::<_, String>(())
+    })?;
+    Ok(leases.into_values().flatten().collect())
+}
+
+// the networks the clients send from; a MAC or hostname that cannot be found is an error, as its traffic would
+// silently bypass the tunnel
+pub fn client_networks(clients: &[Client], neighbours: &Neighbours) -> StrResult<Vec<IpNetwork>> {
+    let mut nets = clients.iter().map(|client| match client {
+        Client::Net(net) => Ok(vec![*net]),
+        Client::Mac(mac) => neighbours.macs.get(mac).cloned().ok_or(format!("no address for {mac} in the neighbour table or the leases, is the client online?")),
+        Client::Name(name) => neighbours.names.get(name).cloned().ok_or(format!("no DHCP lease for '{name}', is the client online?")),
+    }).collect::<StrResult<Vec<_>>>()?.concat();
+    nets.sort_by_key(|net| (net.is_ipv6(), net.ip(), net.prefix()));
+    nets.dedup();
+    Ok(nets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients() {
+        let clients = clients_from_text("# living room TV\n192.168.1.20\nAA-BB-CC-00-11-22\n192.168.2.0/24  # guest LAN\n\nfd00::20\n").unwrap();
+        assert_eq!(clients, vec![
+            Client::Net("192.168.1.20".parse().unwrap()),
+            Client::Mac("aa:bb:cc:00:11:22".to_string()),
+            Client::Net("192.168.2.0/24".parse().unwrap()),
+            Client::Net("fd00::20".parse().unwrap()),
+        ]);
+        assert_eq!(clients_from_text("TV.lan.\n"), Ok(vec![Client::Name("tv.lan".to_string())]));
+        assert_eq!(clients_from_text("living room\n"), Err("'living room' is neither an IP address, a network, a MAC address nor a hostname on line 1".to_string()));
+
+        let arp = "IP address       HW type     Flags       HW address            Mask     Device\n\
+            192.168.1.31     0x1         0x2         aa:bb:cc:00:11:22     *        br-lan\n\
+            192.168.1.44     0x1         0x0         00:00:00:00:00:00     *        br-lan\n";
+        let neighbours = neighbours_from_proc_arp(arp);
+        assert_eq!(neighbours.macs.len(), 1);
+        assert_eq!(client_networks(&clients, &neighbours), Ok(vec![
+            "192.168.1.20".parse().unwrap(),
+            "192.168.1.31".parse().unwrap(),
+            "192.168.2.0/24".parse().unwrap(),
+            "fd00::20".parse().unwrap(),
+        ]));
+        assert!(client_networks(&[Client::Mac("00:11:22:33:44:55".to_string())], &neighbours).unwrap_err().contains("00:11:22:33:44:55"));
+    }
+
+    #[test]
+    fn test_leases() {
+        let dnsmasq = "1760003600 aa:bb:cc:00:11:22 192.168.1.31 Laptop 01:aa:bb:cc:00:11:22\n\
+            1759990000 aa:bb:cc:00:11:33 192.168.1.32 old-phone *\n\
+            0 aa:bb:cc:00:11:44 192.168.1.40 * *\n\
+            duid 00:01:00:01:2c:5f:1e:00:aa:bb:cc:00:11:22\n\
+            1760003600 305419896 fd00::31 laptop 00:01:00:01\n";
+        let leases = leases_from_text(dnsmasq, 1_760_000_000).unwrap();
+        assert_eq!(leases, vec![
+            Lease { ip: "192.168.1.31".parse().unwrap(), mac: Some("aa:bb:cc:00:11:22".to_string()), hostname: Some("laptop".to_string()) },
+            Lease { ip: "192.168.1.40".parse().unwrap(), mac: Some("aa:bb:cc:00:11:44".to_string()), hostname: None },
+            Lease { ip: "fd00::31".parse().unwrap(), mac: None, hostname: Some("laptop".to_string()) },
+        ]);
+        assert_eq!(leases_from_text("soon aa:bb:cc:00:11:22 192.168.1.31 laptop *\n", 0), Err("bad lease on line 1".to_string()));
+
+        let kea = "address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context\n\
+            192.168.1.50,aa:bb:cc:00:11:55,,3600,1760003600,1,0,0,tv.lan.,0,\n\
+            192.168.1.51,aa:bb:cc:00:11:66,,3600,1760003600,1,0,0,tablet,0,\n\
+            192.168.1.51,aa:bb:cc:00:11:66,,3600,1760003600,1,0,0,tablet,2,\n";
+        let leases = leases_from_text(kea, 1_760_000_000).unwrap();
+        assert_eq!(leases, vec![Lease { ip: "192.168.1.50".parse().unwrap(), mac: Some("aa:bb:cc:00:11:55".to_string()), hostname: Some("tv.lan".to_string()) }]);
+
+        let mut neighbours = Neighbours::default();
+        neighbours.add_leases(&leases);
+        let clients = [Client::Name("tv".to_string()), Client::Name("tv.lan".to_string()), Client::Mac("aa:bb:cc:00:11:55".to_string())];
+        assert_eq!(client_networks(&clients, &neighbours), Ok(vec!["192.168.1.50".parse().unwrap()]));
+        assert_eq!(client_networks(&[Client::Name("tablet".to_string())], &neighbours), Err("no DHCP lease for 'tablet', is the client online?".to_string()));
+    }
+}
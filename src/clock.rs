@@ -0,0 +1,134 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// where timestamps, deadlines and random choices come from; everything that stores or schedules by time goes
+// through one, so tests can move time by hand and a bug report can be replayed with WGROUTER_FAKE_TIME and
+// WGROUTER_SEED set to what it was run with
+pub trait Clock: Debug + Send + Sync {
+    // seconds since the Unix epoch, for what is stored or logged
+    fn unix_time(&self) -> u64;
+    // monotonic, for deadlines and timeouts
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// stands still until advanced
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    unix_start: u64,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new(unix_time: u64) -> Self {
+        Self { start: Instant::now(), unix_start: unix_time, elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Clock for ManualClock {
+    fn unix_time(&self) -> u64 {
+        self.unix_start + self.elapsed().as_secs()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+// the clock of this process: the system one, or one frozen at WGROUTER_FAKE_TIME (Unix seconds)
+pub fn system() -> Arc<dyn Clock> {
+    static CLOCK: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+    CLOCK.get_or_init(|| match std::env::var("WGROUTER_FAKE_TIME").ok().and_then(|t| t.parse().ok()) {
+        Some(unix_time) => Arc::new(ManualClock::new(unix_time)),
+        None => Arc::new(SystemClock),
+    }).clone()
+}
+
+// SplitMix64: tiny, and the same sequence for the same seed on every platform, which is all that is needed for
+// jitter and tie-breaking; not for anything secret
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    // seeded from WGROUTER_SEED if set, from the time and the process id otherwise
+    pub fn from_env() -> Self {
+        let seed = std::env::var("WGROUTER_SEED").ok().and_then(|s| s.parse().ok());
+        Self(seed.unwrap_or_else(|| SystemClock.unix_time() ^ u64::from(std::process::id()).rotate_left(32)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in 0..bound (0 for a bound of 0)
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        // rejecting the top partial range keeps the result unbiased
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let n = self.next_u64();
+            if n < zone {
+                return n % bound;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(1_700_000_000);
+        let start = clock.now();
+        assert_eq!(clock.unix_time(), 1_700_000_000);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.unix_time(), 1_700_000_001);
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::seeded(42);
+        let mut b = Rng::seeded(42);
+        let sequence = (0..4).map(|_| a.next_u64()).collect::<Vec<_>>();
+        assert_eq!(sequence, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_eq!(Rng::seeded(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_ne!(sequence[0], sequence[1]);
+        assert!((0..100).all(|_| a.below(10) < 10));
+        assert_eq!(a.below(0), 0);
+    }
+}
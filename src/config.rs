@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use crate::dns::StrResult;
+
+// $WGROUTER_CONFIG, or $XDG_CONFIG_HOME/wgrouter/config (~/.config/wgrouter/config); none without a home
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("WGROUTER_CONFIG").map(PathBuf::from).or_else(|| {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("wgrouter").join("config"))
+    })
+}
+
+// the text of the config; none when there is no such file
+pub fn read(path: &Path) -> StrResult<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("could not read config {}: {e}", path.display())),
+    }
+}
+
+fn is_option_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('-') && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// a line without its comment, numbered from 1
+type Line<'a> = (usize, &'a str);
+
+// the lines of the text up to the first section, and each section with its header and the lines under it
+fn sections(text: &str) -> (Vec<Line<'_>>, Vec<(Line<'_>, Vec<Line<'_>>)>) {
+    let mut top = Vec::new();
+    let mut sections = Vec::<(Line, Vec<Line>)>::new();
+    text.lines().enumerate().map(|(n, line)| (n + 1, line.split('#').next().unwrap_or_default().trim())).filter(|(_, line)| !line.is_empty()).for_each(|(n, line)| {
+        match (line.strip_prefix('[').and_then(|line| line.strip_suffix(']')), sections.last_mut()) {
+            (Some(header), _) => sections.push(((n, header.trim()), Vec::new())),
+            (None, Some((_, lines))) => lines.push((n, line)),
+            (None, None) => top.push((n, line)),
+        }
+    });
+    (top, sections)
+}
+
+// defaults for the options of the commands that take inputs, as "option = value" lines naming the long option without
+// its dashes, e.g. "prefix-len = 20"; true stands for a flag that is given, an option given several times takes a line
+// each, # starts a comment; the [deployment] sections that may follow have options of their own
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config(pub Vec<(String, String)>);
+
+// what a step of a deployment does with the networks
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepKind {
+    // installs them as routes, like apply
+    Routes,
+    // adds them to the allowed IPs of a WireGuard peer, on the running interface or in its config file
+    Peer,
+    // writes them to the file in an output format
+    File(String),
+}
+
+// a "[deployment NAME KIND]" section: one of the targets the networks go to together, with the options for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub kind: StepKind,
+    pub options: Config,
+}
+
+// the steps of the deployment, in the order of their sections in the file
+pub fn deployment(text: &str, name: &str) -> StrResult<Vec<Step>> {
+    let (_, sections) = sections(text);
+    let steps = sections
+        .into_iter()
+        .map(|((n, header), lines)| {
+            let kind = match header.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["deployment", _, "routes"] => StepKind::Routes,
+                ["deployment", _, "peer"] => StepKind::Peer,
+                ["deployment", _, "file", path] => StepKind::File(path.to_string()),
+                _ => return Err(format!("expected '[deployment NAME routes|peer|file PATH]' on line {n}")),
+            };
+            Ok((header.split_whitespace().nth(1) == Some(name)).then_some(Step { kind, options: Config::from_lines(lines)? }))
+        })
+        .filter_map(Result::transpose)
+        .collect::<StrResult<Vec<_>>>()?;
+    if steps.is_empty() {
+        return Err(format!("no [deployment {name} ...] sections"));
+    }
+    Ok(steps)
+}
+
+impl Config {
+    // none when there is no such file
+    pub fn load(path: &Path) -> StrResult<Option<Self>> {
+        read(path)?.map(|text| Self::parse(&text).map_err(|e| format!("{e} in config {}", path.display()))).transpose()
+    }
+
+    // the options before the first section
+    pub fn parse(text: &str) -> StrResult<Self> {
+        Self::from_lines(sections(text).0)
+    }
+
+    fn from_lines(lines: Vec<Line>) -> StrResult<Self> {
+        lines
+            .into_iter()
+            .map(|(n, line)| match line.split_once('=') {
+                Some((option, value)) if is_option_name(option.trim()) => Ok((option.trim().to_string(), value.trim().to_string())),
+                _ => Err(format!("expected 'option = value' on line {n}")),
+            })
+            .collect::<StrResult<Vec<_>>>()
+            .map(Self)
+    }
+
+    // the options as command line arguments; true gives a flag and false leaves it out, other options take their value
+    // whatever it is
+    pub fn to_args(&self, is_flag: impl Fn(&str) -> bool) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|(option, value)| match (is_flag(option), value.as_str()) {
+                (true, "true") => Some(format!("--{option}")),
+                (true, "false") => None,
+                _ => Some(format!("--{option}={value}")),
+            })
+            .collect()
+    }
+
+    // the file, each option under a comment saying what it is for
+    pub fn render(&self, comments: &[&str]) -> String {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(n, (option, value))| match comments.get(n).filter(|comment| !comment.is_empty()) {
+                Some(comment) => format!("\n# {comment}\n{option} = {value}\n"),
+                None => format!("{option} = {value}\n"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("# made by wgrouter init\nrule-interface = wg1\nendpoint = vpn.example.net:51820  # the server\n\n\
+            endpoint = 198.51.100.7:51820\nipv6 = true\nweighted = false\nvpn-dns = tls://10.8.0.1\n").unwrap();
+        let is_flag = |option: &str| matches!(option, "ipv6" | "weighted");
+        assert_eq!(config.to_args(is_flag), vec!["--rule-interface=wg1", "--endpoint=vpn.example.net:51820", "--endpoint=198.51.100.7:51820", "--ipv6", "--vpn-dns=tls://10.8.0.1"]);
+        // false is a value like any other for an option that is not a flag, and a flag takes no other value
+        assert_eq!(Config::parse("label = false\nipv6 = yes\n").unwrap().to_args(is_flag), vec!["--label=false", "--ipv6=yes"]);
+        assert_eq!(Config::parse("prefix-len 20").unwrap_err(), "expected 'option = value' on line 1");
+        assert!(Config::parse("\n--prefix-len = 20").unwrap_err().ends_with("line 2"));
+        assert_eq!(Config::parse(&config.render(&["the tunnel"])), Ok(config.clone()));
+        assert!(config.render(&["the tunnel"]).starts_with("\n# the tunnel\nrule-interface = wg1\nendpoint = vpn.example.net:51820\n"));
+    }
+
+    #[test]
+    fn test_deployment() {
+        let text = "prefix-len = 16\n\n[deployment home routes]\nbackend = nft\ninterface = wg0\n\n[deployment office routes]\ninterface = wg1\n\n\
+            [deployment home peer]  # the laptop\nwg-interface = wg0\npeer = abc=\n\n[deployment home file /etc/unbound/wgrouter.conf]\nformat = unbound\n";
+        assert_eq!(Config::parse(text), Ok(Config(vec![("prefix-len".to_string(), "16".to_string())])));
+        let home = deployment(text, "home").unwrap();
+        assert_eq!(home.iter().map(|step| &step.kind).collect::<Vec<_>>(),
+            vec![&StepKind::Routes, &StepKind::Peer, &StepKind::File("/etc/unbound/wgrouter.conf".to_string())]);
+        assert_eq!(home[0].options.to_args(|_| false), vec!["--backend=nft", "--interface=wg0"]);
+        assert_eq!(home[1].options.to_args(|_| false), vec!["--wg-interface=wg0", "--peer=abc="]);
+        assert_eq!(deployment(text, "office").unwrap().len(), 1);
+        assert_eq!(deployment(text, "cabin").unwrap_err(), "no [deployment cabin ...] sections");
+        assert_eq!(deployment("[deployment home file]\n", "home").unwrap_err(), "expected '[deployment NAME routes|peer|file PATH]' on line 1");
+        assert_eq!(deployment("[deployment home routes]\ninterface\n", "home").unwrap_err(), "expected 'option = value' on line 2");
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("wgrouter-config-{}", std::process::id()));
+        assert_eq!(Config::load(&path), Ok(None));
+        std::fs::write(&path, "prefix-len = 20\n").unwrap();
+        assert_eq!(Config::load(&path), Ok(Some(Config(vec![("prefix-len".to_string(), "20".to_string())]))));
+        std::fs::write(&path, "prefix-len\n").unwrap();
+        assert!(Config::load(&path).unwrap_err().contains("in config"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
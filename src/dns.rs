@@ -1,50 +1,150 @@
-use std::collections::HashSet;
-use har;
-use std::net::{IpAddr, Ipv4Addr};
-use futures;
+#[cfg(feature = "native")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "native")]
+use std::sync::OnceLock;
+#[cfg(feature = "native")]
+use hickory_resolver::TokioResolver;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use ipnetwork::IpNetwork;
 
 pub type StrResult<T> = Result<T, String>;
 
-pub fn hostnames_from_har(path: &str) -> StrResult<HashSet<String>> {
-    let har = har::from_path(path).map_err(|e| format!("could not parse HAR file {path}: {e}"))?;
-    match har.log {
-        har::Spec::V1_2(log) => {
-            let hosts = log.entries
-                .into_iter()
-                .try_fold(HashSet::new(), |mut acc, x| {
-                    let hostname = hostname_from_url(&x.request.url)
-                        .ok_or(format!("could not extract hostname from URL {}", &x.request.url))?;
-                    acc.insert(hostname);
-                    Ok::<HashSet<String>, String>(acc)
-                })?;
-            Ok(hosts)
-        },
-        har::Spec::V1_3(_log) => {
-            todo!()
+pub const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_DNS_RETRIES: u32 = 2;
+pub const DEFAULT_DNS_BACKOFF: Duration = Duration::from_secs(1);
+
+// how long a query may go unanswered, and how many times a lookup that timed out or failed on the way is tried again,
+// the first time after backoff and then waiting twice as long each time; a name that does not exist is not retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retry {
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self { timeout: DEFAULT_DNS_TIMEOUT, retries: DEFAULT_DNS_RETRIES, backoff: DEFAULT_DNS_BACKOFF }
+    }
+}
+
+// which servers the hosts are asked: the nameservers of each host's domain along with a few public resolvers, only the
+// given ones, or only the resolvers the system is configured with; both of the latter skip looking up nameservers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Servers {
+    #[default]
+    Discovered,
+    Given(Vec<IpAddr>),
+    System,
+}
+
+pub const DOT_PORT: u16 = 853;
+
+// a resolver the queries go to encrypted, instead of the nameservers of each host's domain: DNS-over-HTTPS at a URL,
+// or DNS-over-TLS at an address, whose certificate must be for the name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Upstream {
+    Https(String),
+    Tls { addr: SocketAddr, name: String },
+}
+
+impl Upstream {
+    // "1.1.1.1:853@cloudflare-dns.com", the port 853 when left out, IPv6 addresses in brackets
+    pub fn parse_tls(s: &str) -> StrResult<Self> {
+        let (addr, name) = s.split_once('@').ok_or(format!("bad DNS-over-TLS server '{s}': expected ADDRESS[:PORT]@NAME"))?;
+        let addr = match (addr.parse::<SocketAddr>(), addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()) {
+            (Ok(addr), _) => addr,
+            (_, Ok(ip)) => SocketAddr::new(ip, DOT_PORT),
+            _ => return Err(format!("bad DNS-over-TLS server '{s}': '{addr}' is not an IP address with an optional port")),
+        };
+        if name.is_empty() {
+            return Err(format!("bad DNS-over-TLS server '{s}': the name its certificate is for is missing after @"));
+        }
+        Ok(Self::Tls { addr, name: name.to_string() })
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Upstream::Https(url) => write!(f, "{url}"),
+            Upstream::Tls { addr, name } => write!(f, "{addr}@{name}"),
+        }
+    }
+}
+
+// the query until it is answered, fails for good, or runs out of retries; each attempt is cut off after the timeout,
+// whatever the resolver's own settings, so a nameserver that never answers cannot hold up the run
+#[cfg(feature = "native")]
+async fn retrying<T, F: std::future::Future<Output = Result<T, hickory_resolver::ResolveError>>>(retry: &Retry, what: &str, query: impl Fn() -> F)
+    -> StrResult<T> {
+    retrying_unless(retry, what, hickory_resolver::ResolveError::is_no_records_found, query).await
+}
+
+// the same for any query, given which of its errors are for good
+#[cfg(feature = "native")]
+pub(crate) async fn retrying_unless<T, E: std::fmt::Display, F: std::future::Future<Output = Result<T, E>>>(retry: &Retry, what: &str, final_error: impl Fn(&E) -> bool,
+    query: impl Fn() -> F) -> StrResult<T> {
+    let mut wait = retry.backoff;
+    let mut attempts = 1;
+    loop {
+        let e = match tokio::time::timeout(retry.timeout, query()).await {
+            Ok(Ok(answer)) => return Ok(answer),
+            Ok(Err(e)) if final_error(&e) => return Err(format!("{what}: {e}")),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("no answer within {:?}", retry.timeout),
+        };
+        if attempts > retry.retries {
+            return Err(if attempts > 1 { format!("{what}: {e} ({attempts} attempts)") } else { format!("{what}: {e}") });
         }
+        tokio::time::sleep(wait).await;
+        wait *= 2;
+        attempts += 1;
     }
 }
 
+// one runtime for all lookups, started on first use; the blocking functions below run the async ones on it, so it is
+// never built per call, and callers that are async already can await those directly
+#[cfg(feature = "native")]
+pub fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().expect("could not start the DNS runtime"))
+}
+
+// the resolver of the system, shared like the runtime so its cache is as well
+#[cfg(feature = "native")]
+fn system_resolver() -> StrResult<&'static TokioResolver> {
+    static RESOLVER: OnceLock<StrResult<TokioResolver>> = OnceLock::new();
+    RESOLVER
+        .get_or_init(|| {
+            let builder = TokioResolver::builder_tokio().map_err(|e| format!("could not read the system resolver config: {e}"))?;
+            Ok(builder.build())
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+#[cfg(feature = "native")]
 pub fn nameservers_from_host(host: &str) -> StrResult<HashSet<String>> {
-    let resolver = hickory_resolver::Resolver::builder_tokio().unwrap().build();
+    runtime().block_on(nameservers_from_host_async(host, &Retry::default()))
+}
+
+#[cfg(feature = "native")]
+pub async fn nameservers_from_host_async(host: &str, retry: &Retry) -> StrResult<HashSet<String>> {
+    let resolver = system_resolver()?;
     let domain_name = domain_from_host(host)?;
-    //println!("getting nameservers for host {host} and its domain name {domain_name}");
-    let lookup_ns_future  = resolver.ns_lookup(domain_name);
-    let io_loop = tokio::runtime::Runtime::new().unwrap();
-    let response = io_loop.block_on(lookup_ns_future).unwrap();
+    let response = retrying(retry, &format!("could not look up the nameservers of {domain_name}"), || resolver.ns_lookup(domain_name.as_str())).await?;
 
     let lookup_ip_futures = response.iter().map(|rsp| {
         let ns_hostname = rsp.to_string().trim_end_matches('.').to_string();
-        resolver.lookup_ip(ns_hostname)
+        async move { retrying(retry, &format!("could not lookup IPs of nameserver {ns_hostname}"), || resolver.lookup_ip(ns_hostname.as_str())).await }
     }).collect::<Vec<_>>();
-
-    let responses = io_loop.block_on(async{
-        futures::future::join_all(lookup_ip_futures).await
-    });
+    let responses = futures::future::join_all(lookup_ip_futures).await;
 
     let ns_ips = responses.into_iter()
         .try_fold(HashSet::new(), |mut acc, x| -> StrResult<HashSet<String>> {
-            let looked_up = x.map_err(|_| "could not lookup IPs of nameserver hostname".to_string())?;
+            let looked_up = x?;
             let ip = looked_up.iter().next().ok_or("empty IP list for nameserver hostname")?;
             acc.insert(ip.to_string());
             Ok(acc)
@@ -52,20 +152,56 @@ pub fn nameservers_from_host(host: &str) -> StrResult<HashSet<String>> {
     Ok(ns_ips)
 }
 
-pub fn resolve_host_multiple(host: &str, nameserver_ips: &HashSet<String>) -> StrResult<HashSet<String>> {
-    println!("resolving host {host} using nameservers {nameserver_ips:?}");
-    let global_dns = ["8.8.8.8", "1.1.1.1", "9.9.9.9"].into_iter().map(|ip_str| IpAddr::V4(ip_str.parse().unwrap()));
+#[cfg(feature = "native")]
+// A records only, or AAAA records as well with ipv6
+pub fn resolve_host_multiple(host: &str, nameserver_ips: &HashSet<String>, ipv6: bool) -> StrResult<HashSet<String>> {
+    runtime().block_on(resolve_host_multiple_async(host, nameserver_ips, ipv6, &Retry::default(), false))
+}
+
+// with dnssec, the answers are validated up from the root's keys, and a host whose addresses came back bogus, or could
+// not be proven either way, fails instead of resolving; those of unsigned zones are taken as they are
+#[cfg(feature = "native")]
+pub async fn resolve_host_multiple_async(host: &str, nameserver_ips: &HashSet<String>, ipv6: bool, retry: &Retry, dnssec: bool) -> StrResult<HashSet<String>> {
+    Ok(resolve_host_chain_async(host, nameserver_ips, ipv6, retry, dnssec).await?.0)
+}
 
+// the same, with the CNAME chain the answer followed from the host, its final target last; empty when the host has
+// addresses of its own
+#[cfg(feature = "native")]
+pub async fn resolve_host_chain_async(host: &str, nameserver_ips: &HashSet<String>, ipv6: bool, retry: &Retry, dnssec: bool)
+    -> StrResult<(HashSet<String>, Vec<String>)> {
+    eprintln!("resolving host {host} using nameservers {nameserver_ips:?}");
     let nameserver_addrs: Result<Vec<IpAddr>, String> = nameserver_ips
-        .into_iter()
-        .try_fold(Vec::from_iter(global_dns), |mut acc, x| {
-            let a = x.parse::<Ipv4Addr>().map_err(|e| format!("could not parse {x} as IPv4 addr: {e}"))?;
-            acc.push(IpAddr::V4(a));
+        .iter()
+        .try_fold(Vec::from_iter(global_dns()), |mut acc, x| {
+            let a = x.parse::<IpAddr>().map_err(|e| format!("could not parse {x} as IP addr: {e}"))?;
+            acc.push(a);
             Ok(acc)
         });
+    let resolver = resolver_of(&nameserver_addrs?, ipv6, retry, dnssec);
+    lookup(&resolver, host, &format!("cannot resolve host {host} with nameservers {nameserver_ips:?}"), retry, dnssec).await
+}
+
+// the addresses of the host and its CNAME chain, from the resolver
+#[cfg(feature = "native")]
+async fn lookup(resolver: &TokioResolver, host: &str, what: &str, retry: &Retry, dnssec: bool) -> StrResult<(HashSet<String>, Vec<String>)> {
+    let response = retrying(retry, what, || resolver.lookup_ip(host)).await?;
+    if dnssec {
+        validated(host, response.as_lookup().records())?;
+    }
+    let ips = response.iter().map(|rsp| rsp.to_string()).collect::<HashSet<_>>();
+    Ok((ips, cname_chain(host, response.as_lookup().records())))
+}
+
+#[cfg(feature = "native")]
+fn global_dns() -> impl Iterator<Item = IpAddr> {
+    ["8.8.8.8", "1.1.1.1", "9.9.9.9"].into_iter().map(|ip_str| IpAddr::V4(ip_str.parse().unwrap()))
+}
 
+#[cfg(feature = "native")]
+fn resolver_of(nameserver_addrs: &[IpAddr], ipv6: bool, retry: &Retry, dnssec: bool) -> TokioResolver {
     let server_group = hickory_resolver::config::NameServerConfigGroup::from_ips_clear(
-        nameserver_addrs?.as_slice(),
+        nameserver_addrs,
         53,
         true
     );
@@ -76,46 +212,188 @@ pub fn resolve_host_multiple(host: &str, nameserver_ips: &HashSet<String>) -> St
         server_group
     );
 
-    let resolver = hickory_resolver::Resolver::builder_with_config(
+    let builder = TokioResolver::builder_with_config(
         ns_config, 
-        hickory_resolver::name_server::TokioConnectionProvider::default()).build();
+        hickory_resolver::name_server::TokioConnectionProvider::default());
+    configured(builder, ipv6, retry, dnssec)
+}
 
-    let lookup_ip_future = resolver.lookup_ip(host);
-    let io_loop = tokio::runtime::Runtime::new().unwrap();
-    if let Ok(response) = io_loop.block_on(lookup_ip_future) {
-        Ok(response.iter().map(|rsp| rsp.to_string()).collect::<HashSet<_>>())
+// a resolver of the servers the system is configured with, set up like the others rather than shared
+#[cfg(feature = "native")]
+fn system_resolver_of(ipv6: bool, retry: &Retry, dnssec: bool) -> StrResult<TokioResolver> {
+    let builder = TokioResolver::builder_tokio().map_err(|e| format!("could not read the system resolver config: {e}"))?;
+    Ok(configured(builder, ipv6, retry, dnssec))
+}
+
+#[cfg(feature = "native")]
+fn configured(mut builder: hickory_resolver::ResolverBuilder<hickory_resolver::name_server::TokioConnectionProvider>, ipv6: bool, retry: &Retry, dnssec: bool)
+    -> TokioResolver {
+    builder.options_mut().ip_strategy = if ipv6 {
+        hickory_resolver::config::LookupIpStrategy::Ipv4AndIpv6
     } else {
-        println!("warning: cannot resolve host {host} with nameservers {nameserver_ips:?}");
-        Ok(HashSet::new())
+        hickory_resolver::config::LookupIpStrategy::Ipv4Only
+    };
+    // the retries are ours, with backoff, rather than the resolver's at once
+    builder.options_mut().timeout = retry.timeout;
+    builder.options_mut().attempts = 1;
+    builder.options_mut().validate = dnssec;
+    builder.build()
+}
+
+// the names a lookup of the host went through: each CNAME target in turn, starting from the host
+#[cfg(feature = "native")]
+fn cname_chain(host: &str, records: &[hickory_resolver::proto::rr::Record]) -> Vec<String> {
+    let plain = |name: &hickory_resolver::proto::rr::Name| name.to_ascii().trim_end_matches('.').to_ascii_lowercase();
+    let mut chain = Vec::<String>::new();
+    let mut name = host.trim_end_matches('.').to_ascii_lowercase();
+    // a looping chain ends once every record was followed
+    while chain.len() < records.len() {
+        let Some(target) = records.iter().filter(|r| plain(r.name()) == name).find_map(|r| r.data().as_cname().map(|cname| plain(&cname.0))) else {
+            break;
+        };
+        chain.push(target.clone());
+        name = target;
     }
+    chain
 }
 
-fn hostname_from_url(url: &str) -> Option<String> {
-    let stripped_suffix = url
-        .strip_prefix("https://")
-        .or(url.strip_prefix("http://"))
-        .or(url.strip_prefix("wss://"));
-    stripped_suffix.and_then(|s|s.split('/').next()).map(String::from)
+// the addresses of a CNAME target asked of every public resolver and every nameserver of its own domain separately,
+// or of each of the given servers: CDNs hand each a part of their addresses, so this covers more of what the host may
+// be answered with later; the system's resolvers are not asked again
+#[cfg(feature = "native")]
+pub async fn resolve_target_widely_async(target: &str, servers: &Servers, ipv6: bool, retry: &Retry, dnssec: bool) -> StrResult<HashSet<String>> {
+    let servers = match servers {
+        Servers::Discovered => {
+            let nameservers = nameservers_from_host_async(target, retry).await.unwrap_or_default();
+            global_dns().chain(nameservers.iter().filter_map(|ip| ip.parse().ok())).collect::<Vec<IpAddr>>()
+        }
+        Servers::Given(servers) => servers.clone(),
+        Servers::System => return Ok(HashSet::new()),
+    };
+    let lookups = servers.iter().map(|server| async move {
+        let resolver = resolver_of(&[*server], ipv6, retry, dnssec);
+        Ok::<_, String>(lookup(&resolver, target, &format!("cannot resolve {target} with {server}"), retry, dnssec).await?.0)
+    });
+    let answers = futures::future::join_all(lookups).await;
+    let ips = answers.iter().filter_map(|answer| answer.as_ref().ok()).flatten().cloned().collect::<HashSet<_>>();
+    match answers.into_iter().find_map(Result::err) {
+        Some(e) if ips.is_empty() => Err(e),
+        _ => Ok(ips),
+    }
 }
 
+// an error naming the first address record whose proof is neither secure nor of an unsigned zone
+#[cfg(feature = "native")]
+fn validated(host: &str, records: &[hickory_resolver::proto::rr::Record]) -> StrResult<()> {
+    use hickory_resolver::proto::dnssec::Proof;
+    match records.iter().filter(|r| r.data().ip_addr().is_some()).find(|r| !matches!(r.proof(), Proof::Secure | Proof::Insecure)) {
+        Some(record) => Err(format!("the answer for {host} failed DNSSEC validation: {} is {}", record.data(), record.proof())),
+        None => Ok(()),
+    }
+}
+
+// the nameservers of the host's domain, then the host through them
+#[cfg(feature = "native")]
+pub async fn resolve_host_async(host: &str, ipv6: bool, retry: &Retry, dnssec: bool) -> StrResult<HashSet<String>> {
+    Ok(resolve_host_chained_async(host, &Servers::Discovered, ipv6, retry, dnssec, false).await?.0)
+}
+
+// the same with the host's CNAME chain, asking the servers; with widely, the addresses of its final target are also
+// asked of every server that may answer differently, and a target that cannot be looked up that way leaves the host as
+// it was
+#[cfg(feature = "native")]
+pub async fn resolve_host_chained_async(host: &str, servers: &Servers, ipv6: bool, retry: &Retry, dnssec: bool, widely: bool)
+    -> StrResult<(HashSet<String>, Vec<String>)> {
+    let (mut ips, chain) = match servers {
+        Servers::Discovered => {
+            let nameservers = nameservers_from_host_async(host, retry).await?;
+            resolve_host_chain_async(host, &nameservers, ipv6, retry, dnssec).await?
+        }
+        Servers::Given(given) => {
+            eprintln!("resolving host {host} using nameservers {given:?}");
+            lookup(&resolver_of(given, ipv6, retry, dnssec), host, &format!("cannot resolve host {host} with nameservers {given:?}"), retry, dnssec).await?
+        }
+        Servers::System => {
+            eprintln!("resolving host {host} using the system resolver");
+            lookup(&system_resolver_of(ipv6, retry, dnssec)?, host, &format!("cannot resolve host {host} with the system resolver"), retry, dnssec).await?
+        }
+    };
+    if let Some(target) = chain.last().filter(|_| widely) {
+        match resolve_target_widely_async(target, servers, ipv6, retry, dnssec).await {
+            Ok(more) => ips.extend(more),
+            Err(e) => eprintln!("warning: {e}, routing only the addresses {host} resolved to"),
+        }
+    }
+    Ok((ips, chain))
+}
+
+#[cfg(feature = "native")]
+pub fn reverse_lookup_many(ips: &[IpAddr]) -> HashMap<IpAddr, String> {
+    let Ok(resolver) = system_resolver() else {
+        return HashMap::new();
+    };
+    let responses = runtime().block_on(futures::future::join_all(ips.iter().map(|ip| resolver.reverse_lookup(*ip))));
+    ips.iter()
+        .zip(responses)
+        .filter_map(|(ip, rsp)| {
+            let name = rsp.ok()?.iter().next()?.to_string();
+            Some((*ip, name.trim_end_matches('.').to_string()))
+        })
+        .collect()
+}
+
+#[cfg(feature = "native")]
 fn domain_from_host(h: &str) -> StrResult<String> {
-    let parts = h.split('.').rev().collect::<Vec<_>>();
-    if parts.iter().any(|s| s.is_empty()) {
+    if h.split('.').any(|s| s.is_empty()) {
         return Err(format!("too short component of hostname {h}"));
     }
-    let parts = parts.into_iter().take(2).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>();
-    if parts.len() < 2 {
-        Err(format!("too short hostname {h}"))
-    } else {
-        Ok(parts.join("."))
+    if !h.contains('.') {
+        return Err(format!("too short hostname {h}"));
+    }
+    crate::psl::registrable_domain(h).ok_or(format!("hostname {h} is a public suffix, not a domain"))
+}
+
+pub fn hostname_is_ip(s: &str) -> Option<IpAddr> {
+    s.parse::<IpAddr>().ok()
+}
+
+// a network given in place of a host, as host lists may do: "203.0.113.0/24"
+pub fn hostname_is_net(s: &str) -> Option<IpNetwork> {
+    s.contains('/').then(|| s.parse::<IpNetwork>().ok()).flatten()
+}
+
+// the ASCII (punycode) form DNS and the outputs need of a hostname with other characters, "пример.рф" becoming
+// "xn--e1afmkfd.xn--p1ai", with its port; ASCII names and names IDNA rejects are left as they are
+pub fn ascii_host(host: &str) -> String {
+    if host.is_ascii() {
+        return host.to_string();
+    }
+    let (name, port) = host.split_once(':').map_or((host, None), |(name, port)| (name, Some(port)));
+    match (idna::domain_to_ascii(name), port) {
+        (Ok(ascii), Some(port)) => format!("{ascii}:{port}"),
+        (Ok(ascii), None) => ascii,
+        (Err(_), _) => host.to_string(),
     }
 }
 
-pub fn hostname_is_ip(s: &str) -> Option<Ipv4Addr> {
-    s.parse::<Ipv4Addr>().ok()
+// the Unicode form of a hostname with punycode labels, to show next to it; none for any other
+pub fn unicode_host(host: &str) -> Option<String> {
+    let name = discard_port(host);
+    if !name.split('.').any(|label| label.starts_with("xn--")) {
+        return None;
+    }
+    let (unicode, result) = idna::domain_to_unicode(name);
+    (result.is_ok() && unicode != name).then(|| host.replacen(name, &unicode, 1))
 }
 
-pub fn discard_port<'a>(s: &'a str) -> &'a str {
+// IPv6 literals come either bare or in brackets, as in URLs: "[2001:db8::1]:443"
+pub fn discard_port(s: &str) -> &str {
+    if let Some(bracketed) = s.strip_prefix('[') {
+        return bracketed.split_once(']').map(|(ip, _after)| ip).unwrap_or(bracketed);
+    }
+    if s.parse::<Ipv6Addr>().is_ok() {
+        return s;
+    }
     s.split_once(':').map(|(before, _after)| before).unwrap_or(s)
 }
 
@@ -124,61 +402,127 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_hostname_from_url() {
-        assert_eq!(hostname_from_url("https://x.y"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("https://x.y/"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("http://x.y"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("http://x.y/"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("http://x.y/aksdh/akjsdh"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("http://x.y//s//h///?sdf=ass"), Some("x.y".to_string()));
-        assert_eq!(hostname_from_url("rtsp://x.y"), None);
-        assert_eq!(hostname_from_url("rtsp://x.y/"), None);
-        assert_eq!(hostname_from_url("x.y"), None);
-        assert_eq!(hostname_from_url("x.y/"), None);
+    fn test_idn() {
+        assert_eq!(ascii_host("пример.рф"), "xn--e1afmkfd.xn--p1ai");
+        assert_eq!(ascii_host("Bücher.example:8443"), "xn--bcher-kva.example:8443");
+        assert_eq!(ascii_host("i❤.ws"), "xn--i-7iq.ws");
+        assert_eq!(ascii_host("example.com"), "example.com");
+        assert_eq!(unicode_host("xn--e1afmkfd.xn--p1ai"), Some("пример.рф".to_string()));
+        assert_eq!(unicode_host("xn--bcher-kva.example:8443"), Some("bücher.example:8443".to_string()));
+        assert_eq!(unicode_host("example.com"), None);
+        assert_eq!(unicode_host("203.0.113.5"), None);
+    }
+
+    #[test]
+    fn test_parse_tls() {
+        assert_eq!(Upstream::parse_tls("1.1.1.1:853@cloudflare-dns.com"), Ok(Upstream::Tls { addr: "1.1.1.1:853".parse().unwrap(), name: "cloudflare-dns.com".to_string() }));
+        assert_eq!(Upstream::parse_tls("9.9.9.9@dns.quad9.net").unwrap().to_string(), "9.9.9.9:853@dns.quad9.net");
+        assert_eq!(Upstream::parse_tls("[2606:4700:4700::1111]@one.one.one.one").unwrap().to_string(), "[2606:4700:4700::1111]:853@one.one.one.one");
+        assert_eq!(Upstream::parse_tls("[2606:4700:4700::1111]:8853@one.one.one.one").unwrap().to_string(), "[2606:4700:4700::1111]:8853@one.one.one.one");
+        assert!(Upstream::parse_tls("1.1.1.1:853").unwrap_err().contains("@NAME"));
+        assert!(Upstream::parse_tls("dns.google@dns.google").unwrap_err().contains("not an IP address"));
+        assert!(Upstream::parse_tls("1.1.1.1@").unwrap_err().contains("missing"));
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_retrying() {
+        use hickory_resolver::ResolveError;
+        let retry = Retry { timeout: Duration::from_millis(50), retries: 2, backoff: Duration::from_millis(1) };
+        let tries = std::cell::Cell::new(0);
+        let refused = runtime().block_on(retrying(&retry, "lookup", || {
+            tries.set(tries.get() + 1);
+            async { Err::<(), _>(ResolveError::from("refused")) }
+        }));
+        assert_eq!((refused, tries.get()), (Err("lookup: refused (3 attempts)".to_string()), 3));
+        let second = runtime().block_on(retrying(&retry, "lookup", || {
+            tries.set(tries.get() + 1);
+            let n = tries.get();
+            async move { if n == 5 { Ok(n) } else { Err(ResolveError::from("refused")) } }
+        }));
+        assert_eq!(second, Ok(5));
+        let silent = Retry { retries: 0, ..retry };
+        assert_eq!(runtime().block_on(retrying(&silent, "lookup", std::future::pending::<Result<(), ResolveError>>)), Err("lookup: no answer within 50ms".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_validated() {
+        use hickory_resolver::proto::dnssec::Proof;
+        use hickory_resolver::proto::rr::{Name, RData, Record};
+        use hickory_resolver::proto::rr::rdata::{A, CNAME};
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let record = |data, proof| {
+            let mut record = Record::from_rdata(name.clone(), 300, data);
+            record.set_proof(proof);
+            record
+        };
+        let answer = |proof| vec![
+            record(RData::CNAME(CNAME(Name::from_ascii("example.com.").unwrap())), Proof::Bogus),
+            record(RData::A(A::new(93, 184, 216, 34)), Proof::Secure),
+            record(RData::A(A::new(93, 184, 216, 35)), proof),
+        ];
+        assert_eq!(validated("www.example.com", &answer(Proof::Secure)), Ok(()));
+        assert_eq!(validated("www.example.com", &answer(Proof::Insecure)), Ok(()));
+        assert_eq!(validated("www.example.com", &answer(Proof::Bogus)), Err("the answer for www.example.com failed DNSSEC validation: 93.184.216.35 is Bogus".to_string()));
+        assert!(validated("www.example.com", &answer(Proof::Indeterminate)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_cname_chain() {
+        use hickory_resolver::proto::rr::{Name, RData, Record};
+        use hickory_resolver::proto::rr::rdata::{A, CNAME};
+        let cname = |from: &str, to: &str| Record::from_rdata(Name::from_ascii(from).unwrap(), 300, RData::CNAME(CNAME(Name::from_ascii(to).unwrap())));
+        let records = [
+            cname("www.Example.com.", "www.example.com.edgekey.net."),
+            cname("www.example.com.edgekey.net.", "e1234.a.akamaiedge.net."),
+            Record::from_rdata(Name::from_ascii("e1234.a.akamaiedge.net.").unwrap(), 20, RData::A(A::new(23, 45, 67, 89))),
+        ];
+        assert_eq!(cname_chain("www.example.com", &records), vec!["www.example.com.edgekey.net", "e1234.a.akamaiedge.net"]);
+        assert_eq!(cname_chain("e1234.a.akamaiedge.net", &records), Vec::<String>::new());
+        let looping = [cname("a.example.com.", "b.example.com."), cname("b.example.com.", "a.example.com.")];
+        assert_eq!(cname_chain("a.example.com", &looping), vec!["b.example.com", "a.example.com"]);
     }
 
     #[test]
-    fn test_hostnames_from_har() {
-        let may_be_entries = std::fs::read_dir(format!("{}/tests/private/", env!("CARGO_MANIFEST_DIR")))
-            .into_iter()
-            .flatten();
-        std::fs::read_dir(format!("{}/tests/", env!("CARGO_MANIFEST_DIR")))
-            .unwrap()
-            .into_iter()
-            .chain(may_be_entries)
-            .for_each(|path| {
-                let path = path.unwrap().path();
-                if path.is_file() && path.extension().is_some_and(|ext| ext.to_str().unwrap() == "har") {
-                    let path = path.to_str().unwrap();
-                    let hostnames = hostnames_from_har(path).unwrap();
-                    println!("{path} yields hostnames ({}): {hostnames:?}", hostnames.len());
-                    assert!(hostnames.len() >= 3);
-                }
-            });
+    #[cfg(feature = "native")]
+    fn test_given_servers() {
+        // nothing listens on port 53 here, and the nameservers of the domain are not looked up first
+        let retry = Retry { timeout: Duration::from_millis(500), retries: 0, backoff: Duration::ZERO };
+        let servers = Servers::Given(vec!["127.0.0.1".parse().unwrap()]);
+        let e = runtime().block_on(resolve_host_chained_async("example.com", &servers, false, &retry, false, false)).unwrap_err();
+        assert!(e.starts_with("cannot resolve host example.com with nameservers [127.0.0.1]"), "{e}");
+        assert_eq!(runtime().block_on(resolve_target_widely_async("example.com", &Servers::System, false, &retry, false)), Ok(HashSet::new()));
     }
 
     #[test]
+    #[cfg(feature = "native")]
     fn test_resolve_multiple1() {
         let ips = resolve_host_multiple(
             "asus.com", 
-            &HashSet::from(["8.8.8.8".into(), "1.1.1.1".into()])).unwrap();
+            &HashSet::from(["8.8.8.8".into(), "1.1.1.1".into()]), false).unwrap();
         println!("asus.com => {ips:?}");
         assert!(!ips.is_empty());
     }
 
     #[test]
+    #[cfg(feature = "native")]
     fn test_resolve_multiple2() {
         let ips = resolve_host_multiple(
             "amazon.com", 
-            &HashSet::from(["156.154.150.1".into(), "156.154.64.10".into()])).unwrap();
+            &HashSet::from(["156.154.150.1".into(), "156.154.64.10".into()]), true).unwrap();
         println!("amazon.com => {ips:?}");
         assert!(!ips.is_empty());
     }
 
     #[test]
+    #[cfg(feature = "native")]
     fn test_domain_from_host() {
         assert_eq!(domain_from_host("x.y"), Ok("x.y".to_string()));
         assert_eq!(domain_from_host("x.y.z"), Ok("y.z".to_string()));
+        assert_eq!(domain_from_host("shop.example.co.uk"), Ok("example.co.uk".to_string()));
+        assert_eq!(domain_from_host("co.uk"), Err("hostname co.uk is a public suffix, not a domain".to_string()));
         assert!(domain_from_host("").is_err());
         assert!(domain_from_host("x").is_err());
         assert!(domain_from_host("x..").is_err());
@@ -191,6 +535,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "native")]
     fn test_nameservers() {
         let nss = nameservers_from_host("amazon.com").unwrap();
         println!("amazon's webservers: {nss:?}");
@@ -200,6 +545,7 @@ mod tests {
     fn test_url_is_ip() {
         assert!(hostname_is_ip("10.1.2.3").is_some());
         assert!(hostname_is_ip("a.b.c.d").is_none());
+        assert!(hostname_is_ip("2001:db8::1").is_some());
     }
 
     #[test]
@@ -207,5 +553,8 @@ mod tests {
         assert_eq!(discard_port(""), "");
         assert_eq!(discard_port("noport"), "noport");
         assert_eq!(discard_port("a.b.c:4443"), "a.b.c");
+        assert_eq!(discard_port("2001:db8::1"), "2001:db8::1");
+        assert_eq!(discard_port("[2001:db8::1]:8443"), "2001:db8::1");
+        assert_eq!(discard_port("[2001:db8::1]"), "2001:db8::1");
     }
 }
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use hickory_resolver::proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_resolver::proto::rr::{Name, RData, RecordType};
+use crate::dns::{retrying_unless, Retry, StrResult};
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+// a DNS query in wire format, as DNS-over-HTTPS (RFC 8484) sends it; its id is 0 so the answers can be cached by HTTP
+pub fn query(host: &str, record_type: RecordType) -> StrResult<Vec<u8>> {
+    let name = Name::from_ascii(host).map_err(|e| format!("bad hostname {host}: {e}"))?;
+    let mut message = Message::new();
+    message.set_id(0).set_message_type(MessageType::Query).set_op_code(OpCode::Query).set_recursion_desired(true);
+    message.add_query(Query::query(name, record_type));
+    message.to_vec().map_err(|e| format!("could not encode the query for {host}: {e}"))
+}
+
+// the addresses a response answers with; a name that does not exist has none, a server failure is an error
+pub fn addresses(response: &[u8]) -> StrResult<HashSet<String>> {
+    let message = Message::from_vec(response).map_err(|e| format!("could not parse the DNS response: {e}"))?;
+    match message.response_code() {
+        ResponseCode::NoError | ResponseCode::NXDomain => {}
+        code => return Err(format!("the DNS server answered {code}")),
+    }
+    Ok(message
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            RData::A(a) => Some(a.0.to_string()),
+            RData::AAAA(aaaa) => Some(aaaa.0.to_string()),
+            _ => None,
+        })
+        .collect())
+}
+
+// one query POSTed to the server, blocking
+fn exchange(url: &str, body: &[u8], timeout: Duration) -> StrResult<HashSet<String>> {
+    let agent = ureq::Agent::new_with_config(ureq::Agent::config_builder().timeout_global(Some(timeout)).build());
+    let mut response = agent
+        .post(url)
+        .header("Content-Type", DNS_MESSAGE)
+        .header("Accept", DNS_MESSAGE)
+        .send(body)
+        .map_err(|e| format!("request failed: {e}"))?;
+    let response = response.body_mut().read_to_vec().map_err(|e| format!("could not read the answer: {e}"))?;
+    addresses(&response)
+}
+
+// the A records of the host, and the AAAA ones with ipv6, from the server at url; unlike plain DNS, the local network
+// sees neither the names asked for nor the answers, so it cannot change them
+pub async fn resolve_host_async(url: &str, host: &str, ipv6: bool, retry: &Retry) -> StrResult<HashSet<String>> {
+    let owned = url.to_string();
+    resolve_with(url, host, ipv6, retry, move |body, timeout| exchange(&owned, &body, timeout)).await
+}
+
+// the same with any blocking exchange of a query for the addresses of its answer, run off the runtime's threads
+pub(crate) async fn resolve_with(server: &str, host: &str, ipv6: bool, retry: &Retry,
+    exchange: impl Fn(Vec<u8>, Duration) -> StrResult<HashSet<String>> + Clone + Send + 'static) -> StrResult<HashSet<String>> {
+    let record_types = if ipv6 { vec![RecordType::A, RecordType::AAAA] } else { vec![RecordType::A] };
+    let mut ips = HashSet::new();
+    for record_type in record_types {
+        let body = query(host, record_type)?;
+        let what = format!("cannot resolve host {host} with {server}");
+        ips.extend(retrying_unless(retry, &what, |_: &String| false, || {
+            let (exchange, body, timeout) = (exchange.clone(), body.clone(), retry.timeout);
+            async move { tokio::task::spawn_blocking(move || exchange(body, timeout)).await.map_err(|e| e.to_string())? }
+        }).await?);
+    }
+    if ips.is_empty() {
+        return Err(format!("no address found for {host} with {server}"));
+    }
+    Ok(ips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::rr::Record;
+    use hickory_resolver::proto::rr::rdata::{A, AAAA, CNAME};
+
+    #[test]
+    fn test_query() {
+        let message = Message::from_vec(&query("example.com", RecordType::AAAA).unwrap()).unwrap();
+        assert_eq!(message.id(), 0);
+        assert!(message.recursion_desired());
+        assert_eq!(message.queries()[0].query_type(), RecordType::AAAA);
+        assert_eq!(message.queries()[0].name().to_ascii(), "example.com.");
+
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let mut response = Message::new();
+        response.set_message_type(MessageType::Response).add_answers([
+            Record::from_rdata(name.clone(), 300, RData::CNAME(CNAME(Name::from_ascii("example.com.").unwrap()))),
+            Record::from_rdata(name.clone(), 300, RData::A(A::new(93, 184, 216, 34))),
+            Record::from_rdata(name, 300, RData::AAAA(AAAA::new(0x2606, 0x2800, 0x220, 1, 0x248, 0x1893, 0x25c8, 0x1946))),
+        ]);
+        assert_eq!(addresses(&response.to_vec().unwrap()), Ok(HashSet::from(["93.184.216.34".to_string(), "2606:2800:220:1:248:1893:25c8:1946".into()])));
+        response.set_response_code(ResponseCode::ServFail);
+        assert_eq!(addresses(&response.to_vec().unwrap()), Err("the DNS server answered Server Failure".to_string()));
+        assert!(addresses(b"not dns").is_err());
+    }
+}
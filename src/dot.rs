@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use crate::dns::{Retry, StrResult};
+use crate::doh::{addresses, resolve_with};
+
+// the roots browsers trust, built once
+fn tls_config() -> StrResult<Arc<rustls::ClientConfig>> {
+    static CONFIG: OnceLock<StrResult<Arc<rustls::ClientConfig>>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|e| format!("could not set up TLS: {e}"))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Ok(Arc::new(config))
+        })
+        .clone()
+}
+
+// the message prefixed with its length, as DNS over TCP and TLS (RFC 7858) frame it
+pub fn framed(message: &[u8]) -> StrResult<Vec<u8>> {
+    let len = u16::try_from(message.len()).map_err(|_| format!("a DNS message of {} bytes is too long", message.len()))?;
+    Ok([&len.to_be_bytes()[..], message].concat())
+}
+
+// one query over a connection of its own, blocking
+fn exchange(addr: SocketAddr, name: &str, body: &[u8], timeout: Duration) -> StrResult<HashSet<String>> {
+    let server_name = rustls::pki_types::ServerName::try_from(name.to_string()).map_err(|e| format!("bad server name {name}: {e}"))?;
+    let connection = rustls::ClientConnection::new(tls_config()?, server_name).map_err(|e| format!("could not set up TLS: {e}"))?;
+    let socket = TcpStream::connect_timeout(&addr, timeout).map_err(|e| format!("could not connect: {e}"))?;
+    socket.set_read_timeout(Some(timeout)).and(socket.set_write_timeout(Some(timeout))).map_err(|e| format!("could not set a timeout: {e}"))?;
+    let mut tls = rustls::StreamOwned::new(connection, socket);
+    tls.write_all(&framed(body)?).and_then(|_| tls.flush()).map_err(|e| format!("could not send the query: {e}"))?;
+    let mut len = [0u8; 2];
+    tls.read_exact(&mut len).map_err(|e| format!("could not read the answer: {e}"))?;
+    let mut response = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    tls.read_exact(&mut response).map_err(|e| format!("could not read the answer: {e}"))?;
+    addresses(&response)
+}
+
+// the A records of the host, and the AAAA ones with ipv6, from the DNS-over-TLS server at addr, whose certificate must
+// be for name
+pub async fn resolve_host_async(addr: SocketAddr, name: &str, host: &str, ipv6: bool, retry: &Retry) -> StrResult<HashSet<String>> {
+    let owned = name.to_string();
+    resolve_with(&format!("{addr}@{name}"), host, ipv6, retry, move |body, timeout| exchange(addr, &owned, &body, timeout)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framed() {
+        assert_eq!(framed(&[0xab; 3]), Ok(vec![0, 3, 0xab, 0xab, 0xab]));
+        assert_eq!(framed(&vec![0; 300]).unwrap()[..2], [1, 44]);
+        assert!(framed(&vec![0; 70000]).is_err());
+        assert!(tls_config().is_ok());
+    }
+}
@@ -0,0 +1,118 @@
+use ipnetwork::IpNetwork;
+use crate::dns::{ascii_host, discard_port, hostname_is_ip, hostname_is_net, StrResult};
+use crate::guard::Guard;
+
+// hosts and networks never to be routed, whatever the inputs hold: hosts matching a pattern are dropped before they
+// are looked up, and the networks are cut out of the routed ones
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exclusions {
+    pub hosts: Vec<String>,
+    pub nets: Vec<IpNetwork>,
+}
+
+// an address or a network
+pub fn parse_net(s: &str) -> StrResult<IpNetwork> {
+    let s = s.trim();
+    hostname_is_net(s).or(hostname_is_ip(s).map(IpNetwork::from)).ok_or(format!("bad network '{s}': expected an address or a CIDR such as 10.0.0.0/8"))
+}
+
+// a host with * for any run of characters, dots included, and ? for any single one; in punycode and lower case like
+// the hosts it is matched with
+pub fn host_pattern(s: &str) -> StrResult<String> {
+    let pattern = ascii_host(s.trim().trim_end_matches('.')).to_ascii_lowercase();
+    if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+        return Err(format!("bad host pattern '{s}': expected a host such as bank.example or *.bank.example"));
+    }
+    Ok(pattern)
+}
+
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    // the last * seen and where in the text it was last tried to end, to go back to when the rest does not match
+    let (mut p, mut t, mut star) = (0, 0, None);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+impl Exclusions {
+    // lines of host patterns, addresses and networks, # starting a comment
+    pub fn parse(text: &str) -> StrResult<Self> {
+        let mut exclusions = Self::default();
+        text.lines().map(|line| line.split('#').next().unwrap_or_default().trim()).filter(|line| !line.is_empty()).try_for_each(|line| {
+            match parse_net(line) {
+                Ok(net) => exclusions.nets.push(net),
+                Err(_) => exclusions.hosts.push(host_pattern(line)?),
+            }
+            Ok::<_, String>(())
+        })?;
+        Ok(exclusions)
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        self.hosts.extend(other.hosts);
+        self.nets.extend(other.nets);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.nets.is_empty()
+    }
+
+    // the first pattern the host matches, without its port and in any case
+    pub fn excluding(&self, host: &str) -> Option<&str> {
+        let host = discard_port(host).trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.iter().find(|pattern| glob(pattern.as_bytes(), host.as_bytes())).map(String::as_str)
+    }
+
+    // the networks to cut out of the routed ones, as the endpoint guard cuts out its own
+    pub fn guard(&self) -> Guard {
+        let mut guard = Guard::default();
+        self.nets.iter().for_each(|net| guard.add_network(*net, "excluded network".to_string()));
+        guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let exclusions = Exclusions::parse("# never through the VPN\n*.bank.example  # online banking\nbank.example\nLOGIN?.Corp.example.\n\
+            10.0.0.0/8\n198.51.100.7\n*.пример.рф\n").unwrap();
+        assert_eq!(exclusions.hosts, vec!["*.bank.example", "bank.example", "login?.corp.example", "*.xn--e1afmkfd.xn--p1ai"]);
+        assert_eq!(exclusions.nets, vec!["10.0.0.0/8".parse::<IpNetwork>().unwrap(), "198.51.100.7/32".parse().unwrap()]);
+        assert_eq!(exclusions.excluding("www.bank.example:443"), Some("*.bank.example"));
+        assert_eq!(exclusions.excluding("a.b.bank.example"), Some("*.bank.example"));
+        assert_eq!(exclusions.excluding("Bank.Example."), Some("bank.example"));
+        assert_eq!(exclusions.excluding("login2.corp.example"), Some("login?.corp.example"));
+        assert_eq!(exclusions.excluding("login.corp.example"), None);
+        assert_eq!(exclusions.excluding("notbank.example"), None);
+        assert_eq!(exclusions.excluding("shop.xn--e1afmkfd.xn--p1ai"), Some("*.xn--e1afmkfd.xn--p1ai"));
+        assert!(glob(b"*a*b*", b"xxaxxbxx") && glob(b"**", b"") && !glob(b"a*b", b"ab.c"));
+
+        let (nets, warnings) = exclusions.guard().apply(&["10.1.0.0/16".to_string(), "93.184.0.0/16".into()]).unwrap();
+        assert_eq!(nets, vec!["93.184.0.0/16"]);
+        assert_eq!(warnings, vec!["excluded network (10.0.0.0/8) overlaps routed network 10.1.0.0/16, routing around it"]);
+
+        assert!(Exclusions::parse("bank example").is_err());
+        assert!(parse_net("10.0.0.0/33").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use ipnetwork::IpNetwork;
+use serde_json::Value;
+use crate::dns::StrResult;
+use crate::wgconf;
+
+// what a generated file routes where: the kind of file, and where the traffic goes with the networks sent there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub kind: &'static str,
+    pub routes: Vec<(String, Vec<String>)>,
+}
+
+fn push(routes: &mut Vec<(String, Vec<String>)>, to: String, nets: impl IntoIterator<Item = String>) {
+    match routes.iter_mut().find(|(have, _)| *have == to) {
+        Some((_, have)) => have.extend(nets),
+        None => routes.push((to, nets.into_iter().collect())),
+    }
+}
+
+fn wg_conf(text: &str) -> Option<Explanation> {
+    let peers = wgconf::peers(text);
+    if peers.is_empty() {
+        // the bare line of --format allowed-ips
+        let line = text.lines().map(str::trim).find(|line| line.split('=').next().is_some_and(|key| key.trim().eq_ignore_ascii_case("AllowedIPs")))?;
+        let nets = wgconf::allowed_ips(line);
+        return Some(Explanation { kind: "WireGuard AllowedIPs line", routes: vec![("into the tunnel, to the peer this line goes to".to_string(), nets)] });
+    }
+    let routes = peers.into_iter().map(|peer| {
+        let endpoint = peer.endpoint.map(|endpoint| format!(" at {endpoint}")).unwrap_or_default();
+        (format!("into the tunnel, to peer {}{endpoint}", peer.public_key), peer.allowed_ips)
+    }).collect();
+    Some(Explanation { kind: "WireGuard config", routes })
+}
+
+// "a, b, c }" up to the closing brace
+fn elements(text: &str) -> Vec<String> {
+    text.split('}').next().unwrap_or_default().split(',').map(str::trim).filter(|net| !net.is_empty()).map(String::from).collect()
+}
+
+fn nft_sets(text: &str) -> Option<Explanation> {
+    let mut routes = Vec::new();
+    let (mut table, mut set) = (String::new(), String::new());
+    let to = |table: &str, set: &str| format!("into set {set} of table {table}, wherever the rules matching @{set} send it");
+    text.lines().map(str::trim).for_each(|line| {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["table", family, name, "{"] => table = format!("{family} {name}"),
+            ["set", name, "{"] => set = name.to_string(),
+            ["add", "element", family, name, set, "{", ..] => {
+                let nets = line.split_once('{').map(|(_, rest)| elements(rest)).unwrap_or_default();
+                push(&mut routes, to(&format!("{family} {name}"), set), nets);
+            }
+            ["elements", "=", "{", ..] => {
+                let nets = line.split_once('{').map(|(_, rest)| elements(rest)).unwrap_or_default();
+                push(&mut routes, to(&table, &set), nets);
+            }
+            _ => {}
+        }
+    });
+    (!routes.is_empty() || !set.is_empty()).then_some(Explanation { kind: "nftables sets", routes })
+}
+
+fn ip_route_script(text: &str) -> Option<Explanation> {
+    let mut routes = Vec::new();
+    let mut teardown = false;
+    text.lines().for_each(|line| {
+        let words = line.split_whitespace().map(|word| word.trim_matches('\'')).collect::<Vec<_>>();
+        let words = words.strip_prefix(&["ip", "-6"][..]).or_else(|| words.strip_prefix(&["ip"][..])).unwrap_or_default();
+        if let ["route", verb @ ("add" | "replace" | "del"), net, rest @ ..] = words {
+            teardown |= *verb == "del";
+            let dev = rest.iter().skip_while(|word| **word != "dev").nth(1);
+            let table = rest.iter().skip_while(|word| **word != "table").nth(1).map(|table| format!(" in table {table}")).unwrap_or_default();
+            let to = match (*verb, dev) {
+                ("del", Some(dev)) => format!("no longer via {dev}{table}"),
+                (_, Some(dev)) => format!("via {dev}{table}"),
+                (_, None) => format!("somewhere{table}"),
+            };
+            push(&mut routes, to, [net.to_string()]);
+        }
+    });
+    (!routes.is_empty()).then_some(Explanation { kind: if teardown { "ip route teardown script" } else { "ip route script" }, routes })
+}
+
+pub fn explain(text: &str) -> StrResult<Explanation> {
+    wg_conf(text)
+        .or_else(|| ip_route_script(text))
+        .or_else(|| nft_sets(text))
+        .ok_or("not a WireGuard config or AllowedIPs line, nftables set or ip route script".to_string())
+}
+
+// network -> the hosts it is there for, as the latest "aggregated" record of each network in an audit log says
+pub fn provenance(audit_log: &str) -> BTreeMap<String, Vec<String>> {
+    audit_log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|record| record["event"] == "aggregated")
+        .filter_map(|record| {
+            let hosts = record["hosts"].as_array()?.iter().filter_map(|host| host.as_str().map(String::from)).collect();
+            Some((record["network"].as_str()?.to_string(), hosts))
+        })
+        .collect()
+}
+
+// IPv6 ones would dwarf them all
+fn ipv4_addresses(nets: &[String]) -> u64 {
+    nets.iter().filter_map(|net| net.parse::<IpNetwork>().ok()).filter(IpNetwork::is_ipv4).map(|net| 1u64 << (32 - net.prefix())).sum()
+}
+
+// for people: each destination with its networks, and the hosts each network is there for when the provenance knows
+pub fn render(explanation: &Explanation, provenance: Option<&BTreeMap<String, Vec<String>>>) -> String {
+    let mut out = format!("{}, routing {} network(s):\n", explanation.kind, explanation.routes.iter().map(|(_, nets)| nets.len()).sum::<usize>());
+    explanation.routes.iter().for_each(|(to, nets)| {
+        let ipv4 = match ipv4_addresses(nets) {
+            0 => String::new(),
+            n => format!(" ({n} IPv4 addresses)"),
+        };
+        out.push_str(&format!("\ntraffic to these {} network(s){ipv4} goes {to}:\n", nets.len()));
+        nets.iter().for_each(|net| match provenance.map(|provenance| provenance.get(net)) {
+            None => out.push_str(&format!("  {net}\n")),
+            Some(Some(hosts)) if !hosts.is_empty() => out.push_str(&format!("  {net}  for {}\n", hosts.join(", "))),
+            Some(_) => out.push_str(&format!("  {net}  not generated from any host in the audit log\n")),
+        });
+    });
+    out.push_str("\nall other traffic goes the way it would without this file\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{self, Report};
+
+    fn report() -> Report {
+        Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() }
+    }
+
+    #[test]
+    fn test_explain() {
+        let wg = explain("[Interface]\nPrivateKey = x\n[Peer]\nPublicKey = dnBu\nEndpoint = vpn.example.net:51820\nAllowedIPs = 10.8.0.0/24, 1.1.0.0/16\n").unwrap();
+        assert_eq!(wg.routes, vec![("into the tunnel, to peer dnBu at vpn.example.net:51820".to_string(), vec!["10.8.0.0/24".to_string(), "1.1.0.0/16".into()])]);
+        assert_eq!(explain("AllowedIPs = 93.184.0.0/16, 2606:2800:220::/48").unwrap().routes[0].1, report().networks);
+
+        let script = explain(&output::ip_route_script(&report(), "wg0", 100, false)).unwrap();
+        assert_eq!(script, Explanation { kind: "ip route script", routes: vec![("via wg0 in table 100".to_string(), report().networks)] });
+        assert_eq!(explain(&output::ip_route_script(&report(), "wg1", 7, true)).unwrap().routes[0].0, "no longer via wg1 in table 7");
+
+        let sets = explain(&output::nft_sets(&report(), "wgrouter", "vpn", false)).unwrap();
+        assert_eq!(sets.routes, vec![
+            ("into set vpn of table inet wgrouter, wherever the rules matching @vpn send it".to_string(), vec!["93.184.0.0/16".to_string()]),
+            ("into set vpn_v6 of table inet wgrouter, wherever the rules matching @vpn_v6 send it".to_string(), vec!["2606:2800:220::/48".to_string()]),
+        ]);
+        assert_eq!(explain(&output::nft_sets(&report(), "wgrouter", "vpn", true)).unwrap().routes, sets.routes);
+        assert!(explain("hello").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let log = "{\"event\":\"resolved\",\"host\":\"a\"}\n{\"event\":\"aggregated\",\"network\":\"93.184.0.0/16\",\"hosts\":[\"old.example\"]}\n\
+            {\"event\":\"aggregated\",\"network\":\"93.184.0.0/16\",\"hosts\":[\"example.com\",\"www.example.com\"]}\n";
+        let provenance = provenance(log);
+        assert_eq!(provenance["93.184.0.0/16"], vec!["example.com", "www.example.com"]);
+        let explanation = Explanation { kind: "ip route script", routes: vec![("via wg0 in table 100".to_string(), report().networks)] };
+        assert_eq!(render(&explanation, Some(&provenance)), "ip route script, routing 2 network(s):
+
+traffic to these 2 network(s) (65536 IPv4 addresses) goes via wg0 in table 100:
+  93.184.0.0/16  for example.com, www.example.com
+  2606:2800:220::/48  not generated from any host in the audit log
+
+all other traffic goes the way it would without this file
+");
+        assert!(render(&explanation, None).contains("\n  2606:2800:220::/48\n"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::process::Command;
+use crate::apply::{self, Backend};
+use crate::dns::StrResult;
+use crate::history::Transition;
+
+// WireGuard gives up on a session after 180 seconds without a new handshake (REJECT_AFTER_TIME)
+pub const DEFAULT_DEAD_AFTER: u64 = 180;
+
+// what happens to the routes while the tunnel is dead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    // remove them, traffic goes the direct way until the tunnel is back
+    Withdraw,
+    // turn them into blackhole routes, so nothing leaks out the direct way, also when the interface goes away
+    Blackhole,
+    // hand the networks to another peer of the same interface
+    BackupPeer,
+}
+
+// the tunnel is alive while the peer (or, without one, any peer) has had a handshake within dead_after seconds and,
+// if there is a ping address, that answers through the interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liveness {
+    pub interface: String,
+    pub peer: Option<String>,
+    pub dead_after: u64,
+    pub ping: Option<IpAddr>,
+}
+
+fn run(program: &str, args: &[&str]) -> StrResult<String> {
+    let output = Command::new(program).args(args).output().map_err(|e| format!("could not run {program}: {e}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!("'{program} {}' failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+// "pubkey<TAB>value" lines of wg show <iface> latest-handshakes or allowed-ips
+fn by_peer(text: &str) -> HashMap<String, &str> {
+    text.lines().filter_map(|line| line.split_once('\t')).map(|(peer, value)| (peer.to_string(), value.trim())).collect()
+}
+
+// peer -> Unix time of its latest handshake, 0 for never
+pub fn latest_handshakes(text: &str) -> HashMap<String, u64> {
+    by_peer(text).into_iter().map(|(peer, time)| (peer, time.parse().unwrap_or(0))).collect()
+}
+
+// peer -> its allowed IPs ("(none)" for none)
+pub fn allowed_ips(text: &str) -> HashMap<String, Vec<String>> {
+    by_peer(text).into_iter().map(|(peer, ips)| (peer, ips.split_whitespace().filter(|ip| *ip != "(none)").map(String::from).collect())).collect()
+}
+
+impl Liveness {
+    // why the peer (or the newest of all) counts as dead, judging by the handshakes only
+    pub fn stale_handshake(&self, handshakes: &HashMap<String, u64>, peer: Option<&str>, now: u64) -> Option<String> {
+        let latest = match peer {
+            Some(peer) => handshakes.get(peer).copied(),
+            None => handshakes.values().max().copied(),
+        };
+        match latest {
+            None => Some(format!("no peer {} on {}", peer.unwrap_or("at all"), self.interface)),
+            Some(0) => Some(format!("no handshake on {} yet", self.interface)),
+            Some(time) if now.saturating_sub(time) > self.dead_after => {
+                Some(format!("last handshake on {} was {} seconds ago", self.interface, now.saturating_sub(time)))
+            }
+            Some(_) => None,
+        }
+    }
+
+    pub fn handshakes(&self) -> StrResult<HashMap<String, u64>> {
+        run("wg", &["show", &self.interface, "latest-handshakes"]).map(|text| latest_handshakes(&text))
+    }
+
+    // why the ping address does not answer, if there is one
+    pub fn unanswered_ping(&self) -> Option<String> {
+        let ping = self.ping?.to_string();
+        run("ping", &["-c", "1", "-W", "3", "-I", &self.interface, &ping]).err().map(|_| format!("{ping} does not answer through {}", self.interface))
+    }
+
+    // none when alive; an interface that cannot be asked about is dead as well
+    pub fn check(&self, now: u64) -> Option<String> {
+        let handshakes = match self.handshakes() {
+            Ok(handshakes) => handshakes,
+            Err(e) => return Some(e),
+        };
+        self.stale_handshake(&handshakes, self.peer.as_deref(), now).or_else(|| self.unanswered_ping())
+    }
+}
+
+pub fn blackhole_commands(nets: &[String], table: Option<u32>) -> Vec<Vec<String>> {
+    nets.iter()
+        .map(|net| {
+            let mut cmd = ["route", "replace", "blackhole", net].map(String::from).to_vec();
+            if let Some(table) = table {
+                cmd.extend(["table".to_string(), table.to_string()]);
+            }
+            cmd
+        })
+        .collect()
+}
+
+// the allowed IPs of both peers after moving nets from one to the other; the peers' own entries stay where they are
+pub fn moved_allowed_ips(current: &HashMap<String, Vec<String>>, from: &str, to: &str, nets: &[String]) -> (Vec<String>, Vec<String>) {
+    let nets = nets.iter().collect::<BTreeSet<_>>();
+    let from_ips = current.get(from).into_iter().flatten().filter(|ip| !nets.contains(ip)).cloned().collect();
+    let mut to_ips = current.get(to).cloned().unwrap_or_default();
+    nets.into_iter().for_each(|net| {
+        if !to_ips.contains(net) {
+            to_ips.push(net.clone());
+        }
+    });
+    (from_ips, to_ips)
+}
+
+fn move_networks(interface: &str, from: &str, to: &str, nets: &[String]) -> StrResult<()> {
+    let current = allowed_ips(&run("wg", &["show", interface, "allowed-ips"])?);
+    let (from_ips, to_ips) = moved_allowed_ips(&current, from, to, nets);
+    // the receiving peer first, so the networks are never without one
+    run("wg", &["set", interface, "peer", to, "allowed-ips", &to_ips.join(",")])?;
+    run("wg", &["set", interface, "peer", from, "allowed-ips", &from_ips.join(",")]).map(|_| ())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failover {
+    pub liveness: Liveness,
+    pub policy: Policy,
+    // with Policy::BackupPeer, the peers in the order they take the networks, the first one being where they belong
+    pub order: Vec<String>,
+}
+
+impl Failover {
+    // takes the installed networks off the dead tunnel
+    pub fn fail(&self, backend: &Backend, nets: &[String], table: Option<u32>) -> StrResult<()> {
+        let iface = &self.liveness.interface;
+        match self.policy {
+            Policy::Withdraw => apply::install(backend, &Transition { remove: nets.to_vec(), add: vec![] }, &BTreeSet::new(), iface, table),
+            Policy::Blackhole => blackhole_commands(nets, table).iter().try_for_each(|args| apply::run_ip(args)),
+            Policy::BackupPeer => Err("peers are switched with switch_peer".to_string()),
+        }
+    }
+
+    // puts them back once it is alive again
+    pub fn recover(&self, backend: &Backend, nets: &[String], table: Option<u32>) -> StrResult<()> {
+        let next = nets.iter().cloned().collect::<BTreeSet<_>>();
+        apply::install(backend, &Transition { remove: vec![], add: nets.to_vec() }, &next, &self.liveness.interface, table)
+    }
+
+    // the first peer in order with a fresh handshake, skipping the holder of the networks if the ping does not
+    // answer through it; none when no peer is alive
+    pub fn pick_peer(&self, handshakes: &HashMap<String, u64>, now: u64, holder: &str, holder_answers: bool) -> Option<&str> {
+        self.order
+            .iter()
+            .filter(|peer| *peer != holder || holder_answers)
+            .find(|peer| self.liveness.stale_handshake(handshakes, Some(peer), now).is_none())
+            .map(String::as_str)
+    }
+
+    // the peer the networks should move to from the holder, and why; none when they are fine where they are, or when
+    // no peer is alive to take them
+    pub fn switch_peer(&self, holder: &str, now: u64) -> StrResult<Option<(String, String)>> {
+        let handshakes = self.liveness.handshakes()?;
+        // the ping goes out through the interface to whichever peer holds the networks, so it only tells about that one
+        let ping = self.liveness.unanswered_ping();
+        let Some(next) = self.pick_peer(&handshakes, now, holder, ping.is_none()) else {
+            return Ok(None);
+        };
+        if next == holder {
+            return Ok(None);
+        }
+        let reason = match (self.order.first().is_some_and(|first| first == next), ping) {
+            (true, _) => format!("{next} is alive again"),
+            (false, Some(ping)) => ping,
+            (false, None) => self.liveness.stale_handshake(&handshakes, Some(holder), now).unwrap_or_default(),
+        };
+        Ok(Some((next.to_string(), reason)))
+    }
+
+    pub fn move_networks(&self, from: &str, to: &str, nets: &[String]) -> StrResult<()> {
+        move_networks(&self.liveness.interface, from, to, nets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liveness() {
+        let handshakes = latest_handshakes("aG9tZQ==\t1760000000\ndnBu\t1759999900\nbmV3\t0\n");
+        assert_eq!(handshakes["dnBu"], 1_759_999_900);
+        let liveness = Liveness { interface: "wg0".to_string(), peer: None, dead_after: DEFAULT_DEAD_AFTER, ping: None };
+        assert_eq!(liveness.stale_handshake(&handshakes, None, 1_760_000_100), None);
+        assert_eq!(liveness.stale_handshake(&handshakes, None, 1_760_000_181), Some("last handshake on wg0 was 181 seconds ago".to_string()));
+        assert!(liveness.stale_handshake(&handshakes, Some("dnBu"), 1_760_000_100).is_some());
+        assert_eq!(liveness.stale_handshake(&handshakes, Some("bmV3"), 1_760_000_100), Some("no handshake on wg0 yet".to_string()));
+        assert_eq!(liveness.stale_handshake(&handshakes, Some("Z29uZQ=="), 1_760_000_100), Some("no peer Z29uZQ== on wg0".to_string()));
+
+        let failover = Failover { liveness, policy: Policy::BackupPeer, order: vec!["dnBu".to_string(), "aG9tZQ==".into(), "bmV3".into()] };
+        assert_eq!(failover.pick_peer(&handshakes, 1_760_000_100, "dnBu", true), Some("aG9tZQ=="));
+        assert_eq!(failover.pick_peer(&handshakes, 1_760_000_050, "aG9tZQ==", true), Some("dnBu"));
+        assert_eq!(failover.pick_peer(&handshakes, 1_760_000_050, "dnBu", false), Some("aG9tZQ=="));
+        assert_eq!(failover.pick_peer(&handshakes, 1_760_001_000, "dnBu", true), None);
+    }
+
+    #[test]
+    fn test_failover_commands() {
+        assert_eq!(blackhole_commands(&["1.2.0.0/16".to_string()], Some(100)), vec![vec!["route", "replace", "blackhole", "1.2.0.0/16", "table", "100"]]);
+        let current = allowed_ips("dnBu\t10.8.0.0/24 1.2.0.0/16 5.6.0.0/16\nYmFja3Vw\t10.9.0.0/24\nbmV3\t(none)\n");
+        assert_eq!(current["bmV3"], Vec::<String>::new());
+        let nets = ["1.2.0.0/16".to_string(), "5.6.0.0/16".to_string()];
+        assert_eq!(moved_allowed_ips(&current, "dnBu", "YmFja3Vw", &nets), (
+            vec!["10.8.0.0/24".to_string()],
+            vec!["10.9.0.0/24".to_string(), "1.2.0.0/16".into(), "5.6.0.0/16".into()],
+        ));
+        assert_eq!(moved_allowed_ips(&current, "dnBu", "bmV3", &nets).1, nets.to_vec());
+    }
+}
@@ -0,0 +1,107 @@
+use std::ffi::{c_char, CStr, CString};
+use serde_json::{json, Value};
+use crate::dns::StrResult;
+use crate::host::Host;
+use crate::input::har::HarOptions;
+use crate::input::{hostname_from_url, hostnames_from_file, Hosts};
+use crate::aggregate::Aggregation;
+use crate::routes::{allowed_ips_line, networks, resolve_hosts, PassiveDns};
+
+// C ABI for firmware components (OpenWrt LuCI apps and the like), see include/wgrouter.h
+//
+// request:  {"hosts": ["example.com", "https://cdn.example.net/x.js"], "files": ["/tmp/site.har"], "lenient": false, "prefix_len": 16, "slack": 0,
+//            "ipv6": false, "prefix_len6": 48}
+// response: {"networks": [...], "allowed_ips": "AllowedIPs = ...", "unresolved": {...}, "warnings": [...]} or {"error": "..."}
+
+fn strings(request: &Value, name: &str) -> StrResult<Vec<String>> {
+    match request.get(name) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(list) => list.as_array()
+            .and_then(|items| items.iter().map(|v| v.as_str().map(String::from)).collect())
+            .ok_or(format!("'{name}' must be an array of strings")),
+    }
+}
+
+pub fn generate_routes_json(request: &str) -> StrResult<Value> {
+    let request = serde_json::from_str::<Value>(request).map_err(|e| format!("invalid JSON request: {e}"))?;
+    let opts = HarOptions { lenient: request.get("lenient").and_then(Value::as_bool).unwrap_or(false), ..Default::default() };
+    let aggregation = Aggregation::from_json(&request)?;
+    let hosts = strings(&request, "files")?
+        .iter()
+        .map(|file| hostnames_from_file(file, &opts))
+        .try_fold(Hosts::default(), |acc, hosts| hosts.map(|hosts| acc.merge(hosts)))?
+        .confirmed
+        .into_iter()
+        .chain(strings(&request, "hosts")?.iter().map(|h| hostname_from_url(h.trim()).unwrap_or(h.trim().to_string())))
+        .map(|h| h.to_ascii_lowercase())
+        .collect::<std::collections::BTreeSet<_>>();
+    let (resolved, unresolved) = resolve_hosts(hosts.iter(), &PassiveDns::new(), aggregation.ipv6);
+    let host_util = Host::from_proc_net_tcp()?;
+    let (nets, warnings) = networks(resolved.into_values().flatten(), &aggregation, &host_util)?;
+    Ok(json!({"networks": nets, "allowed_ips": allowed_ips_line(&nets), "unresolved": unresolved, "warnings": warnings}))
+}
+
+/// # Safety
+///
+/// `request` must be a valid NUL-terminated string. The returned string must be released with `wgrouter_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wgrouter_generate_routes(request: *const c_char) -> *mut c_char {
+    let response = if request.is_null() {
+        Err("request is NULL".to_string())
+    } else {
+        unsafe { CStr::from_ptr(request) }
+            .to_str()
+            .map_err(|e| format!("request is not UTF-8: {e}"))
+            .and_then(generate_routes_json)
+    };
+    let response = response.unwrap_or_else(|e| json!({"error": e}));
+    // serde_json escapes control characters, so there is no NUL inside
+    CString::new(response.to_string()).unwrap_or_default().into_raw()
+}
+
+/// # Safety
+///
+/// `response` must come from `wgrouter_generate_routes` and not be freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wgrouter_free(response: *mut c_char) {
+    if !response.is_null() {
+        drop(unsafe { CString::from_raw(response) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(request: &str) -> Value {
+        let request = CString::new(request).unwrap();
+        unsafe {
+            let response = wgrouter_generate_routes(request.as_ptr());
+            let value = serde_json::from_str(CStr::from_ptr(response).to_str().unwrap()).unwrap();
+            wgrouter_free(response);
+            value
+        }
+    }
+
+    #[test]
+    fn test_generate_routes() {
+        let response = call(r#"{"hosts": ["93.184.216.34", "https://1.1.1.1/dns-query", "192.168.1.1"]}"#);
+        assert_eq!(response["networks"], json!(["1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(response["allowed_ips"], json!("AllowedIPs = 1.1.0.0/16, 93.184.0.0/16"));
+        assert_eq!(call(r#"{"hosts": ["93.184.216.34"], "prefix_len": 32}"#)["networks"], json!(["93.184.216.34/32"]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(call("{not json")["error"].as_str().unwrap().starts_with("invalid JSON request"));
+        assert_eq!(call(r#"{"hosts": [1]}"#)["error"], json!("'hosts' must be an array of strings"));
+        assert_eq!(call(r#"{"prefix_len": "16"}"#)["error"], json!("'prefix_len' must be a number"));
+        assert!(call(r#"{"files": ["/nonexistent.har"]}"#)["error"].as_str().unwrap().contains("/nonexistent.har"));
+        unsafe {
+            let response = wgrouter_generate_routes(std::ptr::null());
+            assert_eq!(CStr::from_ptr(response).to_str().unwrap(), r#"{"error":"request is NULL"}"#);
+            wgrouter_free(response);
+            wgrouter_free(std::ptr::null_mut());
+        }
+    }
+}
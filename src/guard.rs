@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use ipnetwork::IpNetwork;
+use crate::aggregate::exclude;
+use crate::dns::{discard_port, hostname_is_ip, StrResult};
+use crate::routes::sorted_nets;
+
+// the endpoints in `wg show all endpoints` output, "iface<TAB>pubkey<TAB>ip:port" lines, "(none)" for peers without one
+pub fn wg_show_endpoints(text: &str) -> Vec<IpAddr> {
+    text.lines().filter_map(|line| line.rsplit('\t').next()).filter_map(|endpoint| hostname_is_ip(discard_port(endpoint.trim()))).collect()
+}
+
+// interface, peer and its allowed IPs of each "iface<TAB>pubkey<TAB>ips" line of `wg show all allowed-ips`
+pub fn wg_show_allowed_ips(text: &str) -> Vec<(String, String, Vec<String>)> {
+    text.lines()
+        .filter_map(|line| match line.split('\t').collect::<Vec<_>>()[..] {
+            [iface, peer, ips] => Some((iface.to_string(), peer.to_string(), ips.split_whitespace().filter(|ip| *ip != "(none)").map(String::from).collect())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(feature = "native")]
+fn wg_show_all(what: &str) -> StrResult<String> {
+    let output = std::process::Command::new("wg").args(["show", "all", what]).output().map_err(|e| format!("could not run wg: {e}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!("'wg show all {what}' failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+// the endpoints of the interfaces up right now; none when wg cannot tell, e.g. without the privileges to ask
+#[cfg(feature = "native")]
+pub fn running_endpoints() -> Vec<IpAddr> {
+    wg_show_all("endpoints").map(|text| wg_show_endpoints(&text)).unwrap_or_default()
+}
+
+#[cfg(feature = "native")]
+pub fn running_allowed_ips() -> StrResult<Vec<(String, String, Vec<String>)>> {
+    wg_show_all("allowed-ips").map(|text| wg_show_allowed_ips(&text))
+}
+
+// the kernel writes the addresses in /proc/net/route as hex numbers in its own byte order
+fn proc_net_route_address(hex: &str) -> Option<Ipv4Addr> {
+    u32::from_str_radix(hex, 16).ok().map(|address| Ipv4Addr::from(address.to_ne_bytes()))
+}
+
+// interface and gateway of the routes in /proc/net/route that have one
+pub fn proc_net_route_gateways(text: &str) -> Vec<(String, IpAddr)> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let gateway = proc_net_route_address(fields.get(2)?).filter(|gateway| !gateway.is_unspecified())?;
+            Some((fields[0].to_string(), IpAddr::V4(gateway)))
+        })
+        .collect()
+}
+
+// interface and network of the routes in /proc/net/route that reach a network directly, without a gateway
+pub fn proc_net_route_connected(text: &str) -> Vec<(String, IpNetwork)> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (destination, gateway, mask) = (proc_net_route_address(fields.get(1)?)?, proc_net_route_address(fields.get(2)?)?, proc_net_route_address(fields.get(7)?)?);
+            if destination.is_unspecified() || !gateway.is_unspecified() {
+                return None;
+            }
+            let net = IpNetwork::new(IpAddr::V4(destination), u32::from(mask).count_ones() as u8).ok()?;
+            Some((fields[0].to_string(), net))
+        })
+        .collect()
+}
+
+// the interfaces of the machine that are WireGuard ones, as sysfs tells
+pub fn wireguard_interfaces() -> Vec<String> {
+    let mut interfaces = std::fs::read_dir("/sys/class/net")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| std::fs::read_to_string(entry.path().join("uevent")).is_ok_and(|uevent| uevent.lines().any(|line| line == "DEVTYPE=wireguard")))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    interfaces.sort();
+    interfaces
+}
+
+// the nameservers of a resolv.conf, which the system resolver asks for the endpoint's name
+pub fn resolv_conf_nameservers(text: &str) -> Vec<IpAddr> {
+    text.lines()
+        .filter_map(|line| match line.split(['#', ';']).next().unwrap_or_default().split_whitespace().collect::<Vec<_>>()[..] {
+            ["nameserver", address] => address.split('%').next().and_then(hostname_is_ip),
+            _ => None,
+        })
+        .collect()
+}
+
+// addresses that must never be routed into the tunnel, or the tunnel's own packets would go into it as well: the
+// endpoints it runs to and, for endpoints given by name, the nameservers resolving them, and the gateways and local
+// networks on the way there; network -> what it is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Guard(pub BTreeMap<IpNetwork, String>);
+
+// a single address shows as just that
+fn shown(net: &IpNetwork) -> String {
+    if net.prefix() == IpNetwork::from(net.network()).prefix() { net.network().to_string() } else { net.to_string() }
+}
+
+impl Guard {
+    pub fn add(&mut self, ip: IpAddr, what: String) {
+        self.add_network(IpNetwork::from(ip), what);
+    }
+
+    pub fn add_network(&mut self, net: IpNetwork, what: String) {
+        self.0.entry(net).or_insert(what);
+    }
+
+    // host:port as in a WireGuard config; a name is looked up through the system resolver, as wg-quick does, and its
+    // nameservers are guarded even when the lookup fails
+    pub fn add_endpoint(&mut self, endpoint: &str, nameservers: &[IpAddr]) -> StrResult<()> {
+        let host = discard_port(endpoint);
+        if let Some(ip) = hostname_is_ip(host) {
+            self.add(ip, format!("endpoint {endpoint}"));
+            return Ok(());
+        }
+        nameservers.iter().for_each(|ns| self.add(*ns, format!("nameserver resolving endpoint {host}")));
+        let addrs = (host, 0).to_socket_addrs().map_err(|e| format!("could not resolve endpoint {endpoint}: {e}"))?;
+        addrs.for_each(|addr| self.add(addr.ip(), format!("endpoint {endpoint}")));
+        Ok(())
+    }
+
+    // the networks that overlap a guarded one, with that one, shown, and what it is; a /0 is left alone, as wg-quick
+    // keeps the endpoint out of it with policy routing
+    pub fn loops(&self, nets: &[String]) -> Vec<(String, String, &str)> {
+        nets.iter()
+            .filter_map(|net| net.parse::<IpNetwork>().ok().filter(|net| net.prefix() > 0).map(|parsed| (net, parsed)))
+            .flat_map(|(net, parsed)| {
+                self.0.iter().filter(move |(guarded, _)| overlap(&parsed, guarded)).map(move |(guarded, what)| (net.clone(), shown(guarded), what.as_str()))
+            })
+            .collect()
+    }
+
+    // the networks with the guarded ones cut out of them, and a warning for each guarded one that overlapped some
+    pub fn apply(&self, nets: &[String]) -> StrResult<(Vec<String>, Vec<String>)> {
+        let mut nets = nets.iter().map(|net| net.parse::<IpNetwork>().map_err(|e| format!("could not parse network {net}: {e}"))).collect::<StrResult<Vec<_>>>()?;
+        let mut warnings = Vec::new();
+        self.0.iter().for_each(|(guarded, what)| {
+            if let Some(net) = nets.iter().find(|net| overlap(net, guarded)) {
+                warnings.push(format!("{what} ({}) overlaps routed network {net}, routing around it", shown(guarded)));
+                nets = nets.iter().flat_map(|net| exclude(*net, *guarded)).collect();
+            }
+        });
+        Ok((sorted_nets(nets.into_iter().map(|net| net.to_string()).collect())?, warnings))
+    }
+}
+
+fn overlap(a: &IpNetwork, b: &IpNetwork) -> bool {
+    a.contains(b.network()) || b.contains(a.network())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(wg_show_endpoints("wg0\tdnBu\t198.51.100.7:51820\nwg0\taG9tZQ==\t(none)\nwg1\tbmV3\t[2001:db8::1]:51820\n"),
+            vec!["198.51.100.7".parse::<IpAddr>().unwrap(), "2001:db8::1".parse().unwrap()]);
+        assert_eq!(resolv_conf_nameservers("# generated\nsearch lan\nnameserver 192.168.1.1\nnameserver fe80::1%eth0 ; router\noptions edns0\n"),
+            vec!["192.168.1.1".parse::<IpAddr>().unwrap(), "fe80::1".parse().unwrap()]);
+        assert_eq!(wg_show_allowed_ips("wg0\tdnBu\t10.8.0.0/24 1.1.0.0/16\nwg0\taG9tZQ==\t(none)\n")[0], ("wg0".to_string(), "dnBu".to_string(), vec!["10.8.0.0/24".to_string(), "1.1.0.0/16".into()]));
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+            eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+            eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n";
+        let gateway = if cfg!(target_endian = "little") { "192.168.1.1" } else { "1.1.168.192" };
+        assert_eq!(proc_net_route_gateways(route), vec![("eth0".to_string(), gateway.parse().unwrap())]);
+        let connected = if cfg!(target_endian = "little") { "192.168.1.0/24" } else { "0.1.168.192/24" };
+        assert_eq!(proc_net_route_connected(route), vec![("eth0".to_string(), connected.parse().unwrap())]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut guard = Guard::default();
+        guard.add_endpoint("198.51.100.7:51820", &["192.0.2.53".parse().unwrap()]).unwrap();
+        assert_eq!(guard.0.len(), 1);
+        guard.add("203.0.113.53".parse().unwrap(), "nameserver resolving endpoint vpn.example.net".to_string());
+        guard.add_network("192.168.1.0/24".parse().unwrap(), "local network of eth0".to_string());
+        let (nets, warnings) = guard.apply(&["198.51.100.0/30".to_string(), "198.51.100.4/30".into(), "203.0.113.52/31".into(), "1.1.0.0/16".into(), "192.168.1.128/25".into()]).unwrap();
+        assert_eq!(nets, vec!["1.1.0.0/16", "198.51.100.0/30", "198.51.100.4/31", "198.51.100.6/32", "203.0.113.52/32"]);
+        assert_eq!(guard.loops(&["198.51.0.0/16".to_string(), "0.0.0.0/0".into(), "1.1.0.0/16".into(), "192.168.0.0/16".into()]), vec![
+            ("198.51.0.0/16".to_string(), "198.51.100.7".to_string(), "endpoint 198.51.100.7:51820"),
+            ("192.168.0.0/16".to_string(), "192.168.1.0/24".to_string(), "local network of eth0"),
+        ]);
+        assert_eq!(warnings, vec![
+            "local network of eth0 (192.168.1.0/24) overlaps routed network 192.168.1.128/25, routing around it",
+            "endpoint 198.51.100.7:51820 (198.51.100.7) overlaps routed network 198.51.100.4/30, routing around it",
+            "nameserver resolving endpoint vpn.example.net (203.0.113.53) overlaps routed network 203.0.113.52/31, routing around it",
+        ]);
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::clock::Clock;
+use crate::dns::StrResult;
+
+pub const DEFAULT_STALL_SECONDS: u64 = 60;
+
+// liveness and readiness of a long-running wgrouter, for orchestrators (/healthz, /readyz) and systemd (sd_notify)
+#[derive(Debug)]
+pub struct Health {
+    ready: AtomicBool,
+    // when the running resolution last made progress, none while idle
+    progress: Mutex<Option<Instant>>,
+    // why the last cycle of a daemon failed, none once one goes through again
+    failure: Mutex<Option<String>>,
+    stall_after: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(DEFAULT_STALL_SECONDS))
+    }
+}
+
+// marks a resolution as running for as long as it lives
+pub struct Busy<'a>(&'a Health);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        *self.0.progress.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+impl Health {
+    pub fn new(stall_after: Duration) -> Self {
+        Self::with_clock(stall_after, crate::clock::system())
+    }
+
+    pub fn with_clock(stall_after: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self { ready: AtomicBool::new(false), progress: Mutex::new(None), failure: Mutex::new(None), stall_after, clock }
+    }
+
+    pub fn mark_ready(&self) {
+        if !self.ready.swap(true, Ordering::SeqCst) {
+            notify("READY=1");
+        }
+    }
+
+    // how a cycle of a daemon went: the first that goes through makes it ready, and a failed one keeps it from being
+    // ready until the next goes through
+    pub fn record(&self, cycle: &StrResult<()>) {
+        *self.failure.lock().unwrap_or_else(|e| e.into_inner()) = cycle.as_ref().err().cloned();
+        if cycle.is_ok() {
+            self.mark_ready();
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn busy(&self) -> Busy<'_> {
+        *self.progress.lock().unwrap_or_else(|e| e.into_inner()) = Some(self.clock.now());
+        Busy(self)
+    }
+
+    // one more host resolved (or about to be), so the resolution is not stuck
+    pub fn progress(&self) {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        if progress.is_some() {
+            *progress = Some(self.clock.now());
+        }
+    }
+
+    // how long a resolution has been stuck, if for longer than allowed
+    pub fn stalled(&self) -> Option<Duration> {
+        self.stalled_at(self.clock.now())
+    }
+
+    fn stalled_at(&self, now: Instant) -> Option<Duration> {
+        let progress = (*self.progress.lock().unwrap_or_else(|e| e.into_inner()))?;
+        Some(now.saturating_duration_since(progress)).filter(|idle| *idle > self.stall_after)
+    }
+
+    // status code and body for a GET of path
+    pub fn http_response(&self, path: &str) -> (u16, String) {
+        let stalled = self.stalled();
+        let failure = self.failure.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        match (path, stalled) {
+            ("/healthz" | "/readyz", Some(idle)) => (503, format!("stalled: resolution has made no progress for {}s\n", idle.as_secs())),
+            ("/healthz", None) => (200, "ok\n".to_string()),
+            ("/readyz", None) if let Some(e) = failure => (503, format!("last cycle failed: {e}\n")),
+            ("/readyz", None) if self.is_ready() => (200, "ready\n".to_string()),
+            ("/readyz", None) => (503, "not ready\n".to_string()),
+            _ => (404, "not found\n".to_string()),
+        }
+    }
+}
+
+fn respond(health: &Health, stream: TcpStream) -> StrResult<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request).map_err(|e| format!("could not read health request: {e}"))?;
+    let (status, body) = match request.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
+        ["GET" | "HEAD", path, ..] => health.http_response(path.split('?').next().unwrap_or(path)),
+        _ => (405, "only GET is supported\n".to_string()),
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let body = if request.starts_with("HEAD") { "" } else { &body };
+    write!(&stream, "HTTP/1.1 {status} {reason}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+        .map_err(|e| format!("could not answer health request: {e}"))
+}
+
+// answers /healthz and /readyz on addr from a background thread
+pub fn serve_http(addr: &str, health: Arc<Health>) -> StrResult<()> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("could not listen for health checks on {addr}: {e}"))?;
+    eprintln!("health checks on http://{}/healthz and /readyz", listener.local_addr().map(|a| a.to_string()).unwrap_or(addr.to_string()));
+    std::thread::spawn(move || {
+        listener.incoming().flatten().for_each(|stream| {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+            if let Err(e) = respond(&health, stream) {
+                eprintln!("warning: {e}");
+            }
+        });
+    });
+    Ok(())
+}
+
+// systemd's notification protocol: a datagram to $NOTIFY_SOCKET; nothing happens when not run as a notify service
+pub fn notify(state: &str) {
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET")
+        && let Err(e) = notify_to(&socket.to_string_lossy(), state) {
+        eprintln!("warning: {e}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn notify_to(socket: &str, state: &str) -> StrResult<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    // a leading @ stands for the abstract namespace
+    let addr = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(socket),
+    }.map_err(|e| format!("bad NOTIFY_SOCKET {socket}: {e}"))?;
+    let sender = UnixDatagram::unbound().map_err(|e| format!("could not create a notification socket: {e}"))?;
+    sender.send_to_addr(state.as_bytes(), &addr).map(|_| ()).map_err(|e| format!("could not notify systemd at {socket}: {e}"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify_to(_socket: &str, _state: &str) -> StrResult<()> {
+    Ok(())
+}
+
+// half the WatchdogSec= of the unit, if it is meant for this process
+fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    match std::env::var("WATCHDOG_PID").ok().map(|pid| pid.parse::<u32>()) {
+        Some(Ok(pid)) if pid != std::process::id() => None,
+        _ => Some(Duration::from_micros(usec / 2)),
+    }
+}
+
+// keeps pinging the systemd watchdog while nothing is stalled, so a stuck resolution gets the service restarted
+pub fn spawn_watchdog(health: Arc<Health>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    std::thread::spawn(move || loop {
+        if health.stalled().is_none() {
+            notify("WATCHDOG=1");
+        }
+        std::thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled() {
+        let health = Health::new(Duration::from_secs(10));
+        let later = Instant::now() + Duration::from_secs(30);
+        assert_eq!(health.stalled_at(later), None);
+        let busy = health.busy();
+        assert!(health.stalled_at(later).is_some_and(|idle| idle >= Duration::from_secs(29)));
+        assert_eq!(health.stalled_at(Instant::now() + Duration::from_secs(5)), None);
+        drop(busy);
+        assert_eq!(health.stalled_at(later), None);
+        health.progress();
+        assert_eq!(health.stalled_at(later), None);
+    }
+
+    #[test]
+    fn test_stalled_clock() {
+        let clock = Arc::new(crate::clock::ManualClock::new(0));
+        let health = Health::with_clock(Duration::from_secs(10), clock.clone());
+        let _busy = health.busy();
+        clock.advance(Duration::from_secs(8));
+        health.progress();
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(health.stalled(), None);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(health.stalled(), Some(Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_http_response() {
+        let health = Health::new(Duration::ZERO);
+        assert_eq!(health.http_response("/healthz"), (200, "ok\n".to_string()));
+        assert_eq!(health.http_response("/readyz").0, 503);
+        health.ready.store(true, Ordering::SeqCst);
+        assert_eq!(health.http_response("/readyz"), (200, "ready\n".to_string()));
+        assert_eq!(health.http_response("/metrics").0, 404);
+        let _busy = health.busy();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(health.http_response("/healthz").0, 503);
+        assert!(health.http_response("/readyz").1.starts_with("stalled"));
+    }
+
+    #[test]
+    fn test_record() {
+        let health = Health::default();
+        health.record(&Err("could not resolve the hosts again: timed out".to_string()));
+        assert!(!health.is_ready());
+        assert_eq!(health.http_response("/readyz"), (503, "last cycle failed: could not resolve the hosts again: timed out\n".to_string()));
+        assert_eq!(health.http_response("/healthz").0, 200);
+        health.record(&Ok(()));
+        assert_eq!(health.http_response("/readyz"), (200, "ready\n".to_string()));
+        health.record(&Err("no route".to_string()));
+        assert!(health.is_ready());
+        assert_eq!(health.http_response("/readyz").0, 503);
+        health.record(&Ok(()));
+        assert_eq!(health.http_response("/readyz").0, 200);
+    }
+
+    #[test]
+    fn test_serve_http() {
+        let health = Arc::new(Health::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || listener.incoming().flatten().for_each(|stream| respond(&health, stream).unwrap()));
+        let get = |request: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            response
+        };
+        assert!(get("GET /healthz HTTP/1.1\r\nHost: x\r\n\r\n").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(get("GET /readyz?verbose HTTP/1.1\r\n\r\n").ends_with("\r\n\r\nnot ready\n"));
+        assert!(get("POST /healthz HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_notify_to() {
+        let path = std::env::temp_dir().join(format!("wgrouter-notify-{}", std::process::id()));
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+        assert!(notify_to("/nonexistent/notify", "READY=1").is_err());
+    }
+}
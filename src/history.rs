@@ -0,0 +1,215 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::dns::StrResult;
+
+pub const SCHEMA: u32 = 1;
+pub const DEFAULT_KEEP: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    pub created: u64,
+    pub nets: BTreeSet<String>,
+}
+
+// generations applied to one target, oldest first; the last applied one is what is installed right now
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub schema: u32,
+    pub applied: Vec<Generation>,
+    pub undone: Vec<Generation>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transition {
+    pub remove: Vec<String>,
+    pub add: Vec<String>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { schema: SCHEMA, applied: Vec::new(), undone: Vec::new() }
+    }
+}
+
+fn empty() -> &'static BTreeSet<String> {
+    static EMPTY: BTreeSet<String> = BTreeSet::new();
+    &EMPTY
+}
+
+fn transition(from: &BTreeSet<String>, to: &BTreeSet<String>) -> Transition {
+    Transition {
+        remove: from.difference(to).cloned().collect(),
+        add: to.difference(from).cloned().collect(),
+    }
+}
+
+impl History {
+    pub fn load(path: &Path) -> StrResult<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("could not read apply history {}: {e}", path.display())),
+        };
+        let history: Self = serde_json::from_str(&text).map_err(|e| format!("could not parse apply history {}: {e}", path.display()))?;
+        if history.schema > SCHEMA {
+            return Err(format!("apply history {} has schema {}, this version only understands up to {SCHEMA}", path.display(), history.schema));
+        }
+        Ok(history)
+    }
+
+    pub fn save(&self, path: &Path) -> StrResult<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(|e| format!("could not serialize apply history: {e}"))?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, text).map_err(|e| format!("could not write {}: {e}", tmp.display()))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {e}", path.display()))
+    }
+
+    pub fn current(&self) -> &BTreeSet<String> {
+        self.applied.last().map(|g| &g.nets).unwrap_or(empty())
+    }
+
+    // the operations that take the installed routes to next, nothing for routes that stay
+    pub fn plan(&self, next: &BTreeSet<String>) -> Transition {
+        transition(self.current(), next)
+    }
+
+    pub fn record(&mut self, generation: Generation, keep: usize) {
+        self.applied.push(generation);
+        self.undone.clear();
+        let excess = self.applied.len().saturating_sub(keep.max(1));
+        self.applied.drain(..excess);
+    }
+
+    pub fn undo(&mut self) -> Option<Transition> {
+        let generation = self.applied.pop()?;
+        let t = transition(&generation.nets, self.current());
+        self.undone.push(generation);
+        Some(t)
+    }
+
+    pub fn redo(&mut self) -> Option<Transition> {
+        let generation = self.undone.pop()?;
+        let t = transition(self.current(), &generation.nets);
+        self.applied.push(generation);
+        Some(t)
+    }
+}
+
+pub fn unix_time() -> u64 {
+    crate::clock::system().unix_time()
+}
+
+fn default_state_dir() -> StrResult<PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join("wgrouter"))
+        .ok_or("neither XDG_STATE_HOME nor HOME is set, cannot locate the state directory".to_string())
+}
+
+// dir (or $XDG_STATE_HOME/wgrouter) holds the state of the default profile, and each named profile its own
+// subdirectory of it, so independent route policies on one machine (say, two VPN providers) never share state
+pub fn state_dir(dir: Option<&Path>, profile: Option<&str>) -> StrResult<PathBuf> {
+    let base = dir.map(Path::to_path_buf).map(Ok).unwrap_or_else(default_state_dir)?;
+    match profile {
+        None => Ok(base),
+        Some(name) if is_profile_name(name) => Ok(base.join("profiles").join(name)),
+        Some(name) => Err(format!("bad profile name '{name}': use letters, digits, '-', '_' and '.', not starting with '.'")),
+    }
+}
+
+fn is_profile_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+pub fn target_name(iface: &str, table: Option<u32>) -> String {
+    match table {
+        Some(table) => format!("{iface}-table{table}"),
+        None => iface.to_string(),
+    }
+}
+
+pub fn history_path(state_dir: &Path, target: &str) -> PathBuf {
+    state_dir.join("history").join(format!("{target}.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generation(created: u64, nets: &[&str]) -> Generation {
+        Generation { created, nets: nets.iter().map(|n| n.to_string()).collect() }
+    }
+
+    fn t(remove: &[&str], add: &[&str]) -> Option<Transition> {
+        Some(Transition { remove: remove.iter().map(|n| n.to_string()).collect(), add: add.iter().map(|n| n.to_string()).collect() })
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::default();
+        assert_eq!(history.undo(), None);
+        history.record(generation(1, &["1.0.0.0/16"]), 10);
+        history.record(generation(2, &["1.0.0.0/16", "2.0.0.0/16"]), 10);
+        history.record(generation(3, &["2.0.0.0/16", "3.0.0.0/16"]), 10);
+
+        assert_eq!(history.undo(), t(&["3.0.0.0/16"], &["1.0.0.0/16"]));
+        assert_eq!(history.undo(), t(&["2.0.0.0/16"], &[]));
+        assert_eq!(history.redo(), t(&[], &["2.0.0.0/16"]));
+        assert_eq!(history.current(), &generation(0, &["1.0.0.0/16", "2.0.0.0/16"]).nets);
+        assert_eq!(history.undo(), t(&["2.0.0.0/16"], &[]));
+        assert_eq!(history.undo(), t(&["1.0.0.0/16"], &[]));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), t(&[], &["1.0.0.0/16"]));
+
+        history.record(generation(4, &["4.0.0.0/16"]), 10);
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut history = History::default();
+        assert_eq!(Some(history.plan(&generation(0, &["1.0.0.0/16"]).nets)), t(&[], &["1.0.0.0/16"]));
+        history.record(generation(1, &["1.0.0.0/16", "2.0.0.0/16"]), 10);
+        assert_eq!(Some(history.plan(&generation(0, &["2.0.0.0/16", "3.0.0.0/16"]).nets)), t(&["1.0.0.0/16"], &["3.0.0.0/16"]));
+        assert_eq!(history.plan(history.current()), Transition::default());
+    }
+
+    #[test]
+    fn test_keep() {
+        let mut history = History::default();
+        (1..=5).for_each(|i| history.record(generation(i, &[&format!("{i}.0.0.0/16")]), 3));
+        assert_eq!(history.applied.iter().map(|g| g.created).collect::<Vec<_>>(), vec![3, 4, 5]);
+        history.record(generation(6, &[]), 0);
+        assert_eq!(history.applied.len(), 1);
+    }
+
+    #[test]
+    fn test_state_dir() {
+        let dir = Path::new("/srv/wgrouter");
+        assert_eq!(state_dir(Some(dir), None), Ok(PathBuf::from("/srv/wgrouter")));
+        assert_eq!(state_dir(Some(dir), Some("provider-a")), Ok(PathBuf::from("/srv/wgrouter/profiles/provider-a")));
+        assert_ne!(history_path(&state_dir(Some(dir), Some("a")).unwrap(), "wg0"), history_path(&state_dir(Some(dir), Some("b")).unwrap(), "wg0"));
+        ["", "..", ".hidden", "a/b", "a b"].iter().for_each(|name| assert!(state_dir(Some(dir), Some(name)).is_err(), "{name}"));
+    }
+
+    #[test]
+    fn test_load_save() {
+        let dir = std::env::temp_dir().join(format!("wgrouter-history-{}", std::process::id()));
+        let path = history_path(&dir, &target_name("wg0", Some(100)));
+        assert_eq!(History::load(&path), Ok(History::default()));
+        let mut history = History::default();
+        history.record(generation(1, &["1.0.0.0/16"]), 10);
+        history.save(&path).unwrap();
+        assert_eq!(History::load(&path), Ok(history));
+        assert!(path.ends_with("history/wg0-table100.json"));
+
+        std::fs::write(&path, r#"{"schema": 99, "applied": [], "undone": []}"#).unwrap();
+        assert!(History::load(&path).unwrap_err().contains("schema 99"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
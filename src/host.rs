@@ -1,5 +1,8 @@
-use std::net::Ipv4Addr;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use crate::input::flow::Flow;
 
+#[derive(Default)]
 pub struct Host {
     tcp_conns: Vec<Conn>,
     udp_conns: Vec<Conn>,
@@ -7,60 +10,176 @@ pub struct Host {
 
 #[derive(Debug, Clone, PartialEq)]
 struct Conn {
-    src_ip: Ipv4Addr,
+    src_ip: IpAddr,
     src_port: u16,
-    dst_ip: Ipv4Addr,
-    dst_port: u16
+    dst_ip: IpAddr,
+    dst_port: u16,
+    inode: u64,
 }
 
 impl Host {
     pub fn from_proc_net_tcp() -> Result<Self, String> {
-        let v = ["tcp", "udp"].iter().map(|proto| -> Result<Vec<Conn>, String> {
-            let contents = std::fs::read_to_string(format!("/proc/net/{proto}"))
-                .map_err(|e| format!("could not read /proc/net/{proto}: {e}"))?;
+        // tcp6 and udp6 are missing when the kernel has IPv6 disabled
+        let v = ["tcp", "udp", "tcp6", "udp6"].iter().map(|proto| -> Result<Vec<Conn>, String> {
+            let contents = match std::fs::read_to_string(format!("/proc/net/{proto}")) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && proto.ends_with('6') => return Ok(Vec::new()),
+                contents => contents.map_err(|e| format!("could not read /proc/net/{proto}: {e}"))?,
+            };
             let conns = contents
                 .lines()
                 .skip(1)
                 .try_fold(Vec::new(), |mut acc: Vec<Conn>, line| {
                     //dbg!(line);
-                    let fields = line.trim().split(' ').take(3).collect::<Vec<&str>>();
-                    if fields.len() < 3 {
-                        Err(format!("not enough fields to parse 'ip:port' for proto {proto}: {line}"))
+                    let fields = line.split_ascii_whitespace().take(10).collect::<Vec<&str>>();
+                    if fields.len() < 10 {
+                        Err(format!("not enough fields to parse 'ip:port' and inode for proto {proto}: {line}"))
                     } else {
                         let (src_ip, src_port) = parse_ip_port(fields.get(1).unwrap())?;
                         let (dst_ip, dst_port) = parse_ip_port(fields.get(2).unwrap())?;
-                        acc.push(Conn{ src_ip, dst_ip, src_port, dst_port });
+                        let inode = fields.get(9).unwrap().parse()
+                            .map_err(|e| format!("could not parse inode for proto {proto}: {e}: {line}"))?;
+                        acc.push(Conn{ src_ip, dst_ip, src_port, dst_port, inode });
                         Ok(acc)
                     }
                 })?;
             Ok(conns)
-        }).take(2).collect::<Vec<_>>();
-        let tcp_conns = v.get(0).unwrap().clone()?;
-        let udp_conns = v.get(1).unwrap().clone()?;
+        }).collect::<Result<Vec<_>, String>>()?;
+        let tcp_conns = [&v[0], &v[2]].into_iter().flatten().cloned().collect();
+        let udp_conns = [&v[1], &v[3]].into_iter().flatten().cloned().collect();
         Ok(Self { tcp_conns, udp_conns })
     }
 
+    pub fn scoped_to(self, inodes: &HashSet<u64>) -> Self {
+        let keep = |conns: Vec<Conn>| conns.into_iter().filter(|c| inodes.contains(&c.inode)).collect();
+        Self { tcp_conns: keep(self.tcp_conns), udp_conns: keep(self.udp_conns) }
+    }
+
+    pub fn from_socket_listing(contents: &str) -> Self {
+        let (udp, tcp): (Vec<_>, Vec<_>) = contents
+            .lines()
+            .filter_map(conn_from_listing_line)
+            .partition(|(is_udp, _)| *is_udp);
+        Self {
+            tcp_conns: tcp.into_iter().map(|(_, c)| c).collect(),
+            udp_conns: udp.into_iter().map(|(_, c)| c).collect(),
+        }
+    }
+
+    pub fn flows(&self) -> Vec<Flow> {
+        [(6, &self.tcp_conns), (17, &self.udp_conns)].into_iter()
+            .flat_map(|(protocol, conns)| conns.iter().map(move |c| Flow {
+                src: c.src_ip,
+                dst: c.dst_ip,
+                dst_port: c.dst_port,
+                protocol,
+                bytes: 0,
+            }))
+            .collect()
+    }
+
+    // the addresses and ports of all the connections into the network
+    pub fn dsts_in(&self, net: &ipnetwork::IpNetwork) -> Vec<(IpAddr, u16)> {
+        [&self.tcp_conns, &self.udp_conns].into_iter().flatten().filter(|c| net.contains(c.dst_ip)).map(|c| (c.dst_ip, c.dst_port)).collect()
+    }
+
     pub fn contains_dst(&self, net_str: &str) -> Option<(String, u16)> {
-        let net: ipnetwork::Ipv4Network = net_str.parse().unwrap();
+        let net: ipnetwork::IpNetwork = net_str.parse().unwrap();
         [&self.tcp_conns, &self.udp_conns].into_iter()
-            .flat_map(|conns| conns)
+            .flatten()
             .find(|c| net.contains(c.dst_ip))
             .map(|c| (c.dst_ip.to_string(), c.dst_port))
     }
+
+    // the open connections to addresses the networks cover, as "tcp 192.0.2.10:51000 -> 93.184.216.34:443"
+    pub fn covered(&self, nets: &[String]) -> Vec<String> {
+        self.covered_conns(nets).into_iter().map(|(proto, c)| describe(proto, c)).collect()
+    }
+
+    fn covered_conns(&self, nets: &[String]) -> Vec<(&'static str, &Conn)> {
+        let nets = nets.iter().filter_map(|net| net.parse::<ipnetwork::IpNetwork>().ok()).collect::<Vec<_>>();
+        [("tcp", &self.tcp_conns), ("udp", &self.udp_conns)]
+            .into_iter()
+            .flat_map(|(proto, conns)| conns.iter().map(move |c| (proto, c)))
+            .filter(|(_, c)| nets.iter().any(|net| net.contains(c.dst_ip)))
+            .collect()
+    }
+
+    // what routing nets did to the connections open before them (self): those still open after and those gone since
+    pub fn impact(&self, after: &Host, nets: &[String]) -> Impact {
+        let open_after = |proto: &str, c: &Conn| {
+            let conns = if proto == "tcp" { &after.tcp_conns } else { &after.udp_conns };
+            conns.iter().any(|a| (a.src_ip, a.src_port, a.dst_ip, a.dst_port) == (c.src_ip, c.src_port, c.dst_ip, c.dst_port))
+        };
+        let (kept, ended) = self.covered_conns(nets).into_iter().partition::<Vec<_>, _>(|(proto, c)| open_after(proto, c));
+        let described = |conns: Vec<(&str, &Conn)>| conns.into_iter().map(|(proto, c)| describe(proto, c)).collect();
+        Impact { kept: described(kept), ended: described(ended) }
+    }
+}
+
+// connections that went into new routes: those kept may have moved into the tunnel or be stalled until they time out,
+// those ended most likely broke when their packets took the other way
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Impact {
+    pub kept: Vec<String>,
+    pub ended: Vec<String>,
+}
+
+impl Impact {
+    pub fn is_empty(&self) -> bool {
+        self.kept.is_empty() && self.ended.is_empty()
+    }
+}
+
+fn describe(proto: &str, c: &Conn) -> String {
+    let addr = |ip: IpAddr, port: u16| if ip.is_ipv6() { format!("[{ip}]:{port}") } else { format!("{ip}:{port}") };
+    format!("{proto} {} -> {}", addr(c.src_ip, c.src_port), addr(c.dst_ip, c.dst_port))
 }
 
-fn parse_ip_port(s: &str) -> Result<(Ipv4Addr, u16), String> {
+// the address is one (tcp, udp) or four (tcp6, udp6) 32-bit words, each in the host's little-endian byte order;
+// IPv4-mapped IPv6 addresses of dual-stack sockets come out as plain IPv4 ones
+fn parse_ip_port(s: &str) -> Result<(IpAddr, u16), String> {
     //dbg!(s);
     let mut s_it = s.split(':');
     let s_ip = s_it.next().ok_or(format!("no ip in 'ip:port' pair to parse: {s}"))?;
     let s_port = s_it.next().ok_or(format!("no port in 'ip:port' pair to parse: {s}"))?;
-    if s_ip.len() != 8 || s_port.len() != 4 {
+    if (s_ip.len() != 8 && s_ip.len() != 32) || s_port.len() != 4 {
         return Err(format!("too short ip:port pair to parse: {s}"));
     }
-    let (a, b, c, d) = (from_hex2(&s_ip[0..2])?, from_hex2(&s_ip[2..4])?, from_hex2(&s_ip[4..6])?, from_hex2(&s_ip[6..8])?);
+    let bytes = (0..s_ip.len()).step_by(2).map(|i| from_hex2(&s_ip[i..i + 2])).collect::<Result<Vec<u8>, String>>()?;
+    let bytes = bytes.chunks(4).flat_map(|word| word.iter().rev().copied()).collect::<Vec<u8>>();
+    let ip = match <[u8; 4]>::try_from(bytes.as_slice()) {
+        Ok(v4) => IpAddr::from(v4),
+        Err(_) => IpAddr::from(<[u8; 16]>::try_from(bytes.as_slice()).map_err(|e| format!("bad address in {s}: {e}"))?).to_canonical(),
+    };
     let (x, y) = (from_hex2(&s_port[0..2])? as u16, from_hex2(&s_port[2..4])? as u16);
 
-    Ok((Ipv4Addr::new(d, c, b, a), (x << 8) + y))
+    Ok((ip, (x << 8) + y))
+}
+
+// one line of `ss -tunp` or `netstat -an` (Linux and BSD/macOS flavours); listening and unconnected sockets are skipped
+fn conn_from_listing_line(line: &str) -> Option<(bool, Conn)> {
+    let tokens = line.split_ascii_whitespace().collect::<Vec<_>>();
+    let proto = tokens.first()?.to_ascii_lowercase();
+    if proto.starts_with("unix") || tokens.iter().any(|t| t.eq_ignore_ascii_case("LISTEN") || *t == "UNCONN") {
+        return None;
+    }
+    let mut addrs = tokens.iter().filter_map(|t| parse_listing_addr(t));
+    let (src_ip, src_port) = addrs.next()?;
+    let (dst_ip, dst_port) = addrs.next()?;
+    if dst_ip.is_unspecified() || dst_port == 0 {
+        return None;
+    }
+    Some((proto.starts_with("udp"), Conn { src_ip, src_port, dst_ip, dst_port, inode: 0 }))
+}
+
+// ss writes "ip:port" ("[ip]:port" for IPv6), BSD netstat "ip.port"
+fn parse_listing_addr(s: &str) -> Option<(IpAddr, u16)> {
+    [s.rsplit_once(':'), s.rsplit_once('.')].into_iter().flatten().find_map(|(ip, port)| {
+        let ip = ip.trim_start_matches('[').trim_end_matches(']');
+        let ip = ip.split('%').next()?;
+        let ip = ip.strip_prefix("::ffff:").unwrap_or(ip);
+        Some((ip.parse().ok()?, port.parse().ok()?))
+    })
 }
 
 fn from_hex2(s: &str) -> Result<u8, String> {
@@ -73,7 +192,9 @@ mod tests {
 
     #[test]
     fn test_parse_ip_port() {
-        assert_eq!(parse_ip_port("C301A8C0:E5BC"), Ok((Ipv4Addr::new(192, 168, 1, 195), 58812)));
+        assert_eq!(parse_ip_port("C301A8C0:E5BC"), Ok(("192.168.1.195".parse().unwrap(), 58812)));
+        assert_eq!(parse_ip_port("B80D0120000000000000000001000000:01BB"), Ok(("2001:db8::1".parse().unwrap(), 443)));
+        assert_eq!(parse_ip_port("0000000000000000FFFF00000100A8C0:0035"), Ok(("192.168.0.1".parse().unwrap(), 53)));
         assert!(parse_ip_port("C301A8C:E5BC").is_err());
         assert!(parse_ip_port("C301A8C0:E5BCC").is_err());
         assert!(parse_ip_port("C30xA8C0:E5BC").is_err());
@@ -89,9 +210,42 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_from_socket_listing() {
+        let ss = "\
+Netid State  Recv-Q Send-Q      Local Address:Port       Peer Address:Port Process
+tcp   ESTAB  0      0             192.168.1.5:51000     93.184.216.34:443   users:((\"firefox\",pid=1234,fd=80))
+tcp   ESTAB  0      0      [::ffff:192.168.1.5]:51002 [::ffff:203.0.113.7]:8443
+tcp   ESTAB  0      0       [2001:db8::5]:40000      [2001:db8::1]:443
+udp   ESTAB  0      0      192.168.1.5%wlan0:40000           1.1.1.1:53
+udp   UNCONN 0      0                 0.0.0.0:68              0.0.0.0:*
+tcp   LISTEN 0      128             127.0.0.1:631             0.0.0.0:*
+";
+        let host = Host::from_socket_listing(ss);
+        assert_eq!(host.tcp_conns.iter().map(|c| (c.dst_ip.to_string(), c.dst_port)).collect::<Vec<_>>(),
+            vec![("93.184.216.34".to_string(), 443), ("203.0.113.7".to_string(), 8443), ("2001:db8::1".to_string(), 443)]);
+        assert_eq!(host.udp_conns, vec![conn_no_ports("192.168.1.5", "1.1.1.1")].into_iter()
+            .map(|c| Conn { src_port: 40000, dst_port: 53, ..c }).collect::<Vec<_>>());
+
+        let netstat = "\
+Active Internet connections (servers and established)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN
+tcp        0      0 10.0.0.2:40222          140.82.121.4:443        ESTABLISHED
+udp        0      0 0.0.0.0:5353            0.0.0.0:*
+tcp4       0      0  10.0.0.2.50000         17.253.144.10.443       ESTABLISHED
+tcp6       0      0  2001:db8::5.50001      2001:db8::1.443         ESTABLISHED
+Active UNIX domain sockets (servers and established)
+unix  2      [ ACC ]     STREAM     LISTENING     20785    /run/systemd/private
+";
+        let host = Host::from_socket_listing(netstat);
+        assert_eq!(host.flows().iter().map(|f| (f.dst.to_string(), f.dst_port, f.protocol)).collect::<Vec<_>>(),
+            vec![("140.82.121.4".to_string(), 443, 6), ("17.253.144.10".to_string(), 443, 6), ("2001:db8::1".to_string(), 443, 6)]);
+        assert!(host.udp_conns.is_empty());
+    }
+
     fn conn_no_ports(src_ip: &str, dst_ip: &str) -> Conn {
-        //println!("{src_ip}={:?} {dst_ip}={:?}", src_ip.parse::<Ipv4Addr>(), dst_ip.parse::<Ipv4Addr>());
-        Conn { src_ip: src_ip.parse().unwrap(), src_port: 0, dst_ip: dst_ip.parse().unwrap(), dst_port: 0 }
+        Conn { src_ip: src_ip.parse().unwrap(), src_port: 0, dst_ip: dst_ip.parse().unwrap(), dst_port: 0, inode: 0 }
     }
 
     fn assert_contains_dst(h: &Host, net: &str, expected: Option<&str>) {
@@ -102,7 +256,7 @@ mod tests {
     fn test_contains() {
         let host = Host {
             tcp_conns: vec![conn_no_ports("192.168.100.4", "192.168.200.5"), conn_no_ports("10.0.1.6", "10.0.2.7")],
-            udp_conns: vec![conn_no_ports("172.17.200.4", "172.17.250.5"), conn_no_ports("12.0.1.6", "12.0.2.7")],
+            udp_conns: vec![conn_no_ports("172.17.200.4", "172.17.250.5"), conn_no_ports("12.0.1.6", "12.0.2.7"), conn_no_ports("2001:db8::5", "2001:db8:1::7")],
         };
         assert_contains_dst(&host, "192.168.200.0/24", Some("192.168.200.5"));
         assert_contains_dst(&host, "172.17.250.0/24", Some("172.17.250.5"));
@@ -111,5 +265,36 @@ mod tests {
 
         assert_contains_dst(&host, "13.0.0.0/8", None);
         assert_contains_dst(&host, "192.168.100.0/24", None);
+        assert_contains_dst(&host, "2001:db8::/32", Some("2001:db8:1::7"));
+        assert_contains_dst(&host, "2001:db8::/48", None);
+        assert_contains_dst(&host, "::/0", Some("2001:db8:1::7"));
+    }
+
+    #[test]
+    fn test_impact() {
+        let conn = |dst_ip: &str, src_port| Conn { src_port, dst_port: 443, ..conn_no_ports("10.0.0.2", dst_ip) };
+        let before = Host {
+            tcp_conns: vec![conn("93.184.216.34", 51000), conn("93.184.216.35", 51001), conn("198.51.100.7", 51002)],
+            udp_conns: vec![Conn { src_port: 40000, dst_port: 443, ..conn_no_ports("2001:db8::5", "2001:db8::1") }],
+        };
+        let after = Host { tcp_conns: vec![conn("93.184.216.34", 51000), conn("93.184.216.34", 51005)], udp_conns: vec![] };
+        assert_eq!(before.covered(&["93.184.0.0/16".to_string(), "2001:db8::/32".into()]).len(), 3);
+        assert_eq!(before.impact(&after, &["93.184.0.0/16".to_string(), "2001:db8::/32".into()]), Impact {
+            kept: vec!["tcp 10.0.0.2:51000 -> 93.184.216.34:443".to_string()],
+            ended: vec!["tcp 10.0.0.2:51001 -> 93.184.216.35:443".to_string(), "udp [2001:db8::5]:40000 -> [2001:db8::1]:443".into()],
+        });
+        assert!(before.impact(&after, &["203.0.113.0/24".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_scoped_to() {
+        let conn = |dst_ip: &str, inode| Conn { inode, ..conn_no_ports("10.0.0.1", dst_ip) };
+        let host = Host {
+            tcp_conns: vec![conn("192.168.200.5", 10), conn("10.0.2.7", 11)],
+            udp_conns: vec![conn("172.17.250.5", 12)],
+        }.scoped_to(&HashSet::from([11, 12]));
+        assert_contains_dst(&host, "192.168.200.0/24", None);
+        assert_contains_dst(&host, "10.0.0.0/16", Some("10.0.2.7"));
+        assert_contains_dst(&host, "172.17.250.0/24", Some("172.17.250.5"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+use crate::dns::StrResult;
+use crate::host::Host;
+use super::is_plausible_hostname;
+
+pub fn is_bugreport(path: &str) -> bool {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    name.starts_with("bugreport") && (name.ends_with(".zip") || name.ends_with(".txt"))
+}
+
+pub fn hostnames_from_bugreport(path: &str) -> StrResult<HashSet<String>> {
+    let report = if path.to_ascii_lowercase().ends_with(".zip") {
+        let file = std::fs::File::open(path).map_err(|e| format!("could not open bugreport {path}: {e}"))?;
+        bugreport_from_zip(file).map_err(|e| format!("{e} in bugreport {path}"))?
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("could not read bugreport {path}: {e}"))?
+    };
+    Ok(hostnames_from_bugreport_text(&report))
+}
+
+fn bugreport_from_zip<R: Read + Seek>(reader: R) -> StrResult<String> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| format!("could not open archive: {e}"))?;
+    let name = archive
+        .file_names()
+        .find(|name| !name.contains('/') && name.starts_with("bugreport") && name.ends_with(".txt"))
+        .map(String::from)
+        .ok_or("no bugreport-*.txt inside the archive")?;
+    let mut report = Vec::new();
+    archive
+        .by_name(&name)
+        .and_then(|mut f| Ok(f.read_to_end(&mut report)?))
+        .map_err(|e| format!("could not read {name}: {e}"))?;
+    Ok(String::from_utf8_lossy(&report).to_string())
+}
+
+// dumpstate wraps every command output in "------ TITLE (command) ------" ... "------ 0.012s was the duration of 'TITLE' ------"
+fn hostnames_from_bugreport_text(report: &str) -> HashSet<String> {
+    let mut in_netstat = false;
+    let netstat = report
+        .lines()
+        .filter(|line| {
+            if line.starts_with("------ ") {
+                in_netstat = line.starts_with("------ NETSTAT");
+                false
+            } else {
+                in_netstat
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Host::from_socket_listing(&netstat)
+        .flows()
+        .into_iter()
+        .map(|flow| flow.dst.to_string())
+        .collect()
+}
+
+pub fn hostnames_from_pcapdroid(path: &str) -> StrResult<HashSet<String>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("could not read PCAPdroid export {path}: {e}"))?;
+    hostnames_from_pcapdroid_csv(&contents).map_err(|e| format!("{e} in PCAPdroid export {path}"))
+}
+
+// the Info column holds the SNI, DNS query or HTTP Host of the connection, fall back to the bare IP otherwise
+fn hostnames_from_pcapdroid_csv(contents: &str) -> StrResult<HashSet<String>> {
+    let mut lines = contents.lines();
+    let header = lines
+        .next()
+        .ok_or("empty file")?
+        .trim_start_matches('#')
+        .split(',')
+        .map(|col| col.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    let column = |name: &str| header.iter().position(|col| col == name).ok_or(format!("no '{name}' column"));
+    let (dst_col, info_col) = (column("dstip")?, column("info")?);
+    Ok(lines
+        .map(|line| line.split(',').map(str::trim).collect::<Vec<_>>())
+        .filter_map(|fields| {
+            let info = fields.get(info_col).map(|i| i.trim_end_matches('.').to_ascii_lowercase()).unwrap_or_default();
+            if is_plausible_hostname(&info) {
+                Some(info)
+            } else {
+                fields.get(dst_col).filter(|ip| ip.parse::<std::net::IpAddr>().is_ok()).map(|ip| ip.to_string())
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    const REPORT: &str = "\
+========================================================
+== dumpstate: 2024-05-01 12:00:00
+========================================================
+------ NETWORK DEV INFO (/proc/net/dev) ------
+Inter-|   Receive
+ wlan0: 1.2.3.4:5 6.7.8.9:10
+------ 0.001s was the duration of 'NETWORK DEV INFO' ------
+------ NETSTAT (netstat -nW) ------
+Active Internet connections (established and servers)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State
+tcp        0      0 127.0.0.1:5037          0.0.0.0:*               LISTEN
+tcp6       0      0 ::ffff:10.0.2.15:43210  ::ffff:142.250.74.36:443 ESTABLISHED
+tcp        0      0 10.0.2.15:51234         157.240.1.35:443        ESTABLISHED
+udp        0      0 10.0.2.15:40000         8.8.8.8:53              ESTABLISHED
+------ 0.034s was the duration of 'NETSTAT' ------
+------ DUMPSYS (dumpsys netstats) ------
+  iface=wlan0 ident=[{type=WIFI, subType=COMBINED}] uid=10123 set=DEFAULT tag=0x0
+------ 0.100s was the duration of 'DUMPSYS' ------
+";
+
+    #[test]
+    fn test_hostnames_from_bugreport_text() {
+        assert_eq!(hostnames_from_bugreport_text(REPORT),
+            HashSet::from(["142.250.74.36".into(), "157.240.1.35".into(), "8.8.8.8".into()]));
+        assert!(hostnames_from_bugreport_text("no sections at all").is_empty());
+    }
+
+    #[test]
+    fn test_bugreport_from_zip() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let opts = zip::write::SimpleFileOptions::default();
+        [("version.txt", "2.0"), ("FS/data/anr/trace.txt", "x"), ("bugreport-sdk_gphone-2024-05-01.txt", REPORT)]
+            .into_iter()
+            .for_each(|(name, contents)| {
+                zip.start_file(name, opts).unwrap();
+                zip.write_all(contents.as_bytes()).unwrap();
+            });
+        assert_eq!(bugreport_from_zip(zip.finish().unwrap()), Ok(REPORT.to_string()));
+        assert!(bugreport_from_zip(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_hostnames_from_pcapdroid_csv() {
+        let csv = "\
+#IPProto,SrcIP,SrcPort,DstIp,DstPort,UID,App,Proto,Status,Info,BytesSent,BytesRcvd,PktsSent,PktsRcvd,FirstSeen,LastSeen
+6,10.215.173.1,40312,142.250.74.36,443,10123,YouTube,TLS,CLOSED,Www.YouTube.com,1200,54000,12,40,1714557600,1714557660
+17,10.215.173.1,51000,10.215.173.2,53,10123,YouTube,DNS,CLOSED,i.ytimg.com.,60,120,1,1,1714557600,1714557600
+6,10.215.173.1,40314,157.240.1.35,443,10200,Instagram,TCP,CLOSED,,100,100,1,1,1714557600,1714557600
+";
+        assert_eq!(hostnames_from_pcapdroid_csv(csv),
+            Ok(HashSet::from(["www.youtube.com".into(), "i.ytimg.com".into(), "157.240.1.35".into()])));
+        assert!(hostnames_from_pcapdroid_csv("a,b,c\n1,2,3").is_err());
+        assert!(hostnames_from_pcapdroid_csv("").is_err());
+    }
+
+    #[test]
+    fn test_is_bugreport() {
+        assert!(is_bugreport("/tmp/bugreport-sdk_gphone-2024-05-01-12-00-00.zip"));
+        assert!(is_bugreport("BUGREPORT.txt"));
+        assert!(!is_bugreport("capture.zip"));
+        assert!(!is_bugreport("bugreport.har"));
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashSet;
+use serde_json::Value;
+use crate::dns::StrResult;
+
+pub fn hostnames_from_chlsj(path: &str) -> StrResult<HashSet<String>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read Charles session {path}: {e}"))?;
+    hostnames_from_json_session(&text).map_err(|e| format!("{e} in Charles session {path}"))
+}
+
+pub fn hostnames_from_chlsx(path: &str) -> StrResult<HashSet<String>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read Charles session {path}: {e}"))?;
+    Ok(hostnames_from_xml_session(&text))
+}
+
+fn hostnames_from_json_session(text: &str) -> StrResult<HashSet<String>> {
+    let transactions = serde_json::from_str::<Vec<Value>>(text)
+        .map_err(|e| format!("could not parse JSON session: {e}"))?;
+    Ok(transactions
+        .iter()
+        .filter_map(|t| t.get("host").and_then(Value::as_str))
+        .filter(|host| !host.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn hostnames_from_xml_session(text: &str) -> HashSet<String> {
+    text.split("<transaction")
+        .skip(1)
+        .filter_map(|tag| tag.split('>').next())
+        .filter_map(|attrs| xml_attribute(attrs, "host"))
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+fn xml_attribute(attrs: &str, name: &str) -> Option<String> {
+    let pattern = format!(" {name}=\"");
+    let start = attrs.find(&pattern)? + pattern.len();
+    let value = attrs[start..].split('"').next()?;
+    Some(value.replace("&amp;", "&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostnames_from_json_session() {
+        let session = r#"[
+            {"status": "COMPLETE", "method": "GET", "scheme": "https", "host": "www.example.com", "port": 443, "path": "/"},
+            {"status": "COMPLETE", "method": "CONNECT", "scheme": "https", "host": "push.example.net", "port": 443},
+            {"status": "FAILED", "method": "GET", "host": ""},
+            {"status": "COMPLETE", "method": "GET", "scheme": "https", "host": "www.example.com", "path": "/x"}
+        ]"#;
+        assert_eq!(hostnames_from_json_session(session), Ok(HashSet::from(["www.example.com".into(), "push.example.net".into()])));
+        assert!(hostnames_from_json_session("{}").is_err());
+    }
+
+    #[test]
+    fn test_hostnames_from_xml_session() {
+        let session = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <charles-session>
+              <transaction status="COMPLETE" method="GET" protocolVersion="HTTP/1.1" protocol="https" host="api.example.com" port="443" path="/v1">
+                <request headers="true"><header><name>Host</name><value>ignored.example.com</value></header></request>
+              </transaction>
+              <transaction status="COMPLETE" method="CONNECT" protocol="https" host="ws.example.org" port="443"/>
+              <transaction status="FAILED" method="GET" protocol="http" host="" path="/"/>
+            </charles-session>"#;
+        assert_eq!(hostnames_from_xml_session(session), HashSet::from(["api.example.com".into(), "ws.example.org".into()]));
+    }
+
+    #[test]
+    fn test_xml_attribute() {
+        assert_eq!(xml_attribute(r#" method="GET" host="a.b""#, "host"), Some("a.b".to_string()));
+        assert_eq!(xml_attribute(r#" method="GET" virtualhost="a.b""#, "host"), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use crate::dns::StrResult;
+use super::flow::Flow;
+
+const CONNTRACK_PATHS: [&str; 2] = ["/proc/net/nf_conntrack", "/proc/net/ip_conntrack"];
+
+pub fn flows_from_conntrack() -> StrResult<Vec<Flow>> {
+    let contents = CONNTRACK_PATHS
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .ok_or(format!("could not read any of {CONNTRACK_PATHS:?} (is nf_conntrack loaded and are you root?)"))?;
+    Ok(flows_from_conntrack_table(&contents))
+}
+
+fn flows_from_conntrack_table(contents: &str) -> Vec<Flow> {
+    contents.lines().filter_map(flow_from_line).collect()
+}
+
+fn flow_from_line(line: &str) -> Option<Flow> {
+    let tokens = line.split_ascii_whitespace().collect::<Vec<_>>();
+    let protocol = tokens
+        .iter()
+        .position(|t| ["tcp", "udp", "icmp", "sctp", "dccp", "udplite", "gre", "unknown"].contains(t))
+        .and_then(|pos| tokens.get(pos + 1))
+        .and_then(|n| n.parse::<u8>().ok())?;
+    let fields = tokens
+        .iter()
+        .filter_map(|t| t.split_once('='))
+        .fold(HashMap::new(), |mut acc, (key, value)| {
+            acc.entry(key).or_insert(value);
+            acc
+        });
+    Some(Flow {
+        src: fields.get("src")?.parse().ok()?,
+        dst: fields.get("dst")?.parse().ok()?,
+        dst_port: fields.get("dport").and_then(|p| p.parse().ok()).unwrap_or(0),
+        protocol,
+        bytes: fields.get("bytes").and_then(|b| b.parse().ok()).unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flows_from_conntrack_table() {
+        let table = "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.5 dst=93.184.216.34 sport=51000 dport=443 packets=10 bytes=4200 src=93.184.216.34 dst=192.168.1.5 sport=443 dport=51000 packets=8 bytes=9000 [ASSURED] mark=0 zone=0 use=2
+ipv4     2 udp      17 29 src=192.168.1.5 dst=1.1.1.1 sport=40000 dport=53 src=1.1.1.1 dst=192.168.1.5 sport=53 dport=40000 mark=0 use=1
+ipv6     10 tcp      6 117 TIME_WAIT src=2001:db8::5 dst=2001:db8::1 sport=40000 dport=443 src=2001:db8::1 dst=2001:db8::5 sport=443 dport=40000 [ASSURED] mark=0 use=1
+ipv4     2 icmp     1 29 src=192.168.1.5 dst=8.8.8.8 type=8 code=0 id=1 src=8.8.8.8 dst=192.168.1.5 type=0 code=0 id=1 mark=0 use=1
+garbage line
+";
+        let flows = flows_from_conntrack_table(table);
+        assert_eq!(flows.len(), 4);
+        assert_eq!(flows[0], Flow { src: "192.168.1.5".parse().unwrap(), dst: "93.184.216.34".parse().unwrap(), dst_port: 443, protocol: 6, bytes: 4200 });
+        assert_eq!(flows[1].dst_port, 53);
+        assert_eq!(flows[1].protocol, 17);
+        assert_eq!(flows[2].dst, "2001:db8::1".parse::<std::net::IpAddr>().unwrap());
+        assert_eq!(flows[3].dst_port, 0);
+    }
+
+    #[test]
+    fn test_conntrack_cli_format() {
+        let line = "tcp      6 431999 ESTABLISHED src=10.0.0.2 dst=203.0.113.9 sport=5000 dport=8443 src=203.0.113.9 dst=10.0.0.2 sport=8443 dport=5000 [ASSURED] mark=0 use=1";
+        assert_eq!(flow_from_line(line).map(|f| (f.dst.to_string(), f.dst_port)), Some(("203.0.113.9".to_string(), 8443)));
+    }
+}
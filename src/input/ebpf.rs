@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::dns::StrResult;
+use super::flow::Flow;
+use super::process::ProcessSelector;
+
+// the kernel keeps the events in the queue until they are read, so connections that come and go between two reads are
+// still seen
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const QUEUE_ENTRIES: u32 = 16384;
+const TRACEPOINT: &str = "events/syscalls/sys_enter_connect/id";
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+// what the program pushes for each connect(): pid and tgid, cgroup id, comm, then the sockaddr as the caller passed it
+const EVENT_SIZE: usize = 64;
+
+// bpf(2) commands, map and program types, and helpers, from linux/bpf.h
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_MAP_LOOKUP_AND_DELETE_ELEM: libc::c_long = 21;
+const BPF_MAP_TYPE_QUEUE: u32 = 22;
+const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const GET_CURRENT_PID_TGID: i32 = 14;
+const GET_CURRENT_COMM: i32 = 16;
+const GET_CURRENT_CGROUP_ID: i32 = 80;
+const MAP_PUSH_ELEM: i32 = 87;
+const PROBE_READ_USER: i32 = 112;
+
+// perf_event_open(2) and its ioctls, from linux/perf_event.h
+const PERF_TYPE_TRACEPOINT: u32 = 2;
+const PERF_SAMPLE_RAW: u64 = 1 << 10;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x4004_2408;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn { code, regs: dst | (src << 4), off, imm }
+}
+
+// the tracepoint program: for a connect() to an IPv4 or IPv6 address, the event goes into the queue map
+fn program(map_fd: i32) -> Vec<Insn> {
+    let (mov, mov_imm, add_imm, ldx_dw, ldx_h, stx_dw, st_w, call, exit) = (0xbf, 0xb7, 0x07, 0x79, 0x69, 0x7b, 0x62, 0x85, 0x95);
+    let (jeq_imm, jne_imm, ld_imm64) = (0x15, 0x55, 0x18);
+    vec![
+        insn(mov, 6, 1, 0, 0),
+        // the sockaddr pointer, the second argument of the syscall
+        insn(ldx_dw, 7, 6, 24, 0),
+        insn(call, 0, 0, 0, GET_CURRENT_PID_TGID),
+        insn(stx_dw, 10, 0, -64, 0),
+        insn(call, 0, 0, 0, GET_CURRENT_CGROUP_ID),
+        insn(stx_dw, 10, 0, -56, 0),
+        insn(mov, 1, 10, 0, 0),
+        insn(add_imm, 1, 0, 0, -48),
+        insn(mov_imm, 2, 0, 0, 16),
+        insn(call, 0, 0, 0, GET_CURRENT_COMM),
+        insn(st_w, 10, 0, -4, 0),
+        insn(mov, 1, 10, 0, 0),
+        insn(add_imm, 1, 0, 0, -32),
+        insn(mov_imm, 2, 0, 0, 28),
+        insn(mov, 3, 7, 0, 0),
+        insn(call, 0, 0, 0, PROBE_READ_USER),
+        insn(jne_imm, 0, 0, 9, 0),
+        insn(ldx_h, 1, 10, -32, 0),
+        insn(jeq_imm, 1, 0, 1, libc::AF_INET),
+        insn(jne_imm, 1, 0, 6, libc::AF_INET6),
+        insn(ld_imm64, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+        insn(0, 0, 0, 0, 0),
+        insn(mov, 2, 10, 0, 0),
+        insn(add_imm, 2, 0, 0, -64),
+        insn(mov_imm, 3, 0, 0, 0),
+        insn(call, 0, 0, 0, MAP_PUSH_ELEM),
+        insn(mov_imm, 0, 0, 0, 0),
+        insn(exit, 0, 0, 0, 0),
+    ]
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> std::io::Result<i32> {
+    // SAFETY: attr is one of the bpf_attr layouts that cmd takes, and lives through the call
+    let ret = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, std::mem::size_of::<T>()) };
+    if ret < 0 { Err(std::io::Error::last_os_error()) } else { Ok(ret as i32) }
+}
+
+fn owned(fd: i32) -> OwnedFd {
+    // SAFETY: a file descriptor the kernel just returned, owned by nobody else
+    unsafe { OwnedFd::from_raw_fd(fd) }
+}
+
+#[repr(C)]
+struct MapCreate {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct ProgLoad {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+#[repr(C)]
+struct MapElem {
+    map_fd: u32,
+    pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+fn load(insns: &[Insn]) -> StrResult<OwnedFd> {
+    let license = c"GPL";
+    let mut attr = ProgLoad {
+        prog_type: BPF_PROG_TYPE_TRACEPOINT,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+    };
+    bpf(BPF_PROG_LOAD, &mut attr).map(owned).map_err(|e| {
+        // again with the verifier's log, to tell why
+        let mut log = vec![0u8; 1 << 16];
+        (attr.log_level, attr.log_size, attr.log_buf) = (1, log.len() as u32, log.as_mut_ptr() as u64);
+        let _ = bpf(BPF_PROG_LOAD, &mut attr);
+        let log = String::from_utf8_lossy(&log[..log.iter().position(|b| *b == 0).unwrap_or(0)]).trim().lines().last().unwrap_or_default().to_string();
+        format!("could not load the connect() tracing program: {e}{}", if log.is_empty() { String::new() } else { format!(" ({log})") })
+    })
+}
+
+fn tracepoint_id() -> StrResult<u64> {
+    TRACEFS
+        .iter()
+        .find_map(|dir| std::fs::read_to_string(Path::new(dir).join(TRACEPOINT)).ok())
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or("could not find the sys_enter_connect tracepoint, is tracefs mounted on /sys/kernel/tracing?".to_string())
+}
+
+// the program run by the tracepoint on each CPU, for as long as the descriptors are open
+fn attach(prog: &OwnedFd) -> StrResult<Vec<OwnedFd>> {
+    let id = tracepoint_id()?;
+    // SAFETY: sysconf has no preconditions
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as i32;
+    let events = (0..cpus)
+        .filter_map(|cpu| {
+            let mut attr = PerfEventAttr {
+                kind: PERF_TYPE_TRACEPOINT,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config: id,
+                sample_period: 1,
+                sample_type: PERF_SAMPLE_RAW,
+                wakeup_events: 1,
+                ..PerfEventAttr::default()
+            };
+            // SAFETY: attr is a perf_event_attr of the size it says, pid -1 with a CPU watches every process on it
+            let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &mut attr as *mut PerfEventAttr, -1, cpu, -1, PERF_FLAG_FD_CLOEXEC) };
+            (fd >= 0).then(|| owned(fd as i32))
+        })
+        .collect::<Vec<_>>();
+    if events.is_empty() {
+        return Err(format!("could not open the sys_enter_connect tracepoint: {}", std::io::Error::last_os_error()));
+    }
+    events.iter().try_for_each(|event| {
+        // SAFETY: ioctls of a perf event descriptor, the first taking a program descriptor
+        let attached = unsafe { libc::ioctl(event.as_raw_fd(), PERF_EVENT_IOC_SET_BPF as _, prog.as_raw_fd()) == 0 && libc::ioctl(event.as_raw_fd(), PERF_EVENT_IOC_ENABLE as _, 0) == 0 };
+        if attached { Ok(()) } else { Err(format!("could not attach the connect() tracing program: {}", std::io::Error::last_os_error())) }
+    })?;
+    Ok(events)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Connect {
+    pid: u32,
+    cgroup: u64,
+    comm: String,
+    dst: IpAddr,
+    dst_port: u16,
+}
+
+fn connect_from_event(event: &[u8; EVENT_SIZE]) -> Option<Connect> {
+    let u64_at = |at: usize| u64::from_ne_bytes(event[at..at + 8].try_into().unwrap_or_default());
+    let comm = &event[16..32];
+    let comm = String::from_utf8_lossy(&comm[..comm.iter().position(|b| *b == 0).unwrap_or(comm.len())]).into_owned();
+    let addr = &event[32..60];
+    let family = i32::from(u16::from_ne_bytes([addr[0], addr[1]]));
+    let dst_port = u16::from_be_bytes([addr[2], addr[3]]);
+    let dst = match family {
+        libc::AF_INET => IpAddr::V4(Ipv4Addr::new(addr[4], addr[5], addr[6], addr[7])),
+        libc::AF_INET6 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addr[8..24]).ok()?);
+            ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip))
+        }
+        _ => return None,
+    };
+    Some(Connect { pid: (u64_at(0) >> 32) as u32, cgroup: u64_at(8), comm, dst, dst_port })
+}
+
+// where the cgroup v2 hierarchy is mounted, whose directory inodes are the ids the program sees
+fn cgroup2_root() -> Option<PathBuf> {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .ok()?
+        .lines()
+        .find(|line| line.split_once(" - ").is_some_and(|(_, rest)| rest.starts_with("cgroup2 ")))
+        .and_then(|line| line.split_whitespace().nth(4).map(PathBuf::from))
+}
+
+// the ids of the cgroups the selector takes, found again on each read for the scopes started meanwhile
+fn cgroup_ids(selector: &ProcessSelector, root: &Path) -> HashSet<u64> {
+    let mut ids = HashSet::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let path = format!("/{}", dir.strip_prefix(root).unwrap_or(&dir).display());
+        if selector.cgroup_path_matches(&path) && let Ok(meta) = dir.metadata() {
+            ids.insert(meta.ino());
+        }
+        std::fs::read_dir(&dir).into_iter().flatten().flatten().filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir())).for_each(|entry| dirs.push(entry.path()));
+    }
+    ids
+}
+
+// the destinations of every connect() of the selected processes for the duration, short-lived connections included;
+// needs root, or CAP_BPF and CAP_PERFMON
+pub fn flows_from_connects(selector: &ProcessSelector, duration: Duration) -> StrResult<Vec<Flow>> {
+    let mut attr = MapCreate { map_type: BPF_MAP_TYPE_QUEUE, key_size: 0, value_size: EVENT_SIZE as u32, max_entries: QUEUE_ENTRIES, map_flags: 0 };
+    let map = bpf(BPF_MAP_CREATE, &mut attr).map(owned).map_err(|e| format!("could not create the eBPF queue: {e}"))?;
+    let prog = load(&program(map.as_raw_fd()))?;
+    let _events = attach(&prog)?;
+    let root = cgroup2_root();
+    if selector.is_scoped() && root.is_none() {
+        return Err("no cgroup v2 hierarchy is mounted, so --cgroup and --unit cannot be told apart".to_string());
+    }
+    let deadline = Instant::now() + duration;
+    let mut seen = HashSet::new();
+    let mut flows = Vec::new();
+    loop {
+        let done = Instant::now() >= deadline;
+        let cgroups = root.as_deref().filter(|_| selector.is_scoped()).map(|root| cgroup_ids(selector, root)).unwrap_or_default();
+        let mut event = [0u8; EVENT_SIZE];
+        let mut elem = MapElem { map_fd: map.as_raw_fd() as u32, pad: 0, key: 0, value: event.as_mut_ptr() as u64, flags: 0 };
+        while bpf(BPF_MAP_LOOKUP_AND_DELETE_ELEM, &mut elem).is_ok() {
+            let connect = connect_from_event(&event).filter(|c| !c.dst.is_unspecified() && c.pid != std::process::id());
+            if let Some(c) = connect.filter(|c| (selector.names.is_empty() || selector.matches_comm(&c.comm)) && (!selector.is_scoped() || cgroups.contains(&c.cgroup)))
+                && seen.insert((c.dst, c.dst_port))
+            {
+                flows.push(Flow { src: IpAddr::V4(Ipv4Addr::UNSPECIFIED), dst: c.dst, dst_port: c.dst_port, protocol: 0, bytes: 0 });
+            }
+        }
+        if done {
+            return Ok(flows);
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_from_event() {
+        let mut event = [0u8; EVENT_SIZE];
+        event[..8].copy_from_slice(&((4242u64 << 32) | 4243).to_ne_bytes());
+        event[8..16].copy_from_slice(&77u64.to_ne_bytes());
+        event[16..21].copy_from_slice(b"steam");
+        event[32..34].copy_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+        event[34..36].copy_from_slice(&443u16.to_be_bytes());
+        event[36..40].copy_from_slice(&[93, 184, 216, 34]);
+        assert_eq!(connect_from_event(&event),
+            Some(Connect { pid: 4242, cgroup: 77, comm: "steam".to_string(), dst: "93.184.216.34".parse().unwrap(), dst_port: 443 }));
+
+        event[32..34].copy_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+        event[40..56].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        assert_eq!(connect_from_event(&event).map(|c| c.dst), Some("2001:db8::1".parse().unwrap()));
+        event[40..56].copy_from_slice(&"::ffff:198.51.100.7".parse::<Ipv6Addr>().unwrap().octets());
+        assert_eq!(connect_from_event(&event).map(|c| c.dst), Some("198.51.100.7".parse().unwrap()));
+        event[32..34].copy_from_slice(&(libc::AF_UNIX as u16).to_ne_bytes());
+        assert_eq!(connect_from_event(&event), None);
+    }
+
+    #[test]
+    fn test_program() {
+        let insns = program(7);
+        assert_eq!(insns.last(), Some(&insn(0x95, 0, 0, 0, 0)));
+        // the jumps to the end land on r0 = 0 before exit
+        let end = insns.len() as i16 - 2;
+        [16, 19].iter().for_each(|at| assert_eq!(*at as i16 + 1 + insns[*at].off, end));
+        assert_eq!((insns[20].regs, insns[20].imm), (1 | (BPF_PSEUDO_MAP_FD << 4), 7));
+    }
+}
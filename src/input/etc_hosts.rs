@@ -0,0 +1,67 @@
+use std::net::IpAddr;
+use crate::dns::{ascii_host, StrResult};
+use crate::routes::{is_local, PassiveDns};
+
+// an /etc/hosts style file: its name, or a file with the .hosts extension
+pub fn is_hosts_file(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.file_name().is_some_and(|name| name == "hosts") || path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("hosts"))
+}
+
+pub fn pinned_from_hosts_file(path: &str) -> StrResult<PassiveDns> {
+    let text = if path == super::STDIN {
+        std::io::read_to_string(std::io::stdin()).map_err(|e| format!("could not read hosts file from standard input: {e}"))?
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("could not read hosts file {path}: {e}"))?
+    };
+    pinned_from_hosts_text(&text).map_err(|e| format!("{e} in hosts file {path}"))
+}
+
+// "address name alias..." lines, # starts a comment; the names get the addresses pinned to them; entries with local or
+// unspecified addresses (localhost, the LAN, 0.0.0.0 of blocklists) are skipped, they are never routed via the tunnel
+pub fn pinned_from_hosts_text(text: &str) -> StrResult<PassiveDns> {
+    let mut pinned = PassiveDns::new();
+    text.lines().enumerate().try_for_each(|(n, line)| {
+        let mut fields = line.split('#').next().unwrap_or_default().split_whitespace();
+        let Some(addr) = fields.next() else {
+            return Ok(());
+        };
+        // the zone of a link-local address does not matter, it is skipped anyway
+        let ip = addr.split('%').next().unwrap_or(addr).parse::<IpAddr>().map_err(|e| format!("bad address '{addr}' on line {}: {e}", n + 1))?;
+        let names = fields.map(|name| ascii_host(name.trim_end_matches('.')).to_ascii_lowercase()).collect::<Vec<_>>();
+        if names.is_empty() {
+            return Err(format!("no hostname for {addr} on line {}", n + 1));
+        }
+        if !is_local(&ip) && !ip.is_unspecified() {
+            names.into_iter().for_each(|name| {
+                pinned.entry(name).or_default().insert(ip.to_string());
+            });
+        }
+        Ok(())
+    })?;
+    Ok(pinned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_pinned_from_hosts_text() {
+        let text = "127.0.0.1\tlocalhost\n::1 localhost ip6-localhost\n192.168.1.10 nas.lan\n0.0.0.0 ads.example.net\n\n\
+            # streaming, pinned to what the VPN provider resolves\n203.0.113.5  video.example.com  Cdn.Example.com.\n\
+            203.0.113.6 video.example.com # second edge\n2001:db8::7 api.example.org\nfe80::1%eth0 router\n";
+        assert_eq!(pinned_from_hosts_text(text), Ok(PassiveDns::from([
+            ("video.example.com".to_string(), HashSet::from(["203.0.113.5".to_string(), "203.0.113.6".into()])),
+            ("cdn.example.com".to_string(), HashSet::from(["203.0.113.5".to_string()])),
+            ("api.example.org".to_string(), HashSet::from(["2001:db8::7".to_string()])),
+        ])));
+        assert_eq!(pinned_from_hosts_text("203.0.113.7 пример.рф\n").unwrap().keys().collect::<Vec<_>>(), vec!["xn--e1afmkfd.xn--p1ai"]);
+        assert_eq!(pinned_from_hosts_text("203.0.113.5\n"), Err("no hostname for 203.0.113.5 on line 1".to_string()));
+        assert!(pinned_from_hosts_text("example.com 203.0.113.5\n").unwrap_err().starts_with("bad address 'example.com' on line 1"));
+        assert!(is_hosts_file("/etc/hosts"));
+        assert!(is_hosts_file("streaming.HOSTS"));
+        assert!(!is_hosts_file("hosts.txt"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flow {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+    pub protocol: u8,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FlowFilter {
+    pub src_nets: Vec<ipnetwork::IpNetwork>,
+    pub dst_ports: Vec<u16>,
+    pub min_bytes: u64,
+}
+
+impl FlowFilter {
+    fn matches(&self, flow: &Flow) -> bool {
+        (self.src_nets.is_empty() || self.src_nets.iter().any(|net| net.contains(flow.src)))
+            && (self.dst_ports.is_empty() || self.dst_ports.contains(&flow.dst_port))
+    }
+}
+
+pub fn destinations(flows: &[Flow], filter: &FlowFilter) -> HashMap<IpAddr, u64> {
+    let totals = flows
+        .iter()
+        .filter(|f| filter.matches(f))
+        .fold(HashMap::new(), |mut acc: HashMap<IpAddr, u64>, f| {
+            *acc.entry(f.dst).or_default() += f.bytes;
+            acc
+        });
+    totals.into_iter().filter(|(_, bytes)| *bytes >= filter.min_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(src: &str, dst: &str, dst_port: u16, bytes: u64) -> Flow {
+        Flow { src: src.parse().unwrap(), dst: dst.parse().unwrap(), dst_port, protocol: 6, bytes }
+    }
+
+    #[test]
+    fn test_destinations() {
+        let flows = [
+            flow("10.0.0.2", "8.8.8.8", 443, 100),
+            flow("10.0.0.3", "8.8.8.8", 443, 50),
+            flow("10.0.0.2", "1.1.1.1", 53, 10),
+            flow("192.168.0.2", "9.9.9.9", 443, 1000),
+        ];
+        assert_eq!(destinations(&flows, &FlowFilter::default()).len(), 3);
+        let filter = FlowFilter { src_nets: vec!["10.0.0.0/8".parse().unwrap()], dst_ports: vec![443], min_bytes: 0 };
+        assert_eq!(destinations(&flows, &filter), HashMap::from([("8.8.8.8".parse().unwrap(), 150)]));
+        let filter = FlowFilter { min_bytes: 150, ..Default::default() };
+        assert_eq!(destinations(&flows, &filter).len(), 2);
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use base64::Engine;
+use serde_json::Value;
+use crate::dns::{discard_port, StrResult};
+use super::{hostname_from_url, hostnames_from_text, Hosts};
+use super::headers::hostnames_from_header;
+use super::lenient;
+use super::sockets::hostnames_from_extensions;
+use super::webapp::{hostnames_from_manifest, hostnames_from_worker_script, is_manifest, registered_worker_paths};
+
+#[derive(Debug, Clone, Default)]
+pub struct HarOptions {
+    pub scan_bodies: bool,
+    pub mine_headers: bool,
+    pub lenient: bool,
+    pub tls: TlsFilter,
+    // only the entries that look blocked
+    pub only_blocked: bool,
+}
+
+impl HarOptions {
+    fn reads(&self, entry: &Entry) -> bool {
+        self.tls.keeps(entry.handshake) && (!self.only_blocked || entry.blocked.is_some())
+    }
+}
+
+// how the TLS handshake of an entry went: done, as a response or the browser's security state shows, never done
+// (reset, timed out, certificate swapped: what blocking looks like), or no TLS to do, for plain http
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handshake {
+    Completed,
+    Failed,
+    NotTls,
+}
+
+// which entries are read, by their handshake: all of them, all but those whose handshake failed, or only those
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TlsFilter {
+    #[default]
+    All,
+    Completed,
+    Failed,
+}
+
+impl TlsFilter {
+    fn keeps(self, handshake: Handshake) -> bool {
+        match self {
+            TlsFilter::All => true,
+            TlsFilter::Completed => handshake != Handshake::Failed,
+            TlsFilter::Failed => handshake == Handshake::Failed,
+        }
+    }
+}
+
+struct Log {
+    creator: String,
+    entries: Vec<Entry>,
+    socket_hosts: Vec<String>,
+}
+
+struct Entry {
+    url: String,
+    server_ip: Option<String>,
+    request_headers: Vec<(String, String)>,
+    response_headers: Vec<(String, String)>,
+    body: Option<Body>,
+    bytes: u64,
+    handshake: Handshake,
+    // why it looks blocked, if it does
+    blocked: Option<String>,
+}
+
+struct Body {
+    mime_type: String,
+    text: String,
+    encoding: Option<String>,
+}
+
+impl Body {
+    fn decoded_text(&self) -> Option<String> {
+        match self.encoding.as_deref() {
+            Some("base64") => base64::engine::general_purpose::STANDARD
+                .decode(&self.text)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok()),
+            _ => Some(self.text.clone()),
+        }
+    }
+}
+
+pub fn hostnames_from_har(path: &str, opts: &HarOptions) -> StrResult<Hosts> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read HAR file {path}: {e}"))?;
+    hostnames_from_har_text(&text, path, opts)
+}
+
+fn parse_log(text: &str, name: &str, opts: &HarOptions) -> StrResult<Log> {
+    if opts.lenient {
+        lenient_log_from_har(text, name)
+    } else {
+        log_from_har(text, name)
+    }
+}
+
+// name only labels the HAR in messages, the text may come from anywhere (a file, a browser drop zone)
+pub fn hostnames_from_har_text(text: &str, name: &str, opts: &HarOptions) -> StrResult<Hosts> {
+    hosts_from_log(&parse_log(text, name, opts)?, name, opts)
+}
+
+// hostnames plus the addresses the browser actually connected to (serverIPAddress), from a single parse
+pub fn hostnames_and_addresses_from_har_text(text: &str, name: &str, opts: &HarOptions) -> StrResult<(Hosts, HashMap<String, HashSet<String>>)> {
+    let log = parse_log(text, name, opts)?;
+    Ok((hosts_from_log(&log, name, opts)?, addresses_from_log(&log, opts)))
+}
+
+fn hosts_from_log(log: &Log, name: &str, opts: &HarOptions) -> StrResult<Hosts> {
+    if opts.only_blocked {
+        let blocked = log.entries.iter().filter(|entry| opts.reads(entry)).filter_map(|entry| Some((hostname_from_url(&entry.url)?, entry.blocked.as_deref()?)));
+        blocked.collect::<BTreeMap<_, _>>().into_iter().for_each(|(host, why)| eprintln!("likely blocked in {name}: {host}: {why}"));
+    }
+    let mut hosts = log.entries
+        .iter()
+        .filter(|entry| opts.reads(entry))
+        .try_fold(Hosts::default(), |mut acc, entry| {
+            let hostname = match hostname_from_url(&entry.url) {
+                Some(hostname) => hostname,
+                None if opts.lenient => {
+                    eprintln!("warning: skipping entry with unsupported URL '{}' in HAR file {name}", entry.url);
+                    return Ok(acc);
+                },
+                None => return Err(format!("could not extract hostname from URL {}", &entry.url)),
+            };
+            *acc.traffic.entry(hostname.clone()).or_default() += entry.bytes;
+            acc.confirmed.insert(hostname);
+            if opts.scan_bodies {
+                acc.candidates.extend(entry.body.iter().flat_map(hostnames_from_body));
+            }
+            if opts.mine_headers {
+                acc.candidates.extend(entry.response_headers
+                    .iter()
+                    .flat_map(|(name, value)| hostnames_from_header(name, value)));
+            }
+            Ok::<Hosts, String>(acc)
+        })?;
+    // nothing tells how the handshakes of the sockets went
+    if opts.tls != TlsFilter::Failed && !opts.only_blocked {
+        hosts.confirmed.extend(log.socket_hosts.iter().cloned());
+    }
+    if opts.scan_bodies {
+        hosts.candidates.extend(hostnames_from_webapp(log));
+    }
+    hosts.candidates.retain(|h| !hosts.confirmed.contains(h));
+    Ok(hosts)
+}
+
+// request and response body sizes; -1 is unknown, and a response body of unknown size is taken for its decoded size
+fn transferred(request_body: i64, response_body: i64, content_size: i64) -> u64 {
+    let response = if response_body >= 0 { response_body } else { content_size.max(0) };
+    (request_body.max(0) as u64).saturating_add(response as u64)
+}
+
+fn addresses_from_log(log: &Log, opts: &HarOptions) -> HashMap<String, HashSet<String>> {
+    log.entries
+        .iter()
+        .filter(|entry| opts.reads(entry))
+        .filter_map(|entry| {
+            let ip = entry.server_ip.as_deref()?.trim_matches(['[', ']']).parse::<IpAddr>().ok()?;
+            Some((discard_port(&hostname_from_url(&entry.url)?).to_string(), ip.to_string()))
+        })
+        .fold(HashMap::new(), |mut acc, (host, ip)| {
+            acc.entry(host).or_insert_with(HashSet::new).insert(ip);
+            acc
+        })
+}
+
+// Chromium's _securityState and _error and the timings tell what the spec has no field for; an error of the browser's
+// own blocking (an ad blocker) is no sign of the network blocking anything
+fn handshake(entry: &Value) -> Handshake {
+    let url = entry["request"]["url"].as_str().unwrap_or_default();
+    if !(url.starts_with("https://") || url.starts_with("wss://")) {
+        return Handshake::NotTls;
+    }
+    let responded = entry["response"]["status"].as_i64().is_some_and(|status| status > 0);
+    let secure = entry["_securityState"].as_str() == Some("secure");
+    let ssl_done = entry["timings"]["ssl"].as_f64().is_some_and(|ms| ms > 0.0);
+    let blocked_locally = entry["response"]["_error"].as_str().or(entry["_error"].as_str()).is_some_and(|e| e.contains("BLOCKED_BY_CLIENT"));
+    if responded || secure || ssl_done || blocked_locally { Handshake::Completed } else { Handshake::Failed }
+}
+
+// errors that blocking shows up as, by the names of Chromium's net:: and Firefox's NS_ERROR_ codes
+const BLOCKING_ERRORS: [(&str, &str); 10] = [
+    ("RESET", "connection reset"),
+    ("CONNECTION_CLOSED", "connection closed"),
+    ("EMPTY_RESPONSE", "empty response"),
+    ("TIMED_OUT", "timed out"),
+    ("TIMEOUT", "timed out"),
+    ("REFUSED", "connection refused"),
+    ("NAME_NOT_RESOLVED", "name not resolved"),
+    ("UNKNOWN_HOST", "name not resolved"),
+    ("SSL_PROTOCOL", "TLS protocol error"),
+    ("CERT_", "certificate error"),
+];
+
+// setting up a connection (TCP and TLS) takes this long when packets are dropped and sent again, rarely otherwise
+const SLOW_CONNECT_MS: f64 = 3000.0;
+// a request without a response that waited this long was given up on
+const GIVEN_UP_MS: f64 = 15000.0;
+
+// why the entry looks blocked: an error that blocking shows up as, a handshake that never completed, a connection that
+// took long to set up, or a long wait for no response; none for one that looks fine
+fn likely_blocked(entry: &Value, handshake: Handshake) -> Option<String> {
+    let error = entry["response"]["_error"].as_str().or(entry["_error"].as_str()).unwrap_or_default();
+    if let Some((_, what)) = BLOCKING_ERRORS.iter().find(|(code, _)| error.contains(code)) {
+        return Some(format!("{what} ({error})"));
+    }
+    if handshake == Handshake::Failed {
+        return Some("TLS handshake never completed".to_string());
+    }
+    let connect = entry["timings"]["connect"].as_f64().unwrap_or(-1.0);
+    if connect > SLOW_CONNECT_MS {
+        return Some(format!("connecting took {:.1} s", connect / 1000.0));
+    }
+    let responded = entry["response"]["status"].as_i64().is_some_and(|status| status > 0);
+    let time = entry["time"].as_f64().unwrap_or(-1.0);
+    (!responded && time > GIVEN_UP_MS).then(|| format!("no response after {:.1} s", time / 1000.0))
+}
+
+// the handshake of each entry and why it looks blocked, in the order of the entries; entries that are not objects are
+// left out, as the lenient parser drops them
+fn entry_states(text: &str) -> Vec<(Handshake, Option<String>)> {
+    let states = |entries: &Vec<Value>| {
+        entries.iter().filter(|entry| entry.is_object()).map(|entry| {
+            let handshake = handshake(entry);
+            (handshake, likely_blocked(entry, handshake))
+        }).collect()
+    };
+    serde_json::from_str::<Value>(text).ok().and_then(|har| har["log"]["entries"].as_array().map(states)).unwrap_or_default()
+}
+
+fn with_entry_states(mut log: Log, text: &str) -> Log {
+    log.entries.iter_mut().zip(entry_states(text)).for_each(|(entry, (handshake, blocked))| {
+        entry.handshake = handshake;
+        entry.blocked = blocked;
+    });
+    log
+}
+
+// the 1.2 and 1.3 entries are distinct types with the same fields
+macro_rules! log_from_spec {
+    ($log:expr, $socket_hosts:expr) => {{
+        let log = $log;
+        let entries = log.entries
+            .into_iter()
+            .map(|x| Entry {
+                url: x.request.url,
+                server_ip: x.server_ip_address,
+                request_headers: x.request.headers
+                    .into_iter()
+                    .map(|h| (h.name, h.value))
+                    .collect(),
+                response_headers: x.response.headers
+                    .into_iter()
+                    .map(|h| (h.name, h.value))
+                    .collect(),
+                bytes: transferred(x.request.body_size, x.response.body_size, x.response.content.size),
+                body: x.response.content.text.map(|text| Body {
+                    mime_type: x.response.content.mime_type.unwrap_or_default(),
+                    text,
+                    encoding: x.response.content.encoding,
+                }),
+                handshake: Handshake::NotTls,
+                blocked: None,
+            })
+            .collect();
+        Log { creator: log.creator.name, entries, socket_hosts: $socket_hosts }
+    }};
+}
+
+fn log_from_har(text: &str, path: &str) -> StrResult<Log> {
+    let har = ::har::from_reader(text.as_bytes())
+        .map_err(|e| format!("could not parse HAR file {path}: {e} (try --lenient)"))?;
+    let socket_hosts = hostnames_from_extensions(text).map_err(|e| format!("{e} in {path}"))?;
+    let log = match har.log {
+        ::har::Spec::V1_2(log) => log_from_spec!(log, socket_hosts),
+        ::har::Spec::V1_3(log) => log_from_spec!(log, socket_hosts),
+    };
+    Ok(with_entry_states(log, text))
+}
+
+fn lenient_log_from_har(text: &str, path: &str) -> StrResult<Log> {
+    let har = lenient::parse(text).map_err(|e| format!("{e} in {path}"))?;
+    let socket_hosts = hostnames_from_extensions(text).unwrap_or_else(|e| {
+        eprintln!("warning: ignoring vendor extensions in HAR file {path}: {e}");
+        Vec::new()
+    });
+    let entries = har.log.entries
+        .into_iter()
+        .map(|x| Entry {
+            url: x.request.url,
+            server_ip: x.server_ip_address,
+            request_headers: x.request.headers
+                .into_iter()
+                .map(|h| (h.name, h.value))
+                .collect(),
+            response_headers: x.response.headers
+                .into_iter()
+                .map(|h| (h.name, h.value))
+                .collect(),
+            bytes: transferred(x.request.body_size, x.response.body_size, x.response.content.size),
+            body: x.response.content.text.map(|text| Body {
+                mime_type: x.response.content.mime_type,
+                text,
+                encoding: x.response.content.encoding,
+            }),
+            handshake: Handshake::NotTls,
+            blocked: None,
+        })
+        .collect();
+    Ok(with_entry_states(Log { creator: har.log.creator.name, entries, socket_hosts }, text))
+}
+
+// headers that carry credentials or session state rather than anything about where the requests went
+fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    ["cookie", "set-cookie", "authorization", "proxy-authorization"].contains(&name.as_str())
+        || ["token", "secret", "session", "csrf", "api-key", "apikey", "auth"].iter().any(|part| name.contains(part))
+}
+
+fn without_query(url: &str) -> Option<String> {
+    let end = url.find(['?', '#'])?;
+    Some(url[..end].to_string())
+}
+
+// what scrub took out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scrubbed {
+    pub entries: usize,
+    pub cookies: usize,
+    pub headers: usize,
+    pub bodies: usize,
+    pub queries: usize,
+}
+
+// the HAR without cookies, credential headers, request bodies and query strings, safe to share; URLs keep their
+// scheme, host and path, and everything else (response bodies included, hosts are found in them) stays as it was
+pub fn scrub(text: &str) -> StrResult<(String, Scrubbed)> {
+    let mut har = serde_json::from_str::<serde_json::Value>(text).map_err(|e| format!("could not parse HAR: {e}"))?;
+    let entries = har.pointer_mut("/log/entries").and_then(|entries| entries.as_array_mut()).ok_or("not a HAR: no log.entries".to_string())?;
+    let mut scrubbed = Scrubbed { entries: entries.len(), ..Scrubbed::default() };
+    entries.iter_mut().for_each(|entry| {
+        ["request", "response"].into_iter().for_each(|part| {
+            let Some(part) = entry.get_mut(part).and_then(|part| part.as_object_mut()) else {
+                return;
+            };
+            if let Some(cookies) = part.get_mut("cookies").and_then(|cookies| cookies.as_array_mut()) {
+                scrubbed.cookies += cookies.len();
+                cookies.clear();
+            }
+            if let Some(headers) = part.get_mut("headers").and_then(|headers| headers.as_array_mut()) {
+                let before = headers.len();
+                headers.retain(|header| !header["name"].as_str().is_some_and(is_secret_header));
+                scrubbed.headers += before - headers.len();
+            }
+            if part.remove("postData").is_some() {
+                scrubbed.bodies += 1;
+            }
+            if let Some(query) = part.get_mut("queryString").and_then(|query| query.as_array_mut()) {
+                query.clear();
+            }
+            ["url", "redirectURL"].into_iter().for_each(|key| {
+                if let Some(url) = part.get(key).and_then(|url| url.as_str()).and_then(without_query) {
+                    part.insert(key.to_string(), url.into());
+                    scrubbed.queries += 1;
+                }
+            });
+        });
+    });
+    let text = serde_json::to_string_pretty(&har).map_err(|e| format!("could not serialize HAR: {e}"))?;
+    Ok((text, scrubbed))
+}
+
+fn hostnames_from_body(body: &Body) -> Vec<String> {
+    if !is_text_mime(&body.mime_type) {
+        return Vec::new();
+    }
+    body.decoded_text()
+        .map(|text| hostnames_from_text(&text))
+        .unwrap_or_default()
+}
+
+fn hostnames_from_webapp(log: &Log) -> Vec<String> {
+    let worker_paths = log.entries
+        .iter()
+        .filter_map(|e| e.body.as_ref().and_then(Body::decoded_text))
+        .flat_map(|text| registered_worker_paths(&text))
+        .collect::<Vec<_>>();
+    log.entries
+        .iter()
+        .flat_map(|entry| {
+            let Some(body) = &entry.body else {
+                return Vec::new();
+            };
+            let text = body.decoded_text().unwrap_or_default();
+            if is_manifest(&entry.url, &body.mime_type) {
+                hostnames_from_manifest(&text)
+            } else if is_worker_script(entry, &worker_paths) {
+                hostnames_from_worker_script(&text, &log.creator)
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+fn is_worker_script(entry: &Entry, worker_paths: &[String]) -> bool {
+    let path = entry.url.split(['?', '#']).next().unwrap_or_default();
+    entry.request_headers.iter().any(|(name, value)| name.eq_ignore_ascii_case("service-worker") && value == "script")
+        || worker_paths.iter().any(|p| path.ends_with(p.as_str()))
+}
+
+fn is_text_mime(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("javascript")
+        || mime.ends_with("json")
+        || mime.ends_with("xml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn har_files() -> Vec<String> {
+        let may_be_entries = std::fs::read_dir(format!("{}/tests/private/", env!("CARGO_MANIFEST_DIR")))
+            .into_iter()
+            .flatten();
+        std::fs::read_dir(format!("{}/tests/", env!("CARGO_MANIFEST_DIR")))
+            .unwrap()
+            .chain(may_be_entries)
+            .map(|path| path.unwrap().path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.to_str().unwrap() == "har"))
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_hostnames_from_har() {
+        har_files().into_iter().for_each(|path| {
+            let hostnames = hostnames_from_har(&path, &HarOptions::default()).unwrap();
+            println!("{path} yields hostnames ({}): {:?}", hostnames.confirmed.len(), hostnames.confirmed);
+            assert!(hostnames.confirmed.len() >= 3);
+            assert!(hostnames.candidates.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_har_versions() {
+        let fixture = |version: &str| format!("{}/tests/example-{version}.har", env!("CARGO_MANIFEST_DIR"));
+        let expected = ["api.example.com", "cdn.example.net", "www.example.org"].map(String::from);
+        ["1.2", "1.3"].iter().for_each(|version| {
+            let text = std::fs::read_to_string(fixture(version)).unwrap();
+            assert!(text.contains(&format!("\"version\": \"{version}\"")));
+            let (hosts, addresses) = hostnames_and_addresses_from_har_text(&text, version, &HarOptions::default()).unwrap();
+            assert_eq!(hosts.confirmed, HashSet::from(expected.clone()), "HAR {version}");
+            assert_eq!(addresses["cdn.example.net"], HashSet::from(["2606:4700::6810:84e5".to_string()]), "HAR {version}");
+            assert_eq!(addresses["api.example.com"], HashSet::from(["203.0.113.10".to_string()]), "HAR {version}");
+            assert_eq!(hosts.traffic["cdn.example.net"], 46, "HAR {version}");
+        });
+    }
+
+    #[test]
+    fn test_scrub() {
+        let har = r#"{"log": {"version": "1.2", "creator": {"name": "test", "version": "1"}, "entries": [{
+            "request": {"method": "POST", "url": "https://api.example.com/login?user=me&token=x#top", "cookies": [{"name": "sid", "value": "1"}],
+                "headers": [{"name": "Authorization", "value": "Bearer x"}, {"name": "X-CSRF-Token", "value": "y"}, {"name": "Accept", "value": "*/*"}],
+                "queryString": [{"name": "user", "value": "me"}], "postData": {"mimeType": "application/json", "text": "{\"password\": \"hunter2\"}"}},
+            "response": {"status": 302, "cookies": [], "headers": [{"name": "Set-Cookie", "value": "sid=2"}, {"name": "Location", "value": "https://www.example.com/"}],
+                "content": {"size": 0, "mimeType": "text/html"}, "redirectURL": "https://www.example.com/?welcome=me"}}]}}"#;
+        let (text, scrubbed) = scrub(har).unwrap();
+        assert_eq!(scrubbed, Scrubbed { entries: 1, cookies: 1, headers: 3, bodies: 1, queries: 2 });
+        ["hunter2", "Bearer", "sid", "user=me", "welcome", "X-CSRF-Token"].iter().for_each(|secret| assert!(!text.contains(secret), "{secret} left in {text}"));
+        assert!(text.contains("\"https://api.example.com/login\""));
+        assert!(text.contains("\"Location\""));
+        assert!(scrub("{}").is_err());
+
+        let fixture = format!("{}/tests/example-1.2.har", env!("CARGO_MANIFEST_DIR"));
+        let original = std::fs::read_to_string(&fixture).unwrap();
+        let hosts = |text: &str| hostnames_from_har_text(text, &fixture, &HarOptions::default()).unwrap().confirmed;
+        assert_eq!(hosts(&scrub(&original).unwrap().0), hosts(&original));
+    }
+
+    #[test]
+    fn test_tls_filter() {
+        let har = r#"{"log": {"creator": {"name": "test"}, "entries": [
+            {"request": {"url": "https://ok.example.com/"}, "response": {"status": 200}, "timings": {"ssl": 31.5}},
+            7,
+            {"request": {"url": "https://blocked.example.com/"}, "response": {"status": 0, "_error": "net::ERR_CONNECTION_RESET"}, "timings": {"ssl": -1}},
+            {"request": {"url": "https://ads.example.com/"}, "response": {"status": 0, "_error": "net::ERR_BLOCKED_BY_CLIENT"}},
+            {"request": {"url": "http://plain.example.com/"}, "response": {"status": 0}},
+            {"request": {"url": "wss://live.example.com/"}, "response": {"status": 0}, "_securityState": "secure"}]}}"#;
+        assert_eq!(entry_states(har).into_iter().map(|(handshake, _)| handshake).collect::<Vec<_>>(), vec![Handshake::Completed, Handshake::Failed, Handshake::Completed, Handshake::NotTls, Handshake::Completed]);
+        let hosts = |tls| {
+            let hosts = hostnames_from_har_text(har, "test", &HarOptions { lenient: true, tls, ..Default::default() }).unwrap();
+            hosts.confirmed.into_iter().collect::<std::collections::BTreeSet<_>>().into_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(hosts(TlsFilter::All).len(), 5);
+        assert_eq!(hosts(TlsFilter::Completed), vec!["ads.example.com", "live.example.com", "ok.example.com", "plain.example.com"]);
+        assert_eq!(hosts(TlsFilter::Failed), vec!["blocked.example.com"]);
+    }
+
+    #[test]
+    fn test_likely_blocked() {
+        let har = r#"{"log": {"creator": {"name": "test"}, "entries": [
+            {"request": {"url": "https://ok.example.com/"}, "response": {"status": 200}, "timings": {"connect": 120, "ssl": 60}, "time": 400},
+            {"request": {"url": "http://reset.example.com/"}, "response": {"status": 0, "_error": "net::ERR_CONNECTION_RESET"}},
+            {"request": {"url": "https://tls.example.com/"}, "response": {"status": 0}, "timings": {"connect": -1, "ssl": -1}},
+            {"request": {"url": "https://slow.example.com/"}, "response": {"status": 200}, "timings": {"connect": 9500, "ssl": 9000}},
+            {"request": {"url": "http://hang.example.com/"}, "response": {"status": 0}, "time": 30000},
+            {"request": {"url": "https://ads.example.com/"}, "response": {"status": 0, "_error": "net::ERR_BLOCKED_BY_CLIENT"}}]}}"#;
+        assert_eq!(entry_states(har).into_iter().map(|(_, blocked)| blocked).collect::<Vec<_>>(), vec![
+            None,
+            Some("connection reset (net::ERR_CONNECTION_RESET)".to_string()),
+            Some("TLS handshake never completed".to_string()),
+            Some("connecting took 9.5 s".to_string()),
+            Some("no response after 30.0 s".to_string()),
+            None,
+        ]);
+        let opts = HarOptions { lenient: true, only_blocked: true, ..Default::default() };
+        let blocked = hostnames_from_har_text(har, "test", &opts).unwrap().confirmed.into_iter().collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(blocked.into_iter().collect::<Vec<_>>(), vec!["hang.example.com", "reset.example.com", "slow.example.com", "tls.example.com"]);
+    }
+
+    #[test]
+    fn test_transferred() {
+        assert_eq!(transferred(120, 4000, 9000), 4120);
+        assert_eq!(transferred(-1, -1, 9000), 9000);
+        assert_eq!(transferred(-1, 0, 9000), 0);
+        assert_eq!(transferred(-1, -1, -1), 0);
+    }
+
+    #[test]
+    fn test_hostnames_from_har_bodies() {
+        har_files().into_iter().for_each(|path| {
+            let plain = hostnames_from_har(&path, &HarOptions::default()).unwrap();
+            let scanned = hostnames_from_har(&path, &HarOptions { scan_bodies: true, ..Default::default() }).unwrap();
+            println!("{path} yields candidate hostnames from bodies: {:?}", scanned.candidates);
+            assert_eq!(scanned.confirmed, plain.confirmed);
+            assert!(scanned.candidates.is_disjoint(&scanned.confirmed));
+        });
+    }
+
+    #[test]
+    fn test_hostnames_from_har_lenient() {
+        har_files().into_iter().for_each(|path| {
+            let strict = hostnames_from_har(&path, &HarOptions::default()).unwrap();
+            let lenient = hostnames_from_har(&path, &HarOptions { lenient: true, ..Default::default() }).unwrap();
+            assert_eq!(strict, lenient);
+        });
+    }
+
+    #[test]
+    fn test_hostnames_from_har_headers() {
+        har_files().into_iter().for_each(|path| {
+            let mined = hostnames_from_har(&path, &HarOptions { mine_headers: true, ..Default::default() }).unwrap();
+            println!("{path} yields candidate hostnames from headers: {:?}", mined.candidates);
+            assert!(mined.candidates.is_disjoint(&mined.confirmed));
+        });
+    }
+
+    #[test]
+    fn test_hostnames_from_webapp() {
+        let entry = |url: &str, request_headers: Vec<(String, String)>, mime: &str, text: &str| Entry {
+            url: url.to_string(),
+            server_ip: None,
+            request_headers,
+            response_headers: Vec::new(),
+            body: Some(Body { mime_type: mime.to_string(), text: text.to_string(), encoding: None }),
+            bytes: 0,
+            handshake: Handshake::NotTls,
+            blocked: None,
+        };
+        let log = Log {
+            creator: "WebInspector".to_string(),
+            socket_hosts: Vec::new(),
+            entries: vec![
+                entry("https://x.com/", Vec::new(), "text/html", "<script>navigator.serviceWorker.register('/sw.js')</script>"),
+                entry("https://x.com/sw.js?v=1", Vec::new(), "text/javascript", "self.onpush = e => {}"),
+                entry("https://x.com/site.webmanifest", Vec::new(), "application/json", r#"{"start_url": "https://m.x-app.com/"}"#),
+                entry("https://x.com/other.js", vec![("Service-Worker".into(), "script".into())], "text/javascript", "importScripts('firebase-messaging.js')"),
+            ],
+        };
+        let hosts = hostnames_from_webapp(&log);
+        assert!(hosts.contains(&"fcm.googleapis.com".to_string()));
+        assert!(hosts.contains(&"firebaseinstallations.googleapis.com".to_string()));
+        assert!(hosts.contains(&"m.x-app.com".to_string()));
+    }
+
+    #[test]
+    fn test_hostnames_from_body() {
+        let body = |mime: &str, text: &str, encoding: Option<&str>| Body {
+            mime_type: mime.to_string(),
+            text: text.to_string(),
+            encoding: encoding.map(String::from),
+        };
+        assert_eq!(hostnames_from_body(&body("text/html; charset=utf-8", "<a href=\"https://a.example.com\">", None)),
+            vec!["a.example.com".to_string()]);
+        assert_eq!(hostnames_from_body(&body("application/json", "aHR0cHM6Ly9iLmV4YW1wbGUuY29tLw==", Some("base64"))),
+            vec!["b.example.com".to_string()]);
+        assert!(hostnames_from_body(&body("image/png", "https://c.example.com", None)).is_empty());
+    }
+}
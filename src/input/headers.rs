@@ -0,0 +1,79 @@
+use super::{hostnames_from_text, is_plausible_hostname};
+
+pub fn hostnames_from_header(name: &str, value: &str) -> Vec<String> {
+    match name.to_ascii_lowercase().as_str() {
+        "content-security-policy" | "content-security-policy-report-only" => hostnames_from_csp(value),
+        "access-control-allow-origin" => hostname_from_origin(value).into_iter().collect(),
+        "report-to" | "reporting-endpoints" | "nel" => hostnames_from_text(value),
+        _ => Vec::new(),
+    }
+}
+
+fn hostnames_from_csp(policy: &str) -> Vec<String> {
+    policy
+        .split(';')
+        .flat_map(|directive| directive.split_ascii_whitespace().skip(1))
+        .filter_map(hostname_from_source)
+        .collect()
+}
+
+fn hostname_from_source(source: &str) -> Option<String> {
+    if source.starts_with('\'') {
+        return None;
+    }
+    let without_scheme = match source.split_once("://") {
+        Some((_scheme, rest)) => rest,
+        None if source.ends_with(':') => return None,
+        None => source,
+    };
+    let host = without_scheme
+        .split(['/', '?', '#'])
+        .next()?
+        .split(':')
+        .next()?
+        .trim_start_matches("*.")
+        .to_ascii_lowercase();
+    is_plausible_hostname(&host).then_some(host)
+}
+
+fn hostname_from_origin(origin: &str) -> Option<String> {
+    let origin = origin.trim();
+    if origin == "*" || origin == "null" {
+        None
+    } else {
+        hostname_from_source(origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostnames_from_csp() {
+        let csp = "default-src 'self'; script-src 'self' 'nonce-abc' https://*.cdn.example.com:443/js/ static.example.net; \
+            img-src data: blob: *; connect-src wss://live.example.com api.example.com:8443; \
+            report-uri https://csp.report.example.org/r?x=1";
+        assert_eq!(hostnames_from_csp(csp), vec![
+            "cdn.example.com".to_string(),
+            "static.example.net".into(),
+            "live.example.com".into(),
+            "api.example.com".into(),
+            "csp.report.example.org".into(),
+        ]);
+        assert!(hostnames_from_csp("default-src 'none'").is_empty());
+        assert!(hostnames_from_csp("").is_empty());
+    }
+
+    #[test]
+    fn test_hostnames_from_header() {
+        assert_eq!(hostnames_from_header("Access-Control-Allow-Origin", "https://www.example.com"), vec!["www.example.com".to_string()]);
+        assert!(hostnames_from_header("access-control-allow-origin", "*").is_empty());
+        assert!(hostnames_from_header("access-control-allow-origin", "null").is_empty());
+        assert_eq!(hostnames_from_header("Report-To", r#"{"group":"csp","endpoints":[{"url":"https://r.example.com/csp"}]}"#),
+            vec!["r.example.com".to_string()]);
+        assert_eq!(hostnames_from_header("content-security-policy-report-only", "connect-src x.example.com"),
+            vec!["x.example.com".to_string()]);
+        assert!(hostnames_from_header("content-type", "https://x.example.com").is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::dns::StrResult;
+
+#[derive(Deserialize, Default)]
+pub struct LenientHar {
+    #[serde(default, deserialize_with = "lenient")]
+    pub log: LenientLog,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientLog {
+    #[serde(default, deserialize_with = "lenient")]
+    pub creator: LenientCreator,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub entries: Vec<LenientEntry>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientCreator {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub name: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientEntry {
+    #[serde(default, deserialize_with = "lenient")]
+    pub request: LenientRequest,
+    #[serde(default, deserialize_with = "lenient")]
+    pub response: LenientResponse,
+    #[serde(rename = "serverIPAddress", default, deserialize_with = "lenient_opt_string")]
+    pub server_ip_address: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientRequest {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub url: String,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub headers: Vec<LenientHeader>,
+    #[serde(rename = "bodySize", default = "unknown_size", deserialize_with = "lenient")]
+    pub body_size: i64,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientResponse {
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub headers: Vec<LenientHeader>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub content: LenientContent,
+    #[serde(rename = "bodySize", default = "unknown_size", deserialize_with = "lenient")]
+    pub body_size: i64,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientHeader {
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub name: String,
+    #[serde(default, deserialize_with = "lenient_string")]
+    pub value: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LenientContent {
+    #[serde(rename = "mimeType", default, deserialize_with = "lenient_string")]
+    pub mime_type: String,
+    #[serde(default, deserialize_with = "lenient_opt_string")]
+    pub text: Option<String>,
+    #[serde(default, deserialize_with = "lenient_opt_string")]
+    pub encoding: Option<String>,
+    #[serde(default = "unknown_size", deserialize_with = "lenient")]
+    pub size: i64,
+}
+
+pub fn parse(text: &str) -> StrResult<LenientHar> {
+    serde_json::from_str(text).map_err(|e| format!("could not parse HAR even in lenient mode: {e}"))
+}
+
+// what the spec uses for a size that is not known
+fn unknown_size() -> i64 {
+    -1
+}
+
+fn lenient<'de, D: Deserializer<'de>, T: DeserializeOwned + Default>(d: D) -> Result<T, D::Error> {
+    let value = Value::deserialize(d)?;
+    Ok(serde_json::from_value(value).unwrap_or_default())
+}
+
+fn lenient_vec<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<Vec<T>, D::Error> {
+    match Value::deserialize(d)? {
+        Value::Array(items) => Ok(items.into_iter().filter_map(|v| serde_json::from_value(v).ok()).collect()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn lenient_opt_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::Null => None,
+        Value::String(s) => Some(s),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    })
+}
+
+fn lenient_string<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    Ok(lenient_opt_string(d)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quirks() {
+        let har = parse(r#"{"log": {
+            "version": null,
+            "creator": {"name": "WebKit Web Inspector"},
+            "entries": [
+                {"request": {"url": "https://a.example.com/", "headers": null, "bodySize": 123456789012345678901234567890},
+                 "response": {"status": "200", "content": {"mimeType": null, "text": 42}}, "time": -1},
+                {"request": {"url": null}},
+                "garbage",
+                {"request": {"url": "https://b.example.com/", "headers": [{"name": "Host", "value": null}, 7]},
+                 "response": {"headers": [{"name": "Content-Security-Policy", "value": "default-src c.example.com"}],
+                              "content": {"mimeType": "text/html", "text": null}}}
+            ]
+        }}"#).unwrap();
+        assert_eq!(har.log.creator.name, "WebKit Web Inspector");
+        assert_eq!(har.log.entries.len(), 3);
+        assert_eq!(har.log.entries[0].request.url, "https://a.example.com/");
+        assert_eq!(har.log.entries[0].response.content.text, Some("42".to_string()));
+        assert_eq!(har.log.entries[0].request.body_size, 0);
+        assert_eq!(har.log.entries[2].request.body_size, -1);
+        assert_eq!(har.log.entries[1].request.url, "");
+        assert_eq!(har.log.entries[2].request.headers.len(), 1);
+        assert_eq!(har.log.entries[2].response.headers[0].value, "default-src c.example.com");
+        assert_eq!(har.log.entries[2].response.content.text, None);
+    }
+
+    #[test]
+    fn test_parse_missing_log() {
+        assert_eq!(parse("{}").unwrap().log.entries.len(), 0);
+        assert!(parse("not json").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use crate::dns::{ascii_host, discard_port, hostname_is_ip, hostname_is_net, StrResult};
+use super::{hostname_from_url, is_plausible_hostname};
+
+pub fn hostnames_from_list(path: &str) -> StrResult<HashSet<String>> {
+    let text = if path == super::STDIN {
+        std::io::read_to_string(std::io::stdin()).map_err(|e| format!("could not read host list from standard input: {e}"))?
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("could not read host list {path}: {e}"))?
+    };
+    hostnames_from_list_text(&text).map_err(|e| format!("{e} in host list {path}"))
+}
+
+// one hostname, URL, IP address or network per line; # starts a comment
+pub fn hostnames_from_list_text(text: &str) -> StrResult<HashSet<String>> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            (!entry.is_empty()).then(|| list_entry(entry).ok_or(format!("'{entry}' on line {} is not a hostname, URL, IP address or network", n + 1)))
+        })
+        .collect()
+}
+
+// the unresolved hosts of a --unresolved JSON file or a --format json document, to try them again; none for any other
+// JSON, such as a HAR
+pub fn hostnames_from_unresolved_json(text: &str) -> Option<StrResult<HashSet<String>>> {
+    if !text.contains("\"unresolved\"") {
+        return None;
+    }
+    let document = serde_json::from_str::<serde_json::Value>(text).ok()?;
+    let hosts = document.get("unresolved")?.as_object()?.keys().cloned().collect::<Vec<_>>();
+    Some(hostnames_from_list_text(&hosts.join("\n")))
+}
+
+// a host given on the command line, read as a line of a host list, networks aside
+pub fn list_host(s: &str) -> StrResult<String> {
+    let s = s.trim();
+    list_entry(s).filter(|_| hostname_is_net(s).is_none()).ok_or(format!("'{s}' is not a hostname, URL or IP address"))
+}
+
+fn list_entry(entry: &str) -> Option<String> {
+    if let Some(net) = hostname_is_net(entry) {
+        return Some(net.to_string());
+    }
+    let host = hostname_from_url(entry).unwrap_or(ascii_host(entry)).trim_end_matches('.').to_ascii_lowercase();
+    let name = discard_port(&host);
+    (hostname_is_ip(name).is_some() || is_plausible_hostname(name)).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostnames_from_list_text() {
+        let list = "# streaming\nExample.com\nhttps://cdn.example.net/app.js\n  api.example.org:8443  # staging\n\n\
+            203.0.113.10\n2001:db8::1\n198.51.100.0/24\n2001:db8:1::/48\nwww.example.com.\n";
+        assert_eq!(hostnames_from_list_text(list), Ok(HashSet::from([
+            "example.com".to_string(),
+            "cdn.example.net".into(),
+            "api.example.org:8443".into(),
+            "203.0.113.10".into(),
+            "2001:db8::1".into(),
+            "198.51.100.0/24".into(),
+            "2001:db8:1::/48".into(),
+            "www.example.com".into(),
+        ])));
+        assert_eq!(hostnames_from_list_text("пример.рф\nhttps://Bücher.example/\nxn--e1afmkfd.xn--p1ai\n"),
+            Ok(HashSet::from(["xn--e1afmkfd.xn--p1ai".to_string(), "xn--bcher-kva.example".into()])));
+        assert_eq!(hostnames_from_list_text("example.com\nnot a host\n"), Err("'not a host' on line 2 is not a hostname, URL, IP address or network".to_string()));
+        assert!(hostnames_from_list_text("localhost").is_err());
+        assert_eq!(hostnames_from_list_text("# nothing\n"), Ok(HashSet::new()));
+    }
+
+    #[test]
+    fn test_list_host() {
+        assert_eq!(list_host(" Example.COM. "), Ok("example.com".to_string()));
+        assert_eq!(list_host("https://cdn.example.net/app.js"), Ok("cdn.example.net".to_string()));
+        assert_eq!(list_host("пример.рф"), Ok("xn--e1afmkfd.xn--p1ai".to_string()));
+        assert_eq!(list_host("203.0.113.10"), Ok("203.0.113.10".to_string()));
+        assert!(list_host("198.51.100.0/24").is_err());
+        assert!(list_host("not a host").is_err());
+    }
+
+    #[test]
+    fn test_hostnames_from_unresolved_json() {
+        let document = r#"{"schema": 1, "unresolved": {"gone.example": "no record found", "api.example.org:8443": "timed out"}}"#;
+        assert_eq!(hostnames_from_unresolved_json(document), Some(Ok(HashSet::from(["gone.example".to_string(), "api.example.org:8443".into()]))));
+        assert_eq!(hostnames_from_unresolved_json(r#"{"unresolved": {}}"#), Some(Ok(HashSet::new())));
+        assert_eq!(hostnames_from_unresolved_json(r#"{"log": {"entries": []}}"#), None);
+        assert_eq!(hostnames_from_unresolved_json(r#"{"unresolved": ["gone.example"]}"#), None);
+    }
+}
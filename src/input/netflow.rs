@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::time::{Duration, Instant};
+use crate::dns::StrResult;
+use super::flow::Flow;
+
+const FIELD_IN_BYTES: u16 = 1;
+const FIELD_PROTOCOL: u16 = 4;
+const FIELD_L4_DST_PORT: u16 = 11;
+const FIELD_IPV4_SRC_ADDR: u16 = 8;
+const FIELD_IPV4_DST_ADDR: u16 = 12;
+const FIELD_IPV6_SRC_ADDR: u16 = 27;
+const FIELD_IPV6_DST_ADDR: u16 = 28;
+
+#[derive(Debug, Clone, Copy)]
+struct TemplateField {
+    id: u16,
+    len: u16,
+}
+
+#[derive(Default)]
+pub struct Collector {
+    templates: HashMap<(u32, u16), Vec<TemplateField>>,
+    pub flows: Vec<Flow>,
+}
+
+impl Collector {
+    pub fn read_file(&mut self, path: &str) -> StrResult<()> {
+        let data = std::fs::read(path).map_err(|e| format!("could not read flow export file {path}: {e}"))?;
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let used = self.parse_packet(rest).map_err(|e| format!("{e} in flow export file {path}"))?;
+            rest = &rest[used..];
+        }
+        Ok(())
+    }
+
+    pub fn listen(&mut self, addr: &str, duration: Duration) -> StrResult<()> {
+        let socket = UdpSocket::bind(addr).map_err(|e| format!("could not listen for flow exports on {addr}: {e}"))?;
+        let deadline = Instant::now() + duration;
+        let mut buf = [0u8; 65535];
+        while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+            socket.set_read_timeout(Some(left)).map_err(|e| format!("could not set socket timeout: {e}"))?;
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    if let Err(e) = self.parse_packet(&buf[..len]) {
+                        eprintln!("warning: ignoring flow export packet from {from}: {e}");
+                    }
+                },
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(format!("could not receive flow export: {e}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_packet(&mut self, data: &[u8]) -> StrResult<usize> {
+        match be16(data, 0)? {
+            5 => self.parse_v5(data),
+            9 => self.parse_v9(data),
+            10 => self.parse_ipfix(data),
+            v => Err(format!("unsupported flow export version {v}")),
+        }
+    }
+
+    fn parse_v5(&mut self, data: &[u8]) -> StrResult<usize> {
+        let count = be16(data, 2)? as usize;
+        let len = 24 + count * 48;
+        let records = data.get(24..len).ok_or("truncated NetFlow v5 packet")?;
+        self.flows.extend(records.chunks_exact(48).map(|r| Flow {
+            src: IpAddr::V4(Ipv4Addr::new(r[0], r[1], r[2], r[3])),
+            dst: IpAddr::V4(Ipv4Addr::new(r[4], r[5], r[6], r[7])),
+            bytes: u32::from_be_bytes([r[20], r[21], r[22], r[23]]) as u64,
+            dst_port: u16::from_be_bytes([r[34], r[35]]),
+            protocol: r[38],
+        }));
+        Ok(len)
+    }
+
+    fn parse_v9(&mut self, data: &[u8]) -> StrResult<usize> {
+        let source_id = be32(data, 16)?;
+        let mut pos = 20;
+        while pos + 4 <= data.len() && !matches!(be16(data, pos)?, 5 | 9 | 10) {
+            let (set_id, set_len) = (be16(data, pos)?, be16(data, pos + 2)? as usize);
+            let body = data.get(pos + 4..pos + set_len).filter(|_| set_len >= 4).ok_or("truncated NetFlow v9 flowset")?;
+            match set_id {
+                0 => self.parse_templates(source_id, body, false)?,
+                1 => {},
+                id if id >= 256 => self.parse_data(source_id, id, body),
+                id => return Err(format!("unexpected NetFlow v9 flowset id {id}")),
+            }
+            pos += set_len;
+        }
+        Ok(pos)
+    }
+
+    fn parse_ipfix(&mut self, data: &[u8]) -> StrResult<usize> {
+        let len = be16(data, 2)? as usize;
+        let message = data.get(..len).filter(|_| len >= 16).ok_or("truncated IPFIX message")?;
+        let domain_id = be32(message, 12)?;
+        let mut pos = 16;
+        while pos + 4 <= message.len() {
+            let (set_id, set_len) = (be16(message, pos)?, be16(message, pos + 2)? as usize);
+            let body = message.get(pos + 4..pos + set_len).filter(|_| set_len >= 4).ok_or("truncated IPFIX set")?;
+            match set_id {
+                2 => self.parse_templates(domain_id, body, true)?,
+                3 => {},
+                id if id >= 256 => self.parse_data(domain_id, id, body),
+                id => return Err(format!("unexpected IPFIX set id {id}")),
+            }
+            pos += set_len;
+        }
+        Ok(len)
+    }
+
+    fn parse_templates(&mut self, source_id: u32, body: &[u8], ipfix: bool) -> StrResult<()> {
+        let mut pos = 0;
+        while pos + 4 <= body.len() {
+            let (template_id, field_count) = (be16(body, pos)?, be16(body, pos + 2)?);
+            pos += 4;
+            let fields = (0..field_count).map(|_| {
+                let (id, len) = (be16(body, pos)?, be16(body, pos + 2)?);
+                pos += 4;
+                if ipfix && id & 0x8000 != 0 {
+                    pos += 4;
+                }
+                Ok(TemplateField { id: id & 0x7fff, len })
+            }).collect::<StrResult<Vec<_>>>()?;
+            self.templates.insert((source_id, template_id), fields);
+        }
+        Ok(())
+    }
+
+    fn parse_data(&mut self, source_id: u32, template_id: u16, body: &[u8]) {
+        let Some(fields) = self.templates.get(&(source_id, template_id)) else {
+            return;
+        };
+        let mut pos = 0;
+        while pos < body.len() {
+            let mut flow = Flow {
+                src: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                dst: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                dst_port: 0,
+                protocol: 0,
+                bytes: 0,
+            };
+            let mut complete = true;
+            for field in fields {
+                let len = match field.len {
+                    0xffff => match body.get(pos) {
+                        Some(255) => {
+                            let len = be16(body, pos + 1).unwrap_or(u16::MAX) as usize;
+                            pos += 3;
+                            len
+                        },
+                        Some(len) => {
+                            pos += 1;
+                            *len as usize
+                        },
+                        None => usize::MAX,
+                    },
+                    len => len as usize,
+                };
+                let Some(value) = pos.checked_add(len).and_then(|end| body.get(pos..end)) else {
+                    complete = false;
+                    break;
+                };
+                pos += len;
+                match (field.id, value.len()) {
+                    (FIELD_IPV4_SRC_ADDR, 4) => flow.src = IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3])),
+                    (FIELD_IPV4_DST_ADDR, 4) => flow.dst = IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3])),
+                    (FIELD_IPV6_SRC_ADDR, 16) => flow.src = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap())),
+                    (FIELD_IPV6_DST_ADDR, 16) => flow.dst = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap())),
+                    (FIELD_L4_DST_PORT, 2) => flow.dst_port = u16::from_be_bytes([value[0], value[1]]),
+                    (FIELD_PROTOCOL, 1) => flow.protocol = value[0],
+                    (FIELD_IN_BYTES, 1..=8) => flow.bytes = value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+                    _ => {},
+                }
+            }
+            if !complete {
+                break;
+            }
+            if !flow.dst.is_unspecified() {
+                self.flows.push(flow);
+            }
+        }
+    }
+}
+
+fn be16(data: &[u8], pos: usize) -> StrResult<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(format!("truncated flow export data at offset {pos}"))
+}
+
+fn be32(data: &[u8], pos: usize) -> StrResult<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(format!("truncated flow export data at offset {pos}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::flow::{destinations, FlowFilter};
+
+    fn v5_packet(records: &[([u8; 4], [u8; 4], u16, u32)]) -> Vec<u8> {
+        let mut p = vec![0, 5];
+        p.extend((records.len() as u16).to_be_bytes());
+        p.extend([0u8; 20]);
+        records.iter().for_each(|(src, dst, port, bytes)| {
+            let mut r = [0u8; 48];
+            r[0..4].copy_from_slice(src);
+            r[4..8].copy_from_slice(dst);
+            r[20..24].copy_from_slice(&bytes.to_be_bytes());
+            r[34..36].copy_from_slice(&port.to_be_bytes());
+            r[38] = 6;
+            p.extend(r);
+        });
+        p
+    }
+
+    fn set(id: u16, body: &[u8]) -> Vec<u8> {
+        let mut s = id.to_be_bytes().to_vec();
+        s.extend(((body.len() + 4) as u16).to_be_bytes());
+        s.extend(body);
+        s
+    }
+
+    fn template(id: u16, fields: &[(u16, u16)]) -> Vec<u8> {
+        let mut t = id.to_be_bytes().to_vec();
+        t.extend((fields.len() as u16).to_be_bytes());
+        fields.iter().for_each(|(f, l)| {
+            t.extend(f.to_be_bytes());
+            t.extend(l.to_be_bytes());
+        });
+        t
+    }
+
+    fn data_record(src: [u8; 4], dst: [u8; 4], port: u16, bytes: u32) -> Vec<u8> {
+        [src.to_vec(), dst.to_vec(), port.to_be_bytes().to_vec(), bytes.to_be_bytes().to_vec()].concat()
+    }
+
+    const FIELDS: [(u16, u16); 4] = [(FIELD_IPV4_SRC_ADDR, 4), (FIELD_IPV4_DST_ADDR, 4), (FIELD_L4_DST_PORT, 2), (FIELD_IN_BYTES, 4)];
+
+    #[test]
+    fn test_parse_v5() {
+        let mut c = Collector::default();
+        let p = v5_packet(&[([10, 0, 0, 2], [93, 184, 216, 34], 443, 1500), ([10, 0, 0, 3], [1, 1, 1, 1], 53, 80)]);
+        assert_eq!(c.parse_packet(&p), Ok(p.len()));
+        assert_eq!(c.flows.len(), 2);
+        assert_eq!(c.flows[0], Flow { src: "10.0.0.2".parse().unwrap(), dst: "93.184.216.34".parse().unwrap(), dst_port: 443, protocol: 6, bytes: 1500 });
+        assert!(c.parse_packet(&p[..p.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_v9_and_ipfix_stream() {
+        let mut v9 = vec![0, 9, 0, 2];
+        v9.extend([0u8; 12]);
+        v9.extend(7u32.to_be_bytes());
+        v9.extend(set(0, &template(256, &FIELDS)));
+        v9.extend(set(256, &[data_record([10, 0, 0, 2], [8, 8, 4, 4], 443, 100), vec![0, 0]].concat()));
+
+        let sets = [
+            set(2, &template(300, &FIELDS)),
+            set(300, &[data_record([10, 0, 0, 5], [9, 9, 9, 9], 853, 7), data_record([10, 0, 0, 5], [8, 8, 4, 4], 443, 50)].concat()),
+        ].concat();
+        let mut ipfix = vec![0, 10];
+        ipfix.extend(((sets.len() + 16) as u16).to_be_bytes());
+        ipfix.extend([0u8; 8]);
+        ipfix.extend(1u32.to_be_bytes());
+        ipfix.extend(sets);
+
+        let stream = [v9.clone(), ipfix, v5_packet(&[([192, 168, 1, 4], [8, 8, 4, 4], 443, 1)])].concat();
+        let mut c = Collector::default();
+        let mut rest = stream.as_slice();
+        while !rest.is_empty() {
+            let used = c.parse_packet(rest).unwrap();
+            rest = &rest[used..];
+        }
+        assert_eq!(c.flows.len(), 4);
+
+        let all = destinations(&c.flows, &FlowFilter::default());
+        assert_eq!(all.get(&"8.8.4.4".parse().unwrap()), Some(&151));
+        assert_eq!(all.get(&"9.9.9.9".parse().unwrap()), Some(&7));
+
+        let filter = FlowFilter { src_nets: vec!["10.0.0.0/8".parse().unwrap()], dst_ports: vec![443], min_bytes: 100 };
+        assert_eq!(destinations(&c.flows, &filter), HashMap::from([("8.8.4.4".parse().unwrap(), 150)]));
+    }
+
+    #[test]
+    fn test_data_before_template_is_skipped() {
+        let mut c = Collector::default();
+        let mut v9 = vec![0, 9, 0, 1];
+        v9.extend([0u8; 16]);
+        v9.extend(set(400, &data_record([10, 0, 0, 2], [8, 8, 4, 4], 443, 100)));
+        assert_eq!(c.parse_packet(&v9), Ok(v9.len()));
+        assert!(c.flows.is_empty());
+        assert!(c.parse_packet(&[0, 7, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use crate::dns::StrResult;
+use super::flow::Flow;
+
+const PROC_NET_TABLES: [(&str, u8); 4] = [("tcp", 6), ("tcp6", 6), ("udp", 17), ("udp6", 17)];
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessSelector {
+    pub names: Vec<String>,
+    pub cgroups: Vec<String>,
+    pub units: Vec<String>,
+}
+
+impl ProcessSelector {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && !self.is_scoped()
+    }
+
+    pub fn is_scoped(&self) -> bool {
+        !self.cgroups.is_empty() || !self.units.is_empty()
+    }
+
+    fn matches(&self, pid: u32) -> bool {
+        (self.names.is_empty() || self.matches_name(pid)) && (!self.is_scoped() || self.matches_cgroup(pid))
+    }
+
+    fn matches_name(&self, pid: u32) -> bool {
+        let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+        let exe = std::fs::read_link(format!("/proc/{pid}/exe"))
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        self.names.iter().any(|name| name.eq_ignore_ascii_case(comm.trim()) || name.eq_ignore_ascii_case(&exe))
+    }
+
+    fn matches_cgroup(&self, pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.splitn(3, ':').nth(2))
+            .any(|path| self.cgroup_path_matches(path))
+    }
+
+    // the kernel keeps the first 15 bytes of a process name as its comm
+    pub fn matches_comm(&self, comm: &str) -> bool {
+        self.names.iter().any(|name| name.as_bytes().get(..15).unwrap_or(name.as_bytes()).eq_ignore_ascii_case(comm.as_bytes()))
+    }
+
+    pub fn cgroup_path_matches(&self, path: &str) -> bool {
+        self.cgroups.iter().any(|cg| {
+            let cg = format!("/{}", cg.trim_matches('/'));
+            path == cg || path.starts_with(&format!("{cg}/"))
+        }) || self.units.iter().any(|unit| {
+            let unit = if unit.contains('.') { unit.clone() } else { format!("{unit}.service") };
+            path.split('/').any(|component| component == unit)
+        })
+    }
+
+    pub fn socket_inodes(&self) -> HashSet<u64> {
+        self.pids().into_iter().flat_map(socket_inodes).collect()
+    }
+
+    pub fn pids(&self) -> Vec<u32> {
+        std::fs::read_dir("/proc")
+            .map(|dir| dir
+                .flatten()
+                .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+                .filter(|pid| self.matches(*pid))
+                .collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ProcSocket {
+    src: IpAddr,
+    dst: IpAddr,
+    dst_port: u16,
+    protocol: u8,
+    inode: u64,
+}
+
+pub fn flows_from_processes(selector: &ProcessSelector, duration: Duration) -> StrResult<Vec<Flow>> {
+    let deadline = Instant::now() + duration;
+    let mut seen = HashSet::new();
+    let mut flows = Vec::new();
+    while Instant::now() < deadline {
+        let inodes = selector.socket_inodes();
+        proc_net_sockets()?
+            .into_iter()
+            .filter(|s| inodes.contains(&s.inode) && !s.dst.is_unspecified())
+            .for_each(|s| {
+                if seen.insert((s.dst, s.dst_port, s.protocol)) {
+                    flows.push(Flow { src: s.src, dst: s.dst, dst_port: s.dst_port, protocol: s.protocol, bytes: 0 });
+                }
+            });
+        std::thread::sleep(SAMPLE_INTERVAL);
+    }
+    Ok(flows)
+}
+
+pub fn socket_inodes(pid: u32) -> Vec<u64> {
+    std::fs::read_dir(format!("/proc/{pid}/fd"))
+        .map(|dir| dir
+            .flatten()
+            .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+            .filter_map(|target| target.to_str()?.strip_prefix("socket:[")?.strip_suffix(']')?.parse().ok())
+            .collect())
+        .unwrap_or_default()
+}
+
+fn proc_net_sockets() -> StrResult<Vec<ProcSocket>> {
+    PROC_NET_TABLES.iter().try_fold(Vec::new(), |mut acc, (table, protocol)| {
+        let path = format!("/proc/net/{table}");
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                acc.extend(contents.lines().skip(1).filter_map(|line| socket_from_line(line, *protocol)));
+                Ok(acc)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(acc),
+            Err(e) => Err(format!("could not read {path}: {e}")),
+        }
+    })
+}
+
+fn socket_from_line(line: &str, protocol: u8) -> Option<ProcSocket> {
+    let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+    let (src, _) = parse_proc_addr(fields.get(1)?)?;
+    let (dst, dst_port) = parse_proc_addr(fields.get(2)?)?;
+    let inode = fields.get(9)?.parse().ok()?;
+    Some(ProcSocket { src, dst, dst_port, protocol, inode })
+}
+
+// addresses in /proc/net/{tcp,udp}{,6} are hex dumps of the in-kernel (little-endian words) representation
+fn parse_proc_addr(s: &str) -> Option<(IpAddr, u16)> {
+    let (ip, port) = s.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words = (0..ip.len() / 8)
+        .map(|i| u32::from_str_radix(ip.get(i * 8..i * 8 + 8)?, 16).ok().map(u32::swap_bytes))
+        .collect::<Option<Vec<_>>>()?;
+    let ip = match words.as_slice() {
+        [a] => IpAddr::V4(Ipv4Addr::from(*a)),
+        [a, b, c, d] => {
+            let ip = Ipv6Addr::from((u128::from(*a) << 96) | (u128::from(*b) << 64) | (u128::from(*c) << 32) | u128::from(*d));
+            ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip))
+        }
+        _ => return None,
+    };
+    Some((ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_addr() {
+        assert_eq!(parse_proc_addr("C301A8C0:E5BC"), Some(("192.168.1.195".parse().unwrap(), 58812)));
+        assert_eq!(parse_proc_addr("B80D0120000000000000000001000000:01BB"), Some(("2001:db8::1".parse().unwrap(), 443)));
+        assert_eq!(parse_proc_addr("0000000000000000FFFF00000100A8C0:0035"), Some(("192.168.0.1".parse().unwrap(), 53)));
+        assert_eq!(parse_proc_addr("C301A8C:E5BC"), None);
+        assert_eq!(parse_proc_addr("C301A8C0"), None);
+    }
+
+    #[test]
+    fn test_socket_from_line() {
+        let line = "   3: 0501A8C0:C738 22D8B85D:01BB 01 00000000:00000000 00:00000000 00000000  1000        0 123456 1 0000000000000000 20 4 30 10 -1";
+        assert_eq!(socket_from_line(line, 6),
+            Some(ProcSocket { src: "192.168.1.5".parse().unwrap(), dst: "93.184.216.34".parse().unwrap(), dst_port: 443, protocol: 6, inode: 123456 }));
+        assert_eq!(socket_from_line("  sl  local_address rem_address   st", 6), None);
+    }
+
+    #[test]
+    fn test_cgroup_path_matches() {
+        let selector = ProcessSelector {
+            cgroups: vec!["system.slice/nginx.service".into()],
+            units: vec!["steam".into(), "app-zoom.scope".into()],
+            ..Default::default()
+        };
+        assert!(selector.cgroup_path_matches("/system.slice/nginx.service"));
+        assert!(selector.cgroup_path_matches("/system.slice/nginx.service/worker"));
+        assert!(!selector.cgroup_path_matches("/system.slice/nginx.service2"));
+        assert!(selector.cgroup_path_matches("/user.slice/user-1000.slice/user@1000.service/app.slice/steam.service"));
+        assert!(selector.cgroup_path_matches("/user.slice/user-1000.slice/user@1000.service/app.slice/app-zoom.scope"));
+        assert!(!selector.cgroup_path_matches("/user.slice/user-1000.slice/session-2.scope"));
+    }
+
+    #[test]
+    fn test_matches_comm() {
+        let selector = ProcessSelector { names: vec!["Steam".into(), "gnome-remote-desktop-daemon".into()], ..Default::default() };
+        assert!(selector.matches_comm("steam"));
+        assert!(selector.matches_comm("gnome-remote-de"));
+        assert!(!selector.matches_comm("steamwebhelper"));
+    }
+
+    #[test]
+    fn test_own_process_scope() {
+        let own_cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let path = own_cgroup.lines().filter_map(|line| line.splitn(3, ':').nth(2)).next().unwrap().to_string();
+        let selector = ProcessSelector { cgroups: vec![path], ..Default::default() };
+        assert!(selector.pids().contains(&std::process::id()));
+    }
+
+    #[test]
+    fn test_own_sockets() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let inodes = socket_inodes(std::process::id());
+        assert!(proc_net_sockets().unwrap().iter().any(|s| inodes.contains(&s.inode) && s.dst_port == listener.local_addr().unwrap().port()));
+    }
+}
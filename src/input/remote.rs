@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use base64::Engine;
+use crate::dns::StrResult;
+use super::hostname_from_url;
+
+// what to authenticate remote inputs with, per host: a bearer token, or else a login from .netrc (whose default entry,
+// as with curl, goes to any host); redirects drop the Authorization header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Credentials {
+    bearer: HashMap<String, String>,
+    netrc: HashMap<String, (String, String)>,
+    netrc_default: Option<(String, String)>,
+}
+
+impl Credentials {
+    // $NETRC or ~/.netrc, if there is one, and the "host token" lines of bearer_file
+    pub fn load(bearer_file: Option<&str>) -> StrResult<Self> {
+        let mut credentials = match std::env::var_os("NETRC").map(PathBuf::from).or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc"))) {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+                Self::from_netrc(&text)
+            }
+            _ => Self::default(),
+        };
+        if let Some(path) = bearer_file {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("could not read bearer tokens {path}: {e}"))?;
+            credentials.bearer = bearer_tokens(&text).map_err(|e| format!("{e} in bearer tokens {path}"))?;
+        }
+        Ok(credentials)
+    }
+
+    fn from_netrc(text: &str) -> Self {
+        let mut credentials = Self::default();
+        let mut tokens = text.split_whitespace();
+        // the machine an entry is for, None for the default entry
+        let mut entry: Option<Option<String>> = None;
+        let (mut login, mut password) = (String::new(), String::new());
+        let mut flush = |entry: Option<Option<String>>, login: &mut String, password: &mut String| {
+            let pair = (std::mem::take(login), std::mem::take(password));
+            match entry {
+                Some(Some(machine)) => {
+                    credentials.netrc.entry(machine).or_insert(pair);
+                }
+                Some(None) => credentials.netrc_default = credentials.netrc_default.take().or(Some(pair)),
+                None => {}
+            }
+        };
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => flush(entry.replace(tokens.next().map(str::to_ascii_lowercase)), &mut login, &mut password),
+                "default" => flush(entry.replace(None), &mut login, &mut password),
+                "login" => login = tokens.next().unwrap_or_default().to_string(),
+                "password" => password = tokens.next().unwrap_or_default().to_string(),
+                // macro definitions only matter to ftp, and everything after one is hard to tell from it
+                "macdef" => break,
+                _ => {}
+            }
+        }
+        flush(entry, &mut login, &mut password);
+        credentials
+    }
+
+    pub fn authorization(&self, host: &str) -> Option<String> {
+        let host = host.to_ascii_lowercase();
+        if let Some(token) = self.bearer.get(&host) {
+            return Some(format!("Bearer {token}"));
+        }
+        self.netrc.get(&host).or(self.netrc_default.as_ref()).map(|(login, password)| {
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{login}:{password}")))
+        })
+    }
+}
+
+// "host token" lines, # starts a comment
+fn bearer_tokens(text: &str) -> StrResult<HashMap<String, String>> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.split('#').next().unwrap_or_default().split_whitespace().collect::<Vec<_>>()))
+        .filter(|(_, fields)| !fields.is_empty())
+        .map(|(n, fields)| match fields.as_slice() {
+            [host, token] => Ok((host.to_ascii_lowercase(), token.to_string())),
+            _ => Err(format!("expected 'host token' on line {}", n + 1)),
+        })
+        .collect()
+}
+
+// the file name of the URL, which decides how the download is parsed, like the extension of a local file
+fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = path.rsplit('/').next().filter(|name| !name.is_empty() && !path.ends_with(&format!("//{name}"))).unwrap_or("input.har");
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect()
+}
+
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+// downloads url into a temporary file of the same name and returns its path; proxies come from the usual
+// HTTPS_PROXY, HTTP_PROXY, ALL_PROXY and NO_PROXY variables
+pub fn download(url: &str, credentials: &Credentials) -> StrResult<PathBuf> {
+    let host = hostname_from_url(url).map(|host| crate::dns::discard_port(&host).to_string()).ok_or(format!("bad URL {url}"))?;
+    let mut request = ureq::get(url);
+    if let Some(authorization) = credentials.authorization(&host) {
+        request = request.header("Authorization", authorization);
+    }
+    let response = request.call().map_err(|e| format!("could not fetch {url}: {e}"))?;
+    let dir = std::env::temp_dir().join(format!("wgrouter-{}-{}", std::process::id(), DOWNLOADS.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    let path = dir.join(file_name(url));
+    let mut file = std::fs::File::create(&path).map_err(|e| format!("could not create {}: {e}", path.display()))?;
+    std::io::copy(&mut response.into_body().into_reader(), &mut file).map_err(|e| format!("could not fetch {url}: {e}"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorization() {
+        let mut credentials = Credentials::from_netrc("machine nas.example.com login backup password s3cret\n\
+            machine git.example.com\n  login ci\n  password tok\ndefault login anonymous password guest\nmacdef init\nmachine x.com login a password b\n");
+        assert_eq!(credentials.authorization("NAS.example.com"), Some("Basic YmFja3VwOnMzY3JldA==".to_string()));
+        assert_eq!(credentials.authorization("other.example.com"), Some("Basic YW5vbnltb3VzOmd1ZXN0".to_string()));
+        credentials.bearer = bearer_tokens("# Gitea\ngit.example.com  abc123\n").unwrap();
+        assert_eq!(credentials.authorization("git.example.com"), Some("Bearer abc123".to_string()));
+        assert_eq!(Credentials::from_netrc("machine a.com login x password y").authorization("b.com"), None);
+        assert_eq!(bearer_tokens("git.example.com\n"), Err("expected 'host token' on line 1".to_string()));
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("https://nas.example.com/captures/site.har?raw=1"), "site.har");
+        assert_eq!(file_name("https://git.example.com/api/v1/repos/me/caps/raw/lists/streaming%20hosts.txt"), "streaming_20hosts.txt");
+        assert_eq!(file_name("https://nas.example.com"), "input.har");
+        assert_eq!(file_name("https://nas.example.com/"), "input.har");
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+use crate::dns::StrResult;
+use super::hostname_from_url;
+
+pub fn hostnames_from_saz(path: &str) -> StrResult<HashSet<String>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("could not open SAZ file {path}: {e}"))?;
+    hostnames_from_saz_reader(file).map_err(|e| format!("{e} in SAZ file {path}"))
+}
+
+fn hostnames_from_saz_reader<R: Read + Seek>(reader: R) -> StrResult<HashSet<String>> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| format!("could not open archive: {e}"))?;
+    let request_files = archive
+        .file_names()
+        .filter(|name| name.starts_with("raw/") && name.ends_with("_c.txt"))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    request_files
+        .into_iter()
+        .try_fold(HashSet::new(), |mut acc, name| {
+            let mut request = Vec::new();
+            archive
+                .by_name(&name)
+                .and_then(|mut f| Ok(f.read_to_end(&mut request)?))
+                .map_err(|e| format!("could not read {name}: {e}"))?;
+            acc.extend(hostname_from_raw_request(&String::from_utf8_lossy(&request)));
+            Ok(acc)
+        })
+}
+
+fn hostname_from_raw_request(request: &str) -> Option<String> {
+    let mut lines = request.lines();
+    let mut request_line = lines.next()?.split_ascii_whitespace();
+    let method = request_line.next()?;
+    let target = request_line.next()?;
+    if method.eq_ignore_ascii_case("CONNECT") {
+        return Some(target.to_string());
+    }
+    hostname_from_url(target).or_else(|| {
+        lines
+            .take_while(|l| !l.is_empty())
+            .find_map(|l| l.split_once(':').filter(|(name, _)| name.trim().eq_ignore_ascii_case("host")))
+            .map(|(_, value)| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_hostname_from_raw_request() {
+        assert_eq!(hostname_from_raw_request("CONNECT www.example.com:443 HTTP/1.1\r\nHost: www.example.com:443\r\n\r\n"),
+            Some("www.example.com:443".to_string()));
+        assert_eq!(hostname_from_raw_request("GET http://a.example.com/x HTTP/1.1\r\nHost: b.example.com\r\n\r\n"),
+            Some("a.example.com".to_string()));
+        assert_eq!(hostname_from_raw_request("POST /api HTTP/1.1\r\nUser-Agent: x\r\nhost:  c.example.com\r\n\r\nHost: body"),
+            Some("c.example.com".to_string()));
+        assert_eq!(hostname_from_raw_request("GET /x HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(hostname_from_raw_request(""), None);
+    }
+
+    #[test]
+    fn test_hostnames_from_saz() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let opts = zip::write::SimpleFileOptions::default();
+        [
+            ("raw/01_c.txt", "CONNECT api.example.com:443 HTTP/1.1\r\n\r\n"),
+            ("raw/01_s.txt", "HTTP/1.1 200 Connection Established\r\n\r\n"),
+            ("raw/02_c.txt", "GET https://cdn.example.net/app.js HTTP/1.1\r\nHost: cdn.example.net\r\n\r\n"),
+            ("raw/02_m.xml", "<Session/>"),
+            ("[Content_Types].xml", "<Types/>"),
+        ].into_iter().for_each(|(name, contents)| {
+            zip.start_file(name, opts).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        });
+        let archive = zip.finish().unwrap();
+        assert_eq!(hostnames_from_saz_reader(archive), Ok(HashSet::from(["api.example.com:443".into(), "cdn.example.net".into()])));
+        assert!(hostnames_from_saz_reader(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use crate::dns::StrResult;
+
+const SOCKET_SCHEMES: [&str; 5] = ["ws", "wss", "tcp", "udp", "tls"];
+
+#[derive(Deserialize)]
+struct ExtHar {
+    log: ExtLog,
+}
+
+#[derive(Deserialize)]
+struct ExtLog {
+    #[serde(default)]
+    entries: Vec<ExtEntry>,
+}
+
+#[derive(Deserialize)]
+struct ExtEntry {
+    #[serde(default)]
+    request: ExtRequest,
+    #[serde(rename = "serverIPAddress", default)]
+    server_ip_address: Option<String>,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
+#[derive(Deserialize, Default)]
+struct ExtRequest {
+    #[serde(default)]
+    url: String,
+}
+
+impl ExtEntry {
+    fn is_socket(&self) -> bool {
+        socket_host(&self.request.url).is_some()
+            || self.fields.get("_resourceType").and_then(Value::as_str) == Some("websocket")
+            || self.fields.get("_webSocketMessages").is_some_and(|m| !m.is_null())
+    }
+
+    fn vendor_fields(&self) -> impl Iterator<Item = &Value> {
+        self.fields.iter().filter(|(k, _)| k.starts_with('_')).map(|(_, v)| v)
+    }
+}
+
+pub fn hostnames_from_extensions(har_text: &str) -> StrResult<Vec<String>> {
+    let har = serde_json::from_str::<ExtHar>(har_text)
+        .map_err(|e| format!("could not parse HAR extension fields: {e}"))?;
+    Ok(har.log.entries
+        .iter()
+        .flat_map(|entry| {
+            let mut hosts = Vec::new();
+            if entry.is_socket() {
+                hosts.extend(entry.server_ip_address
+                    .as_deref()
+                    .map(|ip| ip.trim_matches(['[', ']']))
+                    .filter(|ip| ip.parse::<IpAddr>().is_ok())
+                    .map(String::from));
+            }
+            entry.vendor_fields().for_each(|v| collect_socket_hosts(v, &mut hosts));
+            hosts
+        })
+        .collect())
+}
+
+fn collect_socket_hosts(value: &Value, hosts: &mut Vec<String>) {
+    match value {
+        Value::String(s) => hosts.extend(socket_host(s)),
+        Value::Array(items) => items.iter().for_each(|v| collect_socket_hosts(v, hosts)),
+        Value::Object(fields) => fields.values().for_each(|v| collect_socket_hosts(v, hosts)),
+        _ => {}
+    }
+}
+
+fn socket_host(url: &str) -> Option<String> {
+    let (scheme, rest) = url.trim().split_once("://")?;
+    if !SOCKET_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)) {
+        return None;
+    }
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_host() {
+        assert_eq!(socket_host("wss://live.example.com/socket?x=1"), Some("live.example.com".to_string()));
+        assert_eq!(socket_host("WS://user@chat.example.com:8080"), Some("chat.example.com:8080".to_string()));
+        assert_eq!(socket_host("tcp://203.0.113.5:5222"), Some("203.0.113.5:5222".to_string()));
+        assert_eq!(socket_host("https://www.example.com/"), None);
+        assert_eq!(socket_host("udp://"), None);
+        assert_eq!(socket_host("not a url"), None);
+    }
+
+    #[test]
+    fn test_hostnames_from_extensions() {
+        let har = r#"{"log": {"version": "1.2", "entries": [
+            {"request": {"url": "wss://live.example.com/ws"}, "serverIPAddress": "198.51.100.7",
+             "_resourceType": "websocket", "_webSocketMessages": [{"type": "send", "data": "hi"}]},
+            {"request": {"url": "https://www.example.com/"}, "serverIPAddress": "198.51.100.8",
+             "_initiator": {"type": "script", "url": "https://www.example.com/app.js"}},
+            {"request": {"url": "https://api.example.com/"}, "serverIPAddress": "[2001:db8::1]", "_resourceType": "websocket"},
+            {"request": {"url": "https://x.example.com/"}, "_socket": {"remote": "tcp://push.example.net:5223"}}
+        ]}}"#;
+        assert_eq!(hostnames_from_extensions(har), Ok(vec!["198.51.100.7".to_string(), "2001:db8::1".into(), "push.example.net:5223".into()]));
+        assert!(hostnames_from_extensions("[]").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read};
+use crate::dns::StrResult;
+use super::hostname_from_url;
+
+pub fn is_warc(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".warc") || path.ends_with(".warc.gz")
+}
+
+pub fn hostnames_from_warc(path: &str) -> StrResult<HashSet<String>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("could not open WARC file {path}: {e}"))?;
+    let result = if path.to_ascii_lowercase().ends_with(".gz") {
+        hostnames_from_warc_reader(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        hostnames_from_warc_reader(BufReader::new(file))
+    };
+    result.map_err(|e| format!("{e} in WARC file {path}"))
+}
+
+fn hostnames_from_warc_reader<R: BufRead>(mut reader: R) -> StrResult<HashSet<String>> {
+    let mut hosts = HashSet::new();
+    while let Some(line) = read_line(&mut reader)? {
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with("WARC/") {
+            return Err(format!("expected WARC record header, got '{line}'"));
+        }
+        let mut target_uri = None;
+        let mut content_length = 0;
+        while let Some(header) = read_line(&mut reader)? {
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "warc-target-uri" => target_uri = Some(value.trim().trim_matches(['<', '>']).to_string()),
+                "content-length" => content_length = value.trim().parse::<u64>()
+                    .map_err(|e| format!("bad Content-Length '{}': {e}", value.trim()))?,
+                _ => {}
+            }
+        }
+        std::io::copy(&mut reader.by_ref().take(content_length), &mut std::io::sink())
+            .map_err(|e| format!("could not skip record body: {e}"))?;
+        hosts.extend(target_uri.as_deref().and_then(hostname_from_target_uri));
+    }
+    Ok(hosts)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> StrResult<Option<String>> {
+    let mut buf = Vec::new();
+    let read = reader.read_until(b'\n', &mut buf).map_err(|e| format!("read error: {e}"))?;
+    if read == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(String::from_utf8_lossy(&buf).trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+fn hostname_from_target_uri(uri: &str) -> Option<String> {
+    match uri.strip_prefix("dns:") {
+        Some(host) => Some(host.to_string()),
+        None => hostname_from_url(uri),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn record(kind: &str, uri: Option<&str>, body: &str) -> String {
+        let uri = uri.map(|u| format!("WARC-Target-URI: {u}\r\n")).unwrap_or_default();
+        format!("WARC/1.1\r\nWARC-Type: {kind}\r\n{uri}Content-Length: {}\r\n\r\n{body}\r\n\r\n", body.len())
+    }
+
+    fn sample() -> String {
+        [
+            record("warcinfo", None, "software: wget\r\n"),
+            record("dns", Some("dns:cdn.example.net"), "20240101 cdn.example.net. 300 IN A 192.0.2.1\n"),
+            record("request", Some("<https://www.example.com/>"), "GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n"),
+            record("response", Some("https://www.example.com/"), "HTTP/1.1 200 OK\r\n\r\nWARC/1.1 in body\r\nWARC-Target-URI: https://evil.example.org/"),
+            record("metadata", Some("http://static.example.org/a.css"), ""),
+        ].concat()
+    }
+
+    #[test]
+    fn test_hostnames_from_warc_reader() {
+        assert_eq!(hostnames_from_warc_reader(sample().as_bytes()),
+            Ok(HashSet::from(["cdn.example.net".into(), "www.example.com".into(), "static.example.org".into()])));
+        assert!(hostnames_from_warc_reader("garbage\r\n".as_bytes()).is_err());
+        assert_eq!(hostnames_from_warc_reader("".as_bytes()), Ok(HashSet::new()));
+    }
+
+    #[test]
+    fn test_hostnames_from_gzipped_warc() {
+        let mut gz = Vec::new();
+        sample().split_inclusive("\r\n\r\n\r\n").for_each(|member| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            gz.extend(encoder.finish().unwrap());
+        });
+        let reader = BufReader::new(flate2::read::MultiGzDecoder::new(gz.as_slice()));
+        assert_eq!(hostnames_from_warc_reader(reader).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_is_warc() {
+        assert!(is_warc("crawl.warc"));
+        assert!(is_warc("/x/CRAWL-00001.WARC.GZ"));
+        assert!(!is_warc("crawl.har"));
+        assert!(!is_warc("crawl.gz"));
+    }
+}
@@ -0,0 +1,114 @@
+use super::hostnames_from_text;
+
+const FCM_HOSTS: [&str; 3] = ["fcm.googleapis.com", "fcmregistrations.googleapis.com", "firebaseinstallations.googleapis.com"];
+
+pub fn is_manifest(url: &str, mime_type: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    mime_type.starts_with("application/manifest+json")
+        || path.ends_with(".webmanifest")
+        || path.ends_with("/manifest.json")
+}
+
+pub fn hostnames_from_manifest(text: &str) -> Vec<String> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(text) else {
+        return Vec::new();
+    };
+    let mut hosts = Vec::new();
+    collect_string_values(&manifest, &mut hosts);
+    if manifest.get("gcm_sender_id").is_some() {
+        hosts.extend(FCM_HOSTS.map(String::from));
+    }
+    hosts
+}
+
+fn collect_string_values(value: &serde_json::Value, hosts: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(s) => hosts.extend(hostnames_from_text(s)),
+        serde_json::Value::Array(items) => items.iter().for_each(|v| collect_string_values(v, hosts)),
+        serde_json::Value::Object(fields) => fields.values().for_each(|v| collect_string_values(v, hosts)),
+        _ => {}
+    }
+}
+
+pub fn hostnames_from_worker_script(script: &str, creator: &str) -> Vec<String> {
+    let mut hosts = Vec::new();
+    if script.contains("firebase-messaging") || script.contains("firebase.messaging") {
+        hosts.extend(FCM_HOSTS.map(String::from));
+    }
+    if script.contains("pushManager") || script.contains("onpush") || script.contains("\"push\"") || script.contains("'push'") {
+        hosts.extend(push_service_hosts(creator).iter().map(|h| h.to_string()));
+    }
+    hosts
+}
+
+pub fn registered_worker_paths(script: &str) -> Vec<String> {
+    script
+        .match_indices("serviceWorker.register(")
+        .filter_map(|(pos, call)| {
+            let arg = script[pos + call.len()..].trim_start();
+            let quote = arg.chars().next().filter(|c| "'\"`".contains(*c))?;
+            let path = arg[1..].split(quote).next()?;
+            let path = path.split(['?', '#']).next().unwrap_or_default();
+            (!path.is_empty()).then(|| path.trim_start_matches("./").to_string())
+        })
+        .collect()
+}
+
+fn push_service_hosts(creator: &str) -> &'static [&'static str] {
+    let creator = creator.to_ascii_lowercase();
+    if creator.contains("firefox") {
+        &["updates.push.services.mozilla.com"]
+    } else if creator.contains("webkit") || creator.contains("safari") {
+        &["web.push.apple.com"]
+    } else {
+        &FCM_HOSTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_manifest() {
+        assert!(is_manifest("https://x.com/site.webmanifest", "text/plain"));
+        assert!(is_manifest("https://x.com/static/manifest.json?v=3", ""));
+        assert!(is_manifest("https://x.com/m", "application/manifest+json; charset=utf-8"));
+        assert!(!is_manifest("https://x.com/app.json", "application/json"));
+    }
+
+    #[test]
+    fn test_hostnames_from_manifest() {
+        let manifest = r#"{
+            "name": "App",
+            "start_url": "https://app.example.com/?source=pwa",
+            "icons": [{"src": "https://img.example-cdn.net/icon.png", "sizes": "192x192"}],
+            "related_applications": [{"platform": "play", "url": "https://play.google.com/store/apps/details?id=x"}],
+            "gcm_sender_id": "103953800507"
+        }"#;
+        let hosts = hostnames_from_manifest(manifest);
+        ["app.example.com", "img.example-cdn.net", "play.google.com", "fcm.googleapis.com"]
+            .iter()
+            .for_each(|h| assert!(hosts.contains(&h.to_string()), "{h} not in {hosts:?}"));
+        assert!(hostnames_from_manifest("not json").is_empty());
+    }
+
+    #[test]
+    fn test_hostnames_from_worker_script() {
+        let sw = "self.addEventListener('push', e => e.waitUntil(show(e.data)));";
+        assert_eq!(hostnames_from_worker_script(sw, "Firefox"), vec!["updates.push.services.mozilla.com".to_string()]);
+        assert_eq!(hostnames_from_worker_script(sw, "WebKit Web Inspector"), vec!["web.push.apple.com".to_string()]);
+        assert!(hostnames_from_worker_script(sw, "WebInspector").contains(&"fcm.googleapis.com".to_string()));
+        assert!(hostnames_from_worker_script("importScripts('firebase-messaging-compat.js')", "")
+            .contains(&"firebaseinstallations.googleapis.com".to_string()));
+        assert!(hostnames_from_worker_script("self.addEventListener('fetch', f)", "WebInspector").is_empty());
+    }
+
+    #[test]
+    fn test_registered_worker_paths() {
+        assert_eq!(registered_worker_paths("navigator.serviceWorker.register('/sw.js?v=2', {scope: '/'})"), vec!["/sw.js".to_string()]);
+        assert_eq!(registered_worker_paths(r#"n.serviceWorker.register( "./push-worker.js")"#), vec!["push-worker.js".to_string()]);
+        assert!(registered_worker_paths("navigator.serviceWorker.register(swUrl)").is_empty());
+        assert!(registered_worker_paths("navigator.userAgent").is_empty());
+    }
+}
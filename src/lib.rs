@@ -0,0 +1,47 @@
+pub mod aggregate;
+pub mod audit;
+pub mod bounds;
+pub mod bundle;
+pub mod clients;
+#[cfg(feature = "native")]
+pub mod apply;
+#[cfg(feature = "native")]
+pub mod capture;
+pub mod clock;
+pub mod config;
+pub mod dns;
+#[cfg(feature = "remote")]
+pub mod doh;
+#[cfg(feature = "remote")]
+pub mod dot;
+#[cfg(feature = "native")]
+pub mod failover;
+pub mod explain;
+pub mod exclusions;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "native")]
+pub mod health;
+pub mod guard;
+pub mod history;
+pub mod host;
+pub mod input;
+pub mod output;
+#[cfg(feature = "native")]
+pub mod psl;
+#[cfg(feature = "native")]
+pub mod plugin;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod routes;
+#[cfg(feature = "native")]
+pub mod runs;
+#[cfg(feature = "native")]
+pub mod rpc;
+pub mod schedule;
+pub mod stability;
+pub mod state;
+#[cfg(feature = "native")]
+pub mod validate;
+pub mod web;
+pub mod wgconf;
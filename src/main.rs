@@ -1,108 +1,2756 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 
-mod dns;
-use dns::*;
-mod host;
-use host::Host;
+use wgrouter::aggregate::{Aggregation, Weights};
+use wgrouter::capture::packet::PacketDecoder;
+use wgrouter::audit::AuditLog;
+use wgrouter::bounds::Bounds;
+use wgrouter::bundle;
+use wgrouter::exclusions::Exclusions;
+use wgrouter::schedule::{local_week_minute, Schedule};
+use wgrouter::state::State;
+use wgrouter::config::{Config, StepKind};
+use wgrouter::clients::{clients_from_text, leases_from_file, neighbours_from_proc_arp, Client, Neighbours};
+use wgrouter::dns::*;
+use wgrouter::failover::{Failover, Liveness, Policy};
+use wgrouter::runs::{self, LastRun};
+use wgrouter::routes::{address_weights, allowed_ips_line, network_weights, networks_with, resolve_hosts_chained, resolve_hosts_with, Conflict, Lookups, PassiveDns, DEFAULT_PREFIX_LEN, DEFAULT_PREFIX_LEN6, MAX_PREFIX_LEN,
+    MAX_PREFIX_LEN6, MIN_PREFIX_LEN, MIN_PREFIX_LEN6};
+use wgrouter::apply::{Backend, Scope};
+use wgrouter::health::Health;
+use wgrouter::guard::{proc_net_route_connected, proc_net_route_gateways, resolv_conf_nameservers, running_allowed_ips, running_endpoints, wireguard_interfaces, Guard};
+use wgrouter::history::{self, History, Transition};
+use wgrouter::host::Host;
+use wgrouter::input::{hostnames_from_file, is_remote, Hosts, STDIN};
+use wgrouter::input::har::{HarOptions, TlsFilter};
+use wgrouter::input::etc_hosts::{is_hosts_file, pinned_from_hosts_file};
+use wgrouter::input::list::hostnames_from_list;
+use wgrouter::output::{self, Report};
+use wgrouter::plugin;
+use wgrouter::input::conntrack::flows_from_conntrack;
+use wgrouter::input::flow::{destinations, Flow, FlowFilter};
+use wgrouter::input::netflow::Collector;
+use wgrouter::input::process::{flows_from_processes, ProcessSelector};
 use rayon::prelude::*;
+use serde_json::json;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 
-fn parse_hars_from_cmdline() -> StrResult<HashSet<String>> {
-    let parse_results = std::env::args()
-        .skip(1)
-        .collect::<Vec<String>>()
-        .into_par_iter()
-        .map(|file| hostnames_from_har(&file))
-        .collect::<Vec<StrResult<HashSet<String>>>>();
+#[derive(Parser, Debug)]
+#[command(version, about = "Generate VPN routes from the traffic of visited websites", args_conflicts_with_subcommands = true, args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    let ok_hosts = parse_results.into_iter().collect::<StrResult<Vec<HashSet<String>>>>()?;
+    /// Serve line-delimited JSON-RPC 2.0 on stdin/stdout (methods: add_hosts, resolve, generate, apply, status)
+    #[arg(long)]
+    rpc: bool,
 
-    Ok(ok_hosts
-        .into_iter()
-        .flat_map(|hs| hs.into_iter())
-        .collect::<HashSet<String>>())
-}
-
-fn gen_wg_routes() -> StrResult<String> {
-    let hosts = parse_hars_from_cmdline()?;
-
-    let hosts_and_ips = hosts.clone().into_iter().map(|host| -> (String, StrResult<HashSet<String>>) {
-        (
-            host.clone(),
-            {
-                let host = discard_port(&host);
-                if let Some(ip) = hostname_is_ip(&host) {
-                    if ip.is_loopback() || ip.is_broadcast() || ip.is_private() {
-                        Ok(HashSet::new())
-                    } else {
-                        Ok(HashSet::from([host.to_string()]))
-                    }
-                } else {
-                    nameservers_from_host(host).and_then(|nameservers|
-                        resolve_host_multiple(host, &nameservers))
-                }
-            }
-        )
-    }).collect::<HashMap<_, _>>();
-    
-    let ok_hosts = hosts_and_ips
-        .clone()
-        .into_iter()
-        .filter_map(|(host, res_ips)| {
-            if let Ok(ips) = res_ips {
-                Some((host, ips))
-            } else {
-                None
-            }
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    state: StateArgs,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args, Debug)]
+struct HealthArgs {
+    /// With --rpc or apply --daemon, answer HTTP health checks on this address: /healthz (alive, nothing stalled) and
+    /// /readyz (serving, or the last daemon cycle went through)
+    #[arg(long, value_name = "ADDR")]
+    health_listen: Option<String>,
+
+    /// With --rpc or apply --daemon, count a resolution or daemon cycle that makes no progress for this long as
+    /// stalled: health checks fail and the systemd watchdog (WatchdogSec=) is no longer pinged
+    #[arg(long, value_name = "SECONDS", default_value_t = wgrouter::health::DEFAULT_STALL_SECONDS)]
+    stall_timeout: u64,
+}
+
+impl HealthArgs {
+    // the health of a long-running wgrouter, answered on --health-listen and pinged to the systemd watchdog
+    fn serve(&self) -> StrResult<Arc<Health>> {
+        let health = Arc::new(Health::new(std::time::Duration::from_secs(self.stall_timeout)));
+        if let Some(addr) = &self.health_listen {
+            wgrouter::health::serve_http(addr, health.clone())?;
+        }
+        wgrouter::health::spawn_watchdog(health.clone());
+        Ok(health)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct GenerateArgs {
+    #[command(flatten)]
+    state: StateArgs,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the routes the inputs need (what running without a subcommand does)
+    Generate(Box<GenerateArgs>),
+    /// Print the addresses every host of the inputs resolves to, as hosts file lines (see --etc-hosts)
+    Resolve(Box<Args>),
+    /// Read and validate the inputs, bounds and output format without resolving or routing anything
+    Check(Box<Args>),
+    /// Generate the routes and install them, like learn --then apply without the watching
+    Apply(Box<ApplyArgs>),
+    /// Watch the traffic for a while, then show the routes it needs and optionally apply them
+    Learn(Box<LearnArgs>),
+    /// Ask a few questions about the tunnel and write a config file with the options the other commands start from
+    Init(InitArgs),
+    /// Copy a HAR without cookies, credential headers, request bodies and query strings, to share it safely
+    Scrub(ScrubArgs),
+    /// Tell what a file generated earlier (WireGuard config or AllowedIPs line, nft sets, ip route script) routes where
+    Explain(ExplainArgs),
+    /// Tell from an audit log how stable the networks of each host's addresses were over its last runs, to choose
+    /// between tight /32 routes and wider ranges per host
+    Stability(StabilityArgs),
+    /// Look for routing loops: routes applied to a target, or allowed IPs of the running interfaces or a WireGuard
+    /// config, that cover a tunnel endpoint, the nameservers resolving it or a gateway
+    Verify(VerifyArgs),
+    /// Pack the config, the plugins and the state (apply history, last run, profiles) into one zip archive, to move a
+    /// working setup to another machine
+    ExportState(BundleArgs),
+    /// Unpack a bundle of export-state into the config and state locations of this machine
+    ImportState(ImportArgs),
+    /// Go back to the routes applied before the last apply to a target
+    Undo(TargetArgs),
+    /// Re-apply routes taken back by undo
+    Redo(TargetArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct TargetArgs {
+    /// Interface the routes were applied to
+    #[arg(long, value_name = "IFACE", default_value = "wg0")]
+    interface: String,
+
+    /// Routing table the routes were applied to
+    #[arg(long)]
+    table: Option<u32>,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    state: StateArgs,
+
+    /// Append what was undone or redone to this JSON lines audit log
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct StateArgs {
+    /// Keep the apply history here instead of $XDG_STATE_HOME/wgrouter (~/.local/state/wgrouter)
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<std::path::PathBuf>,
+
+    /// Keep the state of this profile apart from all others, e.g. one profile per VPN provider
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+}
+
+impl StateArgs {
+    fn dir(&self) -> StrResult<std::path::PathBuf> {
+        history::state_dir(self.state_dir.as_deref(), self.profile.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum BackendKind {
+    Route,
+    Nft,
+    Netlink,
+}
+
+#[derive(clap::Args, Debug)]
+struct BackendArgs {
+    /// How to steer the traffic: a route per network, or an nftables set whose traffic gets --fwmark (for an 'ip rule fwmark');
+    /// netlink installs the routes itself instead of running ip, tagged with protocol 119 for --flush
+    #[arg(long, value_enum, default_value_t = BackendKind::Route)]
+    backend: BackendKind,
+
+    /// Firewall mark set by the nft backend, decimal or 0x-prefixed hex
+    #[arg(long, value_name = "MARK", default_value_t = wgrouter::apply::DEFAULT_FWMARK, value_parser = parse_mark)]
+    fwmark: u32,
+
+    /// With the nft backend, mark only the traffic of this LAN client (address, network, MAC address or DHCP
+    /// hostname), others go direct
+    #[arg(long, value_name = "ADDR|MAC|NAME", value_parser = Client::parse, conflicts_with = "client_set")]
+    lan_client: Vec<Client>,
+
+    /// dnsmasq or Kea lease file to look the --lan-client MAC addresses and hostnames up in
+    #[arg(long, value_name = "FILE")]
+    lan_leases: Vec<String>,
+
+    /// With the nft backend, mark only the traffic from addresses in this nftables set of table inet wgrouter,
+    /// created if missing and filled by hand or by other tools
+    #[arg(long, value_name = "NAME")]
+    client_set: Option<String>,
+}
+
+// the kernel limits set names to 31 bytes, and the IPv6 set adds _v6
+fn ipset_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.len() + "_v6".len() > 31 || s.contains(char::is_whitespace) {
+        return Err(format!("bad set name '{s}', it needs to be 1 to 28 characters without spaces"));
+    }
+    Ok(s.to_string())
+}
+
+// a BIRD symbol: a letter or underscore, then letters, digits and underscores
+fn bird_protocol(s: &str) -> Result<String, String> {
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("bad protocol name '{s}', use letters, digits and '_', not starting with a digit"));
+    }
+    Ok(s.to_string())
+}
+
+fn parse_mark(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }.map_err(|e| format!("bad mark '{s}': {e}"))
+}
+
+// seconds, from a number with an s, m, h or d unit, seconds without
+fn parse_interval(s: &str) -> Result<u64, String> {
+    let (number, unit) = s.find(|c: char| !c.is_ascii_digit()).map_or((s, ""), |at| s.split_at(at));
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("bad interval '{s}': the unit is one of s, m, h, d")),
+    };
+    match number.parse::<u64>() {
+        Ok(0) => Err(format!("bad interval '{s}': it must not be zero")),
+        Ok(n) => n.checked_mul(seconds).ok_or(format!("bad interval '{s}': too long")),
+        Err(e) => Err(format!("bad interval '{s}': {e}")),
+    }
+}
+
+// an https:// URL, the only kind DNS-over-HTTPS allows; fetched with the HTTP client of the remote feature
+fn parse_doh(s: &str) -> Result<String, String> {
+    if cfg!(not(feature = "remote")) {
+        return Err("built without the remote feature, which DNS-over-HTTPS needs".to_string());
+    }
+    match s.strip_prefix("https://") {
+        Some(rest) if !rest.is_empty() => Ok(s.to_string()),
+        _ => Err(format!("bad DNS-over-HTTPS URL '{s}': it must start with https://")),
+    }
+}
+
+// the TLS of the remote feature's HTTP client does DNS-over-TLS as well
+fn parse_dot(s: &str) -> Result<Upstream, String> {
+    if cfg!(not(feature = "remote")) {
+        return Err("built without the remote feature, which DNS-over-TLS needs".to_string());
+    }
+    Upstream::parse_tls(s)
+}
+
+impl BackendArgs {
+    fn backend(&self) -> StrResult<Backend> {
+        if self.backend != BackendKind::Nft && (!self.lan_client.is_empty() || self.client_set.is_some()) {
+            return Err("--lan-client and --client-set need --backend nft".to_string());
+        }
+        let scope = match &self.client_set {
+            Some(set) => Scope::Set(set.clone()),
+            None if self.lan_client.is_empty() => Scope::All,
+            None => Scope::Clients(lookup_clients(&self.lan_client, &self.lan_leases, "--lan-leases")?),
+        };
+        Ok(match self.backend {
+            BackendKind::Route => Backend::Route,
+            BackendKind::Nft => Backend::Nft { mark: self.fwmark, scope },
+            BackendKind::Netlink => Backend::Netlink,
         })
-        .collect::<HashMap<String, HashSet<String>>>();
+    }
+}
 
-    let fail_hosts = hosts_and_ips
-        .clone()
-        .into_iter()
-        .filter_map(|(host, res_ips)| {
-            if let Err(err) = res_ips {
-                Some((host, err))
-            } else {
-                None
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum DeadPolicy {
+    Withdraw,
+    Blackhole,
+    BackupPeer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Then {
+    Print,
+    Apply,
+}
+
+#[derive(clap::Args, Debug)]
+struct LearnArgs {
+    /// How long to watch; live sources (sniffing, processes, mobile proxy, flow exports) all run for this long
+    #[arg(long, default_value_t = 10)]
+    minutes: u64,
+
+    /// What to do with the learned routes
+    #[arg(long, value_enum, default_value_t = Then::Print)]
+    then: Then,
+
+    /// With --then apply --yes, route the sniffed hosts while learning goes on, a batch every few seconds, so a site
+    /// works through the tunnel right away; the routes the learned networks do not keep are removed at the end
+    #[arg(long)]
+    as_seen: bool,
+
+    #[command(flatten)]
+    target: ApplyTarget,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    #[command(flatten)]
+    target: ApplyTarget,
+
+    /// Instead of applying routes, remove all routes via --interface (in --table) that the netlink backend
+    /// installed, which it tags with protocol 119, even those the history has forgotten
+    #[arg(long, conflicts_with_all = ["schedule", "daemon"])]
+    flush: bool,
+
+    /// Also add the networks to the allowed IPs of --peer on this running WireGuard interface with 'wg set', leaving
+    /// its config file alone; the allowed IPs before and after are shown first
+    #[arg(long, value_name = "IFACE", requires = "peer", conflicts_with_all = ["schedule", "daemon", "flush"])]
+    wg_interface: Option<String>,
+
+    /// Send the networks to each step of the '[deployment NAME ...]' sections of the config file, routes, WireGuard
+    /// peers and output files, all with one resolution and one confirmation
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["flush", "wg_interface", "schedule", "daemon"])]
+    deployment: Option<String>,
+
+    /// Route the networks of each input only in the time windows this file gives its tag, e.g. 'streaming 19:00-24:00'
+    #[arg(long, value_name = "FILE")]
+    schedule: Option<String>,
+
+    /// Keep running, installing and removing the scheduled networks as their windows open and close, and with
+    /// --on-dead watching the tunnel
+    #[arg(long)]
+    daemon: bool,
+
+    /// With --daemon, resolve the hosts of the inputs again this often (e.g. 90s, 15m, 2h) and apply the networks
+    /// when they changed
+    #[arg(long, value_name = "DURATION", requires = "daemon", value_parser = parse_interval)]
+    interval: Option<u64>,
+
+    /// With --daemon, what to do with the routes while the tunnel is dead: take them away, blackhole them so nothing
+    /// goes out the direct way, or hand the networks on to the next live peer of --failover-order; undone when it is back
+    #[arg(long, value_enum, value_name = "POLICY", requires = "daemon")]
+    on_dead: Option<DeadPolicy>,
+
+    /// Count the tunnel as dead when the latest handshake (of the first --failover-order peer, or of any peer) is
+    /// older than this
+    #[arg(long, value_name = "SECONDS", default_value_t = wgrouter::failover::DEFAULT_DEAD_AFTER)]
+    dead_after: u64,
+
+    /// Also count the tunnel as dead when this address does not answer a ping through the interface
+    #[arg(long, value_name = "ADDR")]
+    ping: Option<std::net::IpAddr>,
+
+    /// Peers of the interface by public key, the one the networks belong to first, then the ones taking them over
+    /// with --on-dead backup-peer while those before are dead
+    #[arg(long, value_name = "PUBKEY,...", value_delimiter = ',')]
+    failover_order: Vec<String>,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args, Debug)]
+struct InitArgs {
+    /// Write the config here instead of $WGROUTER_CONFIG or $XDG_CONFIG_HOME/wgrouter/config (~/.config/wgrouter/config)
+    #[arg(long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+
+    /// Take every proposal without asking, overwriting an existing config
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct BundleArgs {
+    /// The bundle, a zip archive
+    bundle: std::path::PathBuf,
+
+    /// The config file instead of $WGROUTER_CONFIG or $XDG_CONFIG_HOME/wgrouter/config (~/.config/wgrouter/config)
+    #[arg(long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+
+    /// The plugin directory instead of $XDG_CONFIG_HOME/wgrouter/plugins
+    #[arg(long, value_name = "DIR")]
+    plugin_dir: Option<std::path::PathBuf>,
+
+    /// The state directory instead of $XDG_STATE_HOME/wgrouter (~/.local/state/wgrouter), with all its profiles
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<std::path::PathBuf>,
+
+    /// The --state file of the runs as well; on import, a bundled one is only imported with this
+    #[arg(long, value_name = "FILE")]
+    state: Option<std::path::PathBuf>,
+}
+
+impl BundleArgs {
+    fn parts(&self) -> StrResult<Vec<bundle::Part>> {
+        let config = self.config.clone().or_else(wgrouter::config::default_path)
+            .ok_or("neither WGROUTER_CONFIG, XDG_CONFIG_HOME nor HOME is set, give the config file with --config".to_string())?;
+        let plugins = plugin::plugin_dir(self.plugin_dir.as_deref()).ok_or("cannot locate the plugin directory, pass --plugin-dir".to_string())?;
+        let mut parts = vec![
+            bundle::Part::new("config", config),
+            bundle::Part::new("plugins", plugins),
+            bundle::Part::new("state", history::state_dir(self.state_dir.as_deref(), None)?),
+        ];
+        parts.extend(self.state.clone().map(|state| bundle::Part::new("state-file", state)));
+        Ok(parts)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct ImportArgs {
+    #[command(flatten)]
+    bundle: BundleArgs,
+
+    /// Overwrite files that exist already instead of importing nothing
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ScrubArgs {
+    /// The HAR to scrub
+    input: String,
+
+    /// Where to write the scrubbed copy, - for standard output
+    output: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExplainArgs {
+    /// The generated file
+    artifact: String,
+
+    /// Audit log of the runs that generated it, to tell which hosts each network is there for
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct StabilityArgs {
+    /// Audit log (--audit-log) of the runs so far
+    audit_log: String,
+
+    /// How many of the latest runs of each host to compare
+    #[arg(long, value_name = "N", default_value_t = wgrouter::stability::DEFAULT_RUNS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..))]
+    runs: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    /// Interface whose applied routes are checked
+    #[arg(long, value_name = "IFACE", default_value = "wg0")]
+    interface: String,
+
+    /// Routing table the routes were applied to
+    #[arg(long)]
+    table: Option<u32>,
+
+    /// WireGuard config whose endpoints are guarded and whose AllowedIPs are checked as well
+    #[arg(long, value_name = "FILE")]
+    wg_conf: Option<String>,
+
+    /// Endpoint (HOST:PORT) of a VPN server to check for, besides those of the running interfaces and of --wg-conf
+    #[arg(long = "endpoint", value_name = "HOST:PORT")]
+    endpoints: Vec<String>,
+
+    #[command(flatten)]
+    state: StateArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ApplyTarget {
+    /// Interface to install the routes on when applying
+    #[arg(long, value_name = "IFACE", default_value = "wg0")]
+    interface: String,
+
+    /// Routing table to install the routes into when applying
+    #[arg(long)]
+    table: Option<u32>,
+
+    /// Apply without asking for confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// How many applied generations to remember for undo
+    #[arg(long, default_value_t = history::DEFAULT_KEEP)]
+    keep: usize,
+
+    /// Make the new networks the only ones routed, removing routes from earlier applies they do not need
+    #[arg(long)]
+    replace: bool,
+
+    /// Show the routes that applying would add and remove, without changing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    state: StateArgs,
+}
+
+impl ApplyTarget {
+    // asking for confirmation needs a terminal that is not busy being an input
+    fn check(&self, args: &Args) -> StrResult<()> {
+        if !self.yes && args.files.iter().chain(&args.hosts_files).chain(&args.etc_hosts).any(|file| file == STDIN) {
+            return Err("standard input is read as an input file, so there is no way to confirm: add --yes".to_string());
+        }
+        self.backend.backend()?;
+        self.state.dir().map(|_| ())
+    }
+
+    fn apply(&self, report: &Report, audit: &mut AuditLog) -> StrResult<()> {
+        let nets = &report.networks;
+        if nets.is_empty() {
+            eprintln!("nothing to apply");
+            return Ok(());
+        }
+        let table = self.table.map(|t| format!(" in table {t}")).unwrap_or_default();
+        if self.dry_run {
+            let transition = wgrouter::apply::plan_generation(nets, &self.interface, self.table, self.replace, &self.state.dir()?)?;
+            show_transition(&transition, &format!("via {}{table}", self.interface));
+            let covered = Host::from_proc_net_tcp().map(|host| host.covered(&transition.add)).unwrap_or_default();
+            if !covered.is_empty() {
+                println!("would move {} open connections into the new routes:\n{}", covered.len(), covered.join("\n"));
             }
+            return Ok(());
+        }
+        if !self.yes && !confirm(&format!("Route these {} networks via {}{table}?", nets.len(), self.interface)) {
+            println!("not applied");
+            return Ok(());
+        }
+        self.install(nets, self.replace, audit)
+    }
+
+    fn install(&self, nets: &[String], replace: bool, audit: &mut AuditLog) -> StrResult<()> {
+        // the connections open before, to tell afterwards which of them the new routes took
+        let before = Host::from_proc_net_tcp().ok();
+        let (transition, installed) = wgrouter::apply::apply_generation(self.backend.backend()?, nets, &self.interface, self.table, self.keep, replace, &self.state.dir()?)?;
+        audit_applied(audit, "apply", &self.interface, self.table, &self.backend, &transition, installed)?;
+        println!("added {} and removed {} routes, {installed} routes via {} now; 'undo' takes them back",
+            transition.add.len(), transition.remove.len(), self.interface);
+        match before {
+            Some(before) => report_impact(&before, &transition.add, audit),
+            None => Ok(()),
+        }
+    }
+}
+
+// how long the connections the new routes took get to break before they are looked at again
+const IMPACT_SECONDS: u64 = 2;
+
+// the connections open before an apply that its new routes cover, and which of them are still open a moment later;
+// nothing when it covers none
+fn report_impact(before: &Host, added: &[String], audit: &mut AuditLog) -> StrResult<()> {
+    if before.covered(added).is_empty() {
+        return Ok(());
+    }
+    std::thread::sleep(std::time::Duration::from_secs(IMPACT_SECONDS));
+    let impact = before.impact(&Host::from_proc_net_tcp()?, added);
+    if !impact.kept.is_empty() {
+        eprintln!("\n{} open connections now go into the new routes, they may move into the tunnel or stall:\n{}", impact.kept.len(), impact.kept.join("\n"));
+    }
+    if !impact.ended.is_empty() {
+        eprintln!("\n{} connections into the new routes closed since the apply, they likely broke:\n{}", impact.ended.len(), impact.ended.join("\n"));
+    }
+    audit.record("impact", json!({"kept": impact.kept, "ended": impact.ended}))
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct Args {
+    /// Capture files: HAR exported from the browser's developer tools, Fiddler .saz, Charles .chlsj/.chlsx sessions, .warc/.warc.gz crawls, Android bugreport-*.zip/.txt, PCAPdroid .csv exports, .pcap/.pcapng captures (DNS queries and answers, TLS/QUIC server names), .txt/.list host lists, hosts files; - reads a HAR or a host list from standard input; http(s):// URLs are fetched
+    files: Vec<String>,
+
+    /// Plain text list of hostnames, URLs, IP addresses or networks (CIDR), one per line; .txt and .list capture files are read as such lists too
+    #[arg(long = "hosts-file", value_name = "FILE")]
+    hosts_files: Vec<String>,
+
+    /// Route this host too, as if an input had it, e.g. login.example.com; given again for more
+    #[arg(long = "include-host", value_name = "HOST", value_parser = wgrouter::input::list::list_host)]
+    include_hosts: Vec<String>,
+
+    /// Route this network too, as if an input had it, e.g. 203.0.113.0/24; given again for more
+    #[arg(long = "include-net", value_name = "CIDR", value_parser = wgrouter::exclusions::parse_net)]
+    include_nets: Vec<ipnetwork::IpNetwork>,
+
+    /// Hosts file ("address name alias..." lines, like /etc/hosts) whose names are routed to the addresses pinned to
+    /// them, without DNS lookups; capture files named hosts or *.hosts are read as such files too
+    #[arg(long = "etc-hosts", value_name = "FILE")]
+    etc_hosts: Vec<String>,
+
+    /// For capture files and host lists given as http(s):// URLs: "host token" lines of bearer tokens to send; hosts
+    /// without one get their login from $NETRC or ~/.netrc, if any
+    #[cfg(feature = "remote")]
+    #[arg(long, value_name = "FILE")]
+    bearer_token_file: Option<String>,
+
+    /// Directory of input-NAME and output-NAME plugin executables, instead of $XDG_CONFIG_HOME/wgrouter/plugins;
+    /// capture files with extension NAME are read by the input-NAME plugin, if there is one
+    #[arg(long, value_name = "DIR")]
+    plugin_dir: Option<std::path::PathBuf>,
+
+    /// Take hosts from the input-NAME plugin, run without a file
+    #[arg(long = "input-plugin", value_name = "NAME")]
+    input_plugins: Vec<String>,
+
+    /// Render the result with the output-NAME plugin instead of --format
+    #[arg(long, value_name = "NAME")]
+    output_plugin: Option<String>,
+
+    /// Look for candidate hosts referenced from captured response bodies (HTML, JS, JSON, manifests, service workers)
+    #[arg(long)]
+    scan_bodies: bool,
+
+    /// Look for candidate hosts a site is permitted to contact in its CSP, CORS and reporting headers
+    #[arg(long)]
+    mine_headers: bool,
+
+    /// Resolve and route candidate hosts too, not only the ones actually requested
+    #[arg(long)]
+    route_candidates: bool,
+
+    /// NetFlow v5/v9 or IPFIX export file (concatenated export packets) to take destination addresses from
+    #[arg(long = "netflow", value_name = "FILE")]
+    netflow_files: Vec<String>,
+
+    /// Listen for NetFlow v5/v9 or IPFIX exports on this UDP address
+    #[arg(long, value_name = "ADDR")]
+    netflow_listen: Option<String>,
+
+    /// How long to listen for flow exports
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    netflow_seconds: u64,
+
+    /// Take destination addresses from the live conntrack table ("whatever is being talked to right now")
+    #[arg(long)]
+    from_conntrack: bool,
+
+    /// Saved output of `ss -tunp` or `netstat -an` (e.g. taken on another machine) to take destination addresses from
+    #[arg(long = "socket-list", value_name = "FILE")]
+    socket_list_files: Vec<String>,
+
+    /// Watch the connections opened by processes with this name (e.g. steam, zoom) and route what they talk to
+    #[arg(long = "process", value_name = "NAME")]
+    process_names: Vec<String>,
+
+    /// Only watch processes in this cgroup (path below the cgroup root, e.g. system.slice/nginx.service); also limits the conflict check to their connections
+    #[arg(long = "cgroup", value_name = "PATH")]
+    cgroups: Vec<String>,
+
+    /// Only watch processes of this systemd unit (e.g. nginx or app-zoom.scope); also limits the conflict check to their connections
+    #[arg(long = "unit", value_name = "UNIT")]
+    units: Vec<String>,
+
+    /// How long to watch the selected processes
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    process_seconds: u64,
+
+    /// Run an HTTP(S) proxy on this address and record the hosts a phone pointed at it talks to (HTTPS is only decrypted
+    /// with --mobile-capture-ca)
+    #[cfg(feature = "mobile-capture")]
+    #[arg(long, value_name = "ADDR")]
+    mobile_capture: Option<String>,
+
+    /// Decrypt HTTPS through the mobile capture proxy: write a CA certificate made up for this run to FILE, to install on
+    /// the phone; request URLs are recorded too, and hosts in the responses become candidates
+    #[cfg(feature = "mobile-capture")]
+    #[arg(long, value_name = "FILE", requires = "mobile_capture")]
+    mobile_capture_ca: Option<String>,
+
+    /// How long to run the mobile capture proxy
+    #[cfg(feature = "mobile-capture")]
+    #[arg(long, value_name = "MINUTES", default_value_t = 5)]
+    mobile_capture_minutes: u64,
+
+    /// Sniff this network interface ("any" for all of them) for TLS/QUIC server names and plaintext DNS answers;
+    /// hosts seen in DNS answers are routed to the addresses the client got, without resolving them again
+    #[arg(long, value_name = "IFACE")]
+    sniff: Option<String>,
+
+    /// How long to sniff
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    sniff_seconds: u64,
+
+    /// Only use flows and connections coming from these source networks
+    #[arg(long, value_name = "CIDR")]
+    flow_src: Vec<ipnetwork::IpNetwork>,
+
+    /// Only use flows and connections going to these destination ports
+    #[arg(long, value_name = "PORT")]
+    flow_dst_port: Vec<u16>,
+
+    /// Only use destinations that received at least this many bytes in matching flows
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    flow_min_bytes: u64,
+
+    /// Prefix length of the network routed for each address, from 32 (just the address) to 8
+    #[arg(long, visible_alias = "granularity", value_name = "LEN", default_value_t = DEFAULT_PREFIX_LEN,
+        value_parser = clap::value_parser!(u8).range(i64::from(MIN_PREFIX_LEN)..=i64::from(MAX_PREFIX_LEN)))]
+    prefix_len: u8,
+
+    /// Prefix length of the network routed for each IPv6 address (with --ipv6), from 128 (just the address) to 16
+    #[arg(long, value_name = "LEN", default_value_t = DEFAULT_PREFIX_LEN6,
+        value_parser = clap::value_parser!(u8).range(i64::from(MIN_PREFIX_LEN6)..=i64::from(MAX_PREFIX_LEN6)))]
+    prefix_len6: u8,
+
+    /// Also look up AAAA records and route IPv6 networks
+    #[arg(long, conflicts_with = "ipv4_only")]
+    ipv6: bool,
+
+    /// Route IPv4 networks only, ignoring IPv6 addresses wherever they were seen (the default)
+    #[arg(long)]
+    ipv4_only: bool,
+
+    /// Addresses that merging networks may claim beyond what the resolved addresses need, in total (0: exact merges only)
+    #[arg(long, value_name = "ADDRS", default_value_t = 0)]
+    slack: u64,
+
+    /// Device the networks are for: warn when there are more than it handles well
+    #[arg(long, value_enum, value_name = "DEVICE")]
+    target_capacity: Option<TargetCapacity>,
+
+    /// Warn when there are more networks than this (instead of the --target-capacity limit)
+    #[arg(long, value_name = "N")]
+    max_networks: Option<usize>,
+
+    /// Merge networks, whatever --slack allows, until they fit --max-networks or --target-capacity
+    #[arg(long)]
+    fit: bool,
+
+    /// Weight --slack merges by traffic: bytes of HAR requests and responses and of flow records; busy networks stay
+    /// close to their addresses, rarely used ones are merged first; the bytes per network are reported
+    #[arg(long)]
+    weighted: bool,
+
+    /// Never route a host matching this pattern, e.g. *.bank.example (* for any characters, ? for one); given again
+    /// for more
+    #[arg(long = "exclude-host", value_name = "PATTERN", value_parser = wgrouter::exclusions::host_pattern)]
+    exclude_hosts: Vec<String>,
+
+    /// Never route this network or address, e.g. an employer's range; it is cut out of the networks that overlap it
+    #[arg(long = "exclude-net", value_name = "CIDR", value_parser = wgrouter::exclusions::parse_net)]
+    exclude_nets: Vec<ipnetwork::IpNetwork>,
+
+    /// File of host patterns, addresses and networks never to route, one per line, # starting a comment
+    #[arg(long = "exclude-file", value_name = "FILE")]
+    exclude_files: Vec<String>,
+
+    /// Per-host prefix length bounds, as lines like: bounds "*.example.com" { min = /24, max = /20 }; addresses of a
+    /// matching host are routed in networks no narrower than min and no wider than max (min6 and max6 for IPv6)
+    #[arg(long, value_name = "FILE")]
+    bounds: Option<String>,
+
+    /// What to do with a network that would capture an open connection of this host
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = ConflictPolicy::Drop)]
+    conflict_policy: ConflictPolicy,
+
+    /// Also merge the networks into the AllowedIPs of --peer in this WireGuard config, keeping a .bak copy
+    #[arg(long, value_name = "FILE", requires = "peer")]
+    wg_conf: Option<String>,
+
+    /// With --wg-conf (or apply --wg-interface), the public key of the peer that gets the networks
+    #[arg(long, value_name = "PUBKEY")]
+    peer: Option<String>,
+
+    /// Endpoint (HOST:PORT) of a VPN server whose address, and the nameservers resolving it when it is a name, are
+    /// cut out of the routed networks; the endpoints of the interfaces that are up and of --wg-conf are always guarded
+    #[arg(long = "endpoint", value_name = "HOST:PORT")]
+    endpoints: Vec<String>,
+
+    /// Append every decision (host extracted, filtered, resolved, aggregated into a network, applied) to this JSON lines audit log
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+
+    /// Tolerate HAR files that do not strictly follow the spec (missing fields, nulls, odd numbers, non-HTTP URLs)
+    #[arg(long)]
+    lenient: bool,
+
+    /// Read only the HAR entries whose TLS handshake completed, or only those whose handshake never did (reset, timed
+    /// out: likely blocked, and often exactly the hosts that need the VPN); plain http entries count as completed
+    #[arg(long, value_enum, value_name = "STATE")]
+    tls_handshake: Option<TlsHandshake>,
+
+    /// Read only the HAR entries that look blocked: reset, refused or timed out connections, failed TLS handshakes,
+    /// very slow connects and long waits for no response; the hosts are listed with the reason
+    #[arg(long)]
+    only_blocked: bool,
+
+    /// Process the inputs even when the same files and options were processed last time; otherwise that run's
+    /// result is reused without parsing or resolving anything
+    #[arg(long)]
+    force: bool,
+
+    /// Keep what each host resolved to, and the networks of its addresses, in this JSON file: hosts looked up less than
+    /// --state-max-age ago are not looked up again, what changed since the last run is shown, and a host that does not
+    /// resolve falls back to its last addresses
+    #[arg(long, value_name = "FILE")]
+    state: Option<String>,
+
+    /// With --state, for how long the addresses of a host are taken from it instead of looking the host up (e.g. 30m, 1d)
+    #[arg(long, value_name = "DURATION", default_value_t = wgrouter::state::DEFAULT_MAX_AGE, value_parser = parse_interval)]
+    state_max_age: u64,
+
+    /// Write the hosts left unresolved to FILE, as JSON for a .json file and a host list for any other, and exit with
+    /// status 2 when there are any; FILE given as an input later tries just those again
+    #[arg(long, value_name = "FILE")]
+    unresolved: Option<String>,
+
+    /// How many hosts to look up at the same time
+    #[arg(long, value_name = "N", default_value_t = wgrouter::routes::DEFAULT_CONCURRENCY)]
+    concurrent_lookups: usize,
+
+    /// Look each host up this many times, the later ones at random moments within --jitter, and route every address
+    /// the answers gave: round-robin CDNs hand out only a few of their addresses per query
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: u32,
+
+    /// With --samples, the time the later lookups of each host are spread over (e.g. 10s, 2m)
+    #[arg(long, value_name = "DURATION", default_value_t = wgrouter::routes::DEFAULT_JITTER.as_secs(), value_parser = parse_interval)]
+    jitter: u64,
+
+    /// How long a DNS query may go unanswered before it counts as failed (e.g. 3s)
+    #[arg(long, value_name = "DURATION", default_value_t = wgrouter::dns::DEFAULT_DNS_TIMEOUT.as_secs(), value_parser = parse_interval)]
+    dns_timeout: u64,
+
+    /// How many times to try a lookup again after it timed out or failed, 0 for never; a name that does not exist is
+    /// not tried again
+    #[arg(long, value_name = "N", default_value_t = wgrouter::dns::DEFAULT_DNS_RETRIES)]
+    dns_retries: u32,
+
+    /// The wait before the first retry of a lookup, doubled before each next one
+    #[arg(long, value_name = "DURATION", default_value_t = wgrouter::dns::DEFAULT_DNS_BACKOFF.as_secs(), value_parser = parse_interval)]
+    dns_backoff: u64,
+
+    /// Resolve the hosts through this DNS-over-HTTPS server (e.g. https://cloudflare-dns.com/dns-query), so the
+    /// local network can neither see nor change the answers
+    #[arg(long, value_name = "URL", value_parser = parse_doh)]
+    doh: Option<String>,
+
+    /// Resolve the hosts through this DNS-over-TLS server, given as its address and the name its certificate is for
+    /// (e.g. 1.1.1.1:853@cloudflare-dns.com, the port 853 when left out)
+    #[arg(long, value_name = "ADDR@NAME", value_parser = parse_dot, conflicts_with = "doh")]
+    dot: Option<Upstream>,
+
+    /// Ask only this nameserver (given again for more) for the hosts, instead of the nameservers of each host's domain
+    /// along with 8.8.8.8, 1.1.1.1 and 9.9.9.9; the nameservers are then not looked up at all
+    #[arg(long, value_name = "IP", conflicts_with_all = ["doh", "dot"])]
+    nameserver: Vec<std::net::IpAddr>,
+
+    /// Ask only the resolvers the system is configured with (/etc/resolv.conf) for the hosts, for networks that block
+    /// other DNS servers
+    #[arg(long, conflicts_with_all = ["nameserver", "doh", "dot"])]
+    system_resolver: bool,
+
+    /// Validate the answers with DNSSEC, leaving hosts whose addresses fail it unresolved; those of unsigned zones
+    /// are taken as they are
+    #[arg(long, conflicts_with_all = ["doh", "dot"])]
+    dnssec: bool,
+
+    /// Also ask every public resolver and every nameserver of its own domain for the final target of a host's CNAME
+    /// chain (or every --nameserver), and route all the addresses they give: CDNs hand out a part of theirs to each
+    #[arg(long, conflicts_with_all = ["doh", "dot", "system_resolver"])]
+    cname_targets: bool,
+
+    /// Check the output with the tool that reads it before printing it (nft -c, sh -n, bird -p, unbound-checkconf),
+    /// failing on what it rejects; skipped when the tool is not installed
+    #[arg(long)]
+    validate: bool,
+
+    /// Output format: the WireGuard AllowedIPs line, data for Ansible or Terraform, cloud egress rules, DNS forwarding,
+    /// or everything as JSON
+    #[arg(long, alias = "output", value_enum, default_value_t = Format::AllowedIps)]
+    format: Format,
+
+    /// With --format ansible, also write a task file that routes the networks via the tunnel
+    #[arg(long, value_name = "FILE")]
+    ansible_tasks: Option<String>,
+
+    /// Name (or description, for AWS) of the rule emitted by the cloud security group formats
+    #[arg(long, value_name = "NAME", default_value = "wgrouter-egress")]
+    rule_name: String,
+
+    /// With --format nft-set, the inet table of the sets
+    #[arg(long, value_name = "TABLE", default_value = "wgrouter")]
+    nft_table: String,
+
+    /// With --format nft-set, the name of the set; IPv6 networks go to a second one with a _v6 suffix
+    #[arg(long, value_name = "NAME", default_value = "wgrouter")]
+    nft_set: String,
+
+    /// With --format nft-set, print add element commands for existing sets instead of their definitions
+    #[arg(long)]
+    nft_elements: bool,
+
+    /// With --format ipset, the name of the set; IPv6 networks go to a second one with a _v6 suffix
+    #[arg(long, value_name = "NAME", default_value = "wgrouter", value_parser = ipset_name)]
+    ipset_name: String,
+
+    /// With --format ipset, print the input of 'ipset restore' instead of commands
+    #[arg(long)]
+    ipset_restore: bool,
+
+    /// With --format pf, the name of the table
+    #[arg(long, value_name = "NAME", default_value = "wgrouter")]
+    pf_table: String,
+
+    /// With --format pf, also write pf.conf rules loading the table from /etc/pf.<table> and sending its traffic
+    /// through --rule-interface to this file
+    #[arg(long, value_name = "FILE")]
+    pf_rules: Option<String>,
+
+    /// With --format bird, the name of the static protocol; IPv6 networks go to a second one with a _v6 suffix
+    #[arg(long, value_name = "NAME", default_value = "wgrouter", value_parser = bird_protocol)]
+    bird_protocol: String,
+
+    /// With --format networkmanager, the name of the WireGuard connection
+    #[arg(long, value_name = "NAME", default_value = "wg0")]
+    nm_connection: String,
+
+    /// With --format networkmanager, print nmcli commands setting the routes instead of keyfile sections
+    #[arg(long)]
+    nmcli: bool,
+
+    /// With --format windows, print PowerShell New-NetRoute commands instead of a cmd script
+    #[arg(long)]
+    powershell: bool,
+
+    /// With --format openvpn, write 'push "route ..."' lines for a server config instead of client ones
+    #[arg(long)]
+    openvpn_push: bool,
+
+    /// With --format ip-rules, a LAN client to steer through the tunnel: an IP address, a network, a MAC address
+    /// looked up in the neighbour table, or the hostname of a DHCP lease
+    #[arg(long = "client", value_name = "ADDR|MAC|NAME", value_parser = Client::parse)]
+    clients: Vec<Client>,
+
+    /// With --format ip-rules, more clients, one per line
+    #[arg(long, value_name = "FILE")]
+    clients_file: Option<String>,
+
+    /// With --format ip-rules, a dnsmasq or Kea lease file to look the client MAC addresses and hostnames up in
+    #[arg(long, value_name = "FILE")]
+    client_leases: Vec<String>,
+
+    /// With --format ip-rules, ip-route, networkd, bird, pf or windows, the tunnel interface (apply takes --interface)
+    #[arg(long, value_name = "IFACE", default_value = "wg0")]
+    rule_interface: String,
+
+    /// With --format ip-rules, ip-route or networkd, the routing table holding the networks
+    #[arg(long, value_name = "TABLE", default_value_t = 100)]
+    rule_table: u32,
+
+    /// With --format ip-route, also write the script removing the routes again to this file
+    #[arg(long, value_name = "FILE")]
+    teardown_script: Option<String>,
+
+    /// With --format ip-rules, the priority of the client rules
+    #[arg(long, value_name = "PRIO", default_value_t = 1000)]
+    rule_priority: u32,
+
+    /// Resolver on the VPN side that the DNS formats send the tracked domains to, in the syntax of the target resolver, e.g. 10.8.0.1, 10.8.0.1@53 or tls://10.8.0.1
+    #[arg(long, value_name = "RESOLVER")]
+    vpn_dns: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum TlsHandshake {
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ConflictPolicy {
+    /// Leave the network out, so the connection stays outside the tunnel
+    Drop,
+    /// Route the network anyway, the connection may break or move into the tunnel
+    Keep,
+    /// Route the parts of the network around the connection that hold resolved addresses
+    Narrow,
+    /// Ask for each such network
+    Ask,
+}
+
+impl ConflictPolicy {
+    fn decide(self, net: &ipnetwork::IpNetwork, conns: &[(std::net::IpAddr, u16)]) -> Conflict {
+        match self {
+            ConflictPolicy::Drop => Conflict::Drop,
+            ConflictPolicy::Keep => Conflict::Keep,
+            ConflictPolicy::Narrow => Conflict::Narrow,
+            ConflictPolicy::Ask => ask_conflict(net, conns),
+        }
+    }
+}
+
+// asked on the terminal, with the connections listed; dropping is the answer for anything else
+fn ask_conflict(net: &ipnetwork::IpNetwork, conns: &[(std::net::IpAddr, u16)]) -> Conflict {
+    let conns = conns.iter().map(|(ip, port)| format!("{ip}:{port}")).collect::<Vec<_>>();
+    eprint!("{net} would capture the connections to {} of this host: [d]rop, [k]eep or [n]arrow it? [d] ", conns.join(", "));
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "k" | "keep" => Conflict::Keep,
+        "n" | "narrow" => Conflict::Narrow,
+        _ => Conflict::Drop,
+    }
+}
+
+// conservative figures for what these devices still handle well, not hard limits
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum TargetCapacity {
+    /// WireGuard for Android: every AllowedIPs entry becomes a VpnService route, and long lists make the tunnel slow
+    /// to come up or fail on some phones (250)
+    WgAndroid,
+    /// Ubiquiti EdgeRouter: every static route goes through an EdgeOS config commit (1000)
+    Edgerouter,
+    /// MikroTik hAP: little RAM and CPU for route lookups (500)
+    MikrotikHap,
+}
+
+impl TargetCapacity {
+    fn max_networks(self) -> usize {
+        match self {
+            TargetCapacity::WgAndroid => 250,
+            TargetCapacity::Edgerouter => 1000,
+            TargetCapacity::MikrotikHap => 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    AllowedIps,
+    /// Hosts with their addresses, unresolved hosts with the reason, networks and warnings as one JSON document
+    Json,
+    Ansible,
+    /// Variables for a .auto.tfvars.json file
+    TerraformJson,
+    /// String map for Terraform's external data source (lists and maps JSON-encoded)
+    TerraformExternal,
+    /// AWS security group egress IpPermissions JSON
+    AwsSg,
+    /// GCP egress firewall rule YAML
+    GcpFirewall,
+    /// Azure NSG outbound securityRules JSON
+    AzureNsg,
+    /// AdGuard Home upstreams sending the tracked domains to --vpn-dns
+    Adguard,
+    /// Blocky conditional mapping sending the tracked domains to --vpn-dns
+    Blocky,
+    /// Unbound forward-zone clauses sending the tracked domains to --vpn-dns
+    Unbound,
+    /// Knot Resolver policy forwarding the tracked domains to --vpn-dns
+    KnotResolver,
+    /// Shell commands routing the networks in --rule-table, looked up for traffic from the --client addresses only
+    IpRules,
+    /// OpenVPN route directives, pushed to the clients with --openvpn-push
+    Openvpn,
+    /// Shell script adding a route per network via --rule-interface in --rule-table, see --teardown-script
+    IpRoute,
+    /// Windows cmd script adding a route per network on --rule-interface, or with --powershell New-NetRoute commands
+    Windows,
+    /// pf table file of the networks, see --pf-rules
+    Pf,
+    /// BIRD 2 static protocol routing the networks via --rule-interface, to redistribute them
+    Bird,
+    /// NetworkManager keyfile route sections for --nm-connection, or with --nmcli the commands setting its routes
+    Networkmanager,
+    /// systemd-networkd drop-in with a [Route] section per network via --rule-interface in --rule-table
+    Networkd,
+    /// nftables set definitions of the networks, or with --nft-elements the add element commands filling them
+    NftSet,
+    /// ipset commands filling a hash:net set with the networks, or with --ipset-restore the input of 'ipset restore'
+    Ipset,
+}
+
+fn har_options(args: &Args) -> HarOptions {
+    let tls = match args.tls_handshake {
+        None => TlsFilter::All,
+        Some(TlsHandshake::Completed) => TlsFilter::Completed,
+        Some(TlsHandshake::Failed) => TlsFilter::Failed,
+    };
+    HarOptions { scan_bodies: args.scan_bodies, mine_headers: args.mine_headers, lenient: args.lenient, tls, only_blocked: args.only_blocked }
+}
+
+fn parse_files(args: &Args, opts: &HarOptions) -> StrResult<Vec<(String, Hosts)>> {
+    let lists = args.hosts_files.par_iter().map(|file| {
+        let hosts = parse_input(file, args, |path| hostnames_from_list(path).map(|confirmed| Hosts { confirmed, ..Hosts::default() }));
+        hosts.map(|hosts| (file_tag(file), hosts))
+    });
+    let sources = args.input_plugins.par_iter().map(|name| {
+        plugin::hostnames_from_plugin(&find_plugin(args, plugin::Kind::Input, name)?, None).map(|hosts| (name.clone(), hosts))
+    });
+    args.files
+        .par_iter()
+        .filter(|file| !is_packet_capture(file, args) && !is_pinned_hosts(file, args))
+        .map(|file| {
+            let hosts = parse_input(file, args, |path| match extension_plugin(path, args) {
+                Some(plugin) => plugin::hostnames_from_plugin(&plugin, Some(path)),
+                None => hostnames_from_file(path, opts),
+            });
+            hosts.map(|hosts| (file_tag(file), hosts))
         })
-        .collect::<HashMap<String, String>>();
+        .chain(lists)
+        .chain(sources)
+        .collect()
+}
+
+fn is_packet_capture(file: &str, args: &Args) -> bool {
+    let extension = std::path::Path::new(file).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+    matches!(extension.as_str(), "pcap" | "pcapng") && extension_plugin(file, args).is_none()
+}
+
+fn is_pinned_hosts(file: &str, args: &Args) -> bool {
+    is_hosts_file(file) && extension_plugin(file, args).is_none()
+}
+
+// hosts seen in each capture file, by its tag
+type CapturedHosts = Vec<(String, HashSet<String>)>;
 
-    println!("\nResolved hosts:\n{ok_hosts:?}\n");
-    println!("\nUnresolved hosts:\n{fail_hosts:?}\n");
+// hosts from the DNS queries and TLS/QUIC handshakes in pcap files, and the DNS answers recorded along with them
+fn packet_capture_hosts(args: &Args) -> StrResult<(CapturedHosts, PassiveDns)> {
+    args.files.iter().filter(|file| is_packet_capture(file, args)).try_fold((Vec::new(), PassiveDns::new()), |(mut tagged, mut passive), file| {
+        let decoder = parse_input(file, args, |path| {
+            let mut decoder = PacketDecoder::default();
+            wgrouter::capture::pcap::decode_file(path, &mut decoder).map(|_| decoder)
+        })?;
+        let hosts = decoder.sni_hosts.iter().chain(&decoder.dns_queries).chain(decoder.passive_dns.hosts.keys()).cloned().collect::<HashSet<_>>();
+        eprintln!("\nHosts seen in {file}:\n{:?}\n", hosts.iter().collect::<BTreeSet<_>>());
+        passive_addresses(decoder.passive_dns, args).into_iter().for_each(|(host, ips)| passive.entry(host).or_default().extend(ips));
+        tagged.push((file_tag(file), hosts));
+        Ok((tagged, passive))
+    })
+}
 
-    let host_util = Host::from_proc_net_tcp()?;
+// hosts of hosts files, by file tag, and the addresses pinned to them
+fn pinned_hosts(args: &Args) -> StrResult<(CapturedHosts, PassiveDns)> {
+    let mut files = args.etc_hosts.iter().chain(args.files.iter().filter(|file| is_pinned_hosts(file, args)));
+    files.try_fold((Vec::new(), PassiveDns::new()), |(mut tagged, mut pinned), file| {
+        let entries = parse_input(file, args, pinned_from_hosts_file)?;
+        tagged.push((file_tag(file), entries.keys().cloned().collect()));
+        entries.into_iter().for_each(|(host, ips)| pinned.entry(host).or_default().extend(ips));
+        Ok((tagged, pinned))
+    })
+}
 
-    let wg_str = ok_hosts
+// the addresses clients got in the DNS answers seen on the wire, of the routed families
+fn passive_addresses(seen: wgrouter::capture::dns::PassiveDns, args: &Args) -> PassiveDns {
+    seen.hosts
         .into_iter()
-        .flat_map(|(_, ips)| ips).collect::<HashSet<String>>()
+        .map(|(host, ips)| (host, ips.into_iter().filter(|ip| args.ipv6 || ip.is_ipv4()).map(|ip| ip.to_string()).collect()))
+        .collect()
+}
+
+fn find_plugin(args: &Args, kind: plugin::Kind, name: &str) -> StrResult<std::path::PathBuf> {
+    let dir = plugin::plugin_dir(args.plugin_dir.as_deref()).ok_or("cannot locate the plugin directory, pass --plugin-dir".to_string())?;
+    plugin::find(&dir, kind, name).ok_or_else(|| {
+        let kind_name = format!("{kind:?}").to_ascii_lowercase();
+        match plugin::available(&dir, kind) {
+            names if names.is_empty() => format!("no {kind_name} plugin '{name}' in {}, there are none", dir.display()),
+            names => format!("no {kind_name} plugin '{name}' in {}, there are: {}", dir.display(), names.join(", ")),
+        }
+    })
+}
+
+// the input plugin named after the extension of file, which takes precedence over the built-in formats
+fn extension_plugin(file: &str, args: &Args) -> Option<std::path::PathBuf> {
+    let extension = std::path::Path::new(file).extension()?.to_str()?.to_ascii_lowercase();
+    plugin::find(&plugin::plugin_dir(args.plugin_dir.as_deref())?, plugin::Kind::Input, &extension)
+}
+
+// remote inputs are parsed from a temporary local copy, removed afterwards
+fn parse_input<T>(file: &str, args: &Args, parse: impl Fn(&str) -> StrResult<T>) -> StrResult<T> {
+    let Some(copy) = local_copy(file, args)? else {
+        return parse(file);
+    };
+    let hosts = parse(&copy.to_string_lossy()).map_err(|e| format!("{e}, fetched from {file}"));
+    if let Some(dir) = copy.parent() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    hosts
+}
+
+#[cfg(feature = "remote")]
+fn local_copy(file: &str, args: &Args) -> StrResult<Option<std::path::PathBuf>> {
+    if !is_remote(file) {
+        return Ok(None);
+    }
+    let credentials = wgrouter::input::remote::Credentials::load(args.bearer_token_file.as_deref())?;
+    wgrouter::input::remote::download(file, &credentials).map(Some)
+}
+
+#[cfg(not(feature = "remote"))]
+fn local_copy(file: &str, _args: &Args) -> StrResult<Option<std::path::PathBuf>> {
+    match is_remote(file) {
+        true => Err(format!("cannot fetch {file}: built without the remote feature")),
+        false => Ok(None),
+    }
+}
+
+fn file_tag(file: &str) -> String {
+    if file == STDIN {
+        return "stdin".to_string();
+    }
+    std::path::Path::new(file)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or(file.to_string())
+}
+
+fn tag(tags: &mut BTreeMap<String, BTreeSet<String>>, name: &str, hosts: impl IntoIterator<Item = String>) {
+    let mut hosts = hosts.into_iter().peekable();
+    if hosts.peek().is_some() {
+        tags.entry(name.to_string()).or_default().extend(hosts);
+    }
+}
+
+#[cfg(feature = "mobile-capture")]
+fn mobile_capture_hosts(args: &Args) -> StrResult<Hosts> {
+    let Some(addr) = &args.mobile_capture else {
+        return Ok(Hosts::default());
+    };
+    let duration = std::time::Duration::from_secs(args.mobile_capture_minutes * 60);
+    let interceptor = match &args.mobile_capture_ca {
+        Some(path) => {
+            // a day to spare for installing the CA before the capture starts
+            let authority = wgrouter::capture::mitm::Authority::generate(duration + std::time::Duration::from_secs(24 * 60 * 60))?;
+            std::fs::write(path, authority.pem()).map_err(|e| format!("could not write {path}: {e}"))?;
+            eprintln!("install {path} on the phone as a trusted CA certificate (and remove it after the capture); its key is only kept \
+                in memory until the capture ends");
+            Some(std::sync::Arc::new(wgrouter::capture::mitm::Interceptor::new(authority)?))
+        }
+        None => None,
+    };
+    eprintln!("set the HTTP proxy of the phone's Wi-Fi network to this machine, port {}, and use the apps for {} minutes",
+        addr.rsplit(':').next().unwrap_or_default(), args.mobile_capture_minutes);
+    let capture = wgrouter::capture::proxy::hostnames_from_proxy(addr, duration, interceptor)?;
+    eprintln!("\nHosts seen by the mobile capture proxy:\n{:?}\n", capture.hosts.confirmed.iter().collect::<BTreeSet<_>>());
+    if !capture.urls.is_empty() {
+        eprintln!("\nRequests seen by the mobile capture proxy:\n{:?}\n", capture.urls);
+    }
+    Ok(capture.hosts)
+}
+
+#[cfg(not(feature = "mobile-capture"))]
+fn mobile_capture_hosts(_args: &Args) -> StrResult<Hosts> {
+    Ok(Hosts::default())
+}
+
+#[cfg(feature = "mobile-capture")]
+fn mobile_capture_enabled(args: &Args) -> bool {
+    args.mobile_capture.is_some()
+}
+
+#[cfg(not(feature = "mobile-capture"))]
+fn mobile_capture_enabled(_args: &Args) -> bool {
+    false
+}
+
+#[cfg(feature = "mobile-capture")]
+fn set_mobile_capture_minutes(args: &mut Args, minutes: u64) {
+    args.mobile_capture_minutes = minutes;
+}
+
+#[cfg(not(feature = "mobile-capture"))]
+fn set_mobile_capture_minutes(_args: &mut Args, _minutes: u64) {}
+
+#[cfg(target_os = "linux")]
+fn sniffed_hosts(args: &Args, mut early: Option<(&mut EarlyApply, &mut AuditLog)>) -> StrResult<(HashSet<String>, PassiveDns)> {
+    let Some(iface) = &args.sniff else {
+        return Ok((HashSet::new(), PassiveDns::new()));
+    };
+    eprintln!("sniffing on {iface} for {} seconds", args.sniff_seconds);
+    let mut decoder = PacketDecoder::default();
+    let (duration, every) = (std::time::Duration::from_secs(args.sniff_seconds), std::time::Duration::from_secs(EARLY_SECONDS));
+    wgrouter::capture::sniff::sniff_ticking(iface, duration, &mut decoder, every, |decoder| match &mut early {
+        Some((early, audit)) => early.take(decoder, args, audit),
+        None => Ok(()),
+    })?;
+    eprintln!("\nServer names seen on {iface}:\n{:?}\n", decoder.sni_hosts);
+    Ok((decoder.sni_hosts.into_iter().collect(), passive_addresses(decoder.passive_dns, args)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sniffed_hosts(args: &Args, _early: Option<(&mut EarlyApply, &mut AuditLog)>) -> StrResult<(HashSet<String>, PassiveDns)> {
+    match &args.sniff {
+        Some(_) => Err("sniffing is only supported on Linux".to_string()),
+        None => Ok((HashSet::new(), PassiveDns::new())),
+    }
+}
+
+fn process_selector(args: &Args) -> ProcessSelector {
+    ProcessSelector { names: args.process_names.clone(), cgroups: args.cgroups.clone(), units: args.units.clone() }
+}
+
+// every connect() of the processes, through the tracepoint; polling /proc when the program cannot be loaded misses
+// the connections that close between two polls
+#[cfg(all(feature = "ebpf", target_os = "linux"))]
+fn process_flows(selector: &ProcessSelector, duration: std::time::Duration) -> StrResult<Vec<Flow>> {
+    wgrouter::input::ebpf::flows_from_connects(selector, duration).or_else(|e| {
+        eprintln!("warning: {e}, polling /proc instead, which misses short-lived connections");
+        flows_from_processes(selector, duration)
+    })
+}
+
+#[cfg(not(all(feature = "ebpf", target_os = "linux")))]
+fn process_flows(selector: &ProcessSelector, duration: std::time::Duration) -> StrResult<Vec<Flow>> {
+    flows_from_processes(selector, duration)
+}
+
+// destination addresses with the bytes they received
+fn flow_destinations(args: &Args) -> StrResult<HashMap<String, u64>> {
+    let mut collector = Collector::default();
+    args.netflow_files.iter().try_for_each(|file| collector.read_file(file))?;
+    if let Some(addr) = &args.netflow_listen {
+        eprintln!("listening for flow exports on {addr} for {} seconds", args.netflow_seconds);
+        collector.listen(addr, std::time::Duration::from_secs(args.netflow_seconds))?;
+    }
+    let filter = FlowFilter {
+        src_nets: args.flow_src.clone(),
+        dst_ports: args.flow_dst_port.clone(),
+        min_bytes: args.flow_min_bytes,
+    };
+    let mut flows = collector.flows;
+    if args.from_conntrack {
+        let conntrack_flows = flows_from_conntrack()?;
+        let conntrack_dsts = destinations(&conntrack_flows, &filter).into_keys().collect::<Vec<_>>();
+        let names = reverse_lookup_many(&conntrack_dsts);
+        let report = conntrack_dsts
+            .iter()
+            .map(|ip| (ip, names.get(ip).map(String::as_str).unwrap_or("-")))
+            .collect::<BTreeMap<_, _>>();
+        eprintln!("\nConntrack destinations:\n{report:?}\n");
+        flows.extend(conntrack_flows);
+    }
+    args.socket_list_files.iter().try_for_each(|file| -> StrResult<()> {
+        let contents = std::fs::read_to_string(file).map_err(|e| format!("could not read socket list {file}: {e}"))?;
+        flows.extend(Host::from_socket_listing(&contents).flows());
+        Ok(())
+    })?;
+    let selector = process_selector(args);
+    if !selector.is_empty() {
+        eprintln!("watching connections of {selector:?} for {} seconds", args.process_seconds);
+        flows.extend(process_flows(&selector, std::time::Duration::from_secs(args.process_seconds))?);
+    }
+    Ok(destinations(&flows, &filter)
         .into_iter()
-        .map(|ip| net_from_ip(&ip))
-        .filter(|net| {
-            if let Some(conn) = host_util.contains_dst(net) {
-                println!("warning: host TCP connection to {}:{} would fall into routed network {net}, ignoring it", conn.0, conn.1);
-                return false;
-            } else {
-                return true;
+        .filter(|(ip, _)| args.ipv6 || ip.is_ipv4())
+        .map(|(ip, bytes)| (ip.to_string(), bytes))
+        .collect())
+}
+
+fn joined<T>(result: std::thread::Result<StrResult<T>>) -> StrResult<T> {
+    result.map_err(|_| "capture thread panicked".to_string())?
+}
+
+// a digest of the input files and the options, none with live, remote or standard input sources, which may differ each run
+fn inputs_digest(args: &Args) -> StrResult<Option<String>> {
+    let live = args.netflow_listen.is_some() || args.from_conntrack || !process_selector(args).is_empty() || mobile_capture_enabled(args)
+        || args.sniff.is_some() || !args.input_plugins.is_empty();
+    let files = args.files.iter()
+        .chain(&args.hosts_files)
+        .chain(&args.etc_hosts)
+        .chain(&args.netflow_files)
+        .chain(&args.socket_list_files)
+        .chain(&args.bounds)
+        .chain(&args.clients_file)
+        .chain(&args.client_leases)
+        .chain(&args.wg_conf)
+        .chain(&args.exclude_files)
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if live || files.iter().any(|file| *file == STDIN || is_remote(file)) {
+        return Ok(None);
+    }
+    runs::inputs_digest(&format!("{:?}", Args { force: false, ..args.clone() }), &files).map(Some)
+}
+
+// the result of the last run in the state directory when it had the same inputs, without --force; otherwise the
+// inputs are processed and the result kept there for the next run
+fn gen_routes_once(args: &Args, state_dir: &std::path::Path, audit: &mut AuditLog) -> StrResult<Report> {
+    let Some(digest) = inputs_digest(args)? else {
+        return gen_routes(args, audit);
+    };
+    let path = runs::last_run_path(state_dir);
+    if let Some(last) = LastRun::load(&path).filter(|last| !args.force && last.digest == digest) {
+        eprintln!("inputs unchanged since the last run, reusing its {} networks (--force processes them again)", last.report.networks.len());
+        audit.record("reused", json!({"digest": digest, "networks": last.report.networks}))?;
+        return Ok(last.report);
+    }
+    let report = gen_routes(args, audit)?;
+    LastRun { digest, report: report.clone() }.save(&path)?;
+    Ok(report)
+}
+
+fn gen_routes(args: &Args, audit: &mut AuditLog) -> StrResult<Report> {
+    gen_routes_with(args, audit, None)
+}
+
+fn gen_routes_with(args: &Args, audit: &mut AuditLog, early: Option<&mut EarlyApply>) -> StrResult<Report> {
+    let collected = collect_hosts(args, audit, early)?;
+    routes_of(args, &collected, audit, || {})
+}
+
+// the hosts of all inputs, with the addresses known for them without a lookup: those pinned, and those seen in DNS
+// answers; --daemon --interval keeps them to resolve again
+struct Collected {
+    hosts: Hosts,
+    tags: BTreeMap<String, BTreeSet<String>>,
+    passive: PassiveDns,
+    pinned: PassiveDns,
+}
+
+impl Collected {
+    // the answers seen on the wire are as old as the capture, so only the pinned addresses are kept
+    fn to_resolve_again(&self) -> Self {
+        Self { hosts: self.hosts.clone(), tags: self.tags.clone(), passive: self.pinned.clone(), pinned: self.pinned.clone() }
+    }
+}
+
+fn collect_hosts(args: &Args, audit: &mut AuditLog, early: Option<&mut EarlyApply>) -> StrResult<Collected> {
+    let har_opts = har_options(args);
+    let parsed = parse_files(args, &har_opts)?;
+    let mut tags = BTreeMap::new();
+    parsed.iter().for_each(|(file, hosts)| {
+        tag(&mut tags, file, hosts.confirmed.iter().cloned());
+        if args.route_candidates {
+            tag(&mut tags, file, hosts.candidates.iter().cloned());
+        }
+    });
+    let Hosts { mut confirmed, mut candidates, mut traffic } = parsed.into_iter().map(|(_, hosts)| hosts).fold(Hosts::default(), Hosts::merge);
+    let (captured, captured_passive) = packet_capture_hosts(args)?;
+    captured.into_iter().for_each(|(file, hosts)| {
+        tag(&mut tags, &file, hosts.iter().cloned());
+        confirmed.extend(hosts);
+    });
+    let (flows, mobile, sniffed) = std::thread::scope(|s| {
+        let flows = s.spawn(|| flow_destinations(args));
+        let mobile = s.spawn(|| mobile_capture_hosts(args));
+        let sniffed = sniffed_hosts(args, early.map(|early| (early, &mut *audit)));
+        (joined(flows.join()), joined(mobile.join()), sniffed)
+    });
+    let (flows, mobile, (sniffed, mut passive)) = (flows?, mobile?, sniffed?);
+    tag(&mut tags, "flows", flows.keys().cloned());
+    tag(&mut tags, "mobile", mobile.confirmed.iter().cloned());
+    if args.route_candidates {
+        tag(&mut tags, "mobile", mobile.candidates.iter().cloned());
+    }
+    tag(&mut tags, "sniff", sniffed.iter().chain(passive.keys()).cloned());
+    confirmed.extend(flows.keys().cloned());
+    flows.into_iter().for_each(|(ip, bytes)| *traffic.entry(ip).or_default() += bytes);
+    confirmed.extend(mobile.confirmed);
+    confirmed.extend(sniffed);
+    confirmed.extend(passive.keys().cloned());
+    candidates.extend(mobile.candidates);
+    candidates.retain(|host| !confirmed.contains(host));
+    captured_passive.into_iter().for_each(|(host, ips)| passive.entry(host).or_default().extend(ips));
+    // pinned addresses replace whatever was seen for the host
+    let (pinned_tags, mut pinned) = pinned_hosts(args)?;
+    pinned_tags.into_iter().for_each(|(file, hosts)| {
+        tag(&mut tags, &file, hosts.iter().cloned());
+        confirmed.extend(hosts);
+    });
+    passive.extend(pinned.clone());
+    // --include-host and --include-net are routed like the hosts of the inputs
+    let included = args.include_hosts.iter().cloned().chain(args.include_nets.iter().map(|net| net.to_string())).collect::<Vec<_>>();
+    tag(&mut tags, "include", included.iter().cloned());
+    confirmed.extend(included);
+    tags.iter().try_for_each(|(source, hosts)| {
+        hosts.iter().try_for_each(|host| audit.record("extracted", json!({"host": host, "source": source})))
+    })?;
+    let exclusions = exclusions(args)?;
+    let mut excluded = BTreeSet::new();
+    confirmed.iter().chain(&candidates).try_for_each(|host| match exclusions.excluding(host) {
+        Some(pattern) => {
+            excluded.insert(host.clone());
+            audit.record("filtered", json!({"host": host, "reason": format!("matches excluded host pattern {pattern}")}))
+        }
+        None => Ok(()),
+    })?;
+    if !excluded.is_empty() {
+        eprintln!("\nExcluded hosts:\n{excluded:?}\n");
+        confirmed.retain(|host| !excluded.contains(host));
+        candidates.retain(|host| !excluded.contains(host));
+        passive.retain(|host, _| !excluded.contains(host));
+        pinned.retain(|host, _| !excluded.contains(host));
+        tags.values_mut().for_each(|hosts| hosts.retain(|host| !excluded.contains(host)));
+    }
+    if !args.route_candidates {
+        candidates.iter().try_for_each(|host| {
+            audit.record("filtered", json!({"host": host, "reason": "candidate only referenced from a response body, not routed without --route-candidates"}))
+        })?;
+    }
+    Ok(Collected { hosts: Hosts { confirmed, candidates, traffic }, tags, passive, pinned })
+}
+
+// how hosts are looked up: the DNS options
+fn lookups(args: &Args) -> Lookups {
+    let retry = Retry { timeout: std::time::Duration::from_secs(args.dns_timeout), retries: args.dns_retries, backoff: std::time::Duration::from_secs(args.dns_backoff) };
+    let servers = if args.system_resolver { Servers::System } else if args.nameserver.is_empty() { Servers::Discovered } else { Servers::Given(args.nameserver.clone()) };
+    Lookups { concurrency: args.concurrent_lookups, samples: args.samples, jitter: std::time::Duration::from_secs(args.jitter), retry, upstream: args.doh.clone().map(Upstream::Https).or(args.dot.clone()), dnssec: args.dnssec, cname_widely: args.cname_targets, servers }
+}
+
+// progress is called as each host is resolved
+fn routes_of(args: &Args, collected: &Collected, audit: &mut AuditLog, progress: impl Fn()) -> StrResult<Report> {
+    let Collected { hosts: Hosts { confirmed, candidates, traffic }, tags, passive, .. } = collected;
+    // with a state, hosts looked up recently are taken from it (what is seen or pinned now goes first), and hosts that do
+    // not resolve fall back to their last addresses
+    let now = wgrouter::clock::system().unix_time();
+    let state = args.state.as_deref().map(|path| State::load(std::path::Path::new(path))).transpose()?;
+    let mut known = state.as_ref().map(|state| state.fresh(now, args.state_max_age)).unwrap_or_default();
+    let mut kept = known.keys().filter(|host| !passive.contains_key(*host)).cloned().collect::<BTreeSet<_>>();
+    known.extend(passive.iter().map(|(host, ips)| (host.clone(), ips.clone())));
+    let lookups = lookups(args);
+    let mut rng = wgrouter::clock::Rng::from_env();
+    let mut fallbacks = Vec::new();
+    let mut fall_back = |(mut ok, mut failed): wgrouter::routes::Resolved| {
+        if let Some(state) = &state {
+            failed.retain(|host, e| match state.hosts.get(host) {
+                Some(last) => {
+                    fallbacks.push(format!("{host} did not resolve ({e}), using its addresses of {} seconds ago", now.saturating_sub(last.resolved)));
+                    ok.insert(host.clone(), last.addresses.clone());
+                    kept.insert(host.clone());
+                    false
+                }
+                None => true,
+            });
+        }
+        (ok, failed)
+    };
+    let (resolved, mut cnames) = resolve_hosts_chained(confirmed.iter(), &known, args.ipv6, &lookups, &mut rng, &progress);
+    let (ok_hosts, fail_hosts) = fall_back(resolved);
+    eprintln!("\nResolved hosts:\n{ok_hosts:?}\n");
+    eprintln!("\nUnresolved hosts:\n{fail_hosts:?}\n");
+
+    let (ok_candidates, fail_candidates) = if args.route_candidates {
+        let (resolved, candidate_cnames) = resolve_hosts_chained(candidates.iter(), &known, args.ipv6, &lookups, &mut rng, &progress);
+        cnames.extend(candidate_cnames);
+        let (ok_candidates, fail_candidates) = fall_back(resolved);
+        eprintln!("\nResolved candidate hosts:\n{ok_candidates:?}\n");
+        eprintln!("\nUnresolved candidate hosts:\n{fail_candidates:?}\n");
+        (ok_candidates, fail_candidates)
+    } else {
+        if !candidates.is_empty() {
+            eprintln!("\nCandidate hosts (not routed, use --route-candidates to include them):\n{:?}\n", candidates.iter().collect::<BTreeSet<_>>());
+        }
+        (BTreeMap::new(), BTreeMap::new())
+    };
+
+    let selector = process_selector(args);
+    let host_util = if selector.is_scoped() {
+        Host::from_proc_net_tcp()?.scoped_to(&selector.socket_inodes())
+    } else {
+        Host::from_proc_net_tcp()?
+    };
+
+    let hosts = ok_hosts.into_iter().chain(ok_candidates).collect::<BTreeMap<_, _>>();
+    if !cnames.is_empty() {
+        eprintln!("\nCNAME chains:\n{}\n", cnames.iter().map(|(host, chain)| format!("{host} -> {}", chain.join(" -> "))).collect::<Vec<_>>().join("\n"));
+    }
+    hosts.iter().try_for_each(|(host, ips)| match cnames.get(host) {
+        Some(chain) => audit.record("resolved", json!({"host": host, "addresses": ips, "cnames": chain})),
+        None => audit.record("resolved", json!({"host": host, "addresses": ips})),
+    })?;
+    fail_hosts.iter().chain(&fail_candidates).try_for_each(|(host, error)| audit.record("unresolved", json!({"host": host, "error": error})))?;
+    let max_networks = args.max_networks.or(args.target_capacity.map(TargetCapacity::max_networks));
+    let aggregation = Aggregation {
+        prefix_len: args.prefix_len,
+        prefix_len6: args.prefix_len6,
+        slack: args.slack,
+        ipv6: args.ipv6,
+        max_networks: max_networks.filter(|_| args.fit),
+    };
+    let bounds = args.bounds.as_deref().map(Bounds::load).transpose()?.unwrap_or_default().for_addresses(&hosts);
+    let weights = if args.weighted { address_weights(&hosts, traffic) } else { Weights::new() };
+    let (nets, mut warnings) = networks_with(hosts.values().flatten().cloned(), &aggregation, &host_util, &bounds, &weights,
+        &|net, conns| args.conflict_policy.decide(net, conns))?;
+    if let Some(max) = max_networks
+        && !args.fit && nets.len() > max {
+        let limit = match args.target_capacity.and_then(|device| device.to_possible_value()).filter(|_| args.max_networks.is_none()) {
+            Some(device) => format!("the {max} a {} handles well", device.get_name()),
+            None => format!("--max-networks {max}"),
+        };
+        warnings.push(format!("{} networks, more than {limit}; --fit merges them down", nets.len()));
+    }
+    let (nets, guarded) = endpoint_guard(args.wg_conf.as_deref(), &args.endpoints)?.apply(&nets)?;
+    warnings.extend(guarded);
+    let (nets, excluded) = exclusions(args)?.guard().apply(&nets)?;
+    warnings.extend(excluded);
+    warnings.extend(fallbacks);
+    warnings.iter().try_for_each(|w| {
+        eprintln!("warning: {w}");
+        audit.record("filtered", json!({"reason": w}))
+    })?;
+    let report = Report {
+        weights: if args.weighted { network_weights(&nets, &weights) } else { BTreeMap::new() },
+        networks: nets,
+        hosts,
+        cnames,
+        tags: tags.clone(),
+        unresolved: fail_hosts.into_iter().chain(fail_candidates).collect(),
+        warnings,
+    };
+    if args.weighted {
+        eprintln!("\nBytes per network:\n{:?}\n", report.weights);
+    }
+    if let (Some(path), Some(mut state)) = (&args.state, state) {
+        match state.changes(&report) {
+            changes if changes.is_empty() => eprintln!("\nNothing changed since the last run\n"),
+            changes => eprintln!("\nChanged since the last run:\n{}\n", changes.join("\n")),
+        }
+        state.update(&report, &kept, now);
+        state.save(std::path::Path::new(path))?;
+    }
+    if let Some(path) = &args.unresolved {
+        let text = if path.ends_with(".json") { pretty(&output::unresolved_document(&report))? + "\n" } else { output::unresolved_list(&report) };
+        std::fs::write(path, text).map_err(|e| format!("could not write {path}: {e}"))?;
+        UNRESOLVED_LEFT.store(!report.unresolved.is_empty(), std::sync::atomic::Ordering::Relaxed);
+    }
+    audit.record_networks(&report)?;
+    Ok(report)
+}
+
+// the tunnel's own traffic must not go into it: the endpoints of the interfaces that are up, of the WireGuard config
+// and of --endpoint, with the nameservers the system resolves them with, and the gateways it goes out through
+fn endpoint_guard(wg_conf: Option<&str>, endpoints: &[String]) -> StrResult<Guard> {
+    let mut guard = Guard::default();
+    running_endpoints().into_iter().for_each(|ip| guard.add(ip, format!("endpoint {ip} of a running interface")));
+    let routes = std::fs::read_to_string("/proc/net/route").unwrap_or_default();
+    proc_net_route_gateways(&routes).into_iter().for_each(|(iface, ip)| guard.add(ip, format!("gateway {ip} of {iface}")));
+    let nameservers = std::fs::read_to_string("/etc/resolv.conf").map(|text| resolv_conf_nameservers(&text)).unwrap_or_default();
+    let conf_endpoints = match wg_conf {
+        Some(path) => wgrouter::wgconf::endpoints(&std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?),
+        None => vec![],
+    };
+    conf_endpoints.iter().chain(endpoints).for_each(|endpoint| {
+        if let Err(e) = guard.add_endpoint(endpoint, &nameservers) {
+            eprintln!("warning: {e}");
+        }
+    });
+    Ok(guard)
+}
+
+fn pretty(value: &serde_json::Value) -> StrResult<String> {
+    serde_json::to_string_pretty(value).map_err(|e| format!("could not serialize output: {e}"))
+}
+
+fn vpn_dns(args: &Args) -> StrResult<&str> {
+    args.vpn_dns.as_deref().ok_or(format!("--format {:?} needs --vpn-dns", args.format).to_ascii_lowercase())
+}
+
+// fail before a possibly long capture rather than after it
+// a peer to give the networks to needs something that has it
+fn check_peer(args: &Args, wg_interface: Option<&str>) -> StrResult<()> {
+    if args.peer.is_some() && args.wg_conf.is_none() && wg_interface.is_none() {
+        return Err("--peer goes with --wg-conf, or for apply with --wg-interface".to_string());
+    }
+    Ok(())
+}
+
+fn check_format(args: &Args) -> StrResult<()> {
+    if args.fit && args.max_networks.is_none() && args.target_capacity.is_none() {
+        return Err("--fit needs --max-networks or --target-capacity".to_string());
+    }
+    if args.conflict_policy == ConflictPolicy::Ask && args.files.iter().chain(&args.hosts_files).chain(&args.etc_hosts).any(|file| file == STDIN) {
+        return Err("standard input is read as an input file, so there is no way to answer --conflict-policy ask".to_string());
+    }
+    if let Some(name) = &args.output_plugin {
+        return find_plugin(args, plugin::Kind::Output, name).map(|_| ());
+    }
+    match args.format {
+        Format::Adguard | Format::Blocky | Format::Unbound | Format::KnotResolver => vpn_dns(args).map(|_| ()),
+        Format::IpRules => client_networks(args).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+fn render(report: &Report, args: &Args) -> StrResult<String> {
+    let text = render_format(report, args)?;
+    if args.validate {
+        validate(&text, args)?;
+    }
+    Ok(text)
+}
+
+// the output handed to the tool that reads it, with a check mode; formats without one, and tools not installed here,
+// are left unchecked with a note
+fn validate(text: &str, args: &Args) -> StrResult<()> {
+    use wgrouter::validate::{self, Outcome};
+    let format = args.format.to_possible_value().map(|format| format.get_name().to_string()).unwrap_or_default();
+    let checker = match args.format {
+        _ if args.output_plugin.is_some() => None,
+        // the add element commands need the set to be there already, which a check cannot assume
+        Format::NftSet if !args.nft_elements => Some((validate::NFT, "nft")),
+        Format::IpRoute | Format::IpRules => Some((validate::SHELL, "sh")),
+        Format::Ipset if !args.ipset_restore => Some((validate::SHELL, "sh")),
+        Format::Networkmanager if args.nmcli => Some((validate::SHELL, "sh")),
+        Format::Bird => Some((validate::BIRD, "conf")),
+        Format::Unbound => Some((validate::UNBOUND, "conf")),
+        _ => None,
+    };
+    let Some((checker, extension)) = checker else {
+        eprintln!("note: nothing to validate --format {format} with");
+        return Ok(());
+    };
+    match validate::check(checker, &format!("{text}\n"), extension)? {
+        Outcome::Passed => eprintln!("validated with {}", checker.program),
+        Outcome::Missing => eprintln!("note: {} is not installed, --format {format} not validated", checker.program),
+    }
+    Ok(())
+}
+
+fn render_format(report: &Report, args: &Args) -> StrResult<String> {
+    if let Some(name) = &args.output_plugin {
+        return plugin::render_with_plugin(&find_plugin(args, plugin::Kind::Output, name)?, report).map(|output| output.trim_end().to_string());
+    }
+    match args.format {
+        Format::AllowedIps => Ok(allowed_ips_line(&report.networks)),
+        Format::Json => pretty(&output::json_document(report)),
+        Format::Ansible => {
+            if let Some(path) = &args.ansible_tasks {
+                std::fs::write(path, output::ANSIBLE_TASKS).map_err(|e| format!("could not write Ansible tasks to {path}: {e}"))?;
+            }
+            Ok(output::ansible_vars(report).trim_end().to_string())
+        }
+        Format::TerraformJson => pretty(&output::terraform_vars(report)),
+        Format::TerraformExternal => Ok(output::terraform_external(report).to_string()),
+        Format::AwsSg => pretty(&output::aws_egress_rules(report, &args.rule_name)),
+        Format::GcpFirewall => Ok(output::gcp_firewall_rule(report, &args.rule_name).trim_end().to_string()),
+        Format::AzureNsg => pretty(&output::azure_nsg_rules(report, &args.rule_name)),
+        Format::Adguard => Ok(output::adguard_upstreams(report, vpn_dns(args)?).trim_end().to_string()),
+        Format::Blocky => Ok(output::blocky_conditional(report, vpn_dns(args)?).trim_end().to_string()),
+        Format::Unbound => Ok(output::unbound_forward_zones(report, vpn_dns(args)?).trim_end().to_string()),
+        Format::KnotResolver => Ok(output::knot_forward_policy(report, vpn_dns(args)?).trim_end().to_string()),
+        Format::NftSet => Ok(output::nft_sets(report, &args.nft_table, &args.nft_set, args.nft_elements).trim_end().to_string()),
+        Format::Ipset => Ok(output::ipsets(report, &args.ipset_name, args.ipset_restore).trim_end().to_string()),
+        Format::IpRoute => {
+            if let Some(path) = &args.teardown_script {
+                let script = output::ip_route_script(report, &args.rule_interface, args.rule_table, true);
+                std::fs::write(path, script).map_err(|e| format!("could not write teardown script to {path}: {e}"))?;
+            }
+            Ok(output::ip_route_script(report, &args.rule_interface, args.rule_table, false).trim_end().to_string())
+        }
+        Format::Pf => {
+            if let Some(path) = &args.pf_rules {
+                let rules = output::pf_rules(&args.pf_table, &format!("/etc/pf.{}", args.pf_table), &args.rule_interface);
+                std::fs::write(path, rules).map_err(|e| format!("could not write pf rules to {path}: {e}"))?;
             }
+            Ok(output::pf_table(report, &args.pf_table).trim_end().to_string())
+        }
+        Format::Bird => Ok(output::bird_static(report, &args.rule_interface, &args.bird_protocol).trim_end().to_string()),
+        Format::Networkmanager => Ok(output::networkmanager_routes(report, &args.nm_connection, args.nmcli).trim_end().to_string()),
+        Format::Networkd => Ok(output::networkd_routes(report, &args.rule_interface, args.rule_table).trim_end().to_string()),
+        Format::Windows => Ok(output::windows_routes(report, &args.rule_interface, args.powershell).trim_end().to_string()),
+        Format::Openvpn => Ok(output::openvpn_routes(report, args.openvpn_push).trim_end().to_string()),
+        Format::IpRules => {
+            Ok(output::ip_rules(report, &client_networks(args)?, &args.rule_interface, args.rule_table, args.rule_priority).trim_end().to_string())
+        }
+    }
+}
+
+// the --client and --clients-file entries
+fn client_networks(args: &Args) -> StrResult<Vec<ipnetwork::IpNetwork>> {
+    let mut clients = args.clients.clone();
+    if let Some(path) = &args.clients_file {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read clients {path}: {e}"))?;
+        clients.extend(clients_from_text(&text).map_err(|e| format!("{e} in clients {path}"))?);
+    }
+    if clients.is_empty() {
+        return Err("--format ip-rules needs --client or --clients-file".to_string());
+    }
+    lookup_clients(&clients, &args.client_leases, "--client-leases")
+}
+
+// MAC addresses are looked up in the neighbour table and the leases, hostnames in the leases, as they are right now
+fn lookup_clients(clients: &[Client], lease_files: &[String], leases_option: &str) -> StrResult<Vec<ipnetwork::IpNetwork>> {
+    if let Some(Client::Name(name)) = clients.iter().find(|client| matches!(client, Client::Name(_))) && lease_files.is_empty() {
+        return Err(format!("client '{name}' is a hostname, which needs the DHCP leases: add {leases_option}"));
+    }
+    let mut neighbours = if clients.iter().any(|client| matches!(client, Client::Mac(_))) {
+        neighbours_from_proc_arp(&std::fs::read_to_string("/proc/net/arp").map_err(|e| format!("could not read the neighbour table: {e}"))?)
+    } else {
+        Neighbours::default()
+    };
+    let now = wgrouter::clock::system().unix_time();
+    lease_files.iter().try_for_each(|path| leases_from_file(path, now).map(|leases| neighbours.add_leases(&leases)))?;
+    wgrouter::clients::client_networks(clients, &neighbours)
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+// the answer, or the proposal for an empty one; with yes, the proposal without asking
+fn ask(question: &str, proposal: &str, yes: bool) -> String {
+    if yes {
+        return proposal.to_string();
+    }
+    print!("{question} [{proposal}] ");
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer).map(|_| answer.trim()) {
+        Ok(answer) if !answer.is_empty() => answer.to_string(),
+        _ => proposal.to_string(),
+    }
+}
+
+fn ask_yes(question: &str, proposal: bool, yes: bool) -> bool {
+    ask(question, if proposal { "y" } else { "n" }, yes).eq_ignore_ascii_case("y")
+}
+
+fn init(init_args: &InitArgs) -> StrResult<()> {
+    let yes = init_args.yes;
+    let path = init_args.config.clone().or_else(wgrouter::config::default_path)
+        .ok_or("neither WGROUTER_CONFIG, XDG_CONFIG_HOME nor HOME is set, give the config file with --config".to_string())?;
+    if path.exists() && !yes && !confirm(&format!("{} exists, overwrite it?", path.display())) {
+        println!("not written");
+        return Ok(());
+    }
+    let mut config = Config::default();
+    let mut comments = vec![];
+    let mut set = |option: &str, value: String, comment: &'static str| {
+        config.0.push((option.to_string(), value));
+        comments.push(comment);
+    };
+
+    let interfaces = wireguard_interfaces();
+    println!("WireGuard interfaces up: {}", if interfaces.is_empty() { "none".to_string() } else { interfaces.join(", ") });
+    let interface = ask("Tunnel interface", interfaces.first().map_or("wg0", String::as_str), yes);
+    set("rule-interface", interface.clone(), "the tunnel, for the route formats");
+    // wg-quick's config tells the rest, when it can be read
+    let wg_quick = format!("/etc/wireguard/{interface}.conf");
+    let conf = std::fs::read_to_string(&wg_quick).unwrap_or_else(|e| {
+        println!("could not read {wg_quick} ({e}), so there is less to propose");
+        String::new()
+    });
+
+    let endpoints = wgrouter::wgconf::endpoints(&conf);
+    if endpoints.is_empty() {
+        let endpoint = ask("Endpoint (HOST:PORT) of the VPN server, to keep out of the routes, empty for none", "", yes);
+        if !endpoint.is_empty() {
+            set("endpoint", endpoint, "routing the server into the tunnel would kill it");
+        }
+    } else if ask_yes(&format!("Keep the endpoints {} of {wg_quick} out of the routes, with the nameservers resolving them?", endpoints.join(", ")), true, yes) {
+        endpoints.into_iter().for_each(|endpoint| set("endpoint", endpoint, "routing the server into the tunnel would kill it"));
+    }
+    let routes = std::fs::read_to_string("/proc/net/route").unwrap_or_default();
+    // the networks behind a tunnel are its to route
+    let local = proc_net_route_connected(&routes).into_iter().filter(|(iface, _)| !interfaces.contains(iface)).collect::<Vec<_>>();
+    let shown = local.iter().map(|(iface, net)| format!("{net} ({iface})")).collect::<Vec<_>>();
+    if !local.is_empty() && ask_yes(&format!("Keep the local networks {} out of the routes?", shown.join(", ")), true, yes) {
+        local.into_iter().for_each(|(_, net)| set("exclude-net", net.to_string(), "a local network, reached without the tunnel"));
+    }
+
+    println!("Each address is routed as a network around it: /24 adds little nobody asked for, /16 (the default) keeps the \
+        list short as sites spread over neighbouring addresses, /12 even more so");
+    let prefix_len = ask("IPv4 prefix length", &DEFAULT_PREFIX_LEN.to_string(), yes);
+    let prefix_len = prefix_len.parse::<u64>().map_err(|e| format!("bad prefix length '{prefix_len}': {e}")).and_then(wgrouter::routes::prefix_len)?;
+    set("prefix-len", prefix_len.to_string(), "how wide the network routed for an address is");
+    let has_ipv6 = wgrouter::wgconf::allowed_ips(&conf).iter().any(|net| net.contains(':'));
+    if ask_yes("Route IPv6 as well?", has_ipv6, yes) {
+        set("ipv6", "true".to_string(), "");
+    }
+    let dns = wgrouter::wgconf::dns_servers(&conf);
+    let vpn_dns = ask("Resolver on the VPN side that the DNS formats forward the routed domains to, empty for none", dns.first().map_or("", String::as_str), yes);
+    if !vpn_dns.is_empty() {
+        set("vpn-dns", vpn_dns, "for --format adguard, blocky, unbound and knot-resolver");
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    }
+    let text = format!("# written by wgrouter init; options as on the command line, which overrides them\n{}", config.render(&comments));
+    std::fs::write(&path, text).map_err(|e| format!("could not write {}: {e}", path.display()))?;
+    println!("wrote {}; generate, resolve, check, apply and learn start from these options now", path.display());
+    Ok(())
+}
+
+fn learn(learn_args: &LearnArgs) -> StrResult<()> {
+    let mut args = learn_args.args.clone();
+    check_format(&args)?;
+    check_peer(&args, None)?;
+    if learn_args.then == Then::Apply {
+        learn_args.target.check(&args)?;
+    }
+    let seconds = learn_args.minutes * 60;
+    let has_live_source = args.sniff.is_some() || args.netflow_listen.is_some() || args.from_conntrack
+        || !process_selector(&args).is_empty() || mobile_capture_enabled(&args);
+    if !has_live_source {
+        args.sniff = Some("any".to_string());
+    }
+    args.sniff_seconds = seconds;
+    args.process_seconds = seconds;
+    args.netflow_seconds = seconds;
+    set_mobile_capture_minutes(&mut args, learn_args.minutes);
+    println!("learning for {} minutes: now use the sites and apps that should go through the VPN", learn_args.minutes);
+
+    if learn_args.as_seen && (learn_args.then != Then::Apply || !learn_args.target.yes || learn_args.target.dry_run) {
+        return Err("--as-seen installs routes unattended while learning: add --then apply --yes, without --dry-run".to_string());
+    }
+    if learn_args.as_seen && args.sniff.is_none() {
+        return Err("--as-seen routes sniffed hosts: add --sniff".to_string());
+    }
+
+    let mut audit = AuditLog::open(args.audit_log.as_deref())?;
+    let mut early = learn_args.as_seen.then(|| EarlyApply::new(&learn_args.target, &args)).transpose()?;
+    let report = gen_routes_with(&args, &mut audit, early.as_mut())?;
+    let nets = &report.networks;
+    println!("\nLearned {} networks:\n{}\n", nets.len(), render(&report, &args)?);
+    match learn_args.then {
+        Then::Print => Ok(()),
+        Then::Apply => {
+            learn_args.target.apply(&report, &mut audit)?;
+            early.map_or(Ok(()), |early| early.finish(&report, &mut audit))
+        }
+    }
+}
+
+// how often learn --as-seen looks at the sniffed hosts, and how many networks it installs at a time at most, so a
+// burst of new hosts does not hold the sniffing up
+const EARLY_SECONDS: u64 = 5;
+const EARLY_BATCH: usize = 32;
+
+// the routes of learn --as-seen: hosts seen with their addresses in DNS answers go in right away, the others once
+// looked up in the background; each network as an address widened to the prefix length, without merges, as the
+// learned networks come at the end
+struct EarlyApply<'a> {
+    target: &'a ApplyTarget,
+    aggregation: Aggregation,
+    host_util: Host,
+    guard: Guard,
+    exclusions: Exclusions,
+    seen: HashSet<String>,
+    lookups: Vec<std::thread::JoinHandle<wgrouter::routes::Resolved>>,
+    waiting: BTreeSet<String>,
+    installed: BTreeSet<String>,
+}
+
+impl<'a> EarlyApply<'a> {
+    fn new(target: &'a ApplyTarget, args: &Args) -> StrResult<Self> {
+        let exclusions = exclusions(args)?;
+        let mut guard = endpoint_guard(args.wg_conf.as_deref(), &args.endpoints)?;
+        exclusions.guard().0.into_iter().for_each(|(net, what)| guard.add_network(net, what));
+        Ok(Self {
+            target,
+            aggregation: Aggregation { prefix_len: args.prefix_len, prefix_len6: args.prefix_len6, slack: 0, ipv6: args.ipv6, max_networks: None },
+            host_util: Host::from_proc_net_tcp()?,
+            guard,
+            exclusions,
+            seen: HashSet::new(),
+            lookups: Vec::new(),
+            waiting: BTreeSet::new(),
+            installed: BTreeSet::new(),
         })
-        .collect::<HashSet<String>>()
+    }
+
+    fn take(&mut self, decoder: &PacketDecoder, args: &Args, audit: &mut AuditLog) -> StrResult<()> {
+        let mut ips = Vec::new();
+        let excluded = |host: &str| self.exclusions.excluding(host).is_some();
+        decoder.passive_dns.hosts.iter().filter(|(host, _)| !excluded(host) && self.seen.insert(host.to_string())).for_each(|(_, addrs)| {
+            ips.extend(addrs.iter().filter(|ip| args.ipv6 || ip.is_ipv4()).map(|ip| ip.to_string()));
+        });
+        let lookup = decoder.sni_hosts.iter().filter(|host| !excluded(host) && self.seen.insert(host.to_string())).cloned().collect::<Vec<_>>();
+        if !lookup.is_empty() {
+            let (ipv6, lookups) = (args.ipv6, lookups(args));
+            self.lookups.push(std::thread::spawn(move || {
+                resolve_hosts_with(lookup.iter(), &PassiveDns::new(), ipv6, &lookups, &mut wgrouter::clock::Rng::from_env(), || {})
+            }));
+        }
+        let (done, running) = std::mem::take(&mut self.lookups).into_iter().partition::<Vec<_>, _>(|lookup| lookup.is_finished());
+        self.lookups = running;
+        done.into_iter().filter_map(|lookup| lookup.join().ok()).for_each(|(resolved, _)| ips.extend(resolved.into_values().flatten()));
+
+        let (nets, _) = wgrouter::routes::networks(ips.into_iter(), &self.aggregation, &self.host_util)?;
+        let (nets, _) = self.guard.apply(&nets)?;
+        self.waiting.extend(nets.into_iter().filter(|net| !self.installed.contains(net)));
+        let batch = self.waiting.iter().take(EARLY_BATCH).cloned().collect::<Vec<_>>();
+        if batch.is_empty() {
+            return Ok(());
+        }
+        batch.iter().for_each(|net| {
+            self.waiting.remove(net);
+        });
+        self.target.install(&batch, false, audit)?;
+        self.installed.extend(batch);
+        Ok(())
+    }
+
+    // the learned networks are installed by now; with --replace they are all there is, otherwise the early ones they
+    // do not keep (merged into wider ones, or dropped) are taken away
+    fn finish(self, report: &Report, audit: &mut AuditLog) -> StrResult<()> {
+        let target = self.target;
+        let stale = self.installed.into_iter().filter(|net| !report.networks.contains(net)).collect::<Vec<_>>();
+        if stale.is_empty() || target.replace {
+            return Ok(());
+        }
+        let (transition, installed) = wgrouter::apply::remove_from_generation(target.backend.backend()?, &stale, &target.interface, target.table, target.keep, &target.state.dir()?)?;
+        audit_applied(audit, "apply", &target.interface, target.table, &target.backend, &transition, installed)?;
+        println!("removed {} routes installed while learning that the learned networks do not keep, {installed} routes via {} now",
+            transition.remove.len(), target.interface);
+        Ok(())
+    }
+}
+
+impl ApplyArgs {
+    fn failover(&self) -> StrResult<Option<Failover>> {
+        let Some(policy) = self.on_dead else {
+            return Ok(None);
+        };
+        let policy = match policy {
+            DeadPolicy::Withdraw => Policy::Withdraw,
+            DeadPolicy::Blackhole if self.target.backend.backend == BackendKind::Route => Policy::Blackhole,
+            DeadPolicy::Blackhole => return Err("--on-dead blackhole works with the route backend; the nft one keeps the traffic on the tunnel anyway".to_string()),
+            DeadPolicy::BackupPeer => Policy::BackupPeer,
+        };
+        if policy == Policy::BackupPeer && self.failover_order.len() < 2 {
+            return Err("--on-dead backup-peer needs at least two peers in --failover-order".to_string());
+        }
+        let liveness = Liveness {
+            interface: self.target.interface.clone(),
+            peer: self.failover_order.first().cloned(),
+            dead_after: self.dead_after,
+            ping: self.ping,
+        };
+        Ok(Some(Failover { liveness, policy, order: self.failover_order.clone() }))
+    }
+}
+
+fn apply(apply_args: &ApplyArgs) -> StrResult<()> {
+    check_format(&apply_args.args)?;
+    check_peer(&apply_args.args, apply_args.wg_interface.as_deref())?;
+    apply_args.target.check(&apply_args.args)?;
+    let schedule = apply_args.schedule.as_deref().map(Schedule::load).transpose()?;
+    let failover = apply_args.failover()?;
+    if apply_args.daemon && !apply_args.target.yes {
+        return Err("--daemon runs unattended, so there is no way to confirm: add --yes".to_string());
+    }
+    if apply_args.daemon && apply_args.args.conflict_policy == ConflictPolicy::Ask {
+        return Err("--daemon runs unattended, so there is no way to answer --conflict-policy ask".to_string());
+    }
+    if apply_args.daemon && schedule.is_none() && failover.is_none() && apply_args.interval.is_none() {
+        return Err("--daemon has nothing to watch: add --schedule, --on-dead or --interval".to_string());
+    }
+    if apply_args.target.dry_run && (schedule.is_some() || apply_args.daemon) {
+        return Err("--dry-run shows a single apply, not what a --schedule or --daemon would do over time".to_string());
+    }
+    if apply_args.health.health_listen.is_some() && !apply_args.daemon {
+        return Err("--health-listen answers for a --daemon, a single apply is over before anything could ask".to_string());
+    }
+    let mut audit = AuditLog::open(apply_args.args.audit_log.as_deref())?;
+    if apply_args.flush {
+        return flush(&apply_args.target, &mut audit);
+    }
+    // not ready until the first cycle went through, but alive (and watched for a stall) from the start
+    let health = apply_args.daemon.then(|| apply_args.health.serve()).transpose()?;
+    // resolving again needs the hosts, not just the networks a last run left
+    let (report, refresh) = match apply_args.interval {
+        Some(every) => {
+            let collected = collect_hosts(&apply_args.args, &mut audit, None)?;
+            let _busy = health.as_ref().map(|health| health.busy());
+            let report = routes_of(&apply_args.args, &collected, &mut audit, || health.iter().for_each(|health| health.progress()))?;
+            let due = wgrouter::clock::system().unix_time() + every;
+            (report, Some(Refresh { args: &apply_args.args, collected: collected.to_resolve_again(), every, due }))
+        }
+        None => (gen_routes_once(&apply_args.args, &apply_args.target.state.dir()?, &mut audit)?, None),
+    };
+    println!("{}", render(&report, &apply_args.args)?);
+    if let (Some(iface), Some(peer)) = (&apply_args.wg_interface, &apply_args.args.peer) {
+        update_peer(iface, peer, &report.networks, &apply_args.target, &mut audit)?;
+    }
+    if schedule.is_none() && !apply_args.daemon {
+        return apply_args.target.apply(&report, &mut audit);
+    }
+    apply_managed(&apply_args.target, &schedule.unwrap_or_default(), failover.as_ref(), report, refresh, health.as_deref(), &mut audit)
+}
+
+// --interval: the hosts resolved again and their networks worked out anew, every so often
+struct Refresh<'a> {
+    args: &'a Args,
+    collected: Collected,
+    every: u64,
+    due: u64,
+}
+
+impl Refresh<'_> {
+    // the new report when it is time and the networks changed; a host that resolved before and does not now is more
+    // likely a DNS hiccup than gone, so then the routes stay as they are until the next time
+    fn changed(&mut self, now: u64, report: &Report, audit: &mut AuditLog, health: &Health) -> StrResult<Option<Report>> {
+        if now < self.due {
+            return Ok(None);
+        }
+        self.due = now + self.every;
+        let fresh = routes_of(self.args, &self.collected, audit, || health.progress())?;
+        let lost = fresh.unresolved.keys().filter(|host| report.hosts.contains_key(*host)).count();
+        if lost > 0 {
+            eprintln!("warning: {lost} hosts that resolved before did not now, keeping the networks as they are");
+            return Ok(None);
+        }
+        if fresh.networks == report.networks {
+            eprintln!("resolved the hosts again, the {} networks are unchanged", report.networks.len());
+            return Ok(None);
+        }
+        let added = fresh.networks.iter().filter(|net| !report.networks.contains(net)).count();
+        let removed = report.networks.iter().filter(|net| !fresh.networks.contains(net)).count();
+        eprintln!("resolved the hosts again: {added} networks new, {removed} gone");
+        audit.record("refreshed", json!({"added": added, "removed": removed, "networks": fresh.networks}))?;
+        println!("{}", render(&fresh, self.args)?);
+        Ok(Some(fresh))
+    }
+}
+
+// apply --deployment: the networks, worked out once from the shared options, go to each step in the order of the
+// config file, with the options of the step on top of the shared ones; all steps are checked before anything changes,
+// confirmed together, and the first that fails stops the rest
+fn deploy(apply_args: &ApplyArgs, name: &str, argv: &[String]) -> StrResult<()> {
+    check_format(&apply_args.args)?;
+    apply_args.target.check(&apply_args.args)?;
+    let path = wgrouter::config::default_path()
+        .ok_or("neither WGROUTER_CONFIG, XDG_CONFIG_HOME nor HOME is set, so there is no config to find deployments in".to_string())?;
+    let text = wgrouter::config::read(&path)?.ok_or(format!("no config {} to find deployments in", path.display()))?;
+    let steps = wgrouter::config::deployment(&text, name).map_err(|e| format!("{e} in config {}", path.display()))?;
+    let apply = <ApplyArgs as clap::Args>::augment_args(clap::Command::new("apply"));
+    let steps = steps.into_iter().map(|step| {
+        let cli = Cli::try_parse_from(argv.iter().cloned().chain(step.options.to_args(|option| is_flag(&apply, option)))).map_err(|e| {
+            let e = e.to_string();
+            let kind = match &step.kind {
+                StepKind::Routes => "routes".to_string(),
+                StepKind::Peer => "peer".to_string(),
+                StepKind::File(path) => format!("file {path}"),
+            };
+            format!("[deployment {name} {kind}]: {}", e.lines().next().unwrap_or_default().trim_start_matches("error: "))
+        })?;
+        let Some(Command::Apply(mut step_args)) = cli.command else {
+            return Err("--deployment goes with apply".to_string());
+        };
+        let what = match &step.kind {
+            StepKind::Routes => {
+                step_args.target.check(&step_args.args)?;
+                let table = step_args.target.table.map(|t| format!(" in table {t}")).unwrap_or_default();
+                format!("routes via {}{table}", step_args.target.interface)
+            }
+            StepKind::Peer => {
+                let peer = step_args.args.peer.as_deref().ok_or(format!("a peer step of deployment {name} needs peer"))?;
+                match (&step_args.wg_interface, &step_args.args.wg_conf) {
+                    (Some(iface), _) => format!("peer {peer} on {iface}"),
+                    (None, Some(conf)) => format!("peer {peer} in {conf}"),
+                    (None, None) => return Err(format!("the peer step of deployment {name} needs wg-interface or wg-conf")),
+                }
+            }
+            StepKind::File(path) => {
+                check_format(&step_args.args)?;
+                format!("{} file {path}", step_args.args.format.to_possible_value().map(|format| format.get_name().to_string()).unwrap_or_default())
+            }
+        };
+        step_args.target.yes = true;
+        step_args.target.dry_run = apply_args.target.dry_run;
+        Ok((what, step.kind, step_args))
+    }).collect::<StrResult<Vec<_>>>()?;
+
+    let mut audit = AuditLog::open(apply_args.args.audit_log.as_deref())?;
+    let report = gen_routes_once(&apply_args.args, &apply_args.target.state.dir()?, &mut audit)?;
+    println!("{}", render(&report, &apply_args.args)?);
+    if report.networks.is_empty() {
+        eprintln!("nothing to deploy");
+        return Ok(());
+    }
+    let targets = steps.iter().map(|(what, _, _)| what.as_str()).collect::<Vec<_>>().join(", ");
+    let dry_run = apply_args.target.dry_run;
+    if !dry_run && !apply_args.target.yes && !confirm(&format!("Deploy these {} networks to {targets}?", report.networks.len())) {
+        println!("not deployed");
+        return Ok(());
+    }
+    steps.iter().enumerate().try_for_each(|(n, (what, kind, step_args))| {
+        println!("{what}:");
+        deploy_step(kind, step_args, &report, &mut audit).map_err(|e| format!("deployment {name} stopped at {what}, after {n} of {} steps: {e}", steps.len()))
+    })?;
+    if !dry_run {
+        println!("deployed {} networks to {targets}", report.networks.len());
+    }
+    Ok(())
+}
+
+fn deploy_step(kind: &StepKind, step_args: &ApplyArgs, report: &Report, audit: &mut AuditLog) -> StrResult<()> {
+    let (target, nets) = (&step_args.target, &report.networks);
+    match (kind, &step_args.wg_interface, &step_args.args.wg_conf, &step_args.args.peer) {
+        (StepKind::Routes, ..) => target.apply(report, audit),
+        (StepKind::Peer, Some(iface), _, Some(peer)) => update_peer(iface, peer, nets, target, audit),
+        (StepKind::Peer, None, Some(conf), Some(peer)) if target.dry_run => {
+            let text = std::fs::read_to_string(conf).map_err(|e| format!("could not read {conf}: {e}"))?;
+            let (_, added) = wgrouter::wgconf::merge_allowed_ips(&text, peer, nets).map_err(|e| format!("{e} in {conf}"))?;
+            println!("would add {} networks to the peer in {conf}", added.len());
+            Ok(())
+        }
+        (StepKind::Peer, None, Some(conf), Some(peer)) => {
+            let added = wgrouter::wgconf::patch_file(std::path::Path::new(conf), peer, nets)?;
+            audit.record("applied", json!({"action": "patch", "target": conf, "peer": peer, "added": added}))?;
+            println!("{} networks added to the peer in {conf}", added.len());
+            Ok(())
+        }
+        (StepKind::Peer, ..) => Err("a peer step needs peer, and wg-interface or wg-conf".to_string()),
+        (StepKind::File(path), ..) => {
+            let text = format!("{}\n", render(report, &step_args.args)?);
+            if std::fs::read_to_string(path).is_ok_and(|old| old == text) {
+                println!("{path} is up to date");
+                return Ok(());
+            }
+            if target.dry_run {
+                println!("would write {path}");
+                return Ok(());
+            }
+            let tmp = format!("{path}.tmp");
+            std::fs::write(&tmp, &text).map_err(|e| format!("could not write {tmp}: {e}"))?;
+            std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {path}: {e}"))?;
+            audit.record("applied", json!({"action": "write", "target": path, "networks": nets}))?;
+            println!("wrote {path}");
+            Ok(())
+        }
+    }
+}
+
+// the networks added to the allowed IPs of the peer on the running interface, which keeps the ones it has
+fn update_peer(iface: &str, peer: &str, nets: &[String], target: &ApplyTarget, audit: &mut AuditLog) -> StrResult<()> {
+    let before = running_allowed_ips()?
         .into_iter()
-        .collect::<Vec<String>>()
-        .join(", ");
+        .find(|(interface, key, _)| interface == iface && key == peer)
+        .map(|(_, _, ips)| ips)
+        .ok_or(format!("no peer {peer} on the running interface {iface}"))?;
+    let added = wgrouter::wgconf::new_allowed_ips(&before.iter().map(String::as_str).collect::<Vec<_>>(), nets)?;
+    let after = before.iter().chain(&added).cloned().collect::<Vec<_>>();
+    println!("AllowedIPs of peer {peer} on {iface}:\n- {}\n+ {}", before.join(", "), after.join(", "));
+    if added.is_empty() || target.dry_run {
+        println!("{} networks to add to the peer", added.len());
+        return Ok(());
+    }
+    if !target.yes && !confirm(&format!("Add these {} networks to the peer?", added.len())) {
+        println!("peer not updated");
+        return Ok(());
+    }
+    wgrouter::apply::wg_set_allowed_ips(iface, peer, &after)?;
+    audit.record("applied", json!({"action": "wg-set", "target": iface, "peer": peer, "added": added}))?;
+    println!("added {} networks to peer {peer} on {iface}", added.len());
+    Ok(())
+}
+
+// "+ net" for each route that would be added, "- net" for each that would be removed
+fn show_transition(transition: &Transition, target: &str) {
+    println!("would add {} and remove {} routes {target}", transition.add.len(), transition.remove.len());
+    transition.add.iter().for_each(|net| println!("+ {net}"));
+    transition.remove.iter().for_each(|net| println!("- {net}"));
+}
+
+#[cfg(target_os = "linux")]
+fn flush(target: &ApplyTarget, audit: &mut AuditLog) -> StrResult<()> {
+    let nets = wgrouter::apply::netlink::tagged_routes(&target.interface, target.table)?;
+    let table = target.table.map(|t| format!(" in table {t}")).unwrap_or_default();
+    if nets.is_empty() {
+        println!("no routes of protocol {} via {}{table}", wgrouter::apply::netlink::RTPROT_WGROUTER, target.interface);
+        return Ok(());
+    }
+    if target.dry_run {
+        show_transition(&Transition { remove: nets, add: vec![] }, &format!("via {}{table}", target.interface));
+        return Ok(());
+    }
+    if !target.yes && !confirm(&format!("Remove these {} routes via {}{table}?", nets.len(), target.interface)) {
+        println!("not flushed");
+        return Ok(());
+    }
+    let (transition, installed) = wgrouter::apply::flush_generation(&nets, &target.interface, target.table, target.keep, &target.state.dir()?)?;
+    audit_applied(audit, "flush", &target.interface, target.table, &target.backend, &transition, installed)?;
+    println!("removed {} routes, {installed} routes via {} now; 'undo' takes them back", transition.remove.len(), target.interface);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn flush(_target: &ApplyTarget, _audit: &mut AuditLog) -> StrResult<()> {
+    Err("--flush lists the routes through netlink, which is only supported on Linux".to_string())
+}
+
+// how often the daemon looks at the schedule and the tunnel
+const DAEMON_TICK: u64 = 30;
+
+// what is installed is exactly what the schedule has active, so closing windows take their routes away; as a daemon
+// this is redone every tick and only changes are applied, and with a failover the tunnel is checked first: while it is
+// dead, the routes are taken off it and schedule changes wait; with a refresh, the networks themselves change too.
+// Each cycle of a daemon is recorded in its health: ready once one went through, not while the last refresh failed
+fn apply_managed(target: &ApplyTarget, schedule: &Schedule, failover: Option<&Failover>, mut report: Report, mut refresh: Option<Refresh>, daemon: Option<&Health>, audit: &mut AuditLog) -> StrResult<()> {
+    let clock = wgrouter::clock::system();
+    let table = target.table.map(|t| format!(" in table {t}")).unwrap_or_default();
+    if !target.yes && !confirm(&format!("Route the networks via {}{table} on schedule, {} of them now?",
+        target.interface, schedule.active_networks(&report, local_week_minute(clock.unix_time())).len())) {
+        println!("not applied");
+        return Ok(());
+    }
+    let backend = target.backend.backend()?;
+    let mut installed = None::<Vec<String>>;
+    let mut dead = false;
+    // with backup peers, the peer holding the networks now
+    let mut holder = failover.and_then(|failover| failover.order.first().cloned()).unwrap_or_default();
+    loop {
+        let busy = daemon.map(|health| health.busy());
+        let now = clock.unix_time();
+        let nets = installed.clone().unwrap_or_default();
+        match failover {
+            Some(failover) if failover.policy == Policy::BackupPeer => {
+                if let Some((next, reason)) = failover.switch_peer(&holder, now)? {
+                    eprintln!("moving {} networks from peer {holder} to {next}: {reason}", nets.len());
+                    failover.move_networks(&holder, &next, &nets)?;
+                    audit.record("failover", json!({"state": "switched", "reason": reason, "from": holder, "to": next, "networks": nets}))?;
+                    holder = next;
+                }
+            }
+            Some(failover) => {
+                let policy = format!("{:?}", failover.policy).to_ascii_lowercase();
+                match (dead, failover.liveness.check(now)) {
+                    (false, Some(reason)) => {
+                        eprintln!("tunnel dead ({reason}), {policy} for {} networks", nets.len());
+                        failover.fail(&backend, &nets, target.table)?;
+                        audit.record("failover", json!({"state": "dead", "reason": reason, "policy": policy, "networks": nets}))?;
+                        dead = true;
+                    }
+                    (true, None) => {
+                        eprintln!("tunnel alive again, {} networks back on it", nets.len());
+                        failover.recover(&backend, &nets, target.table)?;
+                        audit.record("failover", json!({"state": "alive", "policy": policy, "networks": nets}))?;
+                        dead = false;
+                    }
+                    _ => {}
+                }
+            }
+            None => {}
+        }
+        let mut cycle = Ok(());
+        if let (Some(refresh), Some(health)) = (&mut refresh, daemon) {
+            match refresh.changed(now, &report, audit, health) {
+                Ok(Some(fresh)) => report = fresh,
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("warning: could not resolve the hosts again: {e}");
+                    cycle = Err(format!("could not resolve the hosts again: {e}"));
+                }
+            }
+        }
+        if !dead {
+            let active = schedule.active_networks(&report, local_week_minute(now));
+            if installed.as_ref() != Some(&active) {
+                target.install(&active, true, audit)?;
+                // networks a schedule opens while a backup peer stands in go to that peer as well
+                if let Some(failover) = failover.filter(|failover| failover.policy == Policy::BackupPeer && failover.order[0] != holder) {
+                    failover.move_networks(&failover.order[0], &holder, &active)?;
+                }
+                installed = Some(active);
+            }
+        }
+        let Some(health) = daemon else {
+            return Ok(());
+        };
+        health.record(&cycle);
+        drop(busy);
+        std::thread::sleep(std::time::Duration::from_secs(DAEMON_TICK - clock.unix_time() % DAEMON_TICK));
+    }
+}
+
+fn generate(args: &Args, state: &StateArgs) -> StrResult<()> {
+    check_format(args)?;
+    check_peer(args, None)?;
+    let mut audit = AuditLog::open(args.audit_log.as_deref())?;
+    let report = gen_routes_once(args, &state.dir()?, &mut audit)?;
+    println!("{}", render(&report, args)?);
+    if let (Some(path), Some(peer)) = (&args.wg_conf, &args.peer) {
+        let added = wgrouter::wgconf::patch_file(std::path::Path::new(path), peer, &report.networks)?;
+        audit.record("applied", json!({"action": "patch", "target": path, "peer": peer, "added": added}))?;
+        eprintln!("{} networks added to the peer in {path}", added.len());
+    }
+    Ok(())
+}
+
+// hosts file lines, so the addresses can be reviewed, edited and pinned with --etc-hosts
+fn resolve(args: &Args) -> StrResult<()> {
+    let mut audit = AuditLog::open(args.audit_log.as_deref())?;
+    let report = gen_routes(args, &mut audit)?;
+    report.hosts.iter().filter(|(host, _)| hostname_is_ip(discard_port(host)).is_none() && hostname_is_net(host).is_none()).for_each(|(host, ips)| {
+        // IDN hosts as punycode, which is what the resolver looks up, with the Unicode form as a comment
+        let unicode = unicode_host(discard_port(host)).map(|name| format!("  # {name}")).unwrap_or_default();
+        ips.iter().for_each(|ip| println!("{ip} {}{unicode}", discard_port(host)));
+    });
+    Ok(())
+}
+
+// --exclude-host and --exclude-net, with the lines of each --exclude-file
+fn exclusions(args: &Args) -> StrResult<Exclusions> {
+    let mut exclusions = Exclusions { hosts: args.exclude_hosts.clone(), nets: args.exclude_nets.clone() };
+    args.exclude_files.iter().try_for_each(|path| {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        exclusions.extend(Exclusions::parse(&text).map_err(|e| format!("{path}: {e}"))?);
+        Ok::<_, String>(())
+    })?;
+    Ok(exclusions)
+}
+
+// everything that can be checked without the network: inputs parse, plugins and bounds load, the format has what it needs
+fn check(args: &Args) -> StrResult<()> {
+    check_format(args)?;
+    check_peer(args, None)?;
+    let bounds = args.bounds.as_deref().map(Bounds::load).transpose()?.unwrap_or_default();
+    let opts = har_options(args);
+    let parsed = parse_files(args, &opts)?;
+    let (captured, _) = packet_capture_hosts(args)?;
+    let (pinned, _) = pinned_hosts(args)?;
+    parsed.iter().for_each(|(file, hosts)| println!("{file}: {} hosts, {} candidates", hosts.confirmed.len(), hosts.candidates.len()));
+    captured.iter().chain(&pinned).for_each(|(file, hosts)| println!("{file}: {} hosts", hosts.len()));
+    if !bounds.0.is_empty() {
+        println!("{} bounds", bounds.0.len());
+    }
+    if !args.include_hosts.is_empty() || !args.include_nets.is_empty() {
+        println!("{} included hosts, {} included networks", args.include_hosts.len(), args.include_nets.len());
+    }
+    let exclusions = exclusions(args)?;
+    if !exclusions.is_empty() {
+        println!("{} excluded host patterns, {} excluded networks", exclusions.hosts.len(), exclusions.nets.len());
+    }
+    println!("ok");
+    Ok(())
+}
+
+fn scrub(scrub_args: &ScrubArgs) -> StrResult<()> {
+    let (input, output) = (&scrub_args.input, &scrub_args.output);
+    let text = std::fs::read_to_string(input).map_err(|e| format!("could not read HAR file {input}: {e}"))?;
+    let (scrubbed, counts) = wgrouter::input::har::scrub(&text).map_err(|e| format!("{e} in {input}"))?;
+    if output == "-" {
+        println!("{scrubbed}");
+    } else {
+        std::fs::write(output, scrubbed + "\n").map_err(|e| format!("could not write {output}: {e}"))?;
+    }
+    eprintln!("{} entries: took out {} cookies, {} credential headers, {} request bodies and {} query strings",
+        counts.entries, counts.cookies, counts.headers, counts.bodies, counts.queries);
+    Ok(())
+}
+
+fn explain(explain_args: &ExplainArgs) -> StrResult<()> {
+    let path = &explain_args.artifact;
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let explanation = wgrouter::explain::explain(&text).map_err(|e| format!("{path}: {e}"))?;
+    let provenance = explain_args.audit_log.as_ref()
+        .map(|log| std::fs::read_to_string(log).map(|text| wgrouter::explain::provenance(&text)).map_err(|e| format!("could not read audit log {log}: {e}")))
+        .transpose()?;
+    print!("{path}: {}", wgrouter::explain::render(&explanation, provenance.as_ref()));
+    Ok(())
+}
 
-    Ok(format!("AllowedIPs = {wg_str}"))
+fn stability(stability_args: &StabilityArgs) -> StrResult<()> {
+    let log = &stability_args.audit_log;
+    let text = std::fs::read_to_string(log).map_err(|e| format!("could not read audit log {log}: {e}"))?;
+    let report = wgrouter::stability::stability(&wgrouter::stability::runs_of(&text, stability_args.runs));
+    if report.is_empty() {
+        return Err(format!("no resolved hosts in audit log {log}"));
+    }
+    print!("{}", wgrouter::stability::render(&report));
+    Ok(())
 }
 
-fn net_from_ip(ip: &str) -> String {
-    let net_rev = ip.split('.').rev().skip(2).collect::<Vec<&str>>();
-    let mut net = net_rev.into_iter().rev().collect::<Vec<&str>>();
-    net.push("0");
-    net.push("0");
-    format!("{}/16", net.join("."))
+fn export_state(bundle_args: &BundleArgs) -> StrResult<()> {
+    let packed = bundle::export(&bundle_args.bundle, &bundle_args.parts()?)?;
+    if packed.is_empty() {
+        eprintln!("warning: there is no config, plugin or state to pack, {} holds nothing", bundle_args.bundle.display());
+    } else {
+        println!("packed {} into {}", packed.join(", "), bundle_args.bundle.display());
+    }
+    Ok(())
 }
 
+fn import_state(import_args: &ImportArgs) -> StrResult<()> {
+    let written = bundle::import(&import_args.bundle.bundle, &import_args.bundle.parts()?, import_args.force)?;
+    written.iter().for_each(|path| println!("{}", path.display()));
+    eprintln!("imported {} file(s) from {}", written.len(), import_args.bundle.bundle.display());
+    Ok(())
+}
+
+// a route covering the endpoint sends the tunnel's own packets into the tunnel, which then silently stops working
+fn verify(verify_args: &VerifyArgs) -> StrResult<()> {
+    let guard = endpoint_guard(verify_args.wg_conf.as_deref(), &verify_args.endpoints)?;
+    let path = history::history_path(&verify_args.state.dir()?, &history::target_name(&verify_args.interface, verify_args.table));
+    let mut routed = vec![(format!("route applied via {}", verify_args.interface), History::load(&path)?.current().iter().cloned().collect::<Vec<_>>())];
+    match running_allowed_ips() {
+        Ok(peers) => routed.extend(peers.into_iter().map(|(iface, peer, nets)| (format!("AllowedIPs of peer {peer} on {iface}"), nets))),
+        Err(e) => eprintln!("warning: the running interfaces are not checked: {e}"),
+    }
+    if let Some(path) = &verify_args.wg_conf {
+        let conf = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        routed.push((format!("AllowedIPs in {path}"), wgrouter::wgconf::allowed_ips(&conf)));
+    }
+    let loops = routed
+        .iter()
+        .flat_map(|(source, nets)| guard.loops(nets).into_iter().map(move |(net, guarded, what)| format!("{source}: {net} overlaps {what} ({guarded})")))
+        .collect::<Vec<_>>();
+    if loops.is_empty() {
+        println!("ok: none of the {} routed networks overlaps any of the {} endpoint, nameserver, gateway and local addresses",
+            routed.iter().map(|(_, nets)| nets.len()).sum::<usize>(), guard.0.len());
+        return Ok(());
+    }
+    loops.iter().for_each(|l| println!("loop: {l}"));
+    Err(format!("{} routing loop(s): the tunnel's own packets would go into the tunnel; regenerate the networks, which cuts \
+        these addresses out of them, take the network out of the routes or AllowedIPs, or route the address the direct way \
+        with 'ip route add ADDRESS via GATEWAY'", loops.len()))
+}
+
+fn audit_applied(audit: &mut AuditLog, action: &str, iface: &str, table: Option<u32>, backend: &BackendArgs, transition: &Transition, installed: usize) -> StrResult<()> {
+    audit.record("applied", json!({
+        "action": action,
+        "target": history::target_name(iface, table),
+        "backend": format!("{:?}", backend.backend).to_ascii_lowercase(),
+        "added": transition.add,
+        "removed": transition.remove,
+        "installed": installed,
+    }))
+}
+
+fn step_history(target: &TargetArgs, step: fn(&mut History) -> Option<Transition>, what: &str) -> StrResult<()> {
+    let path = history::history_path(&target.state.dir()?, &history::target_name(&target.interface, target.table));
+    let mut history = History::load(&path)?;
+    let Some(transition) = step(&mut history) else {
+        println!("nothing to {what} for {}", target.interface);
+        return Ok(());
+    };
+    wgrouter::apply::install(&target.backend.backend()?, &transition, history.current(), &target.interface, target.table)?;
+    history.save(&path)?;
+    let mut audit = AuditLog::open(target.audit_log.as_deref())?;
+    audit_applied(&mut audit, what, &target.interface, target.table, &target.backend, &transition, history.current().len())?;
+    println!("{what}: removed {:?}, added {:?}; {} routes via {} now", transition.remove, transition.add, history.current().len(), target.interface);
+    Ok(())
+}
+
+fn serve_rpc(cli: &Cli) -> StrResult<()> {
+    let health = cli.health.serve()?;
+    wgrouter::rpc::serve(std::io::stdin().lock(), std::io::stdout().lock(), health)
+}
+
+// the options of the config file go in front of those on the command line, which override them, for the commands that
+// take inputs (running without a subcommand included)
+// the command line with the options of the config at path, if there is one
+fn with_config(argv: Vec<String>, path: Option<std::path::PathBuf>) -> StrResult<Vec<String>> {
+    match path.map(|path| Config::load(&path)).transpose()?.flatten() {
+        Some(config) => Ok(spliced(argv, &config)),
+        None => Ok(argv),
+    }
+}
+
+fn is_flag(command: &clap::Command, option: &str) -> bool {
+    command.get_arguments().any(|arg| arg.get_long() == Some(option) && matches!(arg.get_action(), clap::ArgAction::SetTrue))
+}
+
+// the command line with the options of the config that it neither gives itself nor conflicts with; those it gives
+// several times add to the config's values
+fn spliced(mut argv: Vec<String>, config: &Config) -> Vec<String> {
+    let mut cli = Cli::command();
+    cli.build();
+    // the config holds options of Args, so the subcommands that take them all take it
+    let options = <Args as clap::Args>::augment_args(clap::Command::new("options"));
+    let takes_config = |command: &clap::Command| options.get_arguments().all(|option| command.get_arguments().any(|arg| arg.get_id() == option.get_id()));
+    let (at, command) = match argv.get(1).and_then(|name| cli.find_subcommand(name)) {
+        Some(command) if takes_config(command) => (2, command),
+        Some(_) => return argv,
+        None => (1, &cli),
+    };
+    // when the command line does not parse on its own, clap tells why without the config in the way
+    let Ok(matches) = cli.clone().try_get_matches_from(&argv) else {
+        return argv;
+    };
+    let matches = matches.subcommand().map_or(&matches, |(_, matches)| matches);
+    let given = command.get_arguments().filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(clap::parser::ValueSource::CommandLine)).collect::<Vec<_>>();
+    let overridden = |option: &str| {
+        command.get_arguments().find(|arg| arg.get_long() == Some(option)).is_some_and(|arg| {
+            given.iter().any(|other| {
+                (other.get_id() == arg.get_id() && !matches!(arg.get_action(), clap::ArgAction::Append))
+                    || command.get_arg_conflicts_with(other).iter().any(|conflict| conflict.get_id() == arg.get_id())
+                    || command.get_arg_conflicts_with(arg).iter().any(|conflict| conflict.get_id() == other.get_id())
+            })
+        })
+    };
+    let config = Config(config.0.iter().filter(|(option, _)| !overridden(option)).cloned().collect());
+    let at = at.min(argv.len());
+    argv.splice(at..at, config.to_args(|option| is_flag(command, option)));
+    argv
+}
+
+// set when hosts were left unresolved with --unresolved, so the run ends with a soft failure once all else is done
+static UNRESOLVED_LEFT: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
 fn main() -> Result<(), String>{
-    println!("{}", gen_wg_routes()?);
+    let argv = with_config(std::env::args().collect(), wgrouter::config::default_path())?;
+    let cli = Cli::parse_from(&argv);
+    match &cli.command {
+        Some(Command::Generate(generate_args)) => generate(&generate_args.args, &generate_args.state),
+        Some(Command::Resolve(args)) => resolve(args),
+        Some(Command::Check(args)) => check(args),
+        Some(Command::Apply(apply_args)) => match &apply_args.deployment {
+            Some(name) => deploy(apply_args, name, &argv),
+            None => apply(apply_args),
+        },
+        Some(Command::Learn(learn_args)) => learn(learn_args),
+        Some(Command::Init(init_args)) => init(init_args),
+        Some(Command::Scrub(scrub_args)) => scrub(scrub_args),
+        Some(Command::Explain(explain_args)) => explain(explain_args),
+        Some(Command::Stability(stability_args)) => stability(stability_args),
+        Some(Command::Verify(verify_args)) => verify(verify_args),
+        Some(Command::ExportState(bundle_args)) => export_state(bundle_args),
+        Some(Command::ImportState(import_args)) => import_state(import_args),
+        Some(Command::Undo(target)) => step_history(target, History::undo, "undo"),
+        Some(Command::Redo(target)) => step_history(target, History::redo, "redo"),
+        None if cli.rpc => serve_rpc(&cli),
+        None if cli.health.health_listen.is_some() => Err("--health-listen answers for --rpc or apply --daemon".to_string()),
+        None => generate(&cli.args, &cli.state),
+    }?;
+    if UNRESOLVED_LEFT.load(std::sync::atomic::Ordering::Relaxed) {
+        std::process::exit(2);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spliced() {
+        let argv = |args: &str| args.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let config = Config::parse("ipv6 = true\nnameserver = 9.9.9.9\nprefix-len = 20\nendpoint = vpn.example.net:51820\nweighted = false\n").unwrap();
+        assert_eq!(spliced(argv("wgrouter check h.txt"), &config),
+            argv("wgrouter check --ipv6 --nameserver=9.9.9.9 --prefix-len=20 --endpoint=vpn.example.net:51820 h.txt"));
+        // the command line overrides an option, conflicts with ipv6 and nameserver, and adds an endpoint
+        assert_eq!(spliced(argv("wgrouter h.txt --prefix-len 24"), &config), argv("wgrouter --ipv6 --nameserver=9.9.9.9 --endpoint=vpn.example.net:51820 h.txt --prefix-len 24"));
+        assert_eq!(spliced(argv("wgrouter check --ipv4-only --system-resolver --endpoint 198.51.100.7:51820 h.txt"), &config),
+            argv("wgrouter check --prefix-len=20 --endpoint=vpn.example.net:51820 --ipv4-only --system-resolver --endpoint 198.51.100.7:51820 h.txt"));
+        assert!(Cli::try_parse_from(spliced(argv("wgrouter check --ipv4-only --system-resolver h.txt"), &config)).is_ok());
+        // a flag the config turns off is left out, other values are kept as they are
+        assert_eq!(spliced(argv("wgrouter apply h.txt"), &Config::parse("weighted = false\ninterface = false\n").unwrap()), argv("wgrouter apply --interface=false h.txt"));
+        // generate keeps its last run in the state of the profile, with or without the subcommand
+        let Cli { command: Some(Command::Generate(generate_args)), .. } = Cli::parse_from(spliced(argv("wgrouter generate --profile a h.txt"), &config)) else {
+            panic!("not generate");
+        };
+        assert_eq!(generate_args.state.profile.as_deref(), Some("a"));
+        assert_eq!(Cli::parse_from(argv("wgrouter --state-dir /srv/wgrouter h.txt")).state.state_dir, Some("/srv/wgrouter".into()));
+        // the other subcommands, and command lines clap rejects, are left alone
+        assert_eq!(spliced(argv("wgrouter stability audit.jsonl"), &config), argv("wgrouter stability audit.jsonl"));
+        assert_eq!(spliced(argv("wgrouter help"), &config), argv("wgrouter help"));
+        assert_eq!(spliced(argv("wgrouter check --bogus"), &config), argv("wgrouter check --bogus"));
+    }
+
+    #[test]
+    fn test_with_config() {
+        let argv = |args: &str| args.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("wgrouter-main-config-{}", std::process::id()));
+        assert_eq!(with_config(argv("wgrouter h.txt"), None), Ok(argv("wgrouter h.txt")));
+        assert_eq!(with_config(argv("wgrouter h.txt"), Some(path.clone())), Ok(argv("wgrouter h.txt")));
+        std::fs::write(&path, "# defaults\nipv6 = true\nprefix-len = 20\n").unwrap();
+        assert_eq!(with_config(argv("wgrouter h.txt --prefix-len 24"), Some(path.clone())), Ok(argv("wgrouter --ipv6 h.txt --prefix-len 24")));
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(with_config(argv("wgrouter h.txt"), Some(path.clone())), Ok(argv("wgrouter h.txt")));
+        std::fs::write(&path, "ipv6\n").unwrap();
+        assert!(with_config(argv("wgrouter h.txt"), Some(path.clone())).unwrap_err().contains(&path.display().to_string()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_health_args() {
+        let argv = |args: &str| args.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let cli = Cli::try_parse_from(argv("wgrouter apply h.txt --interface wg0 --yes --daemon --interval 15m --health-listen 127.0.0.1:9190 --stall-timeout 300")).unwrap();
+        let Some(Command::Apply(apply_args)) = cli.command else { panic!("not apply") };
+        assert_eq!(apply_args.health.health_listen.as_deref(), Some("127.0.0.1:9190"));
+        assert_eq!(apply_args.health.stall_timeout, 300);
+        let cli = Cli::try_parse_from(argv("wgrouter --rpc --health-listen 127.0.0.1:9190")).unwrap();
+        assert_eq!(cli.health.stall_timeout, wgrouter::health::DEFAULT_STALL_SECONDS);
+        let cli = Cli::try_parse_from(argv("wgrouter apply h.txt --interface wg0 --yes --health-listen 127.0.0.1:9190")).unwrap();
+        let Some(Command::Apply(apply_args)) = cli.command else { panic!("not apply") };
+        assert!(apply(&apply_args).unwrap_err().contains("--health-listen"));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90"), Ok(90));
+        assert_eq!(parse_interval("90s"), Ok(90));
+        assert_eq!(parse_interval("15m"), Ok(900));
+        assert_eq!(parse_interval("6h"), Ok(21600));
+        assert_eq!(parse_interval("1d"), Ok(86400));
+        ["", "m", "5x", "5M", "1.5h", "-5", "+5", "5 m", "5ms", "0", "0h"].iter().for_each(|s| assert!(parse_interval(s).is_err(), "{s}"));
+        assert_eq!(parse_interval("18446744073709551615"), Ok(u64::MAX));
+        assert!(parse_interval("18446744073709551616").is_err());
+        assert_eq!(parse_interval("213503982334602d"), Err("bad interval '213503982334602d': too long".to_string()));
+    }
+
+    #[test]
+    fn test_parse_mark() {
+        assert_eq!(parse_mark("51820"), Ok(51820));
+        assert_eq!(parse_mark("0xca6c"), Ok(0xca6c));
+        assert_eq!(parse_mark("0XCA6C"), Ok(0xca6c));
+        assert_eq!(parse_mark("0xffffffff"), Ok(u32::MAX));
+        ["", "0x", "-1", "4294967296", "0x100000000", "0xg", "12k"].iter().for_each(|s| assert!(parse_mark(s).is_err(), "{s}"));
+    }
+
+    #[test]
+    fn test_ipset_name() {
+        assert_eq!(ipset_name("wgrouter"), Ok("wgrouter".to_string()));
+        assert!(ipset_name(&"s".repeat(28)).is_ok());
+        ["", "a b", "tab\there"].iter().for_each(|s| assert!(ipset_name(s).is_err(), "{s}"));
+        assert!(ipset_name(&"s".repeat(29)).is_err());
+    }
+
+    #[test]
+    fn test_bird_protocol() {
+        assert_eq!(bird_protocol("wgrouter_static"), Ok("wgrouter_static".to_string()));
+        assert!(bird_protocol("_p1").is_ok());
+        ["", "1st", "a-b", "a b", "ä"].iter().for_each(|s| assert!(bird_protocol(s).is_err(), "{s}"));
+    }
+
+    #[test]
+    fn test_conflict_policy() {
+        assert_eq!(ConflictPolicy::from_str("narrow", true), Ok(ConflictPolicy::Narrow));
+        assert_eq!(ConflictPolicy::from_str("KEEP", true), Ok(ConflictPolicy::Keep));
+        assert!(ConflictPolicy::from_str("", true).is_err());
+        assert!(ConflictPolicy::from_str("dropped", true).is_err());
+        let net = "198.51.100.0/24".parse().unwrap();
+        let conns = [("198.51.100.7".parse().unwrap(), 443)];
+        assert_eq!(ConflictPolicy::Drop.decide(&net, &conns), Conflict::Drop);
+        assert_eq!(ConflictPolicy::Keep.decide(&net, &conns), Conflict::Keep);
+        assert_eq!(ConflictPolicy::Narrow.decide(&net, &[]), Conflict::Narrow);
+        let args = Cli::parse_from(["wgrouter", "h.txt"]).args;
+        assert_eq!(args.conflict_policy, ConflictPolicy::Drop);
+        assert!(Cli::try_parse_from(["wgrouter", "--conflict-policy", "maybe", "h.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_inputs_digest() {
+        let dir = std::env::temp_dir().join(format!("wgrouter-main-digest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (hosts, exclude) = (dir.join("h.txt"), dir.join("exclude.txt"));
+        std::fs::write(&hosts, "a.example.com\nb.example.com\n").unwrap();
+        std::fs::write(&exclude, "a.example.com\n").unwrap();
+        let args = Cli::parse_from(["wgrouter", hosts.to_str().unwrap(), "--exclude-file", exclude.to_str().unwrap()]).args;
+        let digest = inputs_digest(&args).unwrap().unwrap();
+        assert_eq!(inputs_digest(&args), Ok(Some(digest.clone())));
+        // an edited exclude file is not the same input any more
+        std::fs::write(&exclude, "b.example.com\n").unwrap();
+        assert!(inputs_digest(&args).unwrap().is_some_and(|edited| edited != digest));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,818 @@
+use std::collections::{BTreeMap, BTreeSet};
+use ipnetwork::IpNetwork;
+use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
+use crate::dns::{discard_port, hostname_is_ip, hostname_is_net, unicode_host, StrResult};
+use crate::routes::allowed_ips_line;
+
+// everything a run produced, for the output formats
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub networks: Vec<String>,
+    // routed host -> its addresses
+    pub hosts: BTreeMap<String, BTreeSet<String>>,
+    // routed host -> the CNAME targets its lookup went through, the final one last; absent from reports of older runs
+    #[serde(default)]
+    pub cnames: BTreeMap<String, Vec<String>>,
+    // where the hosts came from: capture file name (without extension) or live source
+    pub tags: BTreeMap<String, BTreeSet<String>>,
+    // routed network -> bytes its addresses transferred, when aggregation was weighted by traffic
+    pub weights: BTreeMap<String, u64>,
+    // host -> why it could not be resolved
+    pub unresolved: BTreeMap<String, String>,
+    // networks dropped for a conflicting connection, limits not met and the like
+    pub warnings: Vec<String>,
+}
+
+// each host's addresses with the networks of the list they are routed in
+fn routed_networks(networks: &[String], hosts: &BTreeMap<String, BTreeSet<String>>) -> BTreeMap<String, BTreeSet<String>> {
+    let networks = networks.iter().filter_map(|net| net.parse::<IpNetwork>().ok().map(|parsed| (net, parsed))).collect::<Vec<_>>();
+    hosts
+        .iter()
+        .map(|(host, addresses)| {
+            let routed = addresses
+                .iter()
+                .filter_map(|ip| ip.parse().ok())
+                .filter_map(|ip| networks.iter().find(|(_, net)| net.contains(ip)).map(|(net, _)| net.to_string()))
+                .collect();
+            (host.clone(), routed)
+        })
+        .collect()
+}
+
+// the "hosts" of a JSON document: each host's addresses and the networks routing them
+fn hosts_document(networks: &[String], hosts: &BTreeMap<String, BTreeSet<String>>) -> Value {
+    let routed = routed_networks(networks, hosts);
+    hosts.iter().map(|(host, addresses)| (host.clone(), json!({"addresses": addresses, "networks": routed[host]}))).collect::<serde_json::Map<_, _>>().into()
+}
+
+impl Report {
+    // routed host -> the networks its addresses went into
+    pub fn host_networks(&self) -> BTreeMap<String, BTreeSet<String>> {
+        routed_networks(&self.networks, &self.hosts)
+    }
+
+    // the routed networks needed by the hosts of each tag
+    pub fn tag_networks(&self) -> BTreeMap<String, Vec<String>> {
+        self.tags
+            .iter()
+            .map(|(tag, hosts)| {
+                let ips = hosts
+                    .iter()
+                    .filter_map(|host| self.hosts.get(host))
+                    .flatten()
+                    .filter_map(|ip| ip.parse::<IpNetwork>().ok())
+                    .collect::<Vec<_>>();
+                let nets = self.networks
+                    .iter()
+                    .filter(|net| net.parse::<IpNetwork>().is_ok_and(|net| ips.iter().any(|ip| net.contains(ip.network()))))
+                    .cloned()
+                    .collect();
+                (tag.clone(), nets)
+            })
+            .collect()
+    }
+
+    // hostnames to steer at DNS level; IP literals are dropped and so are names already covered by a parent in the list
+    pub fn tracked_domains(&self) -> Vec<String> {
+        let names = self.hosts
+            .keys()
+            .map(|host| discard_port(host).trim_end_matches('.').to_ascii_lowercase())
+            .filter(|host| hostname_is_ip(host).is_none() && hostname_is_net(host).is_none())
+            .collect::<BTreeSet<_>>();
+        names
+            .iter()
+            .filter(|name| !names.iter().any(|parent| name.len() > parent.len() && name.ends_with(&format!(".{parent}"))))
+            .cloned()
+            .collect()
+    }
+}
+
+// JSON strings are valid double-quoted YAML scalars, so no YAML library is needed for this
+fn quoted(s: &str) -> String {
+    Value::from(s).to_string()
+}
+
+// a word for sh: as is when it cannot be misread, single-quoted otherwise
+fn shell_quoted(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "_.-:/@".contains(c)) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+fn yaml_list(out: &mut String, indent: &str, items: impl IntoIterator<Item = impl AsRef<str>>) {
+    let mut items = items.into_iter().peekable();
+    if items.peek().is_none() {
+        out.pop();
+        out.push_str(" []\n");
+    }
+    items.for_each(|item| out.push_str(&format!("{indent}- {}\n", quoted(item.as_ref()))));
+}
+
+fn yaml_map_of_lists(out: &mut String, name: &str, map: impl IntoIterator<Item = (impl AsRef<str>, impl IntoIterator<Item = impl AsRef<str>>)>) {
+    out.push_str(&format!("{name}:\n"));
+    let before = out.len();
+    map.into_iter().for_each(|(key, items)| {
+        out.push_str(&format!("  {}:\n", quoted(key.as_ref())));
+        yaml_list(out, "    ", items);
+    });
+    if out.len() == before {
+        out.pop();
+        out.push_str(" {}\n");
+    }
+}
+
+pub fn ansible_vars(report: &Report) -> String {
+    let mut out = "---\n# generated by wgrouter\nwgrouter_networks:\n".to_string();
+    yaml_list(&mut out, "  ", &report.networks);
+    out.push_str(&format!("wgrouter_allowed_ips: {}\n", quoted(&allowed_ips_line(&report.networks))));
+    yaml_map_of_lists(&mut out, "wgrouter_hosts", &report.hosts);
+    yaml_map_of_lists(&mut out, "wgrouter_groups", report.tag_networks());
+    if !report.weights.is_empty() {
+        out.push_str("wgrouter_weights:\n");
+        report.weights.iter().for_each(|(net, bytes)| out.push_str(&format!("  {}: {bytes}\n", quoted(net))));
+    }
+    out
+}
+
+// variables for a .auto.tfvars.json file
+pub fn terraform_vars(report: &Report) -> Value {
+    let mut vars = json!({
+        "wgrouter_networks": report.networks,
+        "wgrouter_allowed_ips": allowed_ips_line(&report.networks),
+        "wgrouter_hosts": report.hosts,
+        "wgrouter_groups": report.tag_networks(),
+    });
+    if !report.weights.is_empty() {
+        vars["wgrouter_weights"] = json!(report.weights);
+    }
+    vars
+}
+
+// bumped whenever a field changes meaning or goes away; new fields may appear without a bump. 2: each of the hosts is
+// an object of its addresses and the networks routing them, rather than the list of its addresses
+pub const JSON_SCHEMA: u32 = 2;
+
+// a JSON document or --unresolved file of an older version as this version writes it
+pub fn upgrade_document(mut document: Value) -> StrResult<Value> {
+    match document["schema"].as_u64() {
+        Some(1) => {
+            let networks = serde_json::from_value::<Vec<String>>(document["networks"].clone()).unwrap_or_default();
+            if let Ok(hosts) = serde_json::from_value::<BTreeMap<String, BTreeSet<String>>>(document["hosts"].clone()) {
+                document["hosts"] = hosts_document(&networks, &hosts);
+            }
+            document["schema"] = json!(2);
+            Ok(document)
+        }
+        Some(schema) if schema <= u64::from(JSON_SCHEMA) => Ok(document),
+        Some(schema) => Err(format!("the document has schema {schema}, this version only understands up to {JSON_SCHEMA}")),
+        None => Err("not a wgrouter JSON document, it has no schema".to_string()),
+    }
+}
+
+// the hosts left unresolved, one per line with the reason as a comment: a host list to give as an input again
+pub fn unresolved_list(report: &Report) -> String {
+    let mut out = String::from("# hosts that could not be resolved, give this file as an input to try them again\n");
+    report.unresolved.iter().for_each(|(host, reason)| out.push_str(&format!("{host}  # {}\n", reason.replace('\n', " "))));
+    out
+}
+
+// the same as JSON, the "unresolved" part of json_document, which is read back as an input like the list
+pub fn unresolved_document(report: &Report) -> Value {
+    json!({"schema": JSON_SCHEMA, "unresolved": report.unresolved})
+}
+
+// everything a run found out, for programs: resolved and unresolved hosts, the networks and the warnings
+pub fn json_document(report: &Report) -> Value {
+    let mut document = json!({
+        "schema": JSON_SCHEMA,
+        "networks": report.networks,
+        "allowed_ips": allowed_ips_line(&report.networks),
+        "hosts": hosts_document(&report.networks, &report.hosts),
+        "unresolved": report.unresolved,
+        "groups": report.tag_networks(),
+        "warnings": report.warnings,
+    });
+    // the hosts with punycode labels, as they are written in their own script
+    let unicode = report.hosts.keys().chain(report.unresolved.keys()).filter_map(|host| Some((host.clone(), unicode_host(host)?))).collect::<BTreeMap<_, _>>();
+    if !unicode.is_empty() {
+        document["unicode"] = json!(unicode);
+    }
+    if !report.weights.is_empty() {
+        document["weights"] = json!(report.weights);
+    }
+    if !report.cnames.is_empty() {
+        document["cnames"] = json!(report.cnames);
+    }
+    document
+}
+
+// the external data source only takes string values, so lists and maps are JSON-encoded (jsondecode() on the Terraform side)
+pub fn terraform_external(report: &Report) -> Value {
+    let vars = terraform_vars(report);
+    let result = vars
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| {
+            let value = value.as_str().map(String::from).unwrap_or(value.to_string());
+            (name.trim_start_matches("wgrouter_").to_string(), Value::from(value))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    Value::Object(result)
+}
+
+// IpPermissions for aws ec2 authorize-security-group-egress --ip-permissions file://...
+pub fn aws_egress_rules(report: &Report, name: &str) -> Value {
+    let (v4, v6) = by_family(&report.networks);
+    let mut rule = json!({
+        "IpProtocol": "-1",
+        "IpRanges": v4.iter().map(|net| json!({"CidrIp": net, "Description": name})).collect::<Vec<_>>(),
+    });
+    if !v6.is_empty() {
+        rule["Ipv6Ranges"] = v6.iter().map(|net| json!({"CidrIpv6": net, "Description": name})).collect();
+    }
+    json!([rule])
+}
+
+// IPv4 networks, then IPv6 ones
+fn by_family(nets: &[String]) -> (Vec<&String>, Vec<&String>) {
+    nets.iter().partition(|net| !net.contains(':'))
+}
+
+// a firewall rule resource, as gcloud compute firewall-rules describe prints it; a rule cannot mix address
+// families, so IPv6 networks get a second document with its own rule
+pub fn gcp_firewall_rule(report: &Report, name: &str) -> String {
+    let (v4, v6) = by_family(&report.networks);
+    let rule = |name: &str, ranges: &[&String]| {
+        let mut out = format!("name: {}\nnetwork: global/networks/default\ndirection: EGRESS\npriority: 1000\nallowed:\n  - IPProtocol: all\ndestinationRanges:\n", quoted(name));
+        yaml_list(&mut out, "  ", ranges);
+        out
+    };
+    let mut out = rule(name, &v4);
+    if !v6.is_empty() {
+        out.push_str("---\n");
+        out.push_str(&rule(&format!("{name}-v6"), &v6));
+    }
+    out
+}
+
+// securityRules of an NSG in an ARM template; like on GCP, one rule per address family
+pub fn azure_nsg_rules(report: &Report, name: &str) -> Value {
+    let (v4, v6) = by_family(&report.networks);
+    let rule = |name: String, prefixes: Vec<&String>, priority: u32| json!({
+        "name": name,
+        "properties": {
+            "protocol": "*",
+            "sourcePortRange": "*",
+            "destinationPortRange": "*",
+            "sourceAddressPrefix": "*",
+            "destinationAddressPrefixes": prefixes,
+            "access": "Allow",
+            "priority": priority,
+            "direction": "Outbound",
+        },
+    });
+    let mut rules = vec![rule(name.to_string(), v4, 100)];
+    if !v6.is_empty() {
+        rules.push(rule(format!("{name}-v6"), v6, 101));
+    }
+    Value::from(rules)
+}
+
+// shell commands steering only these clients through the tunnel: the networks go into their own table, looked up
+// for traffic from the clients; anything not in the table falls through to the next rule, so other destinations
+// keep the default route
+pub fn ip_rules(report: &Report, clients: &[IpNetwork], interface: &str, table: u32, priority: u32) -> String {
+    let family = |v6: bool| if v6 { "ip -6" } else { "ip" };
+    let interface = shell_quoted(interface);
+    let mut out = format!("#!/bin/sh\n# generated by wgrouter: traffic of {} client(s) to {} network(s) goes via {interface}\n",
+        clients.len(), report.networks.len());
+    report.networks.iter().for_each(|net| {
+        out.push_str(&format!("{} route replace {net} dev {interface} table {table}\n", family(net.contains(':'))));
+    });
+    clients.iter().for_each(|client| {
+        let ip = family(client.is_ipv6());
+        out.push_str(&format!("{ip} rule del from {client} lookup {table} priority {priority} 2>/dev/null\n"));
+        out.push_str(&format!("{ip} rule add from {client} lookup {table} priority {priority}\n"));
+    });
+    out
+}
+
+// nftables sets of the networks in table inet <table>, to match on in marking or policy rules; a set holds one address
+// family, so IPv6 networks go to <set>_v6; either a definition to include into nftables.conf, or add element commands
+// (nft -f) filling sets that already exist
+pub fn nft_sets(report: &Report, table: &str, set: &str, elements_only: bool) -> String {
+    let (v4, v6) = by_family(&report.networks);
+    let sets = [(set.to_string(), "ipv4_addr", v4), (format!("{set}_v6"), "ipv6_addr", v6)];
+    let list = |nets: &[&String]| nets.iter().map(|net| net.as_str()).collect::<Vec<_>>().join(", ");
+    if elements_only {
+        return sets.iter()
+            .filter(|(_, _, nets)| !nets.is_empty())
+            .map(|(name, _, nets)| format!("add element inet {table} {name} {{ {} }}\n", list(nets)))
+            .collect();
+    }
+    let mut out = format!("table inet {table} {{\n");
+    sets.iter().for_each(|(name, kind, nets)| {
+        out.push_str(&format!("    set {name} {{\n        type {kind}\n        flags interval\n"));
+        if !nets.is_empty() {
+            out.push_str(&format!("        elements = {{ {} }}\n", list(nets)));
+        }
+        out.push_str("    }\n");
+    });
+    out.push_str("}\n");
+    out
+}
+
+// ipset hash:net sets of the networks, <name> and <name>_v6 for IPv6; either shell commands or the input of ipset
+// restore, which are the same lines without the leading "ipset"; -exist makes rerunning them harmless, and flushing
+// drops networks from an earlier run
+pub fn ipsets(report: &Report, name: &str, restore: bool) -> String {
+    let (v4, v6) = by_family(&report.networks);
+    let command = if restore { "" } else { "ipset " };
+    let mut out = String::new();
+    [(name.to_string(), "inet", v4), (format!("{name}_v6"), "inet6", v6)].iter().for_each(|(set, family, nets)| {
+        out.push_str(&format!("{command}create {set} hash:net family {family} -exist\n{command}flush {set}\n"));
+        nets.iter().for_each(|net| out.push_str(&format!("{command}add {set} {net} -exist\n")));
+    });
+    out
+}
+
+// route directives for an OpenVPN client config, IPv4 ones with a netmask and IPv6 ones as route-ipv6; pushed, they go
+// into a server config and reach every client
+pub fn openvpn_routes(report: &Report, push: bool) -> String {
+    report.networks.iter().filter_map(|net| net.parse::<IpNetwork>().ok()).map(|net| {
+        let route = match net {
+            IpNetwork::V4(net) => format!("route {} {}", net.network(), net.mask()),
+            IpNetwork::V6(net) => format!("route-ipv6 {}/{}", net.network(), net.prefix()),
+        };
+        if push { format!("push \"{route}\"\n") } else { format!("{route}\n") }
+    }).collect()
+}
+
+// a script adding a route per network via the interface to the table, or the one deleting them again; the setup stops
+// at the first failure, the teardown goes on past routes that are gone already
+pub fn ip_route_script(report: &Report, interface: &str, table: u32, teardown: bool) -> String {
+    let (verb, what, tail) = if teardown { ("del", "removes", " 2>/dev/null") } else { ("add", "adds", "") };
+    let interface = shell_quoted(interface);
+    let mut out = format!("#!/bin/sh\n# generated by wgrouter: {what} {} route(s) via {interface} in table {table}\n", report.networks.len());
+    if !teardown {
+        out.push_str("set -e\n");
+    }
+    report.networks.iter().for_each(|net| {
+        let ip = if net.contains(':') { "ip -6" } else { "ip" };
+        out.push_str(&format!("{ip} route {verb} {net} dev {interface} table {table}{tail}\n"));
+    });
+    out
+}
+
+// a cmd script (route add, netsh for IPv6) or with powershell New-NetRoute commands adding a route per network on the
+// interface; Windows wants the index of the interface rather than its name, and as it only exists once the tunnel is up,
+// the scripts start with an IF_INDEX placeholder to fill in
+pub fn windows_routes(report: &Report, interface: &str, powershell: bool) -> String {
+    let header = format!("generated by wgrouter: adds {} route(s) via {interface}", report.networks.len());
+    let mut out = if powershell {
+        format!("# {header}\n# replace IF_INDEX with the index of the interface: (Get-NetAdapter -Name '{}').ifIndex\n$IfIndex = IF_INDEX\n",
+            interface.replace('\'', "''"))
+    } else {
+        format!("@echo off\nrem {header}\nrem replace IF_INDEX with the index of the interface, as 'route print' lists it\nset IF=IF_INDEX\n")
+    };
+    report.networks.iter().filter_map(|net| net.parse::<IpNetwork>().ok()).for_each(|net| {
+        out.push_str(&match (net, powershell) {
+            (net, true) => format!("New-NetRoute -DestinationPrefix {net} -InterfaceIndex $IfIndex -PolicyStore ActiveStore\n"),
+            (IpNetwork::V4(net), false) => format!("route add {} mask {} 0.0.0.0 if %IF%\n", net.network(), net.mask()),
+            (IpNetwork::V6(net), false) => format!("netsh interface ipv6 add route {net} interface=%IF% store=active\n"),
+        })
+    });
+    out
+}
+
+// a pf table file, an address or network per line, for 'table <name> persist file' or pfctl -T replace -f
+pub fn pf_table(report: &Report, table: &str) -> String {
+    let mut out = format!("# generated by wgrouter: {} network(s) for the pf table <{table}>\n", report.networks.len());
+    report.networks.iter().for_each(|net| out.push_str(&format!("{net}\n")));
+    out
+}
+
+// pf.conf rules loading the table file and sending the traffic to its networks through the interface; a skeleton, as
+// whether route-to needs the far end of the tunnel as next hop depends on the system
+pub fn pf_rules(table: &str, table_file: &str, interface: &str) -> String {
+    format!("# generated by wgrouter: sends the traffic to the networks of <{table}> through {interface}
+# reload the networks with: pfctl -t {table} -T replace -f {table_file}
+table <{table}> persist file \"{table_file}\"
+# on FreeBSD and macOS add the tunnel's far end as next hop if needed, e.g. route-to ({interface} 10.8.0.1);
+# OpenBSD takes just the next hop: route-to 10.8.0.1
+pass out quick on ! {interface} route-to ({interface}) inet from any to <{table}> keep state
+pass out quick on ! {interface} route-to ({interface}) inet6 from any to <{table}> keep state
+")
+}
+
+// BIRD 2 static protocols routing the networks via the interface, for the daemon to export to its other protocols; a
+// static protocol has one channel, so IPv6 networks get a second one with a _v6 suffix
+pub fn bird_static(report: &Report, interface: &str, name: &str) -> String {
+    let (v4, v6) = by_family(&report.networks);
+    let mut out = format!("# generated by wgrouter: {} route(s) via {interface}\n", report.networks.len());
+    [(name.to_string(), "ipv4", &v4), (format!("{name}_v6"), "ipv6", &v6)]
+        .iter()
+        .filter(|(_, channel, nets)| !nets.is_empty() || (*channel == "ipv4" && v6.is_empty()))
+        .for_each(|(protocol, channel, nets)| {
+            out.push_str(&format!("\nprotocol static {protocol} {{\n    {channel};\n"));
+            nets.iter().for_each(|net| out.push_str(&format!("    route {net} via {};\n", quoted(interface))));
+            out.push_str("}\n");
+        });
+    out
+}
+
+// a drop-in for the systemd-networkd .network file of the interface, one [Route] section per network
+pub fn networkd_routes(report: &Report, interface: &str, table: u32) -> String {
+    let mut out = format!("# generated by wgrouter: {} route(s) via {interface} in table {table}\n\
+        # save as /etc/systemd/network/{interface}.network.d/wgrouter.conf (next to the .network file matching {interface}),\n\
+        # then run networkctl reload\n", report.networks.len());
+    report.networks.iter().for_each(|net| out.push_str(&format!("\n[Route]\nDestination={net}\nTable={table}\n")));
+    out
+}
+
+// the routes of a NetworkManager connection, as the [ipv4] and [ipv6] sections of its keyfile (route1=, route2=, ...),
+// or as the nmcli commands setting ipv4.routes and ipv6.routes and bringing it up again; either way they replace the
+// routes the connection had
+pub fn networkmanager_routes(report: &Report, connection: &str, nmcli: bool) -> String {
+    let (v4, v6) = by_family(&report.networks);
+    if nmcli {
+        let join = |nets: &[&String]| shell_quoted(&nets.iter().map(|net| net.as_str()).collect::<Vec<_>>().join(", "));
+        let connection = shell_quoted(connection);
+        return format!("nmcli connection modify {connection} ipv4.routes {} ipv6.routes {}\nnmcli connection up {connection}\n", join(&v4), join(&v6));
+    }
+    let mut out = format!("# generated by wgrouter: {} route(s) for the keyfile of connection {connection}, in place of its route lines\n", report.networks.len());
+    [("ipv4", v4), ("ipv6", v6)].iter().filter(|(_, nets)| !nets.is_empty()).for_each(|(section, nets)| {
+        out.push_str(&format!("\n[{section}]\n"));
+        nets.iter().enumerate().for_each(|(n, net)| out.push_str(&format!("route{}={net}\n", n + 1)));
+    });
+    out
+}
+
+// upstream lines for AdGuard Home (Settings / DNS settings / Upstream DNS servers, or upstream_dns_file)
+pub fn adguard_upstreams(report: &Report, resolver: &str) -> String {
+    report.tracked_domains().iter().map(|domain| format!("[/{domain}/]{resolver}\n")).collect()
+}
+
+// conditional forwarding section of the Blocky config
+pub fn blocky_conditional(report: &Report, resolver: &str) -> String {
+    let mut out = "conditional:\n  mapping:\n".to_string();
+    report.tracked_domains().iter().for_each(|domain| out.push_str(&format!("    {}: {}\n", quoted(domain), quoted(resolver))));
+    out
+}
+
+// forward-zone clauses for unbound.conf; unbound views cannot hold forward zones, so these apply to all clients
+pub fn unbound_forward_zones(report: &Report, resolver: &str) -> String {
+    report.tracked_domains()
+        .iter()
+        .map(|domain| format!("forward-zone:\n    name: {}\n    forward-addr: {resolver}\n\n", quoted(&format!("{domain}."))))
+        .collect()
+}
+
+// policy rule for Knot Resolver's kresd.conf
+pub fn knot_forward_policy(report: &Report, resolver: &str) -> String {
+    let domains = report.tracked_domains().iter().map(|domain| format!("    {},\n", quoted(domain))).collect::<String>();
+    format!("policy.add(policy.suffix(policy.FORWARD({{{}}}), policy.todnames({{\n{domains}}})))\n", quoted(resolver))
+}
+
+// routes the networks from the vars file via the tunnel; wgrouter_interface defaults to wg0
+pub const ANSIBLE_TASKS: &str = r#"---
+# generated by wgrouter, include with the vars file produced by --format ansible
+- name: Route wgrouter networks via the tunnel
+  ansible.builtin.command: "ip route replace {{ item }} dev {{ wgrouter_interface | default('wg0') }}"
+  loop: "{{ wgrouter_networks }}"
+  become: true
+  changed_when: false
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        Report {
+            networks: vec!["1.1.0.0/16".to_string(), "93.184.0.0/16".into()],
+            hosts: BTreeMap::from([
+                ("example.com".to_string(), BTreeSet::from(["93.184.216.34".to_string()])),
+                ("one.one".to_string(), BTreeSet::from(["1.1.1.1".to_string()])),
+                ("skipped.net".to_string(), BTreeSet::from(["8.8.8.8".to_string()])),
+            ]),
+            tags: BTreeMap::from([
+                ("site".to_string(), BTreeSet::from(["example.com".to_string(), "skipped.net".into()])),
+                ("sniff".to_string(), BTreeSet::from(["one.one".to_string(), "unresolved.org".into()])),
+            ]),
+            ..Report::default()
+        }
+    }
+
+    #[test]
+    fn test_tag_networks() {
+        assert_eq!(report().tag_networks(), BTreeMap::from([
+            ("site".to_string(), vec!["93.184.0.0/16".to_string()]),
+            ("sniff".to_string(), vec!["1.1.0.0/16".to_string()]),
+        ]));
+        let mut dual = report();
+        dual.networks.push("2606:2800:220::/48".to_string());
+        dual.hosts.insert("example.com".to_string(), BTreeSet::from(["93.184.216.34".to_string(), "2606:2800:220:1::1".into()]));
+        assert_eq!(dual.tag_networks()["site"], vec!["93.184.0.0/16", "2606:2800:220::/48"]);
+    }
+
+    #[test]
+    fn test_ansible_vars() {
+        assert_eq!(ansible_vars(&report()), r#"---
+# generated by wgrouter
+wgrouter_networks:
+  - "1.1.0.0/16"
+  - "93.184.0.0/16"
+wgrouter_allowed_ips: "AllowedIPs = 1.1.0.0/16, 93.184.0.0/16"
+wgrouter_hosts:
+  "example.com":
+    - "93.184.216.34"
+  "one.one":
+    - "1.1.1.1"
+  "skipped.net":
+    - "8.8.8.8"
+wgrouter_groups:
+  "site":
+    - "93.184.0.0/16"
+  "sniff":
+    - "1.1.0.0/16"
+"#);
+        assert_eq!(ansible_vars(&Report::default()), r#"---
+# generated by wgrouter
+wgrouter_networks: []
+wgrouter_allowed_ips: "AllowedIPs = "
+wgrouter_hosts: {}
+wgrouter_groups: {}
+"#);
+        let weighted = Report { weights: BTreeMap::from([("1.1.0.0/16".to_string(), 4120), ("93.184.0.0/16".to_string(), 0)]), ..report() };
+        assert!(ansible_vars(&weighted).ends_with("wgrouter_weights:\n  \"1.1.0.0/16\": 4120\n  \"93.184.0.0/16\": 0\n"));
+    }
+
+    #[test]
+    fn test_tracked_domains() {
+        let mut report = report();
+        ["cdn.example.com", "Static.One.One:443", "93.184.216.34", "a.b.example.org", "[2001:db8::1]:443", "198.51.100.0/24"].iter().for_each(|h| {
+            report.hosts.insert(h.to_string(), BTreeSet::new());
+        });
+        assert_eq!(report.tracked_domains(), vec!["a.b.example.org", "example.com", "one.one", "skipped.net"]);
+    }
+
+    #[test]
+    fn test_dns_steering() {
+        assert_eq!(adguard_upstreams(&report(), "10.8.0.1"), "[/example.com/]10.8.0.1\n[/one.one/]10.8.0.1\n[/skipped.net/]10.8.0.1\n");
+        assert_eq!(blocky_conditional(&report(), "10.8.0.1"),
+            "conditional:\n  mapping:\n    \"example.com\": \"10.8.0.1\"\n    \"one.one\": \"10.8.0.1\"\n    \"skipped.net\": \"10.8.0.1\"\n");
+    }
+
+    #[test]
+    fn test_resolver_forwarding() {
+        assert!(unbound_forward_zones(&report(), "10.8.0.1@53").starts_with("forward-zone:\n    name: \"example.com.\"\n    forward-addr: 10.8.0.1@53\n\nforward-zone:\n"));
+        assert_eq!(unbound_forward_zones(&Report::default(), "10.8.0.1"), "");
+        assert_eq!(knot_forward_policy(&report(), "10.8.0.1"),
+            "policy.add(policy.suffix(policy.FORWARD({\"10.8.0.1\"}), policy.todnames({\n    \"example.com\",\n    \"one.one\",\n    \"skipped.net\",\n})))\n");
+    }
+
+    #[test]
+    fn test_cloud_rules() {
+        assert_eq!(aws_egress_rules(&report(), "wgrouter")[0]["IpRanges"][1], json!({"CidrIp": "93.184.0.0/16", "Description": "wgrouter"}));
+        assert_eq!(gcp_firewall_rule(&report(), "vpn-egress"), r#"name: "vpn-egress"
+network: global/networks/default
+direction: EGRESS
+priority: 1000
+allowed:
+  - IPProtocol: all
+destinationRanges:
+  - "1.1.0.0/16"
+  - "93.184.0.0/16"
+"#);
+        let azure = azure_nsg_rules(&report(), "vpn-egress");
+        assert_eq!(azure[0]["properties"]["destinationAddressPrefixes"], json!(["1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(azure[0]["properties"]["direction"], json!("Outbound"));
+        assert_eq!(azure.as_array().map(Vec::len), Some(1));
+
+        let dual = Report { networks: vec!["1.1.0.0/16".to_string(), "2606:2800:220::/48".into()], ..report() };
+        assert_eq!(aws_egress_rules(&dual, "wgrouter"), json!([{
+            "IpProtocol": "-1",
+            "IpRanges": [{"CidrIp": "1.1.0.0/16", "Description": "wgrouter"}],
+            "Ipv6Ranges": [{"CidrIpv6": "2606:2800:220::/48", "Description": "wgrouter"}],
+        }]));
+        assert!(aws_egress_rules(&report(), "wgrouter")[0].get("Ipv6Ranges").is_none());
+        assert!(gcp_firewall_rule(&dual, "vpn-egress").ends_with(r#"  - "1.1.0.0/16"
+---
+name: "vpn-egress-v6"
+network: global/networks/default
+direction: EGRESS
+priority: 1000
+allowed:
+  - IPProtocol: all
+destinationRanges:
+  - "2606:2800:220::/48"
+"#));
+        let azure = azure_nsg_rules(&dual, "vpn-egress");
+        assert_eq!(azure[1]["name"], json!("vpn-egress-v6"));
+        assert_eq!(azure[1]["properties"]["destinationAddressPrefixes"], json!(["2606:2800:220::/48"]));
+    }
+
+    #[test]
+    fn test_ip_rules() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        let clients = ["192.168.1.20".parse().unwrap(), "fd00::/64".parse().unwrap()];
+        assert_eq!(ip_rules(&report, &clients, "wg0", 100, 1000), "#!/bin/sh
+# generated by wgrouter: traffic of 2 client(s) to 2 network(s) goes via wg0
+ip route replace 93.184.0.0/16 dev wg0 table 100
+ip -6 route replace 2606:2800:220::/48 dev wg0 table 100
+ip rule del from 192.168.1.20/32 lookup 100 priority 1000 2>/dev/null
+ip rule add from 192.168.1.20/32 lookup 100 priority 1000
+ip -6 rule del from fd00::/64 lookup 100 priority 1000 2>/dev/null
+ip -6 rule add from fd00::/64 lookup 100 priority 1000
+");
+    }
+
+    #[test]
+    fn test_nft_sets() {
+        assert_eq!(nft_sets(&report(), "filter", "vpn", false), "table inet filter {
+    set vpn {
+        type ipv4_addr
+        flags interval
+        elements = { 1.1.0.0/16, 93.184.0.0/16 }
+    }
+    set vpn_v6 {
+        type ipv6_addr
+        flags interval
+    }
+}
+");
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(nft_sets(&report, "wgrouter", "wgrouter", true),
+            "add element inet wgrouter wgrouter { 93.184.0.0/16 }\nadd element inet wgrouter wgrouter_v6 { 2606:2800:220::/48 }\n");
+    }
+
+    #[test]
+    fn test_json_document() {
+        let report = Report {
+            unresolved: BTreeMap::from([("gone.example".to_string(), "no record found".to_string())]),
+            warnings: vec!["skipping 10.0.0.0/8, it would capture a connection to 10.1.2.3:22".to_string()],
+            ..report()
+        };
+        let document = json_document(&report);
+        assert_eq!(document["schema"], json!(JSON_SCHEMA));
+        assert_eq!(document["networks"], json!(["1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(document["hosts"]["example.com"], json!({"addresses": ["93.184.216.34"], "networks": ["93.184.0.0/16"]}));
+        assert_eq!(document["unresolved"], json!({"gone.example": "no record found"}));
+        assert_eq!(document["warnings"][0], json!("skipping 10.0.0.0/8, it would capture a connection to 10.1.2.3:22"));
+        assert_eq!(document["groups"]["sniff"], json!(["1.1.0.0/16"]));
+        assert_eq!(document.get("weights"), None);
+        assert_eq!(document.get("unicode"), None);
+        assert_eq!(document.get("cnames"), None);
+
+        assert_eq!(unresolved_document(&report), json!({"schema": JSON_SCHEMA, "unresolved": {"gone.example": "no record found"}}));
+        assert_eq!(unresolved_list(&report), "# hosts that could not be resolved, give this file as an input to try them again\n\
+            gone.example  # no record found\n");
+
+        let mut report = report;
+        report.unresolved.insert("xn--e1afmkfd.xn--p1ai".to_string(), "no record found".to_string());
+        assert_eq!(json_document(&report)["unicode"], json!({"xn--e1afmkfd.xn--p1ai": "пример.рф"}));
+        report.cnames.insert("example.com".to_string(), vec!["example.com.edgekey.net".to_string(), "e1234.a.akamaiedge.net".into()]);
+        assert_eq!(json_document(&report)["cnames"], json!({"example.com": ["example.com.edgekey.net", "e1234.a.akamaiedge.net"]}));
+    }
+
+    #[test]
+    fn test_upgrade_document() {
+        // as version 1 wrote them
+        let document = json!({"schema": 1, "networks": ["93.184.0.0/16"], "allowed_ips": "AllowedIPs = 93.184.0.0/16",
+            "hosts": {"example.com": ["93.184.216.34", "198.51.100.7"]}, "unresolved": {"gone.example": "no record found"}, "groups": {}, "warnings": []});
+        let upgraded = upgrade_document(document).unwrap();
+        assert_eq!(upgraded["schema"], json!(JSON_SCHEMA));
+        assert_eq!(upgraded["hosts"], json!({"example.com": {"addresses": ["198.51.100.7", "93.184.216.34"], "networks": ["93.184.0.0/16"]}}));
+        assert_eq!(upgraded["unresolved"], json!({"gone.example": "no record found"}));
+        assert_eq!(upgrade_document(json!({"schema": 1, "unresolved": {}})), Ok(json!({"schema": 2, "unresolved": {}})));
+
+        let current = json_document(&report());
+        assert_eq!(upgrade_document(current.clone()), Ok(current));
+        assert_eq!(upgrade_document(json!({"schema": 3})).unwrap_err(), "the document has schema 3, this version only understands up to 2");
+        assert!(upgrade_document(json!({"log": {}})).is_err());
+    }
+
+    #[test]
+    fn test_ip_route_script() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(ip_route_script(&report, "wg0", 100, false), "#!/bin/sh
+# generated by wgrouter: adds 2 route(s) via wg0 in table 100
+set -e
+ip route add 93.184.0.0/16 dev wg0 table 100
+ip -6 route add 2606:2800:220::/48 dev wg0 table 100
+");
+        assert_eq!(ip_route_script(&report, "wg0", 100, true).lines().nth(2), Some("ip route del 93.184.0.0/16 dev wg0 table 100 2>/dev/null"));
+        assert!(ip_route_script(&report, "vpn; rm -rf /", 7, false).contains(" dev 'vpn; rm -rf /' table 7\n"));
+        assert_eq!(shell_quoted("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_windows_routes() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(windows_routes(&report, "wg0", false), "@echo off
+rem generated by wgrouter: adds 2 route(s) via wg0
+rem replace IF_INDEX with the index of the interface, as 'route print' lists it
+set IF=IF_INDEX
+route add 93.184.0.0 mask 255.255.0.0 0.0.0.0 if %IF%
+netsh interface ipv6 add route 2606:2800:220::/48 interface=%IF% store=active
+");
+        let powershell = windows_routes(&report, "Bob's VPN", true);
+        assert!(powershell.contains("(Get-NetAdapter -Name 'Bob''s VPN').ifIndex\n$IfIndex = IF_INDEX\n"));
+        assert!(powershell.ends_with("\nNew-NetRoute -DestinationPrefix 2606:2800:220::/48 -InterfaceIndex $IfIndex -PolicyStore ActiveStore\n"));
+    }
+
+    #[test]
+    fn test_pf() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(pf_table(&report, "vpn"), "# generated by wgrouter: 2 network(s) for the pf table <vpn>\n93.184.0.0/16\n2606:2800:220::/48\n");
+        let rules = pf_rules("vpn", "/etc/pf.vpn", "utun4");
+        assert!(rules.contains("\ntable <vpn> persist file \"/etc/pf.vpn\"\n"));
+        assert!(rules.contains("\npass out quick on ! utun4 route-to (utun4) inet from any to <vpn> keep state\n"));
+    }
+
+    #[test]
+    fn test_bird_static() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(bird_static(&report, "wg0", "wgrouter"), "# generated by wgrouter: 2 route(s) via wg0
+
+protocol static wgrouter {
+    ipv4;
+    route 93.184.0.0/16 via \"wg0\";
+}
+
+protocol static wgrouter_v6 {
+    ipv6;
+    route 2606:2800:220::/48 via \"wg0\";
+}
+");
+        assert!(bird_static(&Report::default(), "wg0", "vpn").ends_with("\nprotocol static vpn {\n    ipv4;\n}\n"));
+    }
+
+    #[test]
+    fn test_networkd_routes() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        let dropin = networkd_routes(&report, "wg0", 100);
+        assert!(dropin.starts_with("# generated by wgrouter: 2 route(s) via wg0 in table 100\n# save as /etc/systemd/network/wg0.network.d/wgrouter.conf"));
+        assert!(dropin.ends_with("\n\n[Route]\nDestination=93.184.0.0/16\nTable=100\n\n[Route]\nDestination=2606:2800:220::/48\nTable=100\n"));
+    }
+
+    #[test]
+    fn test_networkmanager_routes() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "1.1.0.0/16".into(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(networkmanager_routes(&report, "wg0", false), "# generated by wgrouter: 3 route(s) for the keyfile of connection wg0, in place of its route lines
+
+[ipv4]
+route1=93.184.0.0/16
+route2=1.1.0.0/16
+
+[ipv6]
+route1=2606:2800:220::/48
+");
+        assert_eq!(networkmanager_routes(&report, "My VPN", true), "nmcli connection modify 'My VPN' ipv4.routes '93.184.0.0/16, 1.1.0.0/16' ipv6.routes 2606:2800:220::/48
+nmcli connection up 'My VPN'
+");
+        assert!(networkmanager_routes(&Report::default(), "wg0", true).starts_with("nmcli connection modify wg0 ipv4.routes '' ipv6.routes ''\n"));
+    }
+
+    #[test]
+    fn test_ipsets() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(ipsets(&report, "vpn", false), "ipset create vpn hash:net family inet -exist
+ipset flush vpn
+ipset add vpn 93.184.0.0/16 -exist
+ipset create vpn_v6 hash:net family inet6 -exist
+ipset flush vpn_v6
+ipset add vpn_v6 2606:2800:220::/48 -exist
+");
+        assert!(ipsets(&report, "vpn", true).starts_with("create vpn hash:net family inet -exist\nflush vpn\nadd vpn 93.184.0.0/16 -exist\n"));
+    }
+
+    #[test]
+    fn test_openvpn_routes() {
+        let report = Report { networks: vec!["93.184.0.0/16".to_string(), "203.0.113.128/25".into(), "2606:2800:220::/48".into()], ..Report::default() };
+        assert_eq!(openvpn_routes(&report, false), "route 93.184.0.0 255.255.0.0\nroute 203.0.113.128 255.255.255.128\nroute-ipv6 2606:2800:220::/48\n");
+        assert_eq!(openvpn_routes(&report, true).lines().next(), Some("push \"route 93.184.0.0 255.255.0.0\""));
+    }
+
+    #[test]
+    fn test_terraform() {
+        let vars = terraform_vars(&report());
+        assert_eq!(vars["wgrouter_networks"], json!(["1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(vars["wgrouter_hosts"]["example.com"], json!(["93.184.216.34"]));
+        assert_eq!(vars["wgrouter_groups"], json!({"site": ["93.184.0.0/16"], "sniff": ["1.1.0.0/16"]}));
+        assert_eq!(vars.get("wgrouter_weights"), None);
+        let weighted = Report { weights: BTreeMap::from([("1.1.0.0/16".to_string(), 4120)]), ..report() };
+        assert_eq!(terraform_vars(&weighted)["wgrouter_weights"], json!({"1.1.0.0/16": 4120}));
+        assert_eq!(terraform_external(&report()), json!({
+            "networks": r#"["1.1.0.0/16","93.184.0.0/16"]"#,
+            "allowed_ips": "AllowedIPs = 1.1.0.0/16, 93.184.0.0/16",
+            "hosts": r#"{"example.com":["93.184.216.34"],"one.one":["1.1.1.1"],"skipped.net":["8.8.8.8"]}"#,
+            "groups": r#"{"site":["93.184.0.0/16"],"sniff":["1.1.0.0/16"]}"#,
+        }));
+    }
+}
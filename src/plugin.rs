@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use serde_json::{json, Value};
+use crate::dns::StrResult;
+use crate::input::Hosts;
+use crate::output::Report;
+use crate::routes::allowed_ips_line;
+
+// bumped when a request or response changes incompatibly
+pub const PROTOCOL_VERSION: u64 = 1;
+
+// a plugin is an executable in the plugin directory named input-<name> or output-<name>; it gets one JSON request on
+// stdin and answers with one JSON document on stdout, anything on stderr is passed through as diagnostics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // {"protocol": 1, "kind": "input", "file": path or null} -> {"hosts": [...], "candidates": [...]}
+    Input,
+    // {"protocol": 1, "kind": "output", "networks": [...], "allowed_ips": ..., "hosts": {...}, "groups": {...}} -> {"output": text}
+    Output,
+}
+
+impl Kind {
+    fn prefix(self) -> &'static str {
+        match self {
+            Kind::Input => "input-",
+            Kind::Output => "output-",
+        }
+    }
+}
+
+// dir, or $XDG_CONFIG_HOME/wgrouter/plugins (~/.config/wgrouter/plugins)
+pub fn plugin_dir(dir: Option<&Path>) -> Option<PathBuf> {
+    dir.map(Path::to_path_buf).or_else(|| {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("wgrouter/plugins"))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// names of the plugins of this kind, sorted
+pub fn available(dir: &Path, kind: Kind) -> Vec<String> {
+    let mut names = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.strip_prefix(kind.prefix())).map(String::from))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+pub fn find(dir: &Path, kind: Kind, name: &str) -> Option<PathBuf> {
+    let path = dir.join(format!("{}{name}", kind.prefix()));
+    (!name.contains(['/', '\\']) && is_executable(&path)).then_some(path)
+}
+
+fn run(plugin: &Path, request: &Value) -> StrResult<Value> {
+    let name = plugin.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let mut child = Command::new(plugin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run plugin {name}: {e}"))?;
+    // a plugin that exits without reading its request is reported by its status below, not as a broken pipe
+    let _ = child.stdin.take().map(|mut stdin| stdin.write_all(request.to_string().as_bytes()));
+    let output = child.wait_with_output().map_err(|e| format!("could not run plugin {name}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("plugin {name} failed: {}", output.status));
+    }
+    let response = serde_json::from_slice::<Value>(&output.stdout).map_err(|e| format!("plugin {name} answered with invalid JSON: {e}"))?;
+    match response.get("error").and_then(Value::as_str) {
+        Some(error) => Err(format!("plugin {name}: {error}")),
+        None => Ok(response),
+    }
+}
+
+fn string_set(response: &Value, member: &str) -> StrResult<HashSet<String>> {
+    match response.get(member) {
+        None | Some(Value::Null) => Ok(HashSet::new()),
+        Some(list) => list
+            .as_array()
+            .and_then(|items| items.iter().map(|v| v.as_str().map(|s| s.trim().to_ascii_lowercase())).collect::<Option<HashSet<_>>>())
+            .ok_or(format!("'{member}' must be an array of strings")),
+    }
+}
+
+// file is none for plugins that are a source of their own, e.g. one that asks a router for its connections
+pub fn hostnames_from_plugin(plugin: &Path, file: Option<&str>) -> StrResult<Hosts> {
+    let response = run(plugin, &json!({"protocol": PROTOCOL_VERSION, "kind": "input", "file": file}))?;
+    let hosts = Hosts { confirmed: string_set(&response, "hosts")?, candidates: string_set(&response, "candidates")?, ..Hosts::default() };
+    Ok(Hosts::default().merge(hosts))
+}
+
+pub fn render_with_plugin(plugin: &Path, report: &Report) -> StrResult<String> {
+    let response = run(plugin, &json!({
+        "protocol": PROTOCOL_VERSION,
+        "kind": "output",
+        "networks": report.networks,
+        "allowed_ips": allowed_ips_line(&report.networks),
+        "hosts": report.hosts,
+        "groups": report.tag_networks(),
+    }))?;
+    response.get("output").and_then(Value::as_str).map(String::from).ok_or("plugin answered without an 'output' string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[cfg(unix)]
+    fn plugin(dir: &Path, name: &str, script: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_plugins() {
+        let dir = std::env::temp_dir().join(format!("wgrouter-plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = plugin(&dir, "input-pcapng", r#"grep -q '"file":"cap.pcapng"' && echo '{"hosts": ["Example.com"], "candidates": ["example.com", "cdn.example.net"]}'"#);
+        plugin(&dir, "output-mikrotik", r#"sed 's/.*"networks":\["\([^"]*\)".*/{"output": "\/ip route add dst-address=\1"}/'"#);
+        plugin(&dir, "output-broken", "echo 'not json'");
+        plugin(&dir, "output-failing", r#"echo '{"error": "no router given"}'"#);
+        std::fs::write(dir.join("input-notes"), "not executable").unwrap();
+
+        assert_eq!(available(&dir, Kind::Input), vec!["pcapng"]);
+        assert_eq!(available(&dir, Kind::Output), vec!["broken", "failing", "mikrotik"]);
+        assert_eq!(find(&dir, Kind::Input, "pcapng"), Some(input.clone()));
+        assert_eq!(find(&dir, Kind::Input, "notes"), None);
+        assert_eq!(find(&dir, Kind::Output, "../input-pcapng"), None);
+
+        let hosts = hostnames_from_plugin(&input, Some("cap.pcapng")).unwrap();
+        assert_eq!(hosts, Hosts { confirmed: HashSet::from(["example.com".into()]), candidates: HashSet::from(["cdn.example.net".into()]), ..Hosts::default() });
+        assert!(hostnames_from_plugin(&input, None).unwrap_err().contains("failed"));
+
+        let report = Report {
+            networks: vec!["93.184.0.0/16".to_string()],
+            hosts: BTreeMap::from([("example.com".to_string(), BTreeSet::from(["93.184.216.34".to_string()]))]),
+            ..Report::default()
+        };
+        assert_eq!(render_with_plugin(&dir.join("output-mikrotik"), &report), Ok("/ip route add dst-address=93.184.0.0/16".to_string()));
+        assert!(render_with_plugin(&dir.join("output-broken"), &report).unwrap_err().contains("invalid JSON"));
+        assert_eq!(render_with_plugin(&dir.join("output-failing"), &report), Err("plugin output-failing: no router given".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
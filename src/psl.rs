@@ -0,0 +1,76 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+// https://publicsuffix.org/list/public_suffix_list.dat as published, to be replaced by a newer copy from time to time
+const LIST: &str = include_str!("public_suffix_list.dat");
+
+// the rules of the ICANN section, in ASCII: the private one lists names under which others publish (github.io,
+// cloudfront.net) but whose own zones hold them, so their nameservers are those of the registrable domain above
+fn rules() -> &'static HashSet<String> {
+    static RULES: OnceLock<HashSet<String>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        LIST.lines()
+            .map(str::trim)
+            .skip_while(|line| *line != "// ===BEGIN ICANN DOMAINS===")
+            .take_while(|line| *line != "// ===END ICANN DOMAINS===")
+            .filter(|line| !line.is_empty() && !line.starts_with("//"))
+            .filter_map(|rule| {
+                let (mark, name) = rule.strip_prefix('!').map(|name| ("!", name)).unwrap_or(("", rule));
+                Some(format!("{mark}{}", idna::domain_to_ascii(name).ok()?))
+            })
+            .collect()
+    })
+}
+
+// how many trailing labels of the name are its public suffix: that of the exception or the longest rule matching it,
+// the last label when none does
+fn suffix_labels(labels: &[&str]) -> usize {
+    let rules = rules();
+    (0..labels.len())
+        .find_map(|i| {
+            let name = labels[i..].join(".");
+            let wildcard = (i + 1 < labels.len()).then(|| format!("*.{}", labels[i + 1..].join(".")));
+            if rules.contains(&format!("!{name}")) {
+                Some(labels.len() - i - 1)
+            } else if rules.contains(&name) || wildcard.is_some_and(|wildcard| rules.contains(&wildcard)) {
+                Some(labels.len() - i)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(1)
+}
+
+// the domain one label below the public suffix of the host, in lower case (shop.example.co.uk -> example.co.uk); none
+// for a host that is a public suffix itself
+pub fn registrable_domain(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let labels = host.split('.').collect::<Vec<_>>();
+    let len = suffix_labels(&labels) + 1;
+    (labels.len() >= len).then(|| labels[labels.len() - len..].join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("shop.example.co.uk"), Some("example.co.uk".to_string()));
+        assert_eq!(registrable_domain("WWW.Example.com."), Some("example.com".to_string()));
+        assert_eq!(registrable_domain("example.com"), Some("example.com".to_string()));
+        assert_eq!(registrable_domain("a.b.example.com.au"), Some("example.com.au".to_string()));
+        // wildcards and their exceptions
+        assert_eq!(registrable_domain("a.b.kawasaki.jp"), Some("a.b.kawasaki.jp".to_string()));
+        assert_eq!(registrable_domain("www.city.kawasaki.jp"), Some("city.kawasaki.jp".to_string()));
+        assert_eq!(registrable_domain("www.ck"), Some("www.ck".to_string()));
+        // rules given in their own script
+        assert_eq!(registrable_domain("shop.example.xn--p1ai"), Some("example.xn--p1ai".to_string()));
+        // the private section is left out
+        assert_eq!(registrable_domain("d111111abcdef8.cloudfront.net"), Some("cloudfront.net".to_string()));
+        // not in the list: the last label is the suffix
+        assert_eq!(registrable_domain("a.b.internal"), Some("b.internal".to_string()));
+        assert_eq!(registrable_domain("co.uk"), None);
+        assert_eq!(registrable_domain("com"), None);
+    }
+}
@@ -0,0 +1,66 @@
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use crate::aggregate::Aggregation;
+use crate::host::Host;
+use crate::input::har::HarOptions;
+use crate::input::{hostnames_from_file, Hosts};
+use crate::routes::{self, allowed_ips_line, networks, resolve_hosts, PassiveDns, Resolved, DEFAULT_PREFIX_LEN, DEFAULT_PREFIX_LEN6};
+
+// Python bindings: extract_hosts -> resolve -> aggregate -> render, the same pipeline as the CLI in separate steps
+
+fn py_err(e: String) -> PyErr {
+    PyRuntimeError::new_err(e)
+}
+
+fn sorted(hosts: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut hosts = hosts.into_iter().collect::<Vec<_>>();
+    hosts.sort();
+    hosts
+}
+
+// (confirmed, candidates) hostnames from capture files of any supported format
+#[pyfunction]
+#[pyo3(signature = (files, scan_bodies = false, mine_headers = false, lenient = false))]
+fn extract_hosts(files: Vec<String>, scan_bodies: bool, mine_headers: bool, lenient: bool) -> PyResult<(Vec<String>, Vec<String>)> {
+    let opts = HarOptions { scan_bodies, mine_headers, lenient, ..Default::default() };
+    let hosts = files
+        .iter()
+        .map(|file| hostnames_from_file(file, &opts))
+        .try_fold(Hosts::default(), |acc, hosts| hosts.map(|hosts| acc.merge(hosts)))
+        .map_err(py_err)?;
+    Ok((sorted(hosts.confirmed), sorted(hosts.candidates)))
+}
+
+// ({host: {ip, ...}}, {host: error}); DNS runs without holding the GIL
+#[pyfunction]
+#[pyo3(signature = (hosts, ipv6 = false))]
+fn resolve(py: Python<'_>, hosts: Vec<String>, ipv6: bool) -> Resolved {
+    py.allow_threads(|| resolve_hosts(hosts.iter(), &PassiveDns::new(), ipv6))
+}
+
+#[pyfunction]
+#[pyo3(signature = (ips, prefix_len = DEFAULT_PREFIX_LEN as u64, slack = 0, ipv6 = false, prefix_len6 = DEFAULT_PREFIX_LEN6 as u64, max_networks = None))]
+fn aggregate(ips: Vec<String>, prefix_len: u64, slack: u64, ipv6: bool, prefix_len6: u64, max_networks: Option<usize>) -> PyResult<Vec<String>> {
+    let aggregation = Aggregation {
+        prefix_len: routes::prefix_len(prefix_len).map_err(py_err)?,
+        prefix_len6: routes::prefix_len6(prefix_len6).map_err(py_err)?,
+        slack,
+        ipv6,
+        max_networks,
+    };
+    networks(ips.into_iter(), &aggregation, &Host::default()).map(|(nets, _)| nets).map_err(py_err)
+}
+
+#[pyfunction]
+fn render(networks: Vec<String>) -> String {
+    allowed_ips_line(&networks)
+}
+
+#[pymodule]
+fn wgrouter(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extract_hosts, m)?)?;
+    m.add_function(wrap_pyfunction!(resolve, m)?)?;
+    m.add_function(wrap_pyfunction!(aggregate, m)?)?;
+    m.add_function(wrap_pyfunction!(render, m)?)?;
+    Ok(())
+}
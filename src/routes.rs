@@ -0,0 +1,490 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+use ipnetwork::IpNetwork;
+use crate::aggregate::{aggregate_weighted, compress, exclude, widen, Aggregation, Weights};
+use crate::bounds::AddressBounds;
+use crate::dns::*;
+use crate::host::Host;
+#[cfg(feature = "native")]
+use crate::clock::Rng;
+use std::time::Duration;
+
+pub const DEFAULT_PREFIX_LEN: u8 = 16;
+pub const MIN_PREFIX_LEN: u8 = 8;
+pub const MAX_PREFIX_LEN: u8 = 32;
+// a /48 is what a site usually gets, so one per site a CDN serves from
+pub const DEFAULT_PREFIX_LEN6: u8 = 48;
+pub const MIN_PREFIX_LEN6: u8 = 16;
+pub const MAX_PREFIX_LEN6: u8 = 128;
+
+// from /32 (every address routed on its own) to /8 (very aggressive aggregation)
+pub fn prefix_len(len: u64) -> StrResult<u8> {
+    u8::try_from(len)
+        .ok()
+        .filter(|len| (MIN_PREFIX_LEN..=MAX_PREFIX_LEN).contains(len))
+        .ok_or(format!("prefix length must be between {MIN_PREFIX_LEN} and {MAX_PREFIX_LEN}, got {len}"))
+}
+
+pub fn prefix_len6(len: u64) -> StrResult<u8> {
+    u8::try_from(len)
+        .ok()
+        .filter(|len| (MIN_PREFIX_LEN6..=MAX_PREFIX_LEN6).contains(len))
+        .ok_or(format!("IPv6 prefix length must be between {MIN_PREFIX_LEN6} and {MAX_PREFIX_LEN6}, got {len}"))
+}
+
+pub type PassiveDns = HashMap<String, HashSet<String>>;
+
+pub type Resolved = (BTreeMap<String, BTreeSet<String>>, BTreeMap<String, String>);
+
+// host -> the CNAME targets its lookup went through, the final one last, for the hosts that have any
+pub type Chains = BTreeMap<String, Vec<String>>;
+
+// addresses that never leave the local network, so never go through the tunnel
+pub(crate) fn is_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_broadcast() || ip.is_private(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+// what is known without asking DNS: IP literals and networks (local ones need no route) and passively observed addresses
+fn known_addresses(host: &str, passive: &PassiveDns) -> Option<HashSet<String>> {
+    if let Some(net) = hostname_is_net(host) {
+        return Some(if is_local(&net.network()) { HashSet::new() } else { HashSet::from([net.to_string()]) });
+    }
+    let host = discard_port(host);
+    if let Some(ip) = hostname_is_ip(host) {
+        if is_local(&ip) {
+            Some(HashSet::new())
+        } else {
+            Some(HashSet::from([ip.to_string()]))
+        }
+    } else {
+        passive.get(host).cloned()
+    }
+}
+
+#[cfg(feature = "native")]
+pub fn resolve_hosts<'a>(hosts: impl Iterator<Item = &'a String>, passive: &PassiveDns, ipv6: bool) -> Resolved {
+    resolve_hosts_with(hosts, passive, ipv6, &Lookups::default(), &mut Rng::from_env(), || {})
+}
+
+// at most this many lookups in flight at a time: they wait on the network rather than the CPU, but a resolver flooded
+// with queries starts dropping them
+pub const DEFAULT_CONCURRENCY: usize = 16;
+pub const DEFAULT_JITTER: Duration = Duration::from_secs(30);
+
+// how the hosts are looked up: concurrency lookups at a time, each host samples times, the first at once and the others
+// at random moments within jitter, as round-robin CDNs answer each query with a few of their addresses; retry is for
+// queries that time out or fail; with an upstream, every query goes there, encrypted, instead of to the nameservers of
+// the host's domain; with dnssec, the answers are validated and hosts whose answers fail are unresolved; with
+// cname_widely, the final target of a host's CNAME chain is also asked of every server that may answer differently;
+// servers are those asked without an upstream
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lookups {
+    pub concurrency: usize,
+    pub samples: u32,
+    pub jitter: Duration,
+    pub retry: Retry,
+    pub upstream: Option<Upstream>,
+    pub dnssec: bool,
+    pub cname_widely: bool,
+    pub servers: Servers,
+}
+
+impl Default for Lookups {
+    fn default() -> Self {
+        Self { concurrency: DEFAULT_CONCURRENCY, samples: 1, jitter: DEFAULT_JITTER, retry: Retry::default(), upstream: None, dnssec: false, cname_widely: false, servers: Servers::Discovered }
+    }
+}
+
+// the lookups run side by side on the shared runtime, in the order they are due, so the samples waiting for their
+// moment do not hold up those due before them; each lookup gets a resolver of its own, so no sample is answered from
+// the cache of an earlier one; a host's answers are united, and it is unresolved only when no sample resolved it; done
+// is called as each host is known or each sample finished
+#[cfg(feature = "native")]
+pub fn resolve_hosts_with<'a>(hosts: impl Iterator<Item = &'a String>, passive: &PassiveDns, ipv6: bool, lookups: &Lookups, rng: &mut Rng, done: impl Fn())
+    -> Resolved {
+    resolve_hosts_chained(hosts, passive, ipv6, lookups, rng, done).0
+}
+
+// the same with the CNAME chains of the hosts looked up, the first a sample came back with; the upstreams answer with
+// the addresses only, so hosts resolved through them have none
+#[cfg(feature = "native")]
+pub fn resolve_hosts_chained<'a>(hosts: impl Iterator<Item = &'a String>, passive: &PassiveDns, ipv6: bool, lookups: &Lookups, rng: &mut Rng, done: impl Fn())
+    -> (Resolved, Chains) {
+    use futures::StreamExt;
+    let (known, unknown) = hosts.map(|host| (host, known_addresses(host, passive))).partition::<Vec<_>, _>(|(_, ips)| ips.is_some());
+    known.iter().for_each(|_| done());
+    let jitter = u64::try_from(lookups.jitter.as_millis()).unwrap_or(u64::MAX);
+    let mut due = Vec::new();
+    (0..unknown.len()).for_each(|n| {
+        due.push((n, Duration::ZERO));
+        (1..lookups.samples).for_each(|_| due.push((n, Duration::from_millis(rng.below(jitter)))));
+    });
+    due.sort_by_key(|(_, after)| *after);
+    let (unknown, done) = (&unknown, &done);
+    let answers = runtime().block_on(async {
+        let start = tokio::time::Instant::now();
+        futures::stream::iter(due)
+            .map(|(n, after)| async move {
+                tokio::time::sleep_until(start + after).await;
+                let host = discard_port(unknown[n].0);
+                let ips = match &lookups.upstream {
+                    #[cfg(feature = "remote")]
+                    Some(Upstream::Https(url)) => crate::doh::resolve_host_async(url, host, ipv6, &lookups.retry).await.map(|ips| (ips, Vec::new())),
+                    #[cfg(feature = "remote")]
+                    Some(Upstream::Tls { addr, name }) => crate::dot::resolve_host_async(*addr, name, host, ipv6, &lookups.retry).await.map(|ips| (ips, Vec::new())),
+                    _ => resolve_host_chained_async(host, &lookups.servers, ipv6, &lookups.retry, lookups.dnssec, lookups.cname_widely).await,
+                };
+                done();
+                (n, ips)
+            })
+            .buffer_unordered(lookups.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await
+    });
+    let mut united = vec![None::<StrResult<HashSet<String>>>; unknown.len()];
+    let mut chains = Chains::new();
+    answers.into_iter().for_each(|(n, answer)| {
+        let ips = answer.map(|(ips, chain)| {
+            if !chain.is_empty() {
+                chains.entry(unknown[n].0.clone()).or_insert(chain);
+            }
+            ips
+        });
+        united[n] = Some(match (united[n].take(), ips) {
+            (Some(Ok(mut before)), Ok(ips)) => {
+                before.extend(ips);
+                Ok(before)
+            }
+            (Some(Ok(before)), Err(_)) => Ok(before),
+            (_, ips) => ips,
+        });
+    });
+    let known = known.into_iter().map(|(host, ips)| (host.clone(), Ok(ips.unwrap_or_default())));
+    let looked_up = unknown.iter().zip(united).map(|((host, _), ips)| ((*host).clone(), ips.unwrap_or(Err(format!("{host} was not looked up")))));
+    (split_resolved(known.chain(looked_up)), chains)
+}
+
+// for environments without DNS (the browser): hosts that were not observed stay unresolved
+pub fn resolve_hosts_offline<'a>(hosts: impl Iterator<Item = &'a String>, passive: &PassiveDns) -> Resolved {
+    split_resolved(hosts.map(|host| {
+        let ips = known_addresses(host, passive).ok_or(format!("no address recorded for {host}"));
+        (host.clone(), ips)
+    }))
+}
+
+fn split_resolved(hosts_and_ips: impl Iterator<Item = (String, StrResult<HashSet<String>>)>) -> Resolved {
+    hosts_and_ips.fold(Resolved::default(), |(mut ok, mut failed), (host, ips)| {
+        match ips {
+            Ok(ips) => {
+                ok.insert(host, ips.into_iter().collect());
+            }
+            Err(e) => {
+                failed.insert(host, e);
+            }
+        }
+        (ok, failed)
+    })
+}
+
+// what to do with a network that would capture open connections of this host: drop it, so they stay outside the
+// tunnel, keep it anyway, or narrow it to the parts around them that still hold addresses asked for
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Conflict {
+    #[default]
+    Drop,
+    Keep,
+    Narrow,
+}
+
+// decides about a network and the addresses and ports of the connections it would capture
+pub type OnConflict<'a> = &'a dyn Fn(&IpNetwork, &[(IpAddr, u16)]) -> Conflict;
+
+// aggregated networks covering the ips, minus those that would capture an existing connection of this host; networks
+// among the ips (from host lists) are routed as they are; IPv6 addresses are skipped (with a warning) unless the
+// aggregation routes IPv6
+pub fn networks(ips: impl Iterator<Item = String>, aggregation: &Aggregation, host_util: &Host) -> StrResult<(Vec<String>, Vec<String>)> {
+    networks_with(ips, aggregation, host_util, &AddressBounds::new(), &Weights::new(), &|_, _| Conflict::Drop)
+}
+
+// networks, with the prefix length of bounded addresses kept within their range, both when widening an address and
+// when merging networks with slack (exact merges stay, as they route nothing more than the networks they merge), and
+// slack merges weighted by the traffic of the addresses; on_conflict decides about the networks that would capture a
+// connection, merges never do
+pub fn networks_with(ips: impl Iterator<Item = String>, aggregation: &Aggregation, host_util: &Host, bounds: &AddressBounds, weights: &Weights,
+    on_conflict: OnConflict) -> StrResult<(Vec<String>, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let mut skipped = HashSet::new();
+    let mut requested = Vec::new();
+    let nets = ips
+        .filter_map(|ip| {
+            let net = hostname_is_net(&ip);
+            let addr = match net.map(|net| Ok(net.network())).unwrap_or_else(|| ip.parse::<IpAddr>()) {
+                Ok(addr) => addr,
+                Err(e) => return Some(Err(format!("could not parse address {ip}: {e}"))),
+            };
+            match (aggregation.prefix_len_for(&addr), net) {
+                (Some(_), Some(net)) => {
+                    requested.push(net);
+                    Some(widen(addr, net.prefix()).map(|net| net.to_string()))
+                }
+                (Some(len), None) => {
+                    requested.extend(widen(addr, if addr.is_ipv4() { 32 } else { 128 }));
+                    Some(net_from_ip(&ip, bounds.get(&addr).map_or(len, |&(widest, narrowest)| len.clamp(widest, narrowest))))
+                }
+                (None, _) => {
+                    skipped.insert(addr);
+                    None
+                }
+            }
+        })
+        .collect::<StrResult<HashSet<String>>>()?
+        .into_iter()
+        .map(|net| net.parse::<IpNetwork>().map_err(|e| format!("could not parse network {net}: {e}")))
+        .collect::<StrResult<Vec<_>>>()?
+        .into_iter()
+        .flat_map(|net| {
+            let conns = host_util.dsts_in(&net);
+            let Some((ip, port)) = conns.first() else {
+                return vec![net];
+            };
+            let conflict = format!("host TCP connection to {ip}:{port} would fall into routed network {net}");
+            match on_conflict(&net, &conns) {
+                Conflict::Drop => {
+                    warnings.push(format!("{conflict}, ignoring it"));
+                    vec![]
+                }
+                Conflict::Keep => {
+                    warnings.push(format!("{conflict}, keeping it"));
+                    vec![net]
+                }
+                Conflict::Narrow => {
+                    let holes = conns.iter().filter_map(|(ip, _)| widen(*ip, if ip.is_ipv4() { 32 } else { 128 }).ok()).collect::<Vec<_>>();
+                    let parts = holes
+                        .iter()
+                        .fold(vec![net], |parts, hole| parts.into_iter().flat_map(|part| exclude(part, *hole)).collect())
+                        .into_iter()
+                        .filter(|part| requested.iter().any(|asked: &IpNetwork| part.contains(asked.network()) || asked.contains(part.network())))
+                        .collect::<Vec<_>>();
+                    let narrowed = parts.iter().map(ToString::to_string).collect::<Vec<_>>();
+                    warnings.push(match narrowed.is_empty() {
+                        true => format!("{conflict}, and nothing asked for is left around it"),
+                        false => format!("{conflict}, narrowing it to {}", narrowed.join(", ")),
+                    });
+                    parts
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let allowed = |supernet: &IpNetwork| {
+        host_util.contains_dst(&supernet.to_string()).is_none()
+            && bounds.iter().all(|(addr, &(widest, _))| supernet.prefix() >= widest || !supernet.contains(*addr))
+    };
+    let mut nets = aggregate_weighted(nets, aggregation.slack, allowed, weights);
+    if let Some(max) = aggregation.max_networks
+        && nets.len() > max {
+        nets = compress(nets, max, allowed, weights);
+        if nets.len() > max {
+            warnings.push(format!("could only merge down to {} networks, not {max}, without capturing a host connection or breaking a bound", nets.len()));
+        }
+    }
+    if !skipped.is_empty() {
+        warnings.push(format!("IPv6 routing is off, ignoring {} IPv6 address(es)", skipped.len()));
+    }
+    warnings.sort();
+    Ok((sorted_nets(nets.into_iter().map(|net| net.to_string()).collect())?, warnings))
+}
+
+// IPv4 networks first, then IPv6 ones, each in address order
+pub fn sorted_nets(nets: HashSet<String>) -> StrResult<Vec<String>> {
+    let sorted = nets
+        .into_iter()
+        .map(|net| net.parse::<IpNetwork>().map_err(|e| format!("could not parse network {net}: {e}")))
+        .collect::<StrResult<BTreeSet<_>>>()?;
+    Ok(sorted.into_iter().map(|net| net.to_string()).collect())
+}
+
+pub fn allowed_ips_line(nets: &[String]) -> String {
+    format!("AllowedIPs = {}", nets.join(", "))
+}
+
+// the bytes each host transferred, spread evenly over its addresses
+pub fn address_weights(hosts: &BTreeMap<String, BTreeSet<String>>, traffic: &HashMap<String, u64>) -> Weights {
+    let mut weights = Weights::new();
+    traffic.iter().filter_map(|(host, bytes)| hosts.get(host).filter(|ips| !ips.is_empty()).map(|ips| (ips, bytes))).for_each(|(ips, bytes)| {
+        ips.iter().filter_map(|ip| ip.parse::<IpAddr>().ok()).for_each(|ip| *weights.entry(ip).or_default() += bytes / ips.len() as u64);
+    });
+    weights
+}
+
+// the traffic of the addresses in each network
+pub fn network_weights(nets: &[String], weights: &Weights) -> BTreeMap<String, u64> {
+    nets.iter()
+        .filter_map(|net| net.parse::<IpNetwork>().ok().map(|parsed| (net, parsed)))
+        .map(|(net, parsed)| (net.clone(), weights.iter().filter(|(ip, _)| parsed.contains(**ip)).map(|(_, bytes)| bytes).sum()))
+        .collect()
+}
+
+pub fn net_from_ip(ip: &str, prefix_len: u8) -> StrResult<String> {
+    let ip = ip.parse::<IpAddr>().map_err(|e| format!("could not parse address {ip}: {e}"))?;
+    widen(ip, prefix_len).map(|net| net.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_networks() {
+        let host_util = Host::from_socket_listing("tcp ESTAB 0 0 10.0.0.2:40000 8.8.4.4:443");
+        let ips = ["8.8.8.8", "8.8.4.4", "1.1.1.1", "1.1.2.2"].map(String::from);
+        assert_eq!(networks(ips.clone().into_iter(), &Aggregation::default(), &host_util), Ok((
+            vec!["1.1.0.0/16".to_string()],
+            vec!["host TCP connection to 8.8.4.4:443 would fall into routed network 8.8.0.0/16, ignoring it".to_string()],
+        )));
+        assert_eq!(networks(ips.clone().into_iter(), &Aggregation { prefix_len: 24, ..Aggregation::default() }, &host_util).unwrap().0,
+            vec!["1.1.1.0/24", "1.1.2.0/24", "8.8.8.0/24"]);
+        // 1.1.0.0/22 would do for the 1.1.x.x addresses, but nothing wider that reaches 8.8.4.4
+        assert_eq!(networks(ips.clone().into_iter(), &Aggregation { prefix_len: 24, slack: u64::MAX, ..Aggregation::default() }, &host_util).unwrap().0,
+            vec!["1.1.0.0/22", "8.8.8.0/24"]);
+        let (nets, warnings) = networks(ips.into_iter(), &Aggregation { prefix_len: 24, max_networks: Some(1), ..Aggregation::default() }, &host_util).unwrap();
+        assert_eq!(nets, vec!["1.1.0.0/22", "8.8.8.0/24"]);
+        assert!(warnings.contains(&"could only merge down to 2 networks, not 1, without capturing a host connection or breaking a bound".to_string()));
+        let listed = ["198.51.100.7/24", "203.0.113.0/30", "203.0.113.9", "8.8.0.0/16"].map(String::from);
+        assert_eq!(networks(listed.into_iter(), &Aggregation::default(), &host_util).unwrap().0, vec!["198.51.100.0/24", "203.0.0.0/16"]);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let host_util = Host::from_socket_listing("tcp ESTAB 0 0 10.0.0.2:40000 8.8.4.4:443\ntcp ESTAB 0 0 10.0.0.2:40001 8.8.200.1:443");
+        let ips = ["8.8.8.8", "8.8.4.4", "1.1.1.1"].map(String::from);
+        let nets = |conflict: Conflict| networks_with(ips.clone().into_iter(), &Aggregation::default(), &host_util, &AddressBounds::new(), &Weights::new(),
+            &|net, conns| {
+                assert_eq!((net.to_string(), conns.len()), ("8.8.0.0/16".to_string(), 2));
+                conflict
+            }).unwrap();
+        assert_eq!(nets(Conflict::Drop).0, vec!["1.1.0.0/16"]);
+        assert_eq!(nets(Conflict::Keep), (vec!["1.1.0.0/16".to_string(), "8.8.0.0/16".into()],
+            vec!["host TCP connection to 8.8.4.4:443 would fall into routed network 8.8.0.0/16, keeping it".to_string()]));
+        // the address with the connection is left out, 8.8.8.8 stays in the largest part around both connections
+        let (narrowed, warnings) = nets(Conflict::Narrow);
+        assert_eq!(narrowed, vec!["1.1.0.0/16", "8.8.8.0/21"]);
+        assert!(warnings[0].ends_with("narrowing it to 8.8.8.0/21"), "{warnings:?}");
+    }
+
+    #[test]
+    fn test_networks_bounded() {
+        let host_util = Host::from_socket_listing("");
+        let ips = ["198.51.100.7", "198.51.101.9", "203.0.113.5", "203.0.112.1"].map(String::from);
+        let bounds = AddressBounds::from([
+            ("198.51.100.7".parse().unwrap(), (20, 24)),
+            ("198.51.101.9".parse().unwrap(), (20, 24)),
+            ("203.0.113.5".parse().unwrap(), (23, 23)),
+        ]);
+        // the /16 is narrowed to what the bounds allow, the unbounded address keeps it
+        assert_eq!(networks_with(ips.clone().into_iter(), &Aggregation::default(), &host_util, &bounds, &Weights::new(), &|_, _| Conflict::Drop).unwrap().0,
+            vec!["198.51.96.0/20", "203.0.0.0/16"]);
+        // /32 is widened to the narrowest allowed, slack merges stop at the widest
+        let aggregation = Aggregation { prefix_len: 32, slack: u64::MAX, ..Aggregation::default() };
+        assert_eq!(networks_with(ips.into_iter().take(3), &aggregation, &host_util, &bounds, &Weights::new(), &|_, _| Conflict::Drop).unwrap().0,
+            vec!["198.51.100.0/23", "203.0.112.0/23"]);
+    }
+
+    #[test]
+    fn test_weights() {
+        let hosts = BTreeMap::from([
+            ("video.example.com".to_string(), BTreeSet::from(["203.0.113.5".to_string(), "203.0.113.6".into()])),
+            ("api.example.org".to_string(), BTreeSet::from(["198.51.100.9".to_string()])),
+        ]);
+        let traffic = HashMap::from([("video.example.com".to_string(), 3000), ("api.example.org".to_string(), 500), ("gone.example.net".to_string(), 9)]);
+        let weights = address_weights(&hosts, &traffic);
+        assert_eq!(weights, Weights::from([
+            ("203.0.113.5".parse().unwrap(), 1500),
+            ("203.0.113.6".parse().unwrap(), 1500),
+            ("198.51.100.9".parse().unwrap(), 500),
+        ]));
+        assert_eq!(network_weights(&["198.51.100.0/24".to_string(), "203.0.0.0/16".into(), "192.0.2.0/24".into()], &weights), BTreeMap::from([
+            ("198.51.100.0/24".to_string(), 500),
+            ("203.0.0.0/16".to_string(), 3000),
+            ("192.0.2.0/24".to_string(), 0),
+        ]));
+    }
+
+    #[test]
+    fn test_networks_ipv6() {
+        let host_util = Host::from_socket_listing("tcp ESTAB 0 0 [2001:db8::5]:40000 [2606:4700:4700::1111]:443");
+        let ips = ["1.1.1.1", "2606:2800:220:1::1", "2606:2800:220:2::1", "2606:4700:4700::1001"].map(String::from);
+        assert_eq!(networks(ips.clone().into_iter(), &Aggregation::default(), &host_util), Ok((
+            vec!["1.1.0.0/16".to_string()],
+            vec!["IPv6 routing is off, ignoring 3 IPv6 address(es)".to_string()],
+        )));
+        let ipv6 = Aggregation { ipv6: true, ..Aggregation::default() };
+        assert_eq!(networks(ips.clone().into_iter(), &ipv6, &host_util), Ok((
+            vec!["1.1.0.0/16".to_string(), "2606:2800:220::/48".to_string()],
+            vec!["host TCP connection to 2606:4700:4700::1111:443 would fall into routed network 2606:4700:4700::/48, ignoring it".to_string()],
+        )));
+        assert_eq!(networks(ips.into_iter(), &Aggregation { prefix_len6: 64, ..ipv6 }, &host_util).unwrap().0,
+            vec!["1.1.0.0/16", "2606:2800:220:1::/64", "2606:2800:220:2::/64"]);
+    }
+
+    #[test]
+    fn test_resolve_hosts_offline() {
+        let passive = PassiveDns::from([("api.example.com".to_string(), HashSet::from(["93.184.216.34".to_string()]))]);
+        let hosts = ["api.example.com", "10.0.0.1", "1.1.1.1:443", "cdn.example.com", "[2606:4700::1111]:443", "fe80::1", "198.51.100.0/24", "10.1.0.0/16"]
+            .map(String::from);
+        let (ok, failed) = resolve_hosts_offline(hosts.iter(), &passive);
+        assert_eq!(ok, BTreeMap::from([
+            ("1.1.1.1:443".to_string(), BTreeSet::from(["1.1.1.1".to_string()])),
+            ("10.0.0.1".to_string(), BTreeSet::new()),
+            ("10.1.0.0/16".to_string(), BTreeSet::new()),
+            ("198.51.100.0/24".to_string(), BTreeSet::from(["198.51.100.0/24".to_string()])),
+            ("[2606:4700::1111]:443".to_string(), BTreeSet::from(["2606:4700::1111".to_string()])),
+            ("fe80::1".to_string(), BTreeSet::new()),
+            ("api.example.com".to_string(), BTreeSet::from(["93.184.216.34".to_string()])),
+        ]));
+        assert_eq!(failed.keys().collect::<Vec<_>>(), vec!["cdn.example.com"]);
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_resolve_hosts_with() {
+        let passive = PassiveDns::from([("api.example.com".to_string(), HashSet::from(["93.184.216.34".to_string()]))]);
+        let hosts = (1..=40).map(|n| format!("198.51.100.{n}")).chain(["api.example.com".to_string()]).collect::<Vec<_>>();
+        let done = std::sync::atomic::AtomicUsize::new(0);
+        let lookups = Lookups { concurrency: 4, ..Lookups::default() };
+        let (ok, failed) = resolve_hosts_with(hosts.iter(), &passive, false, &lookups, &mut Rng::seeded(1), || {
+            done.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        assert_eq!((ok.len(), failed.len(), done.into_inner()), (41, 0, 41));
+        assert_eq!(ok["api.example.com"], BTreeSet::from(["93.184.216.34".to_string()]));
+    }
+
+    #[test]
+    fn test_net_from_ip() {
+        assert_eq!(net_from_ip("93.184.216.34", 16), Ok("93.184.0.0/16".to_string()));
+        assert_eq!(net_from_ip("93.184.216.34", 32), Ok("93.184.216.34/32".to_string()));
+        assert_eq!(net_from_ip("93.184.216.34", 20), Ok("93.184.208.0/20".to_string()));
+        assert_eq!(net_from_ip("93.184.216.34", 8), Ok("93.0.0.0/8".to_string()));
+        assert!(net_from_ip("93.184.216.34", 33).is_err());
+        assert!(net_from_ip("a.b.c.d", 16).is_err());
+        assert_eq!(prefix_len(24), Ok(24));
+        assert!(prefix_len(7).is_err());
+        assert!(prefix_len(256 + 16).is_err());
+        assert_eq!(net_from_ip("2606:2800:220:1:248:1893:25c8:1946", 48), Ok("2606:2800:220::/48".to_string()));
+        assert_eq!(net_from_ip("2606:2800:220:1:248:1893:25c8:1946", 128), Ok("2606:2800:220:1:248:1893:25c8:1946/128".to_string()));
+        assert_eq!(prefix_len6(64), Ok(64));
+        assert!(prefix_len6(8).is_err());
+        assert!(prefix_len6(129).is_err());
+    }
+
+    #[test]
+    fn test_sorted_nets() {
+        let nets = HashSet::from(["193.10.0.0/16".to_string(), "8.8.0.0/16".into(), "193.9.0.0/16".into()]);
+        assert_eq!(sorted_nets(nets), Ok(vec!["8.8.0.0/16".to_string(), "193.9.0.0/16".into(), "193.10.0.0/16".into()]));
+        assert!(sorted_nets(HashSet::from(["x.y.0.0/16".to_string()])).is_err());
+        let nets = HashSet::from(["2001:db8::/48".to_string(), "8.8.0.0/16".into(), "::/48".into()]);
+        assert_eq!(sorted_nets(nets), Ok(vec!["8.8.0.0/16".to_string(), "::/48".into(), "2001:db8::/48".into()]));
+    }
+}
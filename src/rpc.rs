@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use serde_json::{json, Value};
+use crate::apply::{Backend, Scope, DEFAULT_FWMARK};
+use crate::clock::Rng;
+use crate::dns::StrResult;
+use crate::health::{self, Health};
+use crate::host::Host;
+use crate::input::hostname_from_url;
+use crate::aggregate::Aggregation;
+use crate::routes::{allowed_ips_line, networks, resolve_hosts_with, Lookups, PassiveDns};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+type RpcResult = Result<Value, (i64, String)>;
+
+// hosts added over the session are resolved lazily, only the ones not resolved yet
+#[derive(Debug, Default)]
+pub struct Session {
+    hosts: BTreeSet<String>,
+    resolved: BTreeMap<String, BTreeSet<String>>,
+    unresolved: BTreeMap<String, String>,
+    last_apply: Option<Value>,
+    health: Arc<Health>,
+}
+
+fn server_error(e: String) -> (i64, String) {
+    (SERVER_ERROR, e)
+}
+
+fn string_list(params: &Value, name: &str) -> Result<Option<Vec<String>>, (i64, String)> {
+    let list = match params {
+        Value::Array(_) if name == "hosts" => params,
+        _ => match params.get(name) {
+            Some(list) => list,
+            None => return Ok(None),
+        },
+    };
+    list.as_array()
+        .and_then(|items| items.iter().map(|v| v.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+        .map(Some)
+        .ok_or((INVALID_PARAMS, format!("'{name}' must be an array of strings")))
+}
+
+// "clients" (addresses or networks) or "client_set" limit the nft backend to the traffic of those LAN clients
+fn scope(params: &Value) -> Result<Scope, (i64, String)> {
+    if let Some(set) = params.get("client_set").and_then(Value::as_str) {
+        return Ok(Scope::Set(set.to_string()));
+    }
+    match string_list(params, "clients")? {
+        None => Ok(Scope::All),
+        Some(clients) => clients.iter()
+            .map(|client| client.parse().map_err(|e| (INVALID_PARAMS, format!("bad client '{client}': {e}"))))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Scope::Clients),
+    }
+}
+
+impl Session {
+    pub fn handle(&mut self, line: &str) -> Option<String> {
+        let request = match serde_json::from_str::<Value>(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, format!("invalid JSON: {e}"))),
+        };
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(Value::as_str) {
+            Some(method) => self.call(method, request.get("params").unwrap_or(&Value::Null)),
+            None => Err((INVALID_REQUEST, "no method".to_string())),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+            Err((code, message)) => error_response(id, code, message),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> RpcResult {
+        match method {
+            "add_hosts" => self.add_hosts(params),
+            "resolve" => self.resolve(params),
+            "generate" => self.generate(params),
+            "apply" => self.apply(params),
+            "status" => Ok(self.status()),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        }
+    }
+
+    fn add_hosts(&mut self, params: &Value) -> RpcResult {
+        let hosts = string_list(params, "hosts")?.ok_or((INVALID_PARAMS, "'hosts' is required".to_string()))?;
+        let before = self.hosts.len();
+        self.hosts.extend(hosts.iter().map(|h| hostname_from_url(h.trim()).unwrap_or(h.trim().to_string()).to_ascii_lowercase()));
+        Ok(json!({"added": self.hosts.len() - before, "total": self.hosts.len()}))
+    }
+
+    fn pending(&self) -> Vec<String> {
+        self.hosts.iter().filter(|h| !self.resolved.contains_key(*h) && !self.unresolved.contains_key(*h)).cloned().collect()
+    }
+
+    fn resolve(&mut self, params: &Value) -> RpcResult {
+        let hosts = match string_list(params, "hosts")? {
+            Some(hosts) => {
+                self.hosts.extend(hosts.iter().cloned());
+                hosts
+            }
+            None => self.pending(),
+        };
+        let ipv6 = params.get("ipv6").and_then(Value::as_bool).unwrap_or(false);
+        let _busy = self.health.busy();
+        let (ok, failed) = resolve_hosts_with(hosts.iter(), &PassiveDns::new(), ipv6, &Lookups::default(), &mut Rng::from_env(), || self.health.progress());
+        ok.keys().for_each(|h| {
+            self.unresolved.remove(h);
+        });
+        self.resolved.extend(ok.clone());
+        self.unresolved.extend(failed.clone());
+        Ok(json!({"resolved": ok, "unresolved": failed}))
+    }
+
+    fn generate(&mut self, params: &Value) -> RpcResult {
+        let aggregation = Aggregation::from_json(params).map_err(|e| (INVALID_PARAMS, e))?;
+        if !self.pending().is_empty() {
+            self.resolve(&json!({"ipv6": aggregation.ipv6}))?;
+        }
+        let host_util = Host::from_proc_net_tcp().map_err(server_error)?;
+        let (nets, warnings) = networks(self.resolved.values().flatten().cloned(), &aggregation, &host_util).map_err(server_error)?;
+        Ok(json!({"networks": nets, "allowed_ips": allowed_ips_line(&nets), "warnings": warnings}))
+    }
+
+    fn apply(&mut self, params: &Value) -> RpcResult {
+        let interface = params.get("interface").and_then(Value::as_str).unwrap_or("wg0").to_string();
+        let table = match params.get("table") {
+            None | Some(Value::Null) => None,
+            Some(t) => Some(t.as_u64().and_then(|t| u32::try_from(t).ok()).ok_or((INVALID_PARAMS, "'table' must be a number".to_string()))?),
+        };
+        let backend = match params.get("backend").and_then(Value::as_str) {
+            None | Some("route") => Backend::Route,
+            Some("nft") => Backend::Nft {
+                mark: params.get("fwmark").and_then(Value::as_u64).and_then(|m| u32::try_from(m).ok()).unwrap_or(DEFAULT_FWMARK),
+                scope: scope(params)?,
+            },
+            Some("netlink") => Backend::Netlink,
+            Some(other) => return Err((INVALID_PARAMS, format!("unknown backend '{other}'"))),
+        };
+        let state_dir = crate::history::state_dir(
+            params.get("state_dir").and_then(Value::as_str).map(std::path::Path::new),
+            params.get("profile").and_then(Value::as_str),
+        ).map_err(|e| (INVALID_PARAMS, e))?;
+        let keep = params.get("keep").and_then(Value::as_u64).map(|k| k as usize).unwrap_or(crate::history::DEFAULT_KEEP);
+        let generated = self.generate(params)?;
+        let nets = serde_json::from_value::<Vec<String>>(generated["networks"].clone()).map_err(|e| server_error(e.to_string()))?;
+        let replace = params.get("replace").and_then(Value::as_bool).unwrap_or(false);
+        let (transition, installed) = crate::apply::apply_generation(backend, &nets, &interface, table, keep, replace, &state_dir).map_err(server_error)?;
+        let summary = json!({
+            "interface": interface,
+            "table": table,
+            "applied": nets.len(),
+            "added": transition.add,
+            "removed": transition.remove,
+            "installed": installed,
+        });
+        self.last_apply = Some(summary.clone());
+        Ok(summary)
+    }
+
+    fn status(&self) -> Value {
+        json!({
+            "hosts": self.hosts.len(),
+            "resolved": self.resolved.len(),
+            "unresolved": self.unresolved.len(),
+            "pending": self.pending().len(),
+            "last_apply": self.last_apply,
+        })
+    }
+}
+
+fn error_response(id: Value, code: i64, message: String) -> String {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}).to_string()
+}
+
+// health is shared with the /healthz and /readyz listener and the systemd watchdog, if any
+pub fn serve(input: impl BufRead, mut output: impl Write, health: Arc<Health>) -> StrResult<()> {
+    let mut session = Session { health, ..Session::default() };
+    session.health.mark_ready();
+    let served = input.lines().try_for_each(|line| {
+        let line = line.map_err(|e| format!("could not read request: {e}"))?;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        match session.handle(&line) {
+            Some(response) => writeln!(output, "{response}").and_then(|_| output.flush()).map_err(|e| format!("could not write response: {e}")),
+            None => Ok(()),
+        }
+    });
+    health::notify("STOPPING=1");
+    served
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(session: &mut Session, request: &str) -> Value {
+        serde_json::from_str(&session.handle(request).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::default();
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":1,"method":"add_hosts","params":{"hosts":["93.184.216.34","https://1.1.1.1/dns-query"]}}"#),
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"added": 2, "total": 2}}));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":2,"method":"add_hosts","params":["93.184.216.34"]}"#)["result"],
+            json!({"added": 0, "total": 2}));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":3,"method":"status"}"#)["result"],
+            json!({"hosts": 2, "resolved": 0, "unresolved": 0, "pending": 2, "last_apply": null}));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":"r","method":"resolve"}"#)["result"]["resolved"],
+            json!({"1.1.1.1": ["1.1.1.1"], "93.184.216.34": ["93.184.216.34"]}));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":5,"method":"generate"}"#)["result"]["networks"],
+            json!(["1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":6,"method":"generate","params":{"prefix_len":24}}"#)["result"]["networks"],
+            json!(["1.1.1.0/24", "93.184.216.0/24"]));
+        assert_eq!(session.handle(r#"{"jsonrpc":"2.0","method":"status"}"#), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut session = Session::default();
+        assert_eq!(call(&mut session, "{not json")["error"]["code"], json!(PARSE_ERROR));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":1,"method":"frobnicate"}"#)["error"]["code"], json!(METHOD_NOT_FOUND));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":2,"method":"add_hosts","params":{"hosts":[1]}}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":3,"method":"add_hosts"}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":4}"#)["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":5,"method":"apply","params":{"table":"main"}}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":6,"method":"generate","params":{"prefix_len":4}}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":7,"method":"apply","params":{"backend":"pf"}}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":8,"method":"apply","params":{"profile":"../x"}}"#)["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&mut session, r#"{"jsonrpc":"2.0","id":8,"method":"apply","params":{"backend":"nft","clients":["tv"]}}"#)["error"]["code"], json!(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn test_serve() {
+        let input = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"status\"}\n\n{\"jsonrpc\":\"2.0\",\"method\":\"status\"}\n";
+        let mut output = Vec::new();
+        let health = Arc::new(Health::default());
+        serve(input.as_bytes(), &mut output, health.clone()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 1);
+        assert!(health.is_ready());
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use crate::dns::StrResult;
+use crate::output::Report;
+
+// SHA-256 over the options and the path and contents of each input file, in order
+pub fn inputs_digest(options: &str, files: &[&str]) -> StrResult<String> {
+    let mut context = Context::new(&SHA256);
+    context.update(options.as_bytes());
+    files.iter().try_for_each(|path| {
+        let contents = std::fs::read(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        // lengths first, so no two different lists of files hash the same
+        [path.len().to_le_bytes().as_slice(), path.as_bytes(), contents.len().to_le_bytes().as_slice(), &contents]
+            .into_iter()
+            .for_each(|part| context.update(part));
+        Ok::<_, String>(())
+    })?;
+    Ok(context.finish().as_ref().iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+// what the last run made of its inputs, for a run with the same inputs and options to pick up instead of doing it all again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastRun {
+    pub digest: String,
+    pub report: Report,
+}
+
+pub fn last_run_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("last-run.json")
+}
+
+impl LastRun {
+    // none when there was no run yet; a file this version cannot read counts as none as well, the run is just redone
+    pub fn load(path: &Path) -> Option<Self> {
+        std::fs::read_to_string(path).ok().and_then(|text| serde_json::from_str(&text).ok())
+    }
+
+    pub fn save(&self, path: &Path) -> StrResult<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+        }
+        let text = serde_json::to_string(self).map_err(|e| format!("could not serialize the last run: {e}"))?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, text).map_err(|e| format!("could not write {}: {e}", tmp.display()))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inputs_digest() {
+        let dir = std::env::temp_dir().join(format!("wgrouter-runs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "example.com\n").unwrap();
+        std::fs::write(&b, "example.org\n").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        let digest = inputs_digest("--prefix-len 16", &[a, b]).unwrap();
+        assert_eq!(digest.len(), 64);
+        assert_eq!(inputs_digest("--prefix-len 16", &[a, b]).unwrap(), digest);
+        assert_ne!(inputs_digest("--prefix-len 24", &[a, b]).unwrap(), digest);
+        assert_ne!(inputs_digest("--prefix-len 16", &[b, a]).unwrap(), digest);
+        std::fs::write(b, "example.net\n").unwrap();
+        assert_ne!(inputs_digest("--prefix-len 16", &[a, b]).unwrap(), digest);
+        assert!(inputs_digest("", &[dir.join("missing").to_str().unwrap()]).is_err());
+
+        let path = last_run_path(&dir);
+        assert_eq!(LastRun::load(&path), None);
+        let run = LastRun { digest, report: Report { networks: vec!["93.184.0.0/16".to_string()], ..Report::default() } };
+        run.save(&path).unwrap();
+        assert_eq!(LastRun::load(&path), Some(run));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use crate::dns::StrResult;
+use crate::output::Report;
+
+const DAY: u32 = 24 * 60;
+const WEEK: u32 = 7 * DAY;
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// minutes since Monday 00:00, local time
+pub fn week_minute(weekday_from_monday: u32, hour: u32, minute: u32) -> u32 {
+    (weekday_from_monday % 7) * DAY + hour * 60 + minute
+}
+
+// the week minute of a Unix time in the local time zone of the machine
+#[cfg(feature = "native")]
+pub fn local_week_minute(unix_time: u64) -> u32 {
+    let time = unix_time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        // no time zone to be had: UTC, where the Unix epoch was a Thursday
+        return ((unix_time / 60 + 3 * u64::from(DAY)) % u64::from(WEEK)) as u32;
+    }
+    week_minute((tm.tm_wday as u32 + 6) % 7, tm.tm_hour as u32, tm.tm_min as u32)
+}
+
+// from start to end (minutes of the day) on each of the days; an end not after the start runs into the next day
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub days: [bool; 7],
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Window {
+    pub fn contains(&self, at: u32) -> bool {
+        let length = if self.end > self.start { self.end - self.start } else { self.end + DAY - self.start };
+        (0..7).filter(|day| self.days[*day as usize]).any(|day| {
+            let start = day * DAY + self.start;
+            (at % WEEK + WEEK - start) % WEEK < length
+        })
+    }
+}
+
+// tag -> the windows its routes are active in; tags without any are always active
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule(pub BTreeMap<String, Vec<Window>>);
+
+impl Schedule {
+    pub fn load(path: &str) -> StrResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read schedule {path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{e} in schedule {path}"))
+    }
+
+    // "tag 19:00-24:00 [days]" lines, days like mon-fri or sat,sun (every day if left out), # starts a comment;
+    // several lines for a tag add up
+    pub fn parse(text: &str) -> StrResult<Self> {
+        let mut schedule = Self::default();
+        text.lines().enumerate().try_for_each(|(n, line)| {
+            let fields = line.split('#').next().unwrap_or_default().split_whitespace().collect::<Vec<_>>();
+            let (tag, window, days) = match fields[..] {
+                [] => return Ok(()),
+                [tag, window] => (tag, window, None),
+                [tag, window, days] => (tag, window, Some(days)),
+                _ => return Err(format!("expected 'tag HH:MM-HH:MM [days]' on line {}", n + 1)),
+            };
+            let window = parse_window(window, days).map_err(|e| format!("{e} on line {}", n + 1))?;
+            schedule.0.entry(tag.to_string()).or_default().push(window);
+            Ok(())
+        })?;
+        Ok(schedule)
+    }
+
+    pub fn is_active(&self, tag: &str, at: u32) -> bool {
+        self.0.get(tag).is_none_or(|windows| windows.iter().any(|window| window.contains(at)))
+    }
+
+    // the networks some active tag needs; networks of no scheduled tag stay routed all the time
+    pub fn active_networks(&self, report: &Report, at: u32) -> Vec<String> {
+        let by_tag = report.tag_networks();
+        report.networks.iter().filter(|net| {
+            let mut tags = by_tag.iter().filter(|(_, nets)| nets.contains(net)).map(|(tag, _)| tag).peekable();
+            tags.peek().is_none() || tags.any(|tag| self.is_active(tag, at))
+        }).cloned().collect()
+    }
+}
+
+fn parse_time(time: &str) -> StrResult<u32> {
+    let (hour, minute) = time.split_once(':').ok_or(format!("bad time '{time}', expected HH:MM"))?;
+    match (hour.parse::<u32>(), minute.parse::<u32>()) {
+        (Ok(24), Ok(0)) => Ok(DAY),
+        (Ok(hour), Ok(minute)) if hour < 24 && minute < 60 => Ok(hour * 60 + minute),
+        _ => Err(format!("bad time '{time}', expected HH:MM")),
+    }
+}
+
+fn parse_day(day: &str) -> StrResult<usize> {
+    DAYS.iter().position(|name| day.eq_ignore_ascii_case(name)).ok_or(format!("bad day '{day}', expected one of {}", DAYS.join(", ")))
+}
+
+fn parse_window(window: &str, days: Option<&str>) -> StrResult<Window> {
+    let (start, end) = window.split_once('-').ok_or(format!("bad window '{window}', expected HH:MM-HH:MM"))?;
+    let (start, end) = (parse_time(start)?, parse_time(end)?);
+    if start == DAY {
+        return Err(format!("bad window '{window}', it cannot start at 24:00"));
+    }
+    let mut selected = [days.is_none(); 7];
+    days.unwrap_or_default().split(',').filter(|range| !range.is_empty()).try_for_each(|range| {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => (parse_day(range)?, parse_day(range)?),
+        };
+        // sat-mon wraps around the end of the week
+        (0..7).map(|offset| (first + offset) % 7).take((last + 7 - first) % 7 + 1).for_each(|day| selected[day] = true);
+        Ok::<_, String>(())
+    })?;
+    Ok(Window { days: selected, start, end: end % DAY })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_parse() {
+        let schedule = Schedule::parse("# evenings only\nstreaming 19:00-24:00\nwork 08:30-18:00 mon-fri\n\
+            games 22:00-02:00 fri,sat  # into the night\nwork 10:00-12:00 sat-sun\n").unwrap();
+        assert_eq!(schedule.0["streaming"], vec![Window { days: [true; 7], start: 19 * 60, end: 0 }]);
+        assert_eq!(schedule.0["work"][0].days, [true, true, true, true, true, false, false]);
+        assert_eq!(schedule.0["work"][1].days, [false, false, false, false, false, true, true]);
+        assert_eq!(schedule.0["games"][0].days, [false, false, false, false, true, true, false]);
+        assert!(Schedule::parse("streaming 19:00-25:00").unwrap_err().ends_with("on line 1"));
+        assert!(Schedule::parse("streaming 19:00").is_err());
+        assert!(Schedule::parse("streaming 19:00-24:00 weekends").unwrap_err().contains("bad day 'weekends'"));
+        assert!(Schedule::parse("streaming evenings 19:00-24:00 mon").is_err());
+    }
+
+    #[test]
+    fn test_is_active() {
+        let schedule = Schedule::parse("streaming 19:00-24:00\nwork 08:30-18:00 mon-fri\ngames 22:00-02:00 sun").unwrap();
+        let (monday, saturday, sunday) = (0, 5, 6);
+        assert!(schedule.is_active("streaming", week_minute(monday, 23, 59)));
+        assert!(!schedule.is_active("streaming", week_minute(monday, 0, 0)));
+        assert!(!schedule.is_active("streaming", week_minute(monday, 18, 59)));
+        assert!(schedule.is_active("work", week_minute(monday, 8, 30)));
+        assert!(!schedule.is_active("work", week_minute(monday, 18, 0)));
+        assert!(!schedule.is_active("work", week_minute(saturday, 12, 0)));
+        // Sunday night runs into Monday morning, across the end of the week
+        assert!(schedule.is_active("games", week_minute(monday, 1, 59)));
+        assert!(schedule.is_active("games", week_minute(sunday, 23, 0)));
+        assert!(!schedule.is_active("games", week_minute(saturday, 23, 0)));
+        assert!(schedule.is_active("unscheduled", week_minute(saturday, 3, 0)));
+    }
+
+    #[test]
+    fn test_active_networks() {
+        let report = Report {
+            networks: vec!["1.1.0.0/16".to_string(), "93.184.0.0/16".into(), "198.51.0.0/16".into()],
+            hosts: BTreeMap::from([
+                ("video.example".to_string(), BTreeSet::from(["93.184.216.34".to_string()])),
+                ("one.one".to_string(), BTreeSet::from(["1.1.1.1".to_string()])),
+            ]),
+            tags: BTreeMap::from([
+                ("streaming".to_string(), BTreeSet::from(["video.example".to_string()])),
+                ("sniff".to_string(), BTreeSet::from(["one.one".to_string()])),
+            ]),
+            ..Report::default()
+        };
+        let schedule = Schedule::parse("streaming 19:00-24:00").unwrap();
+        assert_eq!(schedule.active_networks(&report, week_minute(0, 12, 0)), vec!["1.1.0.0/16", "198.51.0.0/16"]);
+        assert_eq!(schedule.active_networks(&report, week_minute(0, 20, 0)), report.networks);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use serde_json::Value;
+use crate::aggregate::widen;
+use crate::dns::{discard_port, hostname_is_ip, hostname_is_net};
+
+pub const DEFAULT_RUNS: usize = 10;
+// the prefix lengths compared, as IPv4 ones; IPv6 addresses are compared at the matching length of STEPS6
+const STEPS: [u8; 3] = [32, 24, 16];
+const STEPS6: [u8; 3] = [128, 48, 32];
+// percent of overlap from which a prefix length counts as stable
+const STABLE: u32 = 90;
+
+// how much the networks of a host's addresses stayed the same from one run to the next, at each of the STEPS prefix
+// lengths; none with a single run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stability {
+    pub host: String,
+    pub runs: usize,
+    pub addresses: usize,
+    pub overlap: Option<[u32; 3]>,
+}
+
+impl Stability {
+    // the narrowest length the host stays within, or advice for hosts that do not stay within any
+    pub fn advice(&self) -> String {
+        let Some(overlap) = self.overlap else {
+            return "needs more runs".to_string();
+        };
+        match STEPS.iter().zip(overlap).find(|(_, percent)| *percent >= STABLE) {
+            Some((32, _)) => "stable addresses, --prefix-len 32 will do".to_string(),
+            Some((len, _)) => format!("stable /{len} networks, a bounds line with max = /{len}"),
+            None => "rotates widely, route the provider's ranges".to_string(),
+        }
+    }
+}
+
+// host -> the addresses of each of its last runs, oldest first, from the "resolved" records of an audit log; a daemon
+// resolving again counts each time as a run, addresses and networks given as hosts are left out
+pub fn runs_of(audit_log: &str, last: usize) -> BTreeMap<String, Vec<BTreeSet<IpAddr>>> {
+    let mut runs = BTreeMap::<String, Vec<BTreeSet<IpAddr>>>::new();
+    audit_log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|record| record["event"] == "resolved")
+        .filter_map(|record| {
+            let addresses = record["addresses"].as_array()?.iter().filter_map(|ip| ip.as_str()?.parse().ok()).collect();
+            Some((record["host"].as_str()?.to_string(), addresses))
+        })
+        .filter(|(host, _)| hostname_is_net(host).is_none() && hostname_is_ip(discard_port(host)).is_none())
+        .for_each(|(host, addresses)| runs.entry(host).or_default().push(addresses));
+    runs.values_mut().for_each(|sets| {
+        let skip = sets.len().saturating_sub(last);
+        sets.drain(..skip);
+    });
+    runs
+}
+
+fn networks(addresses: &BTreeSet<IpAddr>, step: usize) -> BTreeSet<String> {
+    addresses
+        .iter()
+        .filter_map(|ip| widen(*ip, if ip.is_ipv4() { STEPS[step] } else { STEPS6[step] }).ok())
+        .map(|net| net.to_string())
+        .collect()
+}
+
+// the networks both of two consecutive runs had, out of those either had, in percent, averaged over all the pairs
+fn overlap(runs: &[BTreeSet<IpAddr>], step: usize) -> u32 {
+    let pairs = runs
+        .windows(2)
+        .map(|pair| {
+            let (before, after) = (networks(&pair[0], step), networks(&pair[1], step));
+            (100 * before.intersection(&after).count()).checked_div(before.union(&after).count()).unwrap_or(100)
+        })
+        .collect::<Vec<_>>();
+    (pairs.iter().sum::<usize>() / pairs.len().max(1)) as u32
+}
+
+pub fn stability(runs: &BTreeMap<String, Vec<BTreeSet<IpAddr>>>) -> Vec<Stability> {
+    runs.iter()
+        .map(|(host, sets)| Stability {
+            host: host.clone(),
+            runs: sets.len(),
+            addresses: sets.iter().flatten().collect::<BTreeSet<_>>().len(),
+            overlap: (sets.len() > 1).then(|| [0, 1, 2].map(|step| overlap(sets, step))),
+        })
+        .collect()
+}
+
+// for people: a line per host with the overlap at each length and the advice
+pub fn render(report: &[Stability]) -> String {
+    let width = report.iter().map(|s| s.host.len()).max().unwrap_or(0).max(4);
+    let mut out = format!("{:width$}  runs  addrs   /32   /24   /16  (IPv6: /128 /48 /32)\n", "host");
+    report.iter().for_each(|s| {
+        let overlap = match s.overlap {
+            Some(overlap) => overlap.map(|percent| format!("{percent:>4}%")).join(" "),
+            None => format!("{:>5} {:>5} {:>5}", "-", "-", "-"),
+        };
+        out.push_str(&format!("{:width$}  {:>4}  {:>5} {overlap}  {}\n", s.host, s.runs, s.addresses, s.advice()));
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stability() {
+        let log = [
+            r#"{"event":"resolved","run":"1","host":"api.example.com","addresses":["93.184.216.34"]}"#,
+            r#"{"event":"resolved","run":"1","host":"cdn.example.net","addresses":["198.51.100.7","198.51.100.9"]}"#,
+            r#"{"event":"aggregated","run":"1","network":"93.184.0.0/16","hosts":["api.example.com"]}"#,
+            r#"{"event":"resolved","run":"2","host":"api.example.com","addresses":["93.184.216.34"]}"#,
+            r#"{"event":"resolved","run":"2","host":"cdn.example.net","addresses":["198.51.100.20"]}"#,
+            r#"{"event":"resolved","run":"2","host":"video.example.org","addresses":["203.0.113.5"]}"#,
+            r#"{"event":"resolved","run":"3","host":"cdn.example.net","addresses":["198.51.7.1"]}"#,
+            r#"{"event":"resolved","run":"3","host":"203.0.113.9","addresses":["203.0.113.9"]}"#,
+            "not json",
+        ].join("\n");
+        let runs = runs_of(&log, 10);
+        assert_eq!(runs["cdn.example.net"].len(), 3);
+        assert_eq!(runs_of(&log, 2)["cdn.example.net"], vec![BTreeSet::from(["198.51.100.20".parse().unwrap()]), BTreeSet::from(["198.51.7.1".parse().unwrap()])]);
+
+        let report = stability(&runs);
+        assert_eq!(report, vec![
+            Stability { host: "api.example.com".to_string(), runs: 2, addresses: 1, overlap: Some([100, 100, 100]) },
+            // /24: the same network, then another; /16: the same throughout
+            Stability { host: "cdn.example.net".to_string(), runs: 3, addresses: 4, overlap: Some([0, 50, 100]) },
+            Stability { host: "video.example.org".to_string(), runs: 1, addresses: 1, overlap: None },
+        ]);
+        assert_eq!(report.iter().map(Stability::advice).collect::<Vec<_>>(), vec![
+            "stable addresses, --prefix-len 32 will do",
+            "stable /16 networks, a bounds line with max = /16",
+            "needs more runs",
+        ]);
+        let rotating = Stability { host: "x".to_string(), runs: 5, addresses: 40, overlap: Some([0, 10, 60]) };
+        assert_eq!(rotating.advice(), "rotates widely, route the provider's ranges");
+        assert_eq!(render(&report[1..2]), "host             runs  addrs   /32   /24   /16  (IPv6: /128 /48 /32)\n\
+            cdn.example.net     3      4    0%   50%  100%  stable /16 networks, a bounds line with max = /16\n");
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::dns::StrResult;
+use crate::output::Report;
+use crate::routes::PassiveDns;
+
+pub const SCHEMA: u32 = 1;
+pub const DEFAULT_MAX_AGE: u64 = 60 * 60;
+
+// what a host resolved to when it was last looked up, and the networks routing those addresses
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostState {
+    pub addresses: BTreeSet<String>,
+    pub networks: BTreeSet<String>,
+    pub resolved: u64,
+}
+
+// the hosts of the runs so far with their last answers, and the networks of the last run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub schema: u32,
+    pub hosts: BTreeMap<String, HostState>,
+    pub networks: BTreeSet<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self { schema: SCHEMA, hosts: BTreeMap::new(), networks: BTreeSet::new() }
+    }
+}
+
+impl State {
+    pub fn load(path: &Path) -> StrResult<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("could not read state {}: {e}", path.display())),
+        };
+        let state: Self = serde_json::from_str(&text).map_err(|e| format!("could not parse state {}: {e}", path.display()))?;
+        if state.schema > SCHEMA {
+            return Err(format!("state {} has schema {}, this version only understands up to {SCHEMA}", path.display(), state.schema));
+        }
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &Path) -> StrResult<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(|e| format!("could not serialize state: {e}"))?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, text).map_err(|e| format!("could not write {}: {e}", tmp.display()))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {e}", path.display()))
+    }
+
+    // the addresses of the hosts looked up less than max_age seconds ago, which need no lookup again
+    pub fn fresh(&self, now: u64, max_age: u64) -> PassiveDns {
+        self.hosts
+            .iter()
+            .filter(|(_, host)| now.saturating_sub(host.resolved) < max_age)
+            .map(|(name, host)| (name.clone(), host.addresses.iter().cloned().collect()))
+            .collect()
+    }
+
+    // what changed from the state to the report: hosts new, gone or with other addresses, and networks added or removed
+    pub fn changes(&self, report: &Report) -> Vec<String> {
+        let mut changes = Vec::new();
+        report.hosts.iter().for_each(|(name, addresses)| match self.hosts.get(name) {
+            None => changes.push(format!("new host {name}: {}", join(addresses))),
+            Some(host) if &host.addresses != addresses => changes.push(format!("{name}: {} -> {}", join(&host.addresses), join(addresses))),
+            Some(_) => {}
+        });
+        self.hosts.keys().filter(|name| !report.hosts.contains_key(*name)).for_each(|name| changes.push(format!("host gone: {name}")));
+        let networks = report.networks.iter().cloned().collect::<BTreeSet<_>>();
+        networks.difference(&self.networks).for_each(|net| changes.push(format!("+ {net}")));
+        self.networks.difference(&networks).for_each(|net| changes.push(format!("- {net}")));
+        changes
+    }
+
+    // the hosts of the report with the networks their addresses went into; those kept from the state keep the time they
+    // were looked up, the others get now, and hosts no longer in the inputs are forgotten
+    pub fn update(&mut self, report: &Report, kept: &BTreeSet<String>, now: u64) {
+        let mut routed = report.host_networks();
+        self.hosts = report
+            .hosts
+            .iter()
+            .map(|(name, addresses)| {
+                let resolved = match self.hosts.get(name) {
+                    Some(host) if kept.contains(name) => host.resolved,
+                    _ => now,
+                };
+                (name.clone(), HostState { addresses: addresses.clone(), networks: routed.remove(name).unwrap_or_default(), resolved })
+            })
+            .collect();
+        self.networks = report.networks.iter().cloned().collect();
+    }
+}
+
+fn join(addresses: &BTreeSet<String>) -> String {
+    addresses.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(hosts: &[(&str, &[&str])], networks: &[&str]) -> Report {
+        Report {
+            hosts: hosts.iter().map(|(name, ips)| (name.to_string(), ips.iter().map(|ip| ip.to_string()).collect())).collect(),
+            networks: networks.iter().map(|net| net.to_string()).collect(),
+            ..Report::default()
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let mut state = State::default();
+        let first = report(&[("a.example", &["93.184.216.34"]), ("b.example", &["198.51.100.7"])], &["93.184.0.0/16", "198.51.0.0/16"]);
+        assert_eq!(state.changes(&first).len(), 4);
+        state.update(&first, &BTreeSet::new(), 1000);
+        assert_eq!(state.hosts["a.example"].networks, ["93.184.0.0/16".to_string()].into());
+        assert_eq!(state.fresh(1500, 600).len(), 2);
+        assert!(state.fresh(1600, 600).is_empty());
+
+        let second = report(&[("a.example", &["93.184.216.34"]), ("c.example", &["203.0.113.5"])], &["93.184.0.0/16", "203.0.0.0/16"]);
+        assert_eq!(state.changes(&second), vec!["new host c.example: 203.0.113.5", "host gone: b.example", "+ 203.0.0.0/16", "- 198.51.0.0/16"]);
+        state.update(&second, &["a.example".to_string()].into(), 2000);
+        assert_eq!(state.hosts.keys().collect::<Vec<_>>(), vec!["a.example", "c.example"]);
+        assert_eq!((state.hosts["a.example"].resolved, state.hosts["c.example"].resolved), (1000, 2000));
+        assert!(state.changes(&second).is_empty());
+
+        let path = std::env::temp_dir().join(format!("wgrouter-state-{}.json", std::process::id()));
+        assert_eq!(State::load(&path), Ok(State::default()));
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path), Ok(state));
+        std::fs::write(&path, "{\"schema\": 2, \"hosts\": {}, \"networks\": []}").unwrap();
+        assert!(State::load(&path).unwrap_err().contains("schema 2"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use std::process::Command;
+use crate::dns::StrResult;
+
+// a tool that parses a generated file without acting on it, given the path of the file last
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checker {
+    pub program: &'static str,
+    pub args: &'static [&'static str],
+}
+
+pub const NFT: Checker = Checker { program: "nft", args: &["-c", "-f"] };
+pub const SHELL: Checker = Checker { program: "sh", args: &["-n"] };
+pub const BIRD: Checker = Checker { program: "bird", args: &["-p", "-c"] };
+pub const UNBOUND: Checker = Checker { program: "unbound-checkconf", args: &[] };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    // the tool is not installed here, so nothing was checked
+    Missing,
+}
+
+// the text written to a scratch file and handed to the checker; what it says on failure is the error
+pub fn check(checker: Checker, text: &str, extension: &str) -> StrResult<Outcome> {
+    let path = std::env::temp_dir().join(format!("wgrouter-validate-{}.{extension}", std::process::id()));
+    std::fs::write(&path, text).map_err(|e| format!("could not write {}: {e}", path.display()))?;
+    let output = Command::new(checker.program).args(checker.args).arg(&path).output();
+    let _ = std::fs::remove_file(&path);
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Outcome::Missing),
+        Err(e) => return Err(format!("could not run {}: {e}", checker.program)),
+    };
+    if output.status.success() {
+        return Ok(Outcome::Passed);
+    }
+    let said = [output.stderr, output.stdout].iter().map(|out| String::from_utf8_lossy(out).trim().to_string()).filter(|out| !out.is_empty()).collect::<Vec<_>>();
+    Err(format!("'{} {}' rejects the output: {}", checker.program, checker.args.join(" "), said.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check(SHELL, "ip route add 10.0.0.0/8 dev wg0\n", "sh"), Ok(Outcome::Passed));
+        assert!(check(SHELL, "if ip route add 10.0.0.0/8 dev wg0\n", "sh").unwrap_err().starts_with("'sh -n' rejects the output: "));
+        assert_eq!(check(Checker { program: "wgrouter-no-such-checker", args: &[] }, "", "txt"), Ok(Outcome::Missing));
+    }
+}
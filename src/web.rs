@@ -0,0 +1,68 @@
+use serde_json::json;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::aggregate::Aggregation;
+use crate::dns::StrResult;
+use crate::host::Host;
+use crate::input::har::{hostnames_and_addresses_from_har_text, hostnames_from_har_text, HarOptions};
+use crate::routes::{self, allowed_ips_line, networks, resolve_hosts_offline};
+
+// entry points for a client-side web UI: the dropped HAR text goes in, JSON comes out; no DNS, no filesystem
+
+const HAR_NAME: &str = "dropped HAR";
+
+fn sorted(hosts: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut hosts = hosts.into_iter().collect::<Vec<_>>();
+    hosts.sort();
+    hosts
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = hostsFromHar))]
+pub fn hosts_from_har(har: &str, scan_bodies: bool, lenient: bool) -> StrResult<String> {
+    let opts = HarOptions { scan_bodies, mine_headers: scan_bodies, lenient, ..Default::default() };
+    let hosts = hostnames_from_har_text(har, HAR_NAME, &opts)?;
+    Ok(json!({"confirmed": sorted(hosts.confirmed), "candidates": sorted(hosts.candidates)}).to_string())
+}
+
+// routes come from the addresses the browser recorded for each request, hosts without one are reported back
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = allowedIpsFromHar))]
+pub fn allowed_ips_from_har(har: &str, lenient: bool, prefix_len: u8, slack: u32, ipv6: bool) -> StrResult<String> {
+    let aggregation = Aggregation { prefix_len: routes::prefix_len(prefix_len.into())?, slack: slack.into(), ipv6, ..Default::default() };
+    let opts = HarOptions { lenient, ..Default::default() };
+    let (hosts, addresses) = hostnames_and_addresses_from_har_text(har, HAR_NAME, &opts)?;
+    let (resolved, unresolved) = resolve_hosts_offline(hosts.confirmed.iter(), &addresses);
+    let (nets, _) = networks(resolved.into_values().flatten(), &aggregation, &Host::default())?;
+    Ok(json!({
+        "allowed_ips": allowed_ips_line(&nets),
+        "networks": nets,
+        "unresolved": unresolved.into_keys().collect::<Vec<_>>(),
+    }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn har() -> String {
+        std::fs::read_to_string(format!("{}/tests/asus.com.har", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn test_hosts_from_har() {
+        let hosts = serde_json::from_str::<Value>(&hosts_from_har(&har(), false, false).unwrap()).unwrap();
+        assert!(hosts["confirmed"].as_array().unwrap().contains(&json!("account.asus.com")));
+        assert_eq!(hosts["candidates"], json!([]));
+        assert!(hosts_from_har("{not a har", false, false).is_err());
+    }
+
+    #[test]
+    fn test_allowed_ips_from_har() {
+        let routes = serde_json::from_str::<Value>(&allowed_ips_from_har(&har(), false, 16, 0, false).unwrap()).unwrap();
+        let nets = routes["networks"].as_array().unwrap();
+        assert!(nets.contains(&json!("108.157.0.0/16")));
+        assert_eq!(routes["allowed_ips"].as_str().unwrap(), allowed_ips_line(&serde_json::from_value::<Vec<String>>(routes["networks"].clone()).unwrap()));
+        assert!(routes["unresolved"].is_array());
+        assert!(allowed_ips_from_har(&har(), false, 33, 0, false).is_err());
+    }
+}
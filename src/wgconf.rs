@@ -0,0 +1,254 @@
+use std::io::Write;
+use std::path::Path;
+use ipnetwork::IpNetwork;
+use crate::dns::StrResult;
+
+// key = value, keys are case-insensitive as in wg-quick
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split('#').next().unwrap_or_default().split_once('=')?;
+    Some((key.trim(), value.trim()))
+}
+
+fn is_section(line: &str) -> bool {
+    line.trim_start().starts_with('[')
+}
+
+fn is_peer(line: &str) -> bool {
+    line.trim().eq_ignore_ascii_case("[peer]")
+}
+
+// the lines of the [Peer] section with that public key, header excluded
+fn peer_section(lines: &[&str], peer: &str) -> Option<(usize, usize)> {
+    let starts = lines.iter().enumerate().filter(|(_, line)| is_peer(line)).map(|(n, _)| n + 1).collect::<Vec<_>>();
+    starts.into_iter().map(|start| {
+        let end = lines[start..].iter().position(|line| is_section(line)).map_or(lines.len(), |n| start + n);
+        (start, end)
+    }).find(|(start, end)| {
+        lines[*start..*end].iter().filter_map(|line| key_value(line)).any(|(key, value)| key.eq_ignore_ascii_case("PublicKey") && value == peer)
+    })
+}
+
+// the networks that allowed IPs do not have yet: entries already there, or covered by one that is, are left out
+pub fn new_allowed_ips(existing: &[&str], nets: &[String]) -> StrResult<Vec<String>> {
+    let existing_nets = existing.iter().filter_map(|entry| entry.parse::<IpNetwork>().ok()).collect::<Vec<_>>();
+    let mut added = Vec::<String>::new();
+    nets.iter().try_for_each(|net| {
+        let parsed = net.parse::<IpNetwork>().map_err(|e| format!("bad network '{net}': {e}"))?;
+        let covered = existing_nets.iter().any(|have| have.prefix() <= parsed.prefix() && have.contains(parsed.network()));
+        if !covered && !existing.contains(&net.as_str()) && !added.contains(net) {
+            added.push(net.clone());
+        }
+        Ok::<_, String>(())
+    })?;
+    Ok(added)
+}
+
+// the config with the networks added to the AllowedIPs of the peer, and the networks that were added; entries already
+// there, or covered by one that is, stay as they are, and so do comments and the rest of the file; the new entries go
+// to the end of the peer's last AllowedIPs line, or to a new one after its public key
+pub fn merge_allowed_ips(conf: &str, peer: &str, nets: &[String]) -> StrResult<(String, Vec<String>)> {
+    let lines = conf.lines().collect::<Vec<_>>();
+    let (start, end) = peer_section(&lines, peer).ok_or(format!("no [Peer] with PublicKey = {peer}"))?;
+    let allowed_lines = (start..end)
+        .filter(|n| key_value(lines[*n]).is_some_and(|(key, _)| key.eq_ignore_ascii_case("AllowedIPs")))
+        .collect::<Vec<_>>();
+    let existing = allowed_lines
+        .iter()
+        .flat_map(|n| key_value(lines[*n]).map(|(_, value)| value).unwrap_or_default().split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect::<Vec<_>>();
+    let added = new_allowed_ips(&existing, nets)?;
+    if added.is_empty() {
+        return Ok((conf.to_string(), added));
+    }
+
+    let mut out = lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+    match allowed_lines.last() {
+        Some(&n) => {
+            let line = lines[n];
+            let (body, comment) = line.find('#').map_or((line, ""), |at| line.split_at(at));
+            // the gap before a comment stays as it was
+            let gap = &body[body.trim_end().len()..];
+            let (key, value) = body.split_once('=').unwrap_or((body, ""));
+            let value = value.trim();
+            let entries = if value.is_empty() { added.join(", ") } else { format!("{value}, {}", added.join(", ")) };
+            out[n] = format!("{} = {entries}{gap}{comment}", key.trim_end());
+        }
+        None => {
+            let after = (start..end)
+                .find(|n| key_value(lines[*n]).is_some_and(|(key, _)| key.eq_ignore_ascii_case("PublicKey")))
+                .unwrap_or(start);
+            out.insert(after + 1, format!("AllowedIPs = {}", added.join(", ")));
+        }
+    }
+    let newline = if conf.ends_with('\n') { "\n" } else { "" };
+    Ok((out.join("\n") + newline, added))
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Peer {
+    pub public_key: String,
+    pub endpoint: Option<String>,
+    pub allowed_ips: Vec<String>,
+}
+
+fn entries(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(String::from)
+}
+
+// the [Peer] sections in order
+pub fn peers(conf: &str) -> Vec<Peer> {
+    let lines = conf.lines().collect::<Vec<_>>();
+    lines.iter().enumerate().filter(|(_, line)| is_peer(line)).map(|(n, _)| {
+        let end = lines[n + 1..].iter().position(|line| is_section(line)).map_or(lines.len(), |end| n + 1 + end);
+        lines[n + 1..end].iter().filter_map(|line| key_value(line)).fold(Peer::default(), |mut peer, (key, value)| {
+            match key.to_ascii_lowercase().as_str() {
+                "publickey" => peer.public_key = value.to_string(),
+                "endpoint" if !value.is_empty() => peer.endpoint = Some(value.to_string()),
+                "allowedips" => peer.allowed_ips.extend(entries(value)),
+                _ => {}
+            }
+            peer
+        })
+    }).collect()
+}
+
+// the Endpoint of each peer, host:port as written
+pub fn endpoints(conf: &str) -> Vec<String> {
+    conf.lines()
+        .filter_map(key_value)
+        .filter(|(key, value)| key.eq_ignore_ascii_case("Endpoint") && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+        .collect()
+}
+
+// the addresses in the DNS line of the interface, leaving out the search domains that may be there as well
+pub fn dns_servers(conf: &str) -> Vec<String> {
+    conf.lines()
+        .filter_map(key_value)
+        .filter(|(key, _)| key.eq_ignore_ascii_case("DNS"))
+        .flat_map(|(_, value)| value.split(',').map(str::trim).filter(|entry| entry.parse::<std::net::IpAddr>().is_ok()).map(String::from).collect::<Vec<_>>())
+        .collect()
+}
+
+// the AllowedIPs entries of all peers
+pub fn allowed_ips(conf: &str) -> Vec<String> {
+    conf.lines()
+        .filter_map(key_value)
+        .filter(|(key, _)| key.eq_ignore_ascii_case("AllowedIPs"))
+        .flat_map(|(_, value)| entries(value).collect::<Vec<_>>())
+        .collect()
+}
+
+// a file that did not exist, with no more permissions than the file it replaces from the start: a leftover or a link
+// planted at the name is an error rather than written through
+#[cfg(unix)]
+fn create_new(path: &Path, permissions: &std::fs::Permissions) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    std::fs::OpenOptions::new().write(true).create_new(true).mode(permissions.mode() & 0o777).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path, _: &std::fs::Permissions) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+// the old file is kept as <path>.bak, the new one replaces it in one rename with the same permissions (the private
+// key is in there); nothing is written when nothing is added
+pub fn patch_file(path: &Path, peer: &str, nets: &[String]) -> StrResult<Vec<String>> {
+    let conf = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    let (patched, added) = merge_allowed_ips(&conf, peer, nets).map_err(|e| format!("{e} in {}", path.display()))?;
+    if added.is_empty() {
+        return Ok(added);
+    }
+    let with_suffix = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        std::path::PathBuf::from(name)
+    };
+    let (backup, tmp) = (with_suffix(".bak"), with_suffix(".tmp"));
+    std::fs::copy(path, &backup).map_err(|e| format!("could not back up {} to {}: {e}", path.display(), backup.display()))?;
+    let permissions = std::fs::metadata(path).map_err(|e| format!("could not stat {}: {e}", path.display()))?.permissions();
+    let mut file = create_new(&tmp, &permissions).map_err(|e| format!("could not create {}: {e}", tmp.display()))?;
+    file.write_all(patched.as_bytes()).map_err(|e| format!("could not write {}: {e}", tmp.display()))?;
+    file.set_permissions(permissions).map_err(|e| format!("could not set permissions of {}: {e}", tmp.display()))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {e}", path.display()))?;
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = "[Interface]\nPrivateKey = cHJpdmF0ZQ==\nAddress = 10.8.0.2/32\n\n\
+        # home\n[Peer]\nPublicKey = aG9tZQ==\nAllowedIPs = 192.168.1.0/24\n\n\
+        # VPN provider\n[Peer]\nPublicKey = dnBu\nEndpoint = vpn.example.net:51820\nAllowedIPs = 10.8.0.0/24 # tunnel\n\
+        AllowedIPs = 1.1.0.0/16, 93.184.0.0/16  # streaming\nPersistentKeepalive = 25\n";
+
+    fn nets(nets: &[&str]) -> Vec<String> {
+        nets.iter().map(|net| net.to_string()).collect()
+    }
+
+    #[test]
+    fn test_merge_allowed_ips() {
+        let (patched, added) = merge_allowed_ips(CONF, "dnBu", &nets(&["93.184.0.0/16", "1.1.1.0/24", "203.0.113.0/24", "2001:db8::/32"])).unwrap();
+        assert_eq!(added, nets(&["203.0.113.0/24", "2001:db8::/32"]));
+        assert_eq!(patched, CONF.replace(
+            "AllowedIPs = 1.1.0.0/16, 93.184.0.0/16  # streaming",
+            "AllowedIPs = 1.1.0.0/16, 93.184.0.0/16, 203.0.113.0/24, 2001:db8::/32  # streaming",
+        ));
+
+        let (patched, _) = merge_allowed_ips("[Peer]\npublickey = aG9tZQ==\nEndpoint = h:1\n[Peer]\nPublicKey = dnBu\n", "aG9tZQ==", &nets(&["1.1.0.0/16"])).unwrap();
+        assert_eq!(patched, "[Peer]\npublickey = aG9tZQ==\nAllowedIPs = 1.1.0.0/16\nEndpoint = h:1\n[Peer]\nPublicKey = dnBu\n");
+
+        assert_eq!(merge_allowed_ips(CONF, "dnBu", &nets(&["1.1.0.0/16"])).unwrap(), (CONF.to_string(), vec![]));
+        assert_eq!(merge_allowed_ips(CONF, "b3RoZXI=", &nets(&["1.1.0.0/16"])).unwrap_err(), "no [Peer] with PublicKey = b3RoZXI=");
+        assert_eq!(new_allowed_ips(&["10.8.0.1/32", "1.1.0.0/16"], &nets(&["1.1.4.0/24", "10.8.0.1/32", "9.9.0.0/16", "9.9.0.0/16"])), Ok(nets(&["9.9.0.0/16"])));
+        assert!(new_allowed_ips(&[], &nets(&["example.com"])).is_err());
+    }
+
+    #[test]
+    fn test_endpoints() {
+        assert_eq!(endpoints(CONF), vec!["vpn.example.net:51820"]);
+        assert_eq!(dns_servers("[Interface]\nDNS = 10.8.0.1, vpn.lan, fd00::1\n"), nets(&["10.8.0.1", "fd00::1"]));
+        assert_eq!(allowed_ips(CONF), nets(&["192.168.1.0/24", "10.8.0.0/24", "1.1.0.0/16", "93.184.0.0/16"]));
+        assert_eq!(endpoints("[Peer]\nendpoint = [2001:db8::1]:51820 # v6\nEndpoint =\n"), vec!["[2001:db8::1]:51820"]);
+    }
+
+    #[test]
+    fn test_peers() {
+        let peers = peers(CONF);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0], Peer { public_key: "aG9tZQ==".to_string(), endpoint: None, allowed_ips: nets(&["192.168.1.0/24"]) });
+        assert_eq!(peers[1].endpoint.as_deref(), Some("vpn.example.net:51820"));
+        assert_eq!(peers[1].allowed_ips, nets(&["10.8.0.0/24", "1.1.0.0/16", "93.184.0.0/16"]));
+    }
+
+    #[test]
+    fn test_patch_file() {
+        let path = std::env::temp_dir().join(format!("wgrouter-wg-{}.conf", std::process::id()));
+        std::fs::write(&path, CONF).unwrap();
+        assert_eq!(patch_file(&path, "aG9tZQ==", &nets(&["192.168.2.0/24"])).unwrap(), nets(&["192.168.2.0/24"]));
+        let backup = path.with_extension("conf.bak");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), CONF);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("AllowedIPs = 192.168.1.0/24, 192.168.2.0/24\n"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            patch_file(&path, "aG9tZQ==", &nets(&["192.168.3.0/24"])).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // a file left at the temporary name is not written through
+        let tmp = path.with_extension("conf.tmp");
+        std::fs::write(&tmp, "").unwrap();
+        let before = std::fs::read_to_string(&path).unwrap();
+        assert!(patch_file(&path, "aG9tZQ==", &nets(&["192.168.4.0/24"])).unwrap_err().contains("could not create"));
+        assert_eq!((std::fs::read_to_string(&path).unwrap(), std::fs::read_to_string(&tmp).unwrap()), (before, String::new()));
+        std::fs::remove_file(&tmp).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+        assert!(patch_file(&path, "aG9tZQ==", &nets(&["192.168.2.0/24"])).is_err());
+    }
+}